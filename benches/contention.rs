@@ -0,0 +1,97 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Contention benchmarks
+//!
+//! Measures acquire/release cycles of the `Spinlock`, `Mutex`, `RWLock` and `Semaphore` with 1 to 4 threads contending
+//! for the same lock using criterion. The reported time is the time for one cycle as seen by each of the threads. The
+//! benchmarks run on the host:
+//!
+//! ```text
+//! cargo bench --bench contention --target <host-triple>
+//! ```
+//!
+//! The bare-metal counterpart measuring cpu cycles on the actual cores is provided by `ruspiro_lock::bench` and run
+//! with `cargo make qemu-bench` in `integration-tests/qemu`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use ruspiro_lock::sync::{Mutex, RWLock, Semaphore, Spinlock};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAX_THREADS: usize = 4;
+
+/// Run `iterations` acquire/release cycles on each of `threads` threads and return the time the slowest thread took
+fn contend<L, F>(lock: Arc<L>, threads: usize, iterations: u64, cycle: F) -> Duration
+where
+  L: Send + Sync + 'static,
+  F: Fn(&L) + Copy + Send + 'static,
+{
+  let barrier = Arc::new(Barrier::new(threads));
+  let handles: Vec<_> = (0..threads)
+    .map(|_| {
+      let lock = Arc::clone(&lock);
+      let barrier = Arc::clone(&barrier);
+      thread::spawn(move || {
+        barrier.wait();
+        let start = Instant::now();
+        for _ in 0..iterations {
+          cycle(&lock);
+        }
+        start.elapsed()
+      })
+    })
+    .collect();
+
+  handles
+    .into_iter()
+    .map(|handle| handle.join().unwrap())
+    .max()
+    .unwrap_or_default()
+}
+
+fn bench_primitive<L, F>(c: &mut Criterion, name: &str, lock: Arc<L>, cycle: F)
+where
+  L: Send + Sync + 'static,
+  F: Fn(&L) + Copy + Send + 'static,
+{
+  let mut group = c.benchmark_group(name);
+  for threads in 1..=MAX_THREADS {
+    group.bench_with_input(
+      BenchmarkId::from_parameter(threads),
+      &threads,
+      |b, &threads| {
+        b.iter_custom(|iterations| contend(Arc::clone(&lock), threads, iterations, cycle))
+      },
+    );
+  }
+  group.finish();
+}
+
+fn contention(c: &mut Criterion) {
+  bench_primitive(c, "Spinlock", Arc::new(Spinlock::new()), |lock| {
+    lock.acquire();
+    lock.release();
+  });
+  bench_primitive(c, "Mutex", Arc::new(Mutex::new(0u64)), |lock| {
+    *lock.lock() += 1;
+  });
+  bench_primitive(c, "RWLock write", Arc::new(RWLock::new(0u64)), |lock| {
+    *lock.write() += 1;
+  });
+  bench_primitive(c, "RWLock read", Arc::new(RWLock::new(0u64)), |lock| {
+    let _ = *lock.read();
+  });
+  bench_primitive(c, "Semaphore", Arc::new(Semaphore::new(1)), |lock| {
+    lock.down();
+    lock.up();
+  });
+}
+
+criterion_group!(benches, contention);
+criterion_main!(benches);
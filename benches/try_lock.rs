@@ -0,0 +1,174 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Mutex try_lock micro-benchmark
+//!
+//! Compares the `Mutex::try_lock` fast path, a relaxed pre-check followed by a weak compare-exchange, with the
+//! unconditional atomic swap used before. Both strategies are implemented on a plain flag to compare them without any
+//! other overhead, the actual `Mutex` is measured as well. Each variant is measured uncontended and with the lock
+//! being hammered by 1 to 3 additional threads. The benchmark runs on the host:
+//!
+//! ```text
+//! cargo bench --bench try_lock --target <host-triple>
+//! ```
+//!
+//! On a contended lock the swap writes the cache line on every failed attempt, while the pre-check only reads it. The
+//! gain therefore shows with the contending threads running on other cores, the numbers of a host with less cores
+//! than threads are dominated by the scheduling.
+
+use ruspiro_lock::sync::Mutex;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 1_000_000;
+
+/// The previous `try_lock` implementation using an unconditional swap
+struct SwapLock {
+  locked: AtomicBool,
+}
+
+impl SwapLock {
+  fn try_lock(&self) -> bool {
+    !self.locked.swap(true, Ordering::Acquire)
+  }
+
+  /// release the lock the same way the `Mutex` does
+  fn unlock(&self) {
+    self.locked.swap(false, Ordering::Release);
+  }
+}
+
+/// The current `try_lock` implementation using a relaxed pre-check and a weak compare-exchange
+struct WeakCasLock {
+  locked: AtomicBool,
+}
+
+impl WeakCasLock {
+  fn try_lock(&self) -> bool {
+    if self.locked.load(Ordering::Relaxed) {
+      return false;
+    }
+    loop {
+      match self
+        .locked
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+      {
+        Ok(_) => return true,
+        Err(true) => return false,
+        Err(false) => continue,
+      }
+    }
+  }
+
+  /// release the lock the same way the `Mutex` does
+  fn unlock(&self) {
+    self.locked.swap(false, Ordering::Release);
+  }
+}
+
+trait Lock: Send + Sync + 'static {
+  /// try to lock and immediately unlock again, returns whether the lock could be acquired
+  fn cycle(&self) -> bool;
+}
+
+impl Lock for SwapLock {
+  fn cycle(&self) -> bool {
+    if self.try_lock() {
+      self.unlock();
+      true
+    } else {
+      false
+    }
+  }
+}
+
+impl Lock for WeakCasLock {
+  fn cycle(&self) -> bool {
+    if self.try_lock() {
+      self.unlock();
+      true
+    } else {
+      false
+    }
+  }
+}
+
+impl Lock for Mutex<u32> {
+  fn cycle(&self) -> bool {
+    match self.try_lock() {
+      Some(mut guard) => {
+        *guard += 1;
+        true
+      }
+      None => false,
+    }
+  }
+}
+
+/// Measure the time per successful lock/unlock cycle of the main thread while `contenders` threads keep trying to
+/// acquire the same lock
+fn measure<L: Lock>(lock: Arc<L>, contenders: usize) -> Duration {
+  let stop = Arc::new(AtomicBool::new(false));
+  let threads: Vec<_> = (0..contenders)
+    .map(|_| {
+      let lock = Arc::clone(&lock);
+      let stop = Arc::clone(&stop);
+      thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+          black_box(lock.cycle());
+        }
+      })
+    })
+    .collect();
+
+  let start = Instant::now();
+  let mut acquired = 0;
+  while acquired < ITERATIONS {
+    if lock.cycle() {
+      acquired += 1;
+    }
+  }
+  let elapsed = start.elapsed();
+
+  stop.store(true, Ordering::Relaxed);
+  for thread in threads {
+    thread.join().unwrap();
+  }
+  elapsed / ITERATIONS
+}
+
+fn main() {
+  println!(
+    "{:<12} {:>12} {:>14} {:>14}",
+    "contenders", "swap", "weak cas", "Mutex"
+  );
+  for contenders in 0..=3 {
+    let swap = measure(
+      Arc::new(SwapLock {
+        locked: AtomicBool::new(false),
+      }),
+      contenders,
+    );
+    let weak = measure(
+      Arc::new(WeakCasLock {
+        locked: AtomicBool::new(false),
+      }),
+      contenders,
+    );
+    let mutex = measure(Arc::new(Mutex::new(0u32)), contenders);
+    println!(
+      "{:<12} {:>9} ns {:>11} ns {:>11} ns",
+      contenders,
+      swap.as_nanos(),
+      weak.as_nanos(),
+      mutex.as_nanos()
+    );
+  }
+}
@@ -0,0 +1,161 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # QEMU Stress Test Kernel
+//!
+//! A tiny bare-metal kernel for the Raspberry Pi 3 model of `qemu-system-aarch64`. All 4 cores run the contention
+//! scenarios of `ruspiro_lock::stress`. Once done, core 0 reports the results and exits QEMU using semihosting. The
+//! exit code is 0 if all scenarios passed.
+//!
+//! With the `bench` feature the cores run the benchmarks of `ruspiro_lock::bench` instead, measuring the cpu cycles
+//! with the cycle counter of the performance monitor unit. Core 0 reports the measurements.
+//!
+//! The MMU is not configured, QEMU does not require this for the exclusive load/store instructions to work. On real
+//! hardware the atomics would hang the cores without a proper MMU configuration.
+
+#![no_std]
+#![no_main]
+
+use core::arch::{asm, global_asm};
+use core::fmt::Write;
+use core::panic::PanicInfo;
+#[cfg(feature = "bench")]
+use ruspiro_lock::bench;
+#[cfg(not(feature = "bench"))]
+use ruspiro_lock::stress;
+
+const CORES: usize = 4;
+const ITERATIONS: u32 = 10_000;
+
+// Entry point of all cores. Each core gets its own stack. Core 0 clears the BSS and releases the secondary cores
+// that are parked in the spin table of the QEMU boot stub (addresses 0xe0, 0xe8, 0xf0 for core 1 to 3).
+global_asm!(
+  r#"
+.section .text.boot
+.global _start
+_start:
+  mrs   x0, mpidr_el1
+  and   x0, x0, #3
+  ldr   x1, =__stack_top
+  lsl   x2, x0, #16
+  sub   x1, x1, x2
+  mov   sp, x1
+  cbnz  x0, 3f
+
+  ldr   x1, =__bss_start
+  ldr   x2, =__bss_end
+1:
+  cmp   x1, x2
+  b.ge  2f
+  str   xzr, [x1], #8
+  b     1b
+2:
+  ldr   x1, =_start
+  mov   x2, #0xe0
+  str   x1, [x2]
+  str   x1, [x2, #8]
+  str   x1, [x2, #16]
+  dsb   sy
+  sev
+3:
+  bl    kernel_main
+4:
+  wfe
+  b     4b
+"#
+);
+
+#[cfg(feature = "bench")]
+#[no_mangle]
+extern "C" fn kernel_main(core: usize) -> ! {
+  enable_cycle_counter();
+  bench::run(core, CORES, ITERATIONS, cycles);
+
+  if core == 0 {
+    for measurement in bench::results(CORES, ITERATIONS) {
+      let _ = writeln!(Semihosting, "{}", measurement);
+    }
+    semihosting_exit(0);
+  }
+
+  loop {
+    unsafe { asm!("wfe") };
+  }
+}
+
+/// Enable the cycle counter of the performance monitor unit of the current core
+#[cfg(feature = "bench")]
+fn enable_cycle_counter() {
+  unsafe {
+    // enable the counters and reset the cycle counter
+    asm!("msr pmcr_el0, {}", in(reg) 0b101usize);
+    // enable the cycle counter
+    asm!("msr pmcntenset_el0, {}", in(reg) 1usize << 31);
+    asm!("isb");
+  }
+}
+
+/// Read the cycle counter of the current core
+#[cfg(feature = "bench")]
+fn cycles() -> u64 {
+  let cycles: u64;
+  unsafe { asm!("isb", "mrs {}, pmccntr_el0", out(reg) cycles) };
+  cycles
+}
+
+#[cfg(not(feature = "bench"))]
+#[no_mangle]
+extern "C" fn kernel_main(core: usize) -> ! {
+  stress::run(core, CORES, ITERATIONS);
+
+  if core == 0 {
+    let mut passed = true;
+    for report in stress::reports(CORES, ITERATIONS).iter() {
+      let _ = writeln!(Semihosting, "{}", report);
+      passed &= report.is_ok();
+    }
+    semihosting_exit(if passed { 0 } else { 1 });
+  }
+
+  loop {
+    unsafe { asm!("wfe") };
+  }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  let _ = writeln!(Semihosting, "{}", info);
+  semihosting_exit(101);
+}
+
+/// Output to the host console using the semihosting `SYS_WRITEC` call
+struct Semihosting;
+
+impl Write for Semihosting {
+  fn write_str(&mut self, s: &str) -> core::fmt::Result {
+    for c in s.bytes() {
+      unsafe { semihosting_call(0x03, &c as *const u8 as usize) };
+    }
+    Ok(())
+  }
+}
+
+/// Exit QEMU with the given exit code using the semihosting `SYS_EXIT` call
+fn semihosting_exit(code: usize) -> ! {
+  // ADP_Stopped_ApplicationExit
+  let block: [usize; 2] = [0x20026, code];
+  unsafe { semihosting_call(0x18, block.as_ptr() as usize) };
+  loop {
+    unsafe { asm!("wfe") };
+  }
+}
+
+unsafe fn semihosting_call(op: usize, param: usize) -> usize {
+  let result;
+  asm!("hlt #0xf000", inout("x0") op => result, in("x1") param);
+  result
+}
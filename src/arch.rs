@@ -0,0 +1,103 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Architecture specific operations
+//!
+//! All barriers and event instructions used by the locks are provided here. On other architectures than Arm they
+//! compile to nothing or a spin loop hint.
+//!
+//! Blocking locks wait for an event (`wfe`) and releasing a lock signals an event (`sev`) to all cores. If running as
+//! a guest of a hypervisor the `sev` is broadcast to all virtual cores and usually trapped, which is quite expensive.
+//! With the `no_sev` feature waiting is done with a pure spin loop executing an `isb` as hint and no events are
+//! signalled. This trades a higher power consumption of waiting cores for cheaper lock releases.
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+use core::arch::asm;
+
+/// The number of cores per core state is tracked for
+pub(crate) const MAX_CORES: usize = 4;
+
+/// Data memory barrier. Required after acquiring a lock before accessing the protected resource, see:
+/// http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+#[inline(always)]
+pub(crate) fn dmb() {
+  #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+  unsafe {
+    asm!("dmb sy");
+  }
+}
+
+/// Data synchronisation barrier. Completes all memory accesses before any further instruction is executed.
+#[inline(always)]
+pub(crate) fn dsb() {
+  #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+  unsafe {
+    asm!("dsb sy");
+  }
+}
+
+/// Signal an event to all cores that might wait for a lock to be released. A data synchronisation barrier is done
+/// upfront to ensure any data updates have been finished.
+#[inline(always)]
+pub(crate) fn signal_event() {
+  #[cfg(all(
+    any(target_arch = "arm", target_arch = "aarch64"),
+    not(feature = "no_sev")
+  ))]
+  unsafe {
+    asm!(
+      "dsb sy
+       sev"
+    );
+  }
+}
+
+/// Wait until an event is signalled that indicates that a lock has likely been released. This saves energy and cpu
+/// consumption compared to a pure spin loop.
+#[inline(always)]
+pub(crate) fn wait_for_event() {
+  #[cfg(all(
+    any(target_arch = "arm", target_arch = "aarch64"),
+    not(feature = "no_sev")
+  ))]
+  unsafe {
+    asm!("wfe");
+  }
+  #[cfg(all(any(target_arch = "arm", target_arch = "aarch64"), feature = "no_sev"))]
+  unsafe {
+    asm!("isb");
+  }
+  #[cfg(not(all(
+    any(target_arch = "arm", target_arch = "aarch64"),
+    not(feature = "no_sev")
+  )))]
+  core::hint::spin_loop();
+}
+
+/// Hint to the core that it is spinning, so other hardware threads or a hypervisor can make progress
+#[inline(always)]
+pub(crate) fn yield_hint() {
+  #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+  unsafe {
+    asm!("yield");
+  }
+  #[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
+  core::hint::spin_loop();
+}
+
+/// The id of the current core read from the affinity level 0 of the `MPIDR_EL1` register. This is the default core id
+/// provider of the [CoreConfig](crate::CoreConfig).
+pub(crate) fn mpidr_core_id() -> usize {
+  #[cfg(target_arch = "aarch64")]
+  unsafe {
+    let mpidr: usize;
+    asm!("mrs {}, mpidr_el1", out(reg) mpidr);
+    (mpidr & 0xFF) % MAX_CORES
+  }
+  #[cfg(not(target_arch = "aarch64"))]
+  0
+}
@@ -9,59 +9,139 @@
 //!
 
 extern crate alloc;
+use super::waiters::WaiterSlots;
+use super::{trace, AsyncLock, CancellationToken};
 use crate::sync::{Mutex, MutexGuard};
-use alloc::{collections::BTreeMap, sync::Arc};
+use crate::LockError;
+use alloc::sync::Arc;
 use core::{
   future::Future,
+  mem::ManuallyDrop,
   ops::{Deref, DerefMut},
   pin::Pin,
-  task::{Context, Poll, Waker},
+  sync::atomic::{AtomicUsize, Ordering},
+  task::{Context, Poll},
 };
 
 /// An async mutex lock that can be used in async functions to prevent blocking current execution while waiting for the
 /// lock to become available. So for this to work the `lock()` method does not return a MutexGuard immediately but a
 /// [Future] that will resove into a [AsyncMutexGuard] when `await`ed.
-pub struct AsyncMutex<T> {
-  /// The inner wrapper to the actual [Mutex] requires to be secured with a [Mutex] on it's own
-  /// as we require mutual exclusive access to it. This actually should not harm any concurrent blocking
-  /// as this is a short living lock that will be only aquired to request the actual lock status. So it is
-  /// more then unlikely that this will happen in parallel at the same time
-  inner: Arc<Mutex<AsyncMutexInner>>,
+///
+/// Up to `WAITERS` `Future`s can wait for the lock at the same time without busy polling. Their wakers are kept in a
+/// fixed number of slots, so waiting for the lock does not allocate. If all slots are occupied additional `Future`s
+/// re-schedule themself when polled until a slot gets available.
+pub struct AsyncMutex<T, const WAITERS: usize = 32> {
+  /// The waiters of the lock. They are registered and woken with atomic operations only, so the async path never
+  /// spins on a blocking lock
+  inner: Arc<AsyncMutexInner<WAITERS>>,
   /// The actual [Mutex] securing the contained data for mutual exclusive access
   data: Arc<Mutex<T>>,
 }
 
 impl<T> AsyncMutex<T> {
-  /// Create the [AsyncMutex]
+  /// Create the [AsyncMutex] with the default number of 32 waiter slots
   pub fn new(value: T) -> Self {
+    Self::with_waiter_slots(value)
+  }
+}
+
+impl<T, const WAITERS: usize> AsyncMutex<T, WAITERS> {
+  /// Create the [AsyncMutex] with `WAITERS` waiter slots
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::AsyncMutex;
+  /// let data: AsyncMutex<u32, 4> = AsyncMutex::with_waiter_slots(0);
+  /// ```
+  pub fn with_waiter_slots(value: T) -> Self {
     Self {
-      inner: Arc::new(Mutex::new(AsyncMutexInner::new())),
+      inner: Arc::new(AsyncMutexInner::new()),
       data: Arc::new(Mutex::new(value)),
     }
   }
 
   /// Locking the data secured by the [AsyncMutex] will yield a `Future` that must be awaited to actually acquire
   /// the lock.
-  pub async fn lock(&self) -> AsyncMutexGuard<'_, T> {
+  pub async fn lock(&self) -> AsyncMutexGuard<'_, T, WAITERS> {
     // check if we could immediately get the lock
     if let Some(guard) = self.data.try_lock() {
       // lock immediatly acquired, provide the lock guard as result
-      AsyncMutexGuard {
-        guard,
-        inner: Arc::clone(&self.inner),
-      }
-    } else {
-      // to be able to request the lock we require to upate the inner metadata. For this to work we require a
-      // short living exclusive lock to this data.
-      let mut inner = self.inner.lock();
-      let current_id = inner.next_waiter;
-      inner.next_waiter += 1;
-      drop(inner);
-
-      // once we have updated the metadata we can release the lock to it and create the `Future` that will yield
-      // the lock to the data once available
-      AsyncMutexFuture::new(Arc::clone(&self.inner), Arc::clone(&self.data), current_id).await
+      trace::acquired("AsyncMutex", trace::lock_id(&*self.inner), None);
+      return AsyncMutexGuard::new(guard, Arc::clone(&self.inner));
     }
+
+    self.inner.waiting.fetch_add(1, Ordering::Relaxed);
+    let current_id = self.inner.waiter.next_ticket();
+    trace::requested("AsyncMutex", trace::lock_id(&*self.inner), current_id);
+
+    AsyncMutexFuture::new(Arc::clone(&self.inner), &self.data, current_id).await
+  }
+
+  /// Lock the data secured by the [AsyncMutex] unless the given [CancellationToken] is cancelled while waiting for the
+  /// lock. In this case the request is withdrawn and this resolves to [LockError::Cancelled].
+  pub async fn lock_cancellable(
+    &self,
+    token: &CancellationToken,
+  ) -> Result<AsyncMutexGuard<'_, T, WAITERS>, LockError> {
+    token.run_until_cancelled(self.lock()).await
+  }
+
+  /// Lock the data and call the given closure with a borrow of it. The lock is released once the closure returns. As
+  /// the closure can not `.await` the lock can not be held accidentally across `.await` points. Other than for the
+  /// [AsyncRWLock](super::AsyncRWLock) this acquires the exclusive lock, it is provided to swap both locks easily.
+  pub async fn with_read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+    let guard = self.lock().await;
+    f(guard.as_ref())
+  }
+
+  /// Lock the data and call the given closure with a mutable borrow of it. The lock is released once the closure
+  /// returns. As the closure can not `.await` the lock can not be held accidentally across `.await` points.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::AsyncMutex;
+  /// async fn increment(counter: &AsyncMutex<u32>) -> u32 {
+  ///     counter
+  ///         .with_write(|counter| {
+  ///             *counter += 1;
+  ///             *counter
+  ///         })
+  ///         .await
+  /// }
+  /// ```
+  pub async fn with_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+    let mut guard = self.lock().await;
+    f(guard.as_mut())
+  }
+
+  /// Try to lock the data secured by the [AsyncMutex] without waiting. The returned [OwnedAsyncMutexGuard] is not
+  /// bound to the lifetime of the [AsyncMutex] and can therefore be moved into spawned tasks. Returns `None` if the
+  /// lock is currently held.
+  pub fn try_lock_owned(&self) -> Option<OwnedAsyncMutexGuard<T, WAITERS>> {
+    let guard = self.data.try_lock()?;
+    trace::acquired("AsyncMutex", trace::lock_id(&*self.inner), None);
+    // the lock is released by the OwnedAsyncMutexGuard once it is dropped
+    core::mem::forget(guard);
+    Some(OwnedAsyncMutexGuard {
+      data: Arc::clone(&self.data),
+      inner: Arc::clone(&self.inner),
+    })
+  }
+
+  /// Returns `true` if the [AsyncMutex] is currently locked
+  pub fn is_locked(&self) -> bool {
+    self.data.is_locked()
+  }
+
+  /// The number of `Future`s currently waiting to acquire the lock
+  pub fn waiter_count(&self) -> usize {
+    self.inner.waiting.load(Ordering::Relaxed)
+  }
+
+  /// The id of the lock used in the trace events and by the acquisition accounting of the stress tests
+  #[cfg(feature = "stress_tests")]
+  pub(crate) fn lock_id(&self) -> usize {
+    trace::lock_id(&*self.inner)
   }
 
   /// Provide the inner data wrapped by this [AsyncMutex]. This will only provide the contained data if there is only
@@ -81,12 +161,22 @@ impl<T> AsyncMutex<T> {
   }
 }
 
-pub struct AsyncMutexGuard<'a, T: 'a> {
-  guard: MutexGuard<'a, T>,
-  inner: Arc<Mutex<AsyncMutexInner>>,
+pub struct AsyncMutexGuard<'a, T: 'a, const WAITERS: usize = 32> {
+  /// The guard of the data lock, it is released before the next waiter is woken
+  guard: ManuallyDrop<MutexGuard<'a, T>>,
+  inner: Arc<AsyncMutexInner<WAITERS>>,
 }
 
-impl<'a, T> Deref for AsyncMutexGuard<'a, T> {
+impl<'a, T, const WAITERS: usize> AsyncMutexGuard<'a, T, WAITERS> {
+  fn new(guard: MutexGuard<'a, T>, inner: Arc<AsyncMutexInner<WAITERS>>) -> Self {
+    Self {
+      guard: ManuallyDrop::new(guard),
+      inner,
+    }
+  }
+}
+
+impl<'a, T, const WAITERS: usize> Deref for AsyncMutexGuard<'a, T, WAITERS> {
   type Target = MutexGuard<'a, T>;
 
   fn deref(&self) -> &Self::Target {
@@ -94,96 +184,174 @@ impl<'a, T> Deref for AsyncMutexGuard<'a, T> {
   }
 }
 
-impl<'a, T> DerefMut for AsyncMutexGuard<'a, T> {
+impl<'a, T, const WAITERS: usize> DerefMut for AsyncMutexGuard<'a, T, WAITERS> {
   fn deref_mut(&mut self) -> &mut Self::Target {
     &mut self.guard
   }
 }
 
+impl<T, const WAITERS: usize> AsRef<T> for AsyncMutexGuard<'_, T, WAITERS> {
+  fn as_ref(&self) -> &T {
+    &self.guard
+  }
+}
+
+impl<T, const WAITERS: usize> AsMut<T> for AsyncMutexGuard<'_, T, WAITERS> {
+  fn as_mut(&mut self) -> &mut T {
+    &mut self.guard
+  }
+}
+
 /// If an [AsyncMutexGuard] get's dropped we need to wake the `Future`s that might hav registered themself and
-/// are waiting to aquire the lock.
-impl<T> Drop for AsyncMutexGuard<'_, T> {
+/// are waiting to acquire the lock.
+impl<T, const WAITERS: usize> Drop for AsyncMutexGuard<'_, T, WAITERS> {
   fn drop(&mut self) {
-    // if the mutex guard is about to be locked we need to check if there has been a waker send
-    // already to get woken
-    let mut inner = self.inner.lock();
-    if let Some(&next_waiter) = inner.waiter.keys().next() {
-      // remove the waker from the waiter list as it will re-register itself when the corresponding
-      // Future is polled and can't acquire the lock
-      let waiter = inner
-        .waiter
-        .remove(&next_waiter)
-        .expect("found key but can't remove it ???");
-      waiter.wake_by_ref();
-    }
+    // SAFETY: the guard is dropped exactly once, here
+    unsafe { ManuallyDrop::drop(&mut self.guard) };
+    trace::released("AsyncMutex", trace::lock_id(&*self.inner));
+    // the data lock has been released before, so the woken waiter will be able to acquire it
+    self.inner.wake_next();
   }
 }
 
-/// The `Future` that represents an `await`able [AsynMutex] and can only be created from the functions of [AsyncMutex].
-struct AsyncMutexFuture<'a, T: 'a> {
-  inner: Arc<Mutex<AsyncMutexInner>>,
+impl<T, const WAITERS: usize> AsyncLock<T> for AsyncMutex<T, WAITERS> {
+  type Guard<'a>
+    = AsyncMutexGuard<'a, T, WAITERS>
+  where
+    Self: 'a;
+
+  fn lock(&self) -> impl Future<Output = Self::Guard<'_>> {
+    AsyncMutex::lock(self)
+  }
+}
+
+/// The guard of an [AsyncMutex] acquired with [AsyncMutex::try_lock_owned]. It keeps the secured data alive and
+/// releases the lock once dropped.
+pub struct OwnedAsyncMutexGuard<T, const WAITERS: usize = 32> {
   data: Arc<Mutex<T>>,
+  inner: Arc<AsyncMutexInner<WAITERS>>,
+}
+
+impl<T, const WAITERS: usize> Deref for OwnedAsyncMutexGuard<T, WAITERS> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    // SAFETY: the guard does only exist while the lock is held
+    unsafe { &*self.data.data_ptr() }
+  }
+}
+
+impl<T, const WAITERS: usize> DerefMut for OwnedAsyncMutexGuard<T, WAITERS> {
+  fn deref_mut(&mut self) -> &mut T {
+    // SAFETY: the guard does only exist while the lock is held
+    unsafe { &mut *self.data.data_ptr() }
+  }
+}
+
+impl<T, const WAITERS: usize> Drop for OwnedAsyncMutexGuard<T, WAITERS> {
+  fn drop(&mut self) {
+    // SAFETY: the lock has been acquired when this guard was created and the MutexGuard has been forgotten
+    unsafe { self.data.unlock() };
+    trace::released("AsyncMutex", trace::lock_id(&*self.inner));
+    self.inner.wake_next();
+  }
+}
+
+/// The `Future` that represents an `await`able [AsynMutex] and can only be created from the functions of [AsyncMutex].
+///
+/// It borrows the secured data from the [AsyncMutex] for the lifetime of the lock request, so the [AsyncMutexGuard]
+/// can be handed out without any unsafe lifetime extension. The `Future` is `Send` if `T` is `Send`.
+struct AsyncMutexFuture<'a, T: 'a, const WAITERS: usize> {
+  inner: Arc<AsyncMutexInner<WAITERS>>,
+  data: &'a Mutex<T>,
   id: usize,
-  _p: core::marker::PhantomData<&'a T>,
+  done: bool,
 }
 
-impl<T> AsyncMutexFuture<'_, T> {
-  fn new(inner: Arc<Mutex<AsyncMutexInner>>, data: Arc<Mutex<T>>, id: usize) -> Self {
+impl<'a, T, const WAITERS: usize> AsyncMutexFuture<'a, T, WAITERS> {
+  fn new(inner: Arc<AsyncMutexInner<WAITERS>>, data: &'a Mutex<T>, id: usize) -> Self {
     Self {
       inner,
       data,
       id,
-      _p: core::marker::PhantomData,
+      done: false,
     }
   }
 }
 
-impl<'a, T> Future for AsyncMutexFuture<'a, T> {
-  type Output = AsyncMutexGuard<'a, T>;
+impl<'a, T, const WAITERS: usize> Future for AsyncMutexFuture<'a, T, WAITERS> {
+  type Output = AsyncMutexGuard<'a, T, WAITERS>;
 
   fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-    // we need to elide the lifetime given by self.get_mut() using unsafe code here
-    // SAFETY: it's actually safe as we either return Poll::Pending without any lifetime or we
-    // handout the `AsyncMutexGuard` with lifetime 'a which bound to the AsyncMutex that created this Future and
-    // will always outlive this future and is therefore ok - I guess...
-    let this = unsafe { &*(self.get_mut() as *const Self) };
+    // the Future does not contain any self references, so it is fine to access it mutably
+    let this = self.get_mut();
     if let Some(guard) = this.data.try_lock() {
       // data lock could be acquired
       // provide the AsyncMutexGuard
-      Poll::Ready(AsyncMutexGuard {
-        guard,
-        inner: Arc::clone(&this.inner),
-      })
+      this.done = true;
+      trace::acquired("AsyncMutex", trace::lock_id(&*this.inner), Some(this.id));
+      Poll::Ready(AsyncMutexGuard::new(guard, Arc::clone(&this.inner)))
     } else {
       // data lock could not be acquired this time, so someone else is holding the lock. We need to register
       // ourself to get woken as soon as the lock gets available
-      let mut inner = this.inner.lock();
-      inner.waiter.insert(this.id, cx.waker().clone());
-      drop(inner);
+      let registered = this.inner.waiter.register(this.id, cx.waker());
+      // the lock might have been released while we registered ourself, so give it another try to not miss the wake up
+      if let Some(guard) = this.data.try_lock() {
+        this.inner.waiter.remove(this.id);
+        this.done = true;
+        trace::acquired("AsyncMutex", trace::lock_id(&*this.inner), Some(this.id));
+        return Poll::Ready(AsyncMutexGuard::new(guard, Arc::clone(&this.inner)));
+      }
+
+      if !registered {
+        // all waiter slots are occupied, so re-schedule ourself to try again
+        cx.waker().wake_by_ref();
+      }
 
       Poll::Pending
     }
   }
 }
 
-struct AsyncMutexInner {
-  /// If the lock could not be aquired we store the requestor id here to allow the next one
-  /// already waiting for the lock to retrieve it
-  waiter: BTreeMap<usize, Waker>,
-  /// The id of the next waiter that can be woken once the lock is released and someone else is already waiting for
-  /// the lock to be aquired
-  next_waiter: usize,
+/// A `Future` that is dropped is no longer waiting. If it has been woken but did not acquire the lock the wake up is
+/// passed on to the next waiter.
+impl<T, const WAITERS: usize> Drop for AsyncMutexFuture<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    self.inner.waiting.fetch_sub(1, Ordering::Relaxed);
+    if !self.inner.waiter.remove(self.id) && !self.done {
+      self.inner.wake_next();
+    }
+  }
 }
 
-impl AsyncMutexInner {
+struct AsyncMutexInner<const WAITERS: usize> {
+  /// If the lock could not be acquired we store the waker of the requestor here to allow the one waiting the longest
+  /// to be woken once the lock is released
+  waiter: WaiterSlots<WAITERS>,
+  /// The number of `Future`s currently waiting for the lock
+  waiting: AtomicUsize,
+}
+
+impl<const WAITERS: usize> AsyncMutexInner<WAITERS> {
   fn new() -> Self {
     Self {
-      waiter: BTreeMap::new(),
-      next_waiter: 0,
+      waiter: WaiterSlots::new(),
+      waiting: AtomicUsize::new(0),
     }
   }
+
+  /// Wake the waiter that is waiting the longest
+  fn wake_next(&self) {
+    // the waker is removed from the waiter slots as it will re-register itself when the corresponding Future is
+    // polled and can't acquire the lock
+    self.waiter.wake_next();
+  }
 }
 
+// the async guards are meant to be held across `.await` points, so they stay `Send` with the `unsend_guards` feature
+#[cfg(feature = "unsend_guards")]
+unsafe impl<T: Send, const WAITERS: usize> Send for AsyncMutexGuard<'_, T, WAITERS> {}
+
 #[cfg(testing)]
 mod tests {
   use super::*;
@@ -192,6 +360,7 @@ mod tests {
   use core::time::Duration;
 
   #[async_std::test]
+  #[cfg_attr(miri, ignore = "the async-std runtime is not supported by miri")]
   async fn wait_on_mutex() {
     let mutex = Arc::new(AsyncMutex::new(10_u32));
     let mutex_clone = Arc::clone(&mutex);
@@ -207,7 +376,7 @@ mod tests {
 
     let task2 = task::spawn(async move {
       // if this async is started first wait a bit to really run the
-      // other one first to aquire the AsyncMutexLock
+      // other one first to acquire the AsyncMutexLock
       task::yield_now().await;
       task::sleep(Duration::from_millis(100)).await;
       let guard = mutex.lock().await;
@@ -228,4 +397,48 @@ mod tests {
       _ => panic!("unable to get inner data"),
     }
   }
+
+  fn assert_send<F: Future + Send>(future: F) -> F {
+    future
+  }
+
+  #[async_std::test]
+  #[cfg_attr(miri, ignore = "the async-std runtime is not supported by miri")]
+  async fn lock_future_is_send() {
+    let mutex = Arc::new(AsyncMutex::new(10_u32));
+    let guard = mutex.lock().await;
+    let mutex_clone = Arc::clone(&mutex);
+
+    // the lock request can be spawned on a work stealing executor
+    let task = task::spawn(assert_send(async move {
+      let guard = mutex_clone.lock().await;
+      **guard
+    }));
+    task::sleep(Duration::from_millis(100)).await;
+    drop(guard);
+    assert_eq!(task.await, 10);
+  }
+
+  #[async_std::test]
+  #[cfg_attr(miri, ignore = "the async-std runtime is not supported by miri")]
+  async fn more_waiters_than_slots() {
+    let mutex: Arc<AsyncMutex<u32, 2>> = Arc::new(AsyncMutex::with_waiter_slots(0));
+    let guard = mutex.lock().await;
+
+    let tasks: Vec<_> = (0..4)
+      .map(|_| {
+        let mutex = Arc::clone(&mutex);
+        task::spawn(async move {
+          **mutex.lock().await += 1;
+        })
+      })
+      .collect();
+    task::sleep(Duration::from_millis(100)).await;
+    drop(guard);
+
+    for task in tasks {
+      task.await;
+    }
+    assert_eq!(**mutex.lock().await, 4);
+  }
 }
@@ -0,0 +1,382 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Async Mutex with fixed waiter capacity
+//!
+//! This version of the async mutex does not require any heap allocation. The `Future`s waiting for the lock are kept
+//! in a fixed number of waiter slots. If all slots are occupied additional `Future`s will immediately re-schedule
+//! themself when polled until a slot gets available.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::r#async::AsyncMutexN;
+//!
+//! static DATA: AsyncMutexN<u32, 4> = AsyncMutexN::new(0);
+//!
+//! async fn update() {
+//!     let mut guard = DATA.lock().await;
+//!     **guard = 20;
+//! }
+//! ```
+
+use super::waiters::WaiterSlots;
+use super::{trace, AsyncLock};
+use crate::sync::{Mutex, MutexGuard, SharedHandle};
+use core::{
+  future::Future,
+  mem::ManuallyDrop,
+  ops::{Deref, DerefMut},
+  pin::Pin,
+  task::{Context, Poll},
+};
+
+/// An async mutex lock that does not require `alloc`. Up to `WAITERS` `Future`s can wait for the lock to become
+/// available at the same time without busy polling.
+pub struct AsyncMutexN<T, const WAITERS: usize> {
+  /// The waiters of the lock. They are registered and woken with atomic operations only, so the async path never
+  /// spins on a blocking lock
+  inner: WaiterSlots<WAITERS>,
+  /// The actual [Mutex] securing the contained data for mutual exclusive access
+  data: Mutex<T>,
+}
+
+impl<T, const WAITERS: usize> AsyncMutexN<T, WAITERS> {
+  /// Create the [AsyncMutexN]. As this does not require any allocation it can be assigned to a static variable
+  pub const fn new(value: T) -> Self {
+    Self {
+      inner: WaiterSlots::new(),
+      data: Mutex::new(value),
+    }
+  }
+
+  /// Try to lock the data secured by the [AsyncMutexN] without waiting. Returns `None` if the lock is currently held
+  /// by someone else.
+  pub fn try_lock(&self) -> Option<AsyncMutexNGuard<'_, T, WAITERS>> {
+    self.try_lock_as(None)
+  }
+
+  /// Try to lock the data on behalf of the given waiter
+  fn try_lock_as(&self, waiter: Option<usize>) -> Option<AsyncMutexNGuard<'_, T, WAITERS>> {
+    self.data.try_lock().map(|guard| {
+      trace::acquired("AsyncMutexN", trace::lock_id(&self.inner), waiter);
+      AsyncMutexNGuard {
+        guard: ManuallyDrop::new(guard),
+        inner: &self.inner,
+      }
+    })
+  }
+
+  /// Locking the data secured by the [AsyncMutexN] will yield a `Future` that must be awaited to actually acquire
+  /// the lock.
+  pub async fn lock(&self) -> AsyncMutexNGuard<'_, T, WAITERS> {
+    if let Some(guard) = self.try_lock() {
+      guard
+    } else {
+      let ticket = self.inner.next_ticket();
+      trace::requested("AsyncMutexN", trace::lock_id(&self.inner), ticket);
+      AsyncMutexNFuture {
+        mutex: self,
+        ticket,
+        done: false,
+      }
+      .await
+    }
+  }
+
+  /// Lock the [AsyncMutexN] accessed through the given [SharedHandle]. The returned [OwnedAsyncMutexNGuard] keeps the
+  /// handle, so the [Shared](crate::sync::Shared) knows the lock is in use until the guard is dropped.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::AsyncMutexN;
+  /// # use ruspiro_lock::sync::Shared;
+  /// static DATA: Shared<AsyncMutexN<u32, 4>, 8> = Shared::new(AsyncMutexN::new(0));
+  ///
+  /// async fn update() {
+  ///     let mut guard = AsyncMutexN::lock_owned(DATA.handle().unwrap()).await;
+  ///     *guard = 20;
+  /// }
+  /// ```
+  pub async fn lock_owned<'a, const MAX: usize>(
+    mutex: SharedHandle<'a, Self, MAX>,
+  ) -> OwnedAsyncMutexNGuard<'a, T, WAITERS, MAX> {
+    let guard = SharedHandle::get(&mutex).lock().await;
+    OwnedAsyncMutexNGuard {
+      guard,
+      _mutex: mutex,
+    }
+  }
+
+  /// Try to lock the [AsyncMutexN] accessed through the given [SharedHandle] without waiting. Returns the handle if
+  /// the lock is currently held by someone else.
+  pub fn try_lock_owned<'a, const MAX: usize>(
+    mutex: SharedHandle<'a, Self, MAX>,
+  ) -> Result<OwnedAsyncMutexNGuard<'a, T, WAITERS, MAX>, SharedHandle<'a, Self, MAX>> {
+    match SharedHandle::get(&mutex).try_lock() {
+      Some(guard) => Ok(OwnedAsyncMutexNGuard {
+        guard,
+        _mutex: mutex,
+      }),
+      None => Err(mutex),
+    }
+  }
+
+  /// Consume the [AsyncMutexN] and return the inner value
+  pub fn into_inner(self) -> T {
+    self.data.into_inner()
+  }
+}
+
+/// The guard of a successfully acquired [AsyncMutexN]. If this goes out of scope the lock is released and the next
+/// waiter is woken.
+pub struct AsyncMutexNGuard<'a, T: 'a, const WAITERS: usize> {
+  /// The guard of the data lock, it is released before the next waiter is woken
+  guard: ManuallyDrop<MutexGuard<'a, T>>,
+  inner: &'a WaiterSlots<WAITERS>,
+}
+
+impl<'a, T, const WAITERS: usize> Deref for AsyncMutexNGuard<'a, T, WAITERS> {
+  type Target = MutexGuard<'a, T>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.guard
+  }
+}
+
+impl<'a, T, const WAITERS: usize> DerefMut for AsyncMutexNGuard<'a, T, WAITERS> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.guard
+  }
+}
+
+impl<T, const WAITERS: usize> AsRef<T> for AsyncMutexNGuard<'_, T, WAITERS> {
+  fn as_ref(&self) -> &T {
+    &self.guard
+  }
+}
+
+impl<T, const WAITERS: usize> AsMut<T> for AsyncMutexNGuard<'_, T, WAITERS> {
+  fn as_mut(&mut self) -> &mut T {
+    &mut self.guard
+  }
+}
+
+/// The guard of an [AsyncMutexN] locked through a [SharedHandle]. If this goes out of scope the lock is released
+/// before the handle is dropped.
+pub struct OwnedAsyncMutexNGuard<'a, T: 'a, const WAITERS: usize, const MAX: usize> {
+  guard: AsyncMutexNGuard<'a, T, WAITERS>,
+  _mutex: SharedHandle<'a, AsyncMutexN<T, WAITERS>, MAX>,
+}
+
+impl<T, const WAITERS: usize, const MAX: usize> Deref
+  for OwnedAsyncMutexNGuard<'_, T, WAITERS, MAX>
+{
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    self.guard.as_ref()
+  }
+}
+
+impl<T, const WAITERS: usize, const MAX: usize> DerefMut
+  for OwnedAsyncMutexNGuard<'_, T, WAITERS, MAX>
+{
+  fn deref_mut(&mut self) -> &mut T {
+    self.guard.as_mut()
+  }
+}
+
+impl<T, const WAITERS: usize> AsyncLock<T> for AsyncMutexN<T, WAITERS> {
+  type Guard<'a>
+    = AsyncMutexNGuard<'a, T, WAITERS>
+  where
+    Self: 'a;
+
+  fn lock(&self) -> impl Future<Output = Self::Guard<'_>> {
+    AsyncMutexN::lock(self)
+  }
+}
+
+impl<T, const WAITERS: usize> Drop for AsyncMutexNGuard<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    // SAFETY: the guard is dropped exactly once, here
+    unsafe { ManuallyDrop::drop(&mut self.guard) };
+    trace::released("AsyncMutexN", trace::lock_id(self.inner));
+    // the data lock has been released before, so the woken waiter will be able to acquire it
+    self.inner.wake_next();
+  }
+}
+
+/// The `Future` that represents an `await`able [AsyncMutexN]
+struct AsyncMutexNFuture<'a, T, const WAITERS: usize> {
+  mutex: &'a AsyncMutexN<T, WAITERS>,
+  ticket: usize,
+  done: bool,
+}
+
+impl<'a, T, const WAITERS: usize> Future for AsyncMutexNFuture<'a, T, WAITERS> {
+  type Output = AsyncMutexNGuard<'a, T, WAITERS>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    let mutex = this.mutex;
+    if let Some(guard) = mutex.try_lock_as(Some(this.ticket)) {
+      this.done = true;
+      return Poll::Ready(guard);
+    }
+
+    let registered = mutex.inner.register(this.ticket, cx.waker());
+    // the lock might have been released while we registered ourself, so give it another try to not miss the wake up
+    if let Some(guard) = mutex.try_lock_as(Some(this.ticket)) {
+      mutex.inner.remove(this.ticket);
+      this.done = true;
+      return Poll::Ready(guard);
+    }
+
+    if !registered {
+      // all waiter slots are occupied, so re-schedule ourself to try again
+      cx.waker().wake_by_ref();
+    }
+    Poll::Pending
+  }
+}
+
+impl<T, const WAITERS: usize> Drop for AsyncMutexNFuture<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    // a future shall not occupy a waiter slot any longer. If it has been woken already while still waiting it has
+    // not used the chance to acquire the lock, so pass this on to the next waiter
+    let inner = &self.mutex.inner;
+    if !inner.remove(self.ticket) && !self.done {
+      inner.wake_next();
+    }
+  }
+}
+
+// the async guards are meant to be held across `.await` points, so they stay `Send` with the `unsend_guards` feature
+#[cfg(feature = "unsend_guards")]
+unsafe impl<T: Send, const WAITERS: usize> Send for AsyncMutexNGuard<'_, T, WAITERS> {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use crate::sync::Shared;
+  use core::sync::atomic::{AtomicBool, Ordering};
+  use core::task::Waker;
+  use std::sync::Arc;
+  use std::task::Wake;
+
+  /// A waker recording whether it has been woken
+  struct WakeFlag(AtomicBool);
+
+  impl Wake for WakeFlag {
+    fn wake(self: Arc<Self>) {
+      self.0.store(true, Ordering::SeqCst);
+    }
+  }
+
+  impl WakeFlag {
+    fn new() -> (Arc<Self>, Waker) {
+      let flag = Arc::new(Self(AtomicBool::new(false)));
+      (Arc::clone(&flag), Waker::from(flag))
+    }
+
+    fn woken(&self) -> bool {
+      self.0.swap(false, Ordering::SeqCst)
+    }
+  }
+
+  fn poll_once<F: Future>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+    future.poll(&mut Context::from_waker(waker))
+  }
+
+  #[test]
+  fn try_lock_fails_while_locked() {
+    let mutex: AsyncMutexN<u32, 2> = AsyncMutexN::new(0);
+    let mut guard = mutex.try_lock().unwrap();
+    **guard = 10;
+    assert!(mutex.try_lock().is_none());
+    drop(guard);
+    assert_eq!(**mutex.try_lock().unwrap(), 10);
+  }
+
+  #[test]
+  fn waiters_are_woken_in_request_order() {
+    let mutex: AsyncMutexN<u32, 2> = AsyncMutexN::new(0);
+    let guard = mutex.try_lock().unwrap();
+    let (first_flag, first_waker) = WakeFlag::new();
+    let (second_flag, second_waker) = WakeFlag::new();
+    let mut first = core::pin::pin!(mutex.lock());
+    let mut second = core::pin::pin!(mutex.lock());
+    assert!(poll_once(first.as_mut(), &first_waker).is_pending());
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+
+    drop(guard);
+    assert!(first_flag.woken());
+    assert!(!second_flag.woken());
+    let guard = match poll_once(first.as_mut(), &first_waker) {
+      Poll::Ready(guard) => guard,
+      Poll::Pending => panic!("the first waiter did not get the lock"),
+    };
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+    drop(guard);
+    assert!(second_flag.woken());
+    assert!(poll_once(second.as_mut(), &second_waker).is_ready());
+  }
+
+  #[test]
+  fn dropped_waiter_passes_its_wake_up_on() {
+    let mutex: AsyncMutexN<u32, 2> = AsyncMutexN::new(0);
+    let guard = mutex.try_lock().unwrap();
+    let (_, first_waker) = WakeFlag::new();
+    let (second_flag, second_waker) = WakeFlag::new();
+    let mut first = Box::pin(mutex.lock());
+    let mut second = core::pin::pin!(mutex.lock());
+    assert!(poll_once(first.as_mut(), &first_waker).is_pending());
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+
+    // the first waiter is woken, but cancelled before it acquired the lock
+    drop(guard);
+    drop(first);
+    assert!(second_flag.woken());
+    assert!(poll_once(second.as_mut(), &second_waker).is_ready());
+  }
+
+  #[test]
+  fn waiters_beyond_the_slots_reschedule_themselves() {
+    let mutex: AsyncMutexN<u32, 1> = AsyncMutexN::new(0);
+    let guard = mutex.try_lock().unwrap();
+    let (first_flag, first_waker) = WakeFlag::new();
+    let (second_flag, second_waker) = WakeFlag::new();
+    let mut first = core::pin::pin!(mutex.lock());
+    let mut second = core::pin::pin!(mutex.lock());
+    assert!(poll_once(first.as_mut(), &first_waker).is_pending());
+    assert!(!first_flag.woken());
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+    assert!(second_flag.woken());
+    drop(guard);
+    assert!(first_flag.woken());
+  }
+
+  #[test]
+  fn owned_guard_keeps_the_handle() {
+    let shared: Shared<AsyncMutexN<u32, 2>, 2> = Shared::new(AsyncMutexN::new(0));
+    let mut guard = match AsyncMutexN::try_lock_owned(shared.handle().unwrap()) {
+      Ok(guard) => guard,
+      Err(_) => panic!("the mutex is not locked"),
+    };
+    *guard = 10;
+    assert_eq!(shared.handles(), 1);
+    // the handle is given back if the lock is held
+    let handle = AsyncMutexN::try_lock_owned(shared.handle().unwrap())
+      .err()
+      .unwrap();
+    assert_eq!(SharedHandle::count(&handle), 2);
+    drop(handle);
+    drop(guard);
+    assert_eq!(shared.handles(), 0);
+  }
+}
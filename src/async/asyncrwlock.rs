@@ -7,101 +7,228 @@
 
 //! # Async RWLock
 //!
+//! ## Read locks
+//! The readers are counted in the state of the secured [RWLock] and a read lock is acquired with a single atomic update
+//! of it, so any number of tasks acquire their read locks at the same time. Only a request that has to wait takes a
+//! waiter slot. Releasing a lock wakes a single waiter, so once a read lock has been acquired by a woken reader it
+//! passes the wake up on to the next waiter. The readers queued behind a writer are therefore admitted as one batch
+//! instead of one after the other, up to the next queued writer.
+//!
+//! ## Upgradable read locks
+//! An [AsyncUpgradableReadGuard] coexists with plain read locks but excludes writers and other upgradable readers.
+//! Only one upgradable read lock can exist, so two tasks upgrading the same lock can not deadlock each other, the
+//! second one already waits in [AsyncRWLock::upgradable_read] until the first one released its lock.
+//!
+//! [AsyncUpgradableReadGuard::upgrade] sets the write bit right away, so no new read locks are handed out, and then
+//! waits for the existing readers to leave. While the upgrade is pending it is woken first whenever a lock is released,
+//! ahead of the writers queued in the waiter slots. They could not acquire the lock anyway before the upgrade has
+//! completed and the resulting write lock has been released.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::r#async::AsyncRWLock;
+//!
+//! async fn insert_once(devices: &AsyncRWLock<[u32; 4]>, id: u32) {
+//!     let devices = devices.upgradable_read().await;
+//!     if !devices.contains(&id) {
+//!         let mut devices = devices.upgrade().await;
+//!         devices[0] = id;
+//!     }
+//! }
+//! ```
 
 extern crate alloc;
-use crate::sync::{Mutex, RWLock, ReadLockGuard, WriteLockGuard};
-use alloc::{collections::BTreeMap, sync::Arc};
+use super::leak::LeakDetector;
+use super::waiters::WaiterSlots;
+use super::{trace, AsyncLock, AsyncReadLock, AsyncWriteLock, CancellationToken};
+use crate::sync::{spin, RWLock, ReadLockGuard, UpgradableReadGuard, WriteLockGuard};
+use crate::LockError;
+use alloc::sync::Arc;
 use core::{
-  arch::asm,
   future::Future,
+  mem::ManuallyDrop,
   ops::{Deref, DerefMut},
   pin::Pin,
-  task::{Context, Poll, Waker},
+  task::{Context, Poll},
 };
 
 /// An async mutex lock that can be used in async functions to prevent blocking current execution while waiting for the
 /// lock to become available. So for this to work the `lock` method does not return a WriteGuard immediately but a
 /// [Future] that will resolve into a [AsyncWriteLockGuard] when `await`ed.
 /// In the same way the `read` method will return a `Future` resolving to an [AsyncReadLockGuard] when `await`ed.
-pub struct AsyncRWLock<T> {
-  /// The inner wrapper to the actual [Mutex] requires to be secured with a [Mutex] on it's own
-  /// as we require mutual exclusive access to it. This actually should not harm any concurrent blocking
-  /// as this is a short living lock that will be only aquired to request the actual lock status. So it is
-  /// more then unlikely that this will happen in parallel at the same time
-  inner: Arc<Mutex<AsyncRWLockInner>>,
-  /// The actual [Mutex] securing the contained data for mutual exclusive access
+///
+/// Up to `WAITERS` `Future`s can wait for the lock at the same time without busy polling. Their wakers are kept in a
+/// fixed number of slots, so waiting for the lock does not allocate. If all slots are occupied additional `Future`s
+/// re-schedule themself when polled until a slot gets available.
+pub struct AsyncRWLock<T, const WAITERS: usize = 32> {
+  /// The waiters of the lock. They are registered and woken with atomic operations only, so the async path never
+  /// spins on a blocking lock
+  inner: Arc<AsyncRWLockInner<WAITERS>>,
+  /// The actual [RWLock] securing the contained data for shared or mutual exclusive access
   data: Arc<RWLock<T>>,
 }
 
 impl<T> AsyncRWLock<T> {
-  /// Create the [AsyncRWLock]
+  /// Create the [AsyncRWLock] with the default number of 32 waiter slots
   pub fn new(value: T) -> Self {
+    Self::with_waiter_slots(value)
+  }
+}
+
+impl<T, const WAITERS: usize> AsyncRWLock<T, WAITERS> {
+  /// Create the [AsyncRWLock] with `WAITERS` waiter slots
+  pub fn with_waiter_slots(value: T) -> Self {
     Self {
-      inner: Arc::new(Mutex::new(AsyncRWLockInner::new())),
+      inner: Arc::new(AsyncRWLockInner::new()),
       data: Arc::new(RWLock::new(value)),
     }
   }
 
   /// Locking the data for write access secured by the [AsyncRWLock] will yield a `Future` that must be awaited to
   /// actually acquire the lock.
-  pub async fn write(&self) -> AsyncWriteLockGuard<'_, T> {
+  pub async fn write(&self) -> AsyncWriteLockGuard<'_, T, WAITERS> {
     // check if we could immediately get the lock
     if let Some(guard) = self.data.try_write() {
       // lock immediatly acquired, provide the lock guard as result
-      AsyncWriteLockGuard {
-        guard,
-        inner: Arc::clone(&self.inner),
-      }
-    } else {
-      // to be able to request the lock we require to upate the inner metadata. For this to work we require a
-      // short living exclusive lock to this data.
-      let mut inner = self.inner.lock();
-      let current_id = inner.next_waiter;
-      inner.next_waiter += 1;
-      drop(inner);
-
-      // once we have updated the metadata we can release the lock to it and create the `Future` that will yield
-      // the lock to the data once available
-      AsyncWriteLockFuture::new(Arc::clone(&self.inner), Arc::clone(&self.data), current_id).await
+      trace::acquired("AsyncRWLock::write", trace::lock_id(&*self.inner), None);
+      return AsyncWriteLockGuard::new(guard, Arc::clone(&self.inner));
     }
+
+    let current_id = self.inner.waiter.next_ticket();
+    trace::requested(
+      "AsyncRWLock::write",
+      trace::lock_id(&*self.inner),
+      current_id,
+    );
+
+    AsyncWriteLockFuture::new(Arc::clone(&self.inner), &self.data, current_id).await
+  }
+
+  /// Lock the data for write access unless the given [CancellationToken] is cancelled while waiting for the lock. In
+  /// this case the request is withdrawn and this resolves to [LockError::Cancelled].
+  pub async fn write_cancellable(
+    &self,
+    token: &CancellationToken,
+  ) -> Result<AsyncWriteLockGuard<'_, T, WAITERS>, LockError> {
+    token.run_until_cancelled(self.write()).await
   }
 
   pub fn write_blocking(&self) -> WriteLockGuard<'_, T> {
+    let mut attempt = 0;
     loop {
       if let Some(write_guard) = self.data.try_write() {
         return write_guard;
       }
       // to save energy and cpu consumption we can wait for an event beeing raised that indicates that the
-      // semaphore value has likely beeing changed
-      #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-      unsafe {
-        asm!("wfe");
-      }
+      // semaphore value has likely beeing changed, depending on the selected spin policy
+      spin::on_contention(&mut attempt, "AsyncRWLock::write_blocking");
     }
   }
 
   /// Locking the data for read access secured by the [AsyncRWLock] will yield a `Future` that must be awaited to
   /// actually acquire the lock.
-  pub async fn read(&self) -> AsyncReadLockGuard<'_, T> {
+  pub async fn read(&self) -> AsyncReadLockGuard<'_, T, WAITERS> {
     // check if we could immediately get the lock
     if let Some(guard) = self.data.try_read() {
       // lock immediatly acquired, provide the lock guard as result
-      AsyncReadLockGuard {
-        guard,
-        inner: Arc::clone(&self.inner),
-      }
-    } else {
-      // to be able to request the lock we require to upate the inner metadata. For this to work we require a
-      // short living exclusive lock to this data.
-      let mut inner = self.inner.lock();
-      let current_id = inner.next_waiter;
-      inner.next_waiter += 1;
-      drop(inner);
+      trace::acquired("AsyncRWLock::read", trace::lock_id(&*self.inner), None);
+      return AsyncReadLockGuard::new(guard, Arc::clone(&self.inner));
+    }
+
+    let current_id = self.inner.waiter.next_ticket();
+    trace::requested(
+      "AsyncRWLock::read",
+      trace::lock_id(&*self.inner),
+      current_id,
+    );
+
+    AsyncReadLockFuture::new(Arc::clone(&self.inner), &self.data, current_id).await
+  }
+
+  /// Lock the data for read access unless the given [CancellationToken] is cancelled while waiting for the lock. In
+  /// this case the request is withdrawn and this resolves to [LockError::Cancelled].
+  pub async fn read_cancellable(
+    &self,
+    token: &CancellationToken,
+  ) -> Result<AsyncReadLockGuard<'_, T, WAITERS>, LockError> {
+    token.run_until_cancelled(self.read()).await
+  }
 
-      // once we have updated the metadata we can release the lock to it and create the `Future` that will yield
-      // the lock to the data once available
-      AsyncReadLockFuture::new(Arc::clone(&self.inner), Arc::clone(&self.data), current_id).await
+  /// Locking the data for upgradable read access secured by the [AsyncRWLock] will yield a `Future` that must be
+  /// awaited to actually acquire the lock. It resolves once there is no write lock and no other upgradable read lock.
+  pub async fn upgradable_read(&self) -> AsyncUpgradableReadGuard<'_, T, WAITERS> {
+    // check if we could immediately get the lock
+    if let Some(guard) = self.try_upgradable_read() {
+      return guard;
     }
+
+    let current_id = self.inner.waiter.next_ticket();
+    trace::requested(
+      "AsyncRWLock::upgradable_read",
+      trace::lock_id(&*self.inner),
+      current_id,
+    );
+
+    AsyncUpgradableReadFuture::new(Arc::clone(&self.inner), &self.data, current_id).await
+  }
+
+  /// Try to lock the data for upgradable read access without waiting. Returns `None` if there is a write lock or
+  /// another upgradable read lock.
+  pub fn try_upgradable_read(&self) -> Option<AsyncUpgradableReadGuard<'_, T, WAITERS>> {
+    let guard = self.data.try_upgradable_read()?;
+    trace::acquired(
+      "AsyncRWLock::upgradable_read",
+      trace::lock_id(&*self.inner),
+      None,
+    );
+    Some(AsyncUpgradableReadGuard::new(
+      guard,
+      Arc::clone(&self.inner),
+    ))
+  }
+
+  /// Lock the data for read access and call the given closure with a borrow of it. The read lock is released once the
+  /// closure returns. As the closure can not `.await` the lock can not be held accidentally across `.await` points.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::AsyncRWLock;
+  /// async fn sum(values: &AsyncRWLock<[u32; 4]>) -> u32 {
+  ///     values.with_read(|values| values.iter().sum()).await
+  /// }
+  /// ```
+  pub async fn with_read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+    let guard = self.read().await;
+    f(guard.as_ref())
+  }
+
+  /// Lock the data for write access and call the given closure with a mutable borrow of it. The write lock is released
+  /// once the closure returns. As the closure can not `.await` the lock can not be held accidentally across `.await`
+  /// points.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::AsyncRWLock;
+  /// async fn clear(values: &AsyncRWLock<[u32; 4]>) {
+  ///     values.with_write(|values| values.fill(0)).await
+  /// }
+  /// ```
+  pub async fn with_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+    let mut guard = self.write().await;
+    f(guard.as_mut())
+  }
+
+  /// The number of guards of this [AsyncRWLock] currently alive. The answer might be outdated already when it is
+  /// returned, so this is only meant for diagnostics.
+  #[cfg(feature = "leak_detection")]
+  pub fn outstanding_guards(&self) -> usize {
+    self.inner.leaks.guards()
+  }
+
+  /// The id of the lock used in the trace events and by the acquisition accounting of the stress tests
+  #[cfg(feature = "stress_tests")]
+  pub(crate) fn lock_id(&self) -> usize {
+    trace::lock_id(&*self.inner)
   }
 
   /// Provide the inner data wrapped by this [AsyncRWLock]. This will only provide the contained data if there is only
@@ -118,12 +245,57 @@ impl<T> AsyncRWLock<T> {
   }
 }
 
-pub struct AsyncWriteLockGuard<'a, T: 'a> {
-  guard: WriteLockGuard<'a, T>,
-  inner: Arc<Mutex<AsyncRWLockInner>>,
+impl<T, const WAITERS: usize> AsyncReadLock<T> for AsyncRWLock<T, WAITERS> {
+  type ReadGuard<'a>
+    = AsyncReadLockGuard<'a, T, WAITERS>
+  where
+    Self: 'a;
+
+  fn read(&self) -> impl Future<Output = Self::ReadGuard<'_>> {
+    AsyncRWLock::read(self)
+  }
+}
+
+impl<T, const WAITERS: usize> AsyncWriteLock<T> for AsyncRWLock<T, WAITERS> {
+  type WriteGuard<'a>
+    = AsyncWriteLockGuard<'a, T, WAITERS>
+  where
+    Self: 'a;
+
+  fn write(&self) -> impl Future<Output = Self::WriteGuard<'_>> {
+    AsyncRWLock::write(self)
+  }
+}
+
+/// Locking an [AsyncRWLock] as [AsyncLock] acquires the write lock
+impl<T, const WAITERS: usize> AsyncLock<T> for AsyncRWLock<T, WAITERS> {
+  type Guard<'a>
+    = AsyncWriteLockGuard<'a, T, WAITERS>
+  where
+    Self: 'a;
+
+  fn lock(&self) -> impl Future<Output = Self::Guard<'_>> {
+    AsyncRWLock::write(self)
+  }
+}
+
+pub struct AsyncWriteLockGuard<'a, T: 'a, const WAITERS: usize = 32> {
+  /// The guard of the data lock, it is released before the next waiter is woken
+  guard: ManuallyDrop<WriteLockGuard<'a, T>>,
+  inner: Arc<AsyncRWLockInner<WAITERS>>,
 }
 
-impl<'a, T> Deref for AsyncWriteLockGuard<'a, T> {
+impl<'a, T, const WAITERS: usize> AsyncWriteLockGuard<'a, T, WAITERS> {
+  fn new(guard: WriteLockGuard<'a, T>, inner: Arc<AsyncRWLockInner<WAITERS>>) -> Self {
+    inner.leaks.acquired();
+    Self {
+      guard: ManuallyDrop::new(guard),
+      inner,
+    }
+  }
+}
+
+impl<'a, T, const WAITERS: usize> Deref for AsyncWriteLockGuard<'a, T, WAITERS> {
   type Target = WriteLockGuard<'a, T>;
 
   fn deref(&self) -> &Self::Target {
@@ -131,37 +303,54 @@ impl<'a, T> Deref for AsyncWriteLockGuard<'a, T> {
   }
 }
 
-impl<'a, T> DerefMut for AsyncWriteLockGuard<'a, T> {
+impl<'a, T, const WAITERS: usize> DerefMut for AsyncWriteLockGuard<'a, T, WAITERS> {
   fn deref_mut(&mut self) -> &mut Self::Target {
     &mut self.guard
   }
 }
 
+impl<T, const WAITERS: usize> AsRef<T> for AsyncWriteLockGuard<'_, T, WAITERS> {
+  fn as_ref(&self) -> &T {
+    &self.guard
+  }
+}
+
+impl<T, const WAITERS: usize> AsMut<T> for AsyncWriteLockGuard<'_, T, WAITERS> {
+  fn as_mut(&mut self) -> &mut T {
+    &mut self.guard
+  }
+}
+
 /// If an [AsyncWriteLockGuard] get's dropped we need to wake the `Future`s that might have registered themself and
-/// are waiting to aquire the lock.
-impl<T> Drop for AsyncWriteLockGuard<'_, T> {
+/// are waiting to acquire the lock.
+impl<T, const WAITERS: usize> Drop for AsyncWriteLockGuard<'_, T, WAITERS> {
   fn drop(&mut self) {
-    // if the mutex guard is about to be locked we need to check if there has been a waker send
-    // already to get woken
-    let mut inner = self.inner.lock();
-    if let Some(&next_waiter) = inner.waiter.keys().next() {
-      // remove the waker from the waiter list as it will re-register itself when the corresponding
-      // Future is polled and can't acquire the lock
-      let waiter = inner
-        .waiter
-        .remove(&next_waiter)
-        .expect("found key but can't remove it ???");
-      waiter.wake();
-    }
+    // SAFETY: the guard is dropped exactly once, here
+    unsafe { ManuallyDrop::drop(&mut self.guard) };
+    self.inner.leaks.released();
+    trace::released("AsyncRWLock::write", trace::lock_id(&*self.inner));
+    // the data lock has been released before, so the woken waiter will be able to acquire it
+    self.inner.wake_next();
   }
 }
 
-pub struct AsyncReadLockGuard<'a, T: 'a> {
-  guard: ReadLockGuard<'a, T>,
-  inner: Arc<Mutex<AsyncRWLockInner>>,
+pub struct AsyncReadLockGuard<'a, T: 'a, const WAITERS: usize = 32> {
+  /// The guard of the data lock, it is released before the next waiter is woken
+  guard: ManuallyDrop<ReadLockGuard<'a, T>>,
+  inner: Arc<AsyncRWLockInner<WAITERS>>,
+}
+
+impl<'a, T, const WAITERS: usize> AsyncReadLockGuard<'a, T, WAITERS> {
+  fn new(guard: ReadLockGuard<'a, T>, inner: Arc<AsyncRWLockInner<WAITERS>>) -> Self {
+    inner.leaks.acquired();
+    Self {
+      guard: ManuallyDrop::new(guard),
+      inner,
+    }
+  }
 }
 
-impl<'a, T> Deref for AsyncReadLockGuard<'a, T> {
+impl<'a, T, const WAITERS: usize> Deref for AsyncReadLockGuard<'a, T, WAITERS> {
   type Target = ReadLockGuard<'a, T>;
 
   fn deref(&self) -> &Self::Target {
@@ -169,67 +358,155 @@ impl<'a, T> Deref for AsyncReadLockGuard<'a, T> {
   }
 }
 
+impl<T, const WAITERS: usize> AsRef<T> for AsyncReadLockGuard<'_, T, WAITERS> {
+  fn as_ref(&self) -> &T {
+    &self.guard
+  }
+}
+
 /// If an [AsyncReadLockGuard] get's dropped we need to wake the `Future`s that might have registered themself and
-/// are waiting to aquire the lock.
-impl<T> Drop for AsyncReadLockGuard<'_, T> {
+/// are waiting to acquire the lock.
+impl<T, const WAITERS: usize> Drop for AsyncReadLockGuard<'_, T, WAITERS> {
   fn drop(&mut self) {
-    // if the mutex guard is about to be locked we need to check if there has been a waker send
-    // already to get woken
-    let mut inner = self.inner.lock();
-    if let Some(&next_waiter) = inner.waiter.keys().next() {
-      // remove the waker from the waiter list as it will re-register itself when the corresponding
-      // Future is polled and can't acquire the lock
-      let waiter = inner
-        .waiter
-        .remove(&next_waiter)
-        .expect("found key but can't remove it ???");
-      waiter.wake();
+    // SAFETY: the guard is dropped exactly once, here
+    unsafe { ManuallyDrop::drop(&mut self.guard) };
+    self.inner.leaks.released();
+    trace::released("AsyncRWLock::read", trace::lock_id(&*self.inner));
+    // the data lock has been released before, so the woken waiter will be able to acquire it
+    self.inner.wake_next();
+  }
+}
+pub struct AsyncUpgradableReadGuard<'a, T: 'a, const WAITERS: usize = 32> {
+  /// The guard of the data lock, it is released before the next waiter is woken
+  guard: ManuallyDrop<UpgradableReadGuard<'a, T>>,
+  inner: Arc<AsyncRWLockInner<WAITERS>>,
+}
+
+impl<'a, T, const WAITERS: usize> AsyncUpgradableReadGuard<'a, T, WAITERS> {
+  fn new(guard: UpgradableReadGuard<'a, T>, inner: Arc<AsyncRWLockInner<WAITERS>>) -> Self {
+    inner.leaks.acquired();
+    Self {
+      guard: ManuallyDrop::new(guard),
+      inner,
+    }
+  }
+
+  /// Upgrade to an [AsyncWriteLockGuard]. The write bit is set immediately, so no new read locks are handed out, and
+  /// the returned `Future` resolves once the existing read locks are released. If it is dropped before, the lock is
+  /// released entirely.
+  pub async fn upgrade(self) -> AsyncWriteLockGuard<'a, T, WAITERS> {
+    let (guard, inner) = self.into_parts();
+    AsyncUpgradeFuture {
+      lock: Some(guard.begin_upgrade()),
+      inner,
     }
+    .await
+  }
+
+  /// Split the guard into its fields without releasing the lock
+  fn into_parts(self) -> (UpgradableReadGuard<'a, T>, Arc<AsyncRWLockInner<WAITERS>>) {
+    let this = ManuallyDrop::new(self);
+    // SAFETY: each field is read exactly once and the guard is not dropped, so they are not dropped twice
+    unsafe {
+      (
+        ManuallyDrop::into_inner(core::ptr::read(&this.guard)),
+        core::ptr::read(&this.inner),
+      )
+    }
+  }
+}
+
+impl<'a, T, const WAITERS: usize> Deref for AsyncUpgradableReadGuard<'a, T, WAITERS> {
+  type Target = UpgradableReadGuard<'a, T>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.guard
+  }
+}
+
+impl<T, const WAITERS: usize> AsRef<T> for AsyncUpgradableReadGuard<'_, T, WAITERS> {
+  fn as_ref(&self) -> &T {
+    &self.guard
+  }
+}
+
+/// If an [AsyncUpgradableReadGuard] get's dropped we need to wake the `Future`s that might have registered themself and
+/// are waiting to acquire the lock.
+impl<T, const WAITERS: usize> Drop for AsyncUpgradableReadGuard<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    // SAFETY: the guard is dropped exactly once, here
+    unsafe { ManuallyDrop::drop(&mut self.guard) };
+    self.inner.leaks.released();
+    trace::released("AsyncRWLock::upgradable_read", trace::lock_id(&*self.inner));
+    // the data lock has been released before, so the woken waiter will be able to acquire it
+    self.inner.wake_next();
   }
 }
+
 /// The `Future` that represents an `await`able write request to an [AsynRWLock] and can only be created from the
 /// functions of [AsyncRWLock].
-struct AsyncWriteLockFuture<'a, T: ?Sized> {
-  inner: Arc<Mutex<AsyncRWLockInner>>,
-  data: Arc<RWLock<T>>,
+///
+/// It borrows the secured data from the [AsyncRWLock] for the lifetime of the lock request, so the guard can be
+/// handed out without any unsafe lifetime extension. The `Future` is `Send` if `T` is `Send`.
+struct AsyncWriteLockFuture<'a, T: ?Sized, const WAITERS: usize> {
+  inner: Arc<AsyncRWLockInner<WAITERS>>,
+  data: &'a RWLock<T>,
   id: usize,
-  _p: core::marker::PhantomData<&'a T>,
+  done: bool,
 }
 
-impl<T> AsyncWriteLockFuture<'_, T> {
-  fn new(inner: Arc<Mutex<AsyncRWLockInner>>, data: Arc<RWLock<T>>, id: usize) -> Self {
+impl<'a, T, const WAITERS: usize> AsyncWriteLockFuture<'a, T, WAITERS> {
+  fn new(inner: Arc<AsyncRWLockInner<WAITERS>>, data: &'a RWLock<T>, id: usize) -> Self {
     Self {
       inner,
       data,
       id,
-      _p: core::marker::PhantomData,
+      done: false,
     }
   }
 }
 
-impl<'a, T> Future for AsyncWriteLockFuture<'a, T> {
-  type Output = AsyncWriteLockGuard<'a, T>;
+impl<'a, T, const WAITERS: usize> Future for AsyncWriteLockFuture<'a, T, WAITERS> {
+  type Output = AsyncWriteLockGuard<'a, T, WAITERS>;
 
   fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-    // we need to elide the lifetime given by self.get_mut() using unsafe code here
-    // SAFETY: it's actually safe as we either return Poll::Pending without any lifetime or we
-    // handout the `AsyncMutexGuard` with lifetime 'a which bound to the AsyncMutex that created this Future and
-    // will always outlive this future and is therefore ok - I guess...
-    let this = unsafe { &*(self.get_mut() as *const Self) };
+    // the Future does not contain any self references, so it is fine to access it mutably
+    let this = self.get_mut();
     if let Some(guard) = this.data.try_write() {
+      this.done = true;
       // data lock could be acquired
       // provide the AsyncWriteGuard
-      Poll::Ready(AsyncWriteLockGuard {
-        guard,
-        inner: Arc::clone(&this.inner),
-      })
+      trace::acquired(
+        "AsyncRWLock::write",
+        trace::lock_id(&*this.inner),
+        Some(this.id),
+      );
+      Poll::Ready(AsyncWriteLockGuard::new(guard, Arc::clone(&this.inner)))
     } else {
       // data lock could not be acquired this time, so someone else is holding the lock. We need to register
       // ourself to get woken as soon as the lock gets available
-      let mut inner = this.inner.lock();
-      inner.waiter.insert(this.id, cx.waker().clone());
-      drop(inner);
+      let registered = this.inner.waiter.register(this.id, cx.waker());
+      // the lock might have been released while we registered ourself, so give it another try to not miss the wake up
+      if let Some(guard) = this.data.try_write() {
+        this.inner.waiter.remove(this.id);
+        this.done = true;
+        trace::acquired(
+          "AsyncRWLock::write",
+          trace::lock_id(&*this.inner),
+          Some(this.id),
+        );
+        return Poll::Ready(AsyncWriteLockGuard::new(guard, Arc::clone(&this.inner)));
+      }
 
+      if !registered {
+        // all waiter slots are occupied, so re-schedule ourself to try again
+        cx.waker().wake_by_ref();
+      }
+
+      this
+        .inner
+        .leaks
+        .pending("AsyncRWLock", trace::lock_id(&*this.inner));
       Poll::Pending
     }
   }
@@ -237,74 +514,293 @@ impl<'a, T> Future for AsyncWriteLockFuture<'a, T> {
 
 /// The `Future` that represents an `await`able read lock request of an [AsynRWLock] and can only be created from the
 /// functions of [AsyncRWLock].
-struct AsyncReadLockFuture<'a, T> {
-  inner: Arc<Mutex<AsyncRWLockInner>>,
-  data: Arc<RWLock<T>>,
+///
+/// It borrows the secured data from the [AsyncRWLock] for the lifetime of the lock request, so the guard can be
+/// handed out without any unsafe lifetime extension. The `Future` is `Send` if `T` is `Send`.
+struct AsyncReadLockFuture<'a, T, const WAITERS: usize> {
+  inner: Arc<AsyncRWLockInner<WAITERS>>,
+  data: &'a RWLock<T>,
   id: usize,
-  _p: core::marker::PhantomData<&'a T>,
+  done: bool,
+  /// The `Future` has been pending, so it acquires the lock after being woken
+  waited: bool,
 }
 
-impl<T> AsyncReadLockFuture<'_, T> {
-  fn new(inner: Arc<Mutex<AsyncRWLockInner>>, data: Arc<RWLock<T>>, id: usize) -> Self {
+impl<'a, T, const WAITERS: usize> AsyncReadLockFuture<'a, T, WAITERS> {
+  fn new(inner: Arc<AsyncRWLockInner<WAITERS>>, data: &'a RWLock<T>, id: usize) -> Self {
     Self {
       inner,
       data,
       id,
-      _p: core::marker::PhantomData,
+      done: false,
+      waited: false,
     }
   }
+
+  fn acquired(
+    &mut self,
+    guard: ReadLockGuard<'a, T>,
+    woken: bool,
+  ) -> AsyncReadLockGuard<'a, T, WAITERS> {
+    self.done = true;
+    trace::acquired(
+      "AsyncRWLock::read",
+      trace::lock_id(&*self.inner),
+      Some(self.id),
+    );
+    let guard = AsyncReadLockGuard::new(guard, Arc::clone(&self.inner));
+    if woken {
+      // the lock is shared, so the next waiter might be able to acquire it as well. If it is a reader it passes the
+      // wake up on in the same way, a writer registers itself again and keeps its place in the queue
+      self.inner.waiter.wake_next();
+    }
+    guard
+  }
 }
 
-impl<'a, T> Future for AsyncReadLockFuture<'a, T> {
-  type Output = AsyncReadLockGuard<'a, T>;
+impl<'a, T, const WAITERS: usize> Future for AsyncReadLockFuture<'a, T, WAITERS> {
+  type Output = AsyncReadLockGuard<'a, T, WAITERS>;
 
   fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-    // we need to elide the lifetime given by self.get_mut() using unsafe code here
-    // SAFETY: it's actually safe as we either return Poll::Pending without any lifetime or we
-    // handout the `AsyncMutexGuard` with lifetime 'a which bound to the AsyncMutex that created this Future and
-    // will always outlive this future and is therefore ok - I guess...
-    let this = unsafe { &*(self.get_mut() as *const Self) };
+    // the Future does not contain any self references, so it is fine to access it mutably
+    let this = self.get_mut();
     if let Some(guard) = this.data.try_read() {
-      // data lock could be acquired
-      // provide the AsyncWriteGuard
-      Poll::Ready(AsyncReadLockGuard {
+      // data lock could be acquired, provide the AsyncReadLockGuard
+      let woken = this.waited;
+      Poll::Ready(this.acquired(guard, woken))
+    } else {
+      // data lock could not be acquired this time, so someone else is holding the lock. We need to register
+      // ourself to get woken as soon as the lock gets available
+      let registered = this.inner.waiter.register(this.id, cx.waker());
+      // the lock might have been released while we registered ourself, so give it another try to not miss the wake up
+      if let Some(guard) = this.data.try_read() {
+        // if the waker has been taken already the wake up of a released lock has been consumed, so pass it on
+        let taken = !this.inner.waiter.remove(this.id) && registered;
+        let woken = this.waited || taken;
+        return Poll::Ready(this.acquired(guard, woken));
+      }
+
+      if !registered {
+        // all waiter slots are occupied, so re-schedule ourself to try again
+        cx.waker().wake_by_ref();
+      }
+
+      this.waited = true;
+      this
+        .inner
+        .leaks
+        .pending("AsyncRWLock", trace::lock_id(&*this.inner));
+      Poll::Pending
+    }
+  }
+}
+/// The `Future` that represents an `await`able upgradable read lock request of an [AsynRWLock] and can only be
+/// created from the functions of [AsyncRWLock].
+struct AsyncUpgradableReadFuture<'a, T, const WAITERS: usize> {
+  inner: Arc<AsyncRWLockInner<WAITERS>>,
+  data: &'a RWLock<T>,
+  id: usize,
+  done: bool,
+}
+
+impl<'a, T, const WAITERS: usize> AsyncUpgradableReadFuture<'a, T, WAITERS> {
+  fn new(inner: Arc<AsyncRWLockInner<WAITERS>>, data: &'a RWLock<T>, id: usize) -> Self {
+    Self {
+      inner,
+      data,
+      id,
+      done: false,
+    }
+  }
+}
+
+impl<'a, T, const WAITERS: usize> Future for AsyncUpgradableReadFuture<'a, T, WAITERS> {
+  type Output = AsyncUpgradableReadGuard<'a, T, WAITERS>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    // the Future does not contain any self references, so it is fine to access it mutably
+    let this = self.get_mut();
+    if let Some(guard) = this.data.try_upgradable_read() {
+      this.done = true;
+      trace::acquired(
+        "AsyncRWLock::upgradable_read",
+        trace::lock_id(&*this.inner),
+        Some(this.id),
+      );
+      Poll::Ready(AsyncUpgradableReadGuard::new(
         guard,
-        inner: Arc::clone(&this.inner),
-      })
+        Arc::clone(&this.inner),
+      ))
     } else {
       // data lock could not be acquired this time, so someone else is holding the lock. We need to register
       // ourself to get woken as soon as the lock gets available
-      let mut inner = this.inner.lock();
-      inner.waiter.insert(this.id, cx.waker().clone());
-      drop(inner);
+      let registered = this.inner.waiter.register(this.id, cx.waker());
+      // the lock might have been released while we registered ourself, so give it another try to not miss the wake up
+      if let Some(guard) = this.data.try_upgradable_read() {
+        this.inner.waiter.remove(this.id);
+        this.done = true;
+        trace::acquired(
+          "AsyncRWLock::upgradable_read",
+          trace::lock_id(&*this.inner),
+          Some(this.id),
+        );
+        return Poll::Ready(AsyncUpgradableReadGuard::new(
+          guard,
+          Arc::clone(&this.inner),
+        ));
+      }
+
+      if !registered {
+        // all waiter slots are occupied, so re-schedule ourself to try again
+        cx.waker().wake_by_ref();
+      }
 
+      this
+        .inner
+        .leaks
+        .pending("AsyncRWLock", trace::lock_id(&*this.inner));
       Poll::Pending
     }
   }
 }
-struct AsyncRWLockInner {
-  /// If the lock could not be aquired we store the requestor id here to allow the next one
-  /// already waiting for the lock to retrieve it
-  waiter: BTreeMap<usize, Waker>,
-  /// The id of the next waiter that can be woken once the lock is released and someone else is already waiting for
-  /// the lock to be aquired
-  next_waiter: usize,
+
+/// The `Future` completing the upgrade of an [AsyncUpgradableReadGuard] once the existing read locks are released
+struct AsyncUpgradeFuture<'a, T, const WAITERS: usize> {
+  /// The lock the upgrade has been begun on, `None` once it has been completed
+  lock: Option<&'a RWLock<T>>,
+  inner: Arc<AsyncRWLockInner<WAITERS>>,
+}
+
+impl<'a, T, const WAITERS: usize> AsyncUpgradeFuture<'a, T, WAITERS> {
+  fn complete(&mut self) -> Option<AsyncWriteLockGuard<'a, T, WAITERS>> {
+    let lock = self.lock?;
+    // SAFETY: the upgrade has been begun when this Future has been created and is only completed here
+    let guard = unsafe { lock.try_complete_upgrade() }?;
+    self.lock = None;
+    // the upgradable read lock is turned into the write lock
+    self.inner.leaks.released();
+    trace::acquired("AsyncRWLock::upgrade", trace::lock_id(&*self.inner), None);
+    Some(AsyncWriteLockGuard::new(guard, Arc::clone(&self.inner)))
+  }
+}
+
+impl<'a, T, const WAITERS: usize> Future for AsyncUpgradeFuture<'a, T, WAITERS> {
+  type Output = AsyncWriteLockGuard<'a, T, WAITERS>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    // the Future does not contain any self references, so it is fine to access it mutably
+    let this = self.get_mut();
+    if let Some(guard) = this.complete() {
+      return Poll::Ready(guard);
+    }
+
+    // the upgrade has its own slot, so it can always register and is woken ahead of the queued waiters
+    this.inner.upgrader.register(0, cx.waker());
+    // the last reader might have left while registering, so check once more
+    match this.complete() {
+      Some(guard) => {
+        this.inner.upgrader.remove(0);
+        Poll::Ready(guard)
+      }
+      None => {
+        this
+          .inner
+          .leaks
+          .pending("AsyncRWLock", trace::lock_id(&*this.inner));
+        Poll::Pending
+      }
+    }
+  }
+}
+
+/// If the `Future` is dropped before the upgrade completed the lock is released and the next waiter is woken
+impl<T, const WAITERS: usize> Drop for AsyncUpgradeFuture<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    if let Some(lock) = self.lock.take() {
+      // SAFETY: the upgrade has been begun when this Future has been created and has not been completed
+      unsafe { lock.abort_upgrade() };
+      self.inner.leaks.released();
+      self.inner.upgrader.remove(0);
+      self.inner.wake_next();
+    }
+  }
+}
+
+/// If the `Future` is dropped before it could acquire the lock it shall no longer be woken. If it has been woken
+/// already the wake up is passed on to the next waiter.
+impl<T, const WAITERS: usize> Drop for AsyncUpgradableReadFuture<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    if !self.inner.waiter.remove(self.id) && !self.done {
+      self.inner.wake_next();
+    }
+  }
+}
+
+/// If the `Future` is dropped before it could acquire the lock it shall no longer be woken. If it has been woken
+/// already the wake up is passed on to the next waiter.
+impl<T: ?Sized, const WAITERS: usize> Drop for AsyncWriteLockFuture<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    if !self.inner.waiter.remove(self.id) && !self.done {
+      self.inner.wake_next();
+    }
+  }
+}
+
+/// If the `Future` is dropped before it could acquire the lock it shall no longer be woken. If it has been woken
+/// already the wake up is passed on to the next waiter.
+impl<T, const WAITERS: usize> Drop for AsyncReadLockFuture<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    if !self.inner.waiter.remove(self.id) && !self.done {
+      self.inner.wake_next();
+    }
+  }
+}
+
+struct AsyncRWLockInner<const WAITERS: usize> {
+  /// If the lock could not be acquired we store the waker of the requestor here to allow the one waiting the longest
+  /// to be woken once the lock is released
+  waiter: WaiterSlots<WAITERS>,
+  /// The waker of a pending upgrade, it is woken ahead of the waiters. Only one upgrade can be pending at a time.
+  upgrader: WaiterSlots<1>,
+  /// The counters detecting guards that are held while the waiters are polled over and over again
+  leaks: LeakDetector,
 }
 
-impl AsyncRWLockInner {
+impl<const WAITERS: usize> AsyncRWLockInner<WAITERS> {
   fn new() -> Self {
     Self {
-      waiter: BTreeMap::new(),
-      next_waiter: 0,
+      waiter: WaiterSlots::new(),
+      upgrader: WaiterSlots::new(),
+      leaks: LeakDetector::default(),
+    }
+  }
+
+  fn wake_next(&self) {
+    // a pending upgrade only waits for the readers to leave and holds off all other requests, so it is woken first
+    if let Some(upgrader) = self.upgrader.take_next() {
+      upgrader.wake();
+      return;
     }
+    // the waker is removed from the waiter slots as it will re-register itself when the corresponding Future is
+    // polled and can't acquire the lock
+    self.waiter.wake_next();
   }
 }
 
+// the async guards are meant to be held across `.await` points, so they stay `Send` with the `unsend_guards` feature
+#[cfg(feature = "unsend_guards")]
+unsafe impl<T: Send, const WAITERS: usize> Send for AsyncWriteLockGuard<'_, T, WAITERS> {}
+#[cfg(feature = "unsend_guards")]
+unsafe impl<T: Send, const WAITERS: usize> Send for AsyncReadLockGuard<'_, T, WAITERS> {}
+#[cfg(feature = "unsend_guards")]
+unsafe impl<T: Send, const WAITERS: usize> Send for AsyncUpgradableReadGuard<'_, T, WAITERS> {}
+
 #[cfg(testing)]
 mod tests {
   use super::*;
   use async_std::prelude::*;
   use async_std::task;
+  use core::task::Waker;
   use core::time::Duration;
 
   #[async_std::test]
@@ -324,7 +820,7 @@ mod tests {
 
     let task2 = task::spawn(async move {
       // if this async is started first wait a bit to really run the
-      // other one first to aquire the AsyncMutexLock
+      // other one first to acquire the AsyncMutexLock
       task::yield_now().await;
       task::sleep(Duration::from_secs(1)).await;
       let guard = rwlock.lock().await;
@@ -353,7 +849,7 @@ mod tests {
 
     let task2 = task::spawn(async move {
       // if this async is started first wait a bit to really run the
-      // other one first to aquire the AsyncMutexLock
+      // other one first to acquire the AsyncMutexLock
       task::yield_now().await;
       task::sleep(Duration::from_secs(1)).await;
       let guard = rwlock.read().await;
@@ -382,7 +878,7 @@ mod tests {
 
     let task2 = task::spawn(async move {
       // if this async is started first wait a bit to really run the
-      // other one first to aquire the AsyncWriteLock
+      // other one first to acquire the AsyncWriteLock
       task::sleep(Duration::from_secs(5)).await;
       let mut guard = rwlock.lock().await;
       **guard = 20;
@@ -404,4 +900,197 @@ mod tests {
       _ => panic!("unable to get inner data"),
     }
   }
+
+  /// A waker recording whether it has been woken
+  struct WakeFlag(core::sync::atomic::AtomicBool);
+
+  impl alloc::task::Wake for WakeFlag {
+    fn wake(self: Arc<Self>) {
+      self.0.store(true, core::sync::atomic::Ordering::SeqCst);
+    }
+  }
+
+  impl WakeFlag {
+    fn new() -> (Arc<Self>, Waker) {
+      let flag = Arc::new(Self(core::sync::atomic::AtomicBool::new(false)));
+      (Arc::clone(&flag), Waker::from(flag))
+    }
+
+    fn woken(&self) -> bool {
+      self.0.swap(false, core::sync::atomic::Ordering::SeqCst)
+    }
+  }
+
+  fn poll_once<F: Future>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+    future.poll(&mut Context::from_waker(waker))
+  }
+
+  #[test]
+  fn upgrade_is_woken_ahead_of_queued_writers() {
+    let rwlock = AsyncRWLock::new(10_u32);
+    let (_, noop) = WakeFlag::new();
+
+    let reader = match poll_once(core::pin::pin!(rwlock.read()), &noop) {
+      Poll::Ready(guard) => guard,
+      Poll::Pending => panic!("read lock not acquired"),
+    };
+    let upgradable = rwlock.try_upgradable_read().unwrap();
+    // only one upgradable read lock can exist
+    assert!(rwlock.try_upgradable_read().is_none());
+
+    let (writer_woken, writer_waker) = WakeFlag::new();
+    let mut writer = Box::pin(rwlock.write());
+    assert!(poll_once(writer.as_mut(), &writer_waker).is_pending());
+
+    let (upgrade_woken, upgrade_waker) = WakeFlag::new();
+    let mut upgrade = Box::pin(upgradable.upgrade());
+    assert!(poll_once(upgrade.as_mut(), &upgrade_waker).is_pending());
+    // the pending upgrade holds off new readers
+    assert!(rwlock.data.try_read().is_none());
+
+    // releasing the last reader wakes the upgrade ahead of the writer queued before
+    drop(reader);
+    assert!(upgrade_woken.woken());
+    assert!(!writer_woken.woken());
+    let mut guard = match poll_once(upgrade.as_mut(), &noop) {
+      Poll::Ready(guard) => guard,
+      Poll::Pending => panic!("upgrade not completed"),
+    };
+    **guard = 20;
+
+    // the writer gets the lock once the upgraded lock is released
+    assert!(poll_once(writer.as_mut(), &writer_waker).is_pending());
+    drop(guard);
+    assert!(writer_woken.woken());
+    match poll_once(writer.as_mut(), &noop) {
+      Poll::Ready(guard) => assert_eq!(**guard, 20),
+      Poll::Pending => panic!("write lock not acquired"),
+    };
+  }
+
+  #[test]
+  fn queued_readers_are_admitted_as_one_batch() {
+    let rwlock = AsyncRWLock::new(10_u32);
+    let (_, noop) = WakeFlag::new();
+    let guard = match poll_once(core::pin::pin!(rwlock.write()), &noop) {
+      Poll::Ready(guard) => guard,
+      Poll::Pending => panic!("write lock not acquired"),
+    };
+
+    let (first_woken, first_waker) = WakeFlag::new();
+    let mut first = Box::pin(rwlock.read());
+    assert!(poll_once(first.as_mut(), &first_waker).is_pending());
+    let (second_woken, second_waker) = WakeFlag::new();
+    let mut second = Box::pin(rwlock.read());
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+    let (writer_woken, writer_waker) = WakeFlag::new();
+    let mut writer = Box::pin(rwlock.write());
+    assert!(poll_once(writer.as_mut(), &writer_waker).is_pending());
+    let (third_woken, third_waker) = WakeFlag::new();
+    let mut third = Box::pin(rwlock.read());
+    assert!(poll_once(third.as_mut(), &third_waker).is_pending());
+
+    // releasing the write lock wakes the first reader, which passes the wake up on to the second one
+    drop(guard);
+    assert!(first_woken.woken());
+    assert!(!second_woken.woken());
+    let first = match poll_once(first.as_mut(), &noop) {
+      Poll::Ready(guard) => guard,
+      Poll::Pending => panic!("read lock not acquired"),
+    };
+    assert!(second_woken.woken());
+    let second = match poll_once(second.as_mut(), &noop) {
+      Poll::Ready(guard) => guard,
+      Poll::Pending => panic!("read lock not acquired"),
+    };
+
+    // the batch ends at the queued writer, the reader queued behind it keeps waiting
+    assert!(writer_woken.woken());
+    assert!(poll_once(writer.as_mut(), &writer_waker).is_pending());
+    assert!(!third_woken.woken());
+    drop(first);
+    assert!(writer_woken.woken());
+    assert!(poll_once(writer.as_mut(), &writer_waker).is_pending());
+    drop(second);
+    assert!(writer_woken.woken());
+    assert!(!third_woken.woken());
+    assert!(poll_once(writer.as_mut(), &noop).is_ready());
+  }
+
+  #[test]
+  fn dropped_upgrade_releases_the_lock() {
+    let rwlock = AsyncRWLock::new(10_u32);
+    let (_, noop) = WakeFlag::new();
+
+    let reader = rwlock.data.try_read().unwrap();
+    let upgradable = rwlock.try_upgradable_read().unwrap();
+    let mut upgrade = Box::pin(upgradable.upgrade());
+    assert!(poll_once(upgrade.as_mut(), &noop).is_pending());
+    drop(upgrade);
+    drop(reader);
+    assert!(rwlock.data.try_write().is_some());
+    assert!(rwlock.try_upgradable_read().is_some());
+  }
+
+  #[cfg(feature = "leak_detection")]
+  #[test]
+  fn guard_held_across_polls_is_reported() {
+    use crate::r#async::{clear_leak_hook, set_leak_hook, GuardLeak};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static REPORTED: AtomicUsize = AtomicUsize::new(0);
+    fn report(leak: &GuardLeak) {
+      assert_eq!(leak.guards, 1);
+      assert_eq!(leak.polls, 4);
+      REPORTED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let rwlock = AsyncRWLock::new(10_u32);
+    let (_, noop) = WakeFlag::new();
+    set_leak_hook(3, report);
+
+    let guard = match poll_once(core::pin::pin!(rwlock.write()), &noop) {
+      Poll::Ready(guard) => guard,
+      Poll::Pending => panic!("write lock not acquired"),
+    };
+    assert_eq!(rwlock.outstanding_guards(), 1);
+    let mut reader = Box::pin(rwlock.read());
+    for _ in 0..10 {
+      assert!(poll_once(reader.as_mut(), &noop).is_pending());
+    }
+    // the leak is reported once until the guard is released
+    assert_eq!(REPORTED.load(Ordering::SeqCst), 1);
+
+    drop(guard);
+    assert!(poll_once(reader.as_mut(), &noop).is_ready());
+    drop(reader);
+    assert_eq!(rwlock.outstanding_guards(), 0);
+    clear_leak_hook();
+  }
+
+  fn assert_send<F: Future + Send>(future: F) -> F {
+    future
+  }
+
+  #[async_std::test]
+  #[cfg_attr(miri, ignore = "the async-std runtime is not supported by miri")]
+  async fn lock_futures_are_send() {
+    let rwlock = Arc::new(AsyncRWLock::new(10_u32));
+    let guard = rwlock.write().await;
+    let reader = Arc::clone(&rwlock);
+    let writer = Arc::clone(&rwlock);
+
+    // the lock requests can be spawned on a work stealing executor
+    let read_task = task::spawn(assert_send(async move {
+      let guard = reader.read().await;
+      **guard
+    }));
+    let write_task = task::spawn(assert_send(async move {
+      let mut guard = writer.write().await;
+      **guard += 1;
+    }));
+    task::sleep(Duration::from_millis(100)).await;
+    drop(guard);
+    write_task.await;
+    assert!(read_task.await >= 10);
+  }
 }
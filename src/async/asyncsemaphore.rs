@@ -7,102 +7,692 @@
 
 //! # Async Semaphore
 //!
+//! Permits are granted in the order they have been requested, like the [Semaphore] does for blocking cores. Each
+//! waiting `Future` places a request with its ticket. Releasing permits hands them directly to the request with the
+//! lowest ticket and wakes only this waiter, so a burst of releases does not leave the waiters racing for the permits
+//! in the order the executor happens to poll them. A new request does not take available permits while older requests
+//! are still waiting.
 
 extern crate alloc;
 
-use crate::sync::{Mutex, Semaphore};
-use alloc::{collections::BTreeMap, sync::Arc};
+use super::waiters::WaiterSlots;
+use super::{trace, CancellationToken};
+use crate::sync::Semaphore;
+use crate::LockError;
+use alloc::sync::Arc;
 use core::{
   future::Future,
   pin::Pin,
-  task::{Context, Poll, Waker},
+  sync::atomic::{fence, AtomicBool, AtomicU32, AtomicUsize, Ordering},
+  task::{Context, Poll},
 };
 
-pub struct AsyncSemaphore {
-  inner: Arc<Mutex<AsyncSemaphoreInner>>,
+/// An async semaphore. Up to `WAITERS` `Future`s can wait for permits at the same time without busy polling. Their
+/// wakers and requests are kept in a fixed number of slots, so waiting does not allocate. If all slots are occupied
+/// additional `Future`s re-schedule themself when polled until a slot gets available.
+///
+/// The waiters are served strictly in the order of their requests. A request for more permits than are available
+/// holds off all requests made after it, even if those could be served with the available permits.
+pub struct AsyncSemaphore<const WAITERS: usize = 32> {
+  inner: Arc<AsyncSemaphoreInner<WAITERS>>,
   sema: Arc<Semaphore>,
 }
 
 impl AsyncSemaphore {
+  /// Create the [AsyncSemaphore] with the given number of permits and the default number of 32 waiter slots
   pub fn new(initial: u32) -> Self {
+    Self::with_waiter_slots(initial)
+  }
+}
+
+impl<const WAITERS: usize> AsyncSemaphore<WAITERS> {
+  /// Create the [AsyncSemaphore] with the given number of permits and `WAITERS` waiter slots
+  pub fn with_waiter_slots(initial: u32) -> Self {
     Self {
-      inner: Arc::new(Mutex::new(AsyncSemaphoreInner::new())),
+      inner: Arc::new(AsyncSemaphoreInner::new()),
       sema: Arc::new(Semaphore::new(initial)),
     }
   }
 
   pub async fn down(&self) {
+    self.inner.dispatch_deferred(&self.sema);
     // if we cann't immediately pull the semaphore down we need to use a future to poll the
     // result
-    if self.sema.try_down().is_err() {
-      let mut inner = self.inner.lock();
-      let current_id = inner.next_waiter;
-      inner.next_waiter += 1;
-      drop(inner);
+    if !self.inner.try_acquire(&self.sema, 1) {
+      let current_id = self.inner.waiter.next_ticket();
+      trace::requested("AsyncSemaphore", trace::lock_id(&*self.inner), current_id);
+
+      AsyncSemaphoreFuture::new(
+        Arc::clone(&self.inner),
+        Arc::clone(&self.sema),
+        current_id,
+        1,
+      )
+      .await
+    } else {
+      trace::acquired("AsyncSemaphore", trace::lock_id(&*self.inner), None);
+    }
+  }
+
+  /// Acquire the given number of permits from the [AsyncSemaphore]. The returned `Future` resolves into a
+  /// [SemaphorePermit] once all permits could be acquired at once. The permits are released as soon as the
+  /// [SemaphorePermit] is dropped. This ensures the permits are not leaked if the task holding them is cancelled.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::AsyncSemaphore;
+  /// async fn work(sema: &AsyncSemaphore) {
+  ///     let permit = sema.acquire(2).await;
+  ///     // do something while holding 2 permits
+  ///     drop(permit);
+  /// }
+  /// ```
+  pub async fn acquire(&self, n: u32) -> SemaphorePermit<'_, WAITERS> {
+    self.inner.dispatch_deferred(&self.sema);
+    if !self.inner.try_acquire(&self.sema, n) {
+      let current_id = self.inner.waiter.next_ticket();
+      trace::requested("AsyncSemaphore", trace::lock_id(&*self.inner), current_id);
+
+      AsyncSemaphoreFuture::new(
+        Arc::clone(&self.inner),
+        Arc::clone(&self.sema),
+        current_id,
+        n,
+      )
+      .await
+    } else {
+      trace::acquired("AsyncSemaphore", trace::lock_id(&*self.inner), None);
+    }
+
+    SemaphorePermit {
+      sema: self,
+      permits: n,
+    }
+  }
+
+  /// Acquire the given number of permits unless the given [CancellationToken] is cancelled while waiting for them. In
+  /// this case the request is withdrawn and this resolves to [LockError::Cancelled].
+  pub async fn acquire_cancellable(
+    &self,
+    n: u32,
+    token: &CancellationToken,
+  ) -> Result<SemaphorePermit<'_, WAITERS>, LockError> {
+    token.run_until_cancelled(self.acquire(n)).await
+  }
+
+  /// Try to acquire the given number of permits without waiting. Returns `None` if not enough permits are available
+  /// or other `Future`s are still waiting for permits, as those are served first.
+  pub fn try_acquire_n(&self, n: u32) -> Option<SemaphorePermit<'_, WAITERS>> {
+    self.inner.try_acquire(&self.sema, n).then(|| {
+      trace::acquired("AsyncSemaphore", trace::lock_id(&*self.inner), None);
+      SemaphorePermit {
+        sema: self,
+        permits: n,
+      }
+    })
+  }
+
+  /// Acquire the given number of permits from the [AsyncSemaphore] shared with an `Arc`. The returned
+  /// [OwnedSemaphorePermit] keeps the semaphore alive and is not bound to the lifetime of a borrow, so it can be moved
+  /// into spawned tasks. The permits are released as soon as the [OwnedSemaphorePermit] is dropped.
+  ///
+  /// # Example
+  /// ```
+  /// # extern crate alloc;
+  /// # use alloc::sync::Arc;
+  /// # use ruspiro_lock::r#async::AsyncSemaphore;
+  /// async fn accept(connections: &Arc<AsyncSemaphore>) {
+  ///     let permit = connections.acquire_owned(1).await;
+  ///     // hand the permit to the task serving the connection
+  ///     drop(permit);
+  /// }
+  /// ```
+  pub async fn acquire_owned(self: &Arc<Self>, n: u32) -> OwnedSemaphorePermit<WAITERS> {
+    self.acquire(n).await.forget();
+    OwnedSemaphorePermit {
+      sema: Arc::clone(self),
+      permits: n,
+    }
+  }
+
+  /// Try to acquire the given number of permits from the [AsyncSemaphore] shared with an `Arc` without waiting.
+  /// Returns `None` if not enough permits are available or other `Future`s are still waiting for permits.
+  pub fn try_acquire_owned(self: &Arc<Self>, n: u32) -> Option<OwnedSemaphorePermit<WAITERS>> {
+    self.try_acquire_n(n)?.forget();
+    Some(OwnedSemaphorePermit {
+      sema: Arc::clone(self),
+      permits: n,
+    })
+  }
 
-      AsyncSemaphoreFuture::new(Arc::clone(&self.inner), Arc::clone(&self.sema), current_id).await
+  /// Create a [PermitStream] yielding one [SemaphorePermit] each time a permit could be acquired. This simplifies an
+  /// async dispatcher that starts a unit of work for each available permit. With the `stream` feature it implements
+  /// the `futures_core::Stream` trait, so any `StreamExt::next` could be used instead of
+  /// [PermitStream::poll_permit]. The stream never ends.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::{block_on, AsyncSemaphore};
+  /// fn main() {
+  ///     let workers = AsyncSemaphore::new(2);
+  ///     let mut permits = workers.permits_stream();
+  ///     block_on(async {
+  ///         let first = core::future::poll_fn(|cx| permits.poll_permit(cx)).await;
+  ///         let second = core::future::poll_fn(|cx| permits.poll_permit(cx)).await;
+  ///         // the work started with the permit releases it once done
+  ///         drop(first);
+  ///         drop(second);
+  ///     });
+  /// }
+  /// ```
+  pub fn permits_stream(&self) -> PermitStream<'_, WAITERS> {
+    PermitStream {
+      sema: self,
+      pending: None,
     }
   }
 
-  /// when increasing the [AsyncSemaphore] we will increase the embedded [Semaphore] and notify the next waiter in the
-  /// list that previously did not got the chance to decrease the [Semaphore]
+  /// when increasing the [AsyncSemaphore] we will increase the embedded [Semaphore] and hand the permit to the waiter
+  /// that requested permits first
   pub fn up(&self) {
-    self.sema.up();
+    self.up_n(1);
+  }
+
+  /// Increase the [AsyncSemaphore] by the given number of permits. They are handed to the waiting requests in the
+  /// order they have been made, and each request served is woken.
+  pub fn up_n(&self, n: u32) {
+    self.sema.up_n(n);
+    trace::released("AsyncSemaphore", trace::lock_id(&*self.inner));
+    self.inner.dispatch(&self.sema);
+  }
+
+  /// Increase the [AsyncSemaphore] from an interrupt handler. The code behind a [Waker](core::task::Waker) might
+  /// block or allocate, so the permit is not handed to a waiter here. This is deferred until the next call of `down`,
+  /// `acquire` or [AsyncSemaphore::wake_pending], or until a waiting `Future` of this semaphore is polled. This only
+  /// updates atomic counters, so it never waits for another core.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::AsyncSemaphore;
+  /// fn dma_irq_handler(done: &AsyncSemaphore) {
+  ///     done.up_from_isr();
+  /// }
+  ///
+  /// fn executor_idle(done: &AsyncSemaphore) {
+  ///     // back in thread context, wake the task waiting for the DMA transfer
+  ///     done.wake_pending();
+  /// }
+  /// ```
+  pub fn up_from_isr(&self) {
+    self.sema.up_from_isr();
+    self.inner.deferred.store(true, Ordering::Release);
+  }
+
+  /// Hand the permits released by [AsyncSemaphore::up_from_isr] to the waiters and wake them. The executor shall call
+  /// this outside of the interrupt handler, eg. in its idle loop, if the tasks waiting for the semaphore are not polled
+  /// otherwise.
+  pub fn wake_pending(&self) {
+    self.inner.dispatch_deferred(&self.sema);
+  }
+}
+
+/// RAII guard of permits acquired from an [AsyncSemaphore]. The permits are given back to the semaphore when this is
+/// dropped.
+#[must_use = "if unused the permits are immediately released"]
+pub struct SemaphorePermit<'a, const WAITERS: usize = 32> {
+  sema: &'a AsyncSemaphore<WAITERS>,
+  permits: u32,
+}
+
+impl<const WAITERS: usize> SemaphorePermit<'_, WAITERS> {
+  /// The number of permits held by this [SemaphorePermit]
+  pub fn permits(&self) -> u32 {
+    self.permits
+  }
+
+  /// Consume the [SemaphorePermit] without releasing the permits to the [AsyncSemaphore].
+  pub fn forget(mut self) {
+    self.permits = 0;
+  }
+}
+
+impl<const WAITERS: usize> Drop for SemaphorePermit<'_, WAITERS> {
+  fn drop(&mut self) {
+    if self.permits > 0 {
+      self.sema.up_n(self.permits);
+    }
+  }
+}
+
+/// RAII guard of permits acquired from an [AsyncSemaphore] shared with an `Arc`. It keeps the semaphore alive and
+/// gives the permits back when this is dropped.
+#[must_use = "if unused the permits are immediately released"]
+pub struct OwnedSemaphorePermit<const WAITERS: usize = 32> {
+  sema: Arc<AsyncSemaphore<WAITERS>>,
+  permits: u32,
+}
+
+impl<const WAITERS: usize> OwnedSemaphorePermit<WAITERS> {
+  /// The number of permits held by this [OwnedSemaphorePermit]
+  pub fn permits(&self) -> u32 {
+    self.permits
+  }
+
+  /// Consume the [OwnedSemaphorePermit] without releasing the permits to the [AsyncSemaphore].
+  pub fn forget(mut self) {
+    self.permits = 0;
+  }
+}
 
-    let mut inner = self.inner.lock();
-    if let Some(&waiter_id) = inner.waiter.keys().next() {
-      let waiter = inner.waiter.remove(&waiter_id).unwrap();
-      waiter.wake();
+impl<const WAITERS: usize> Drop for OwnedSemaphorePermit<WAITERS> {
+  fn drop(&mut self) {
+    if self.permits > 0 {
+      self.sema.up_n(self.permits);
     }
   }
 }
 
+/// A stream of single permits acquired from an [AsyncSemaphore], created with [AsyncSemaphore::permits_stream]. A
+/// permit request that is still waiting when the stream is dropped is withdrawn.
+pub struct PermitStream<'a, const WAITERS: usize = 32> {
+  sema: &'a AsyncSemaphore<WAITERS>,
+  /// The request waiting for the next permit
+  pending: Option<AsyncSemaphoreFuture<WAITERS>>,
+}
+
+impl<'a, const WAITERS: usize> PermitStream<'a, WAITERS> {
+  /// Poll the next permit of the stream. This is the same as polling the `Stream` implementation but does not require
+  /// the `stream` feature or to pin the stream.
+  pub fn poll_permit(&mut self, cx: &mut Context<'_>) -> Poll<SemaphorePermit<'a, WAITERS>> {
+    let sema = self.sema;
+    let pending = match self.pending.as_mut() {
+      Some(pending) => pending,
+      None => {
+        sema.inner.dispatch_deferred(&sema.sema);
+        if sema.inner.try_acquire(&sema.sema, 1) {
+          trace::acquired("AsyncSemaphore", trace::lock_id(&*sema.inner), None);
+          return Poll::Ready(SemaphorePermit { sema, permits: 1 });
+        }
+
+        let current_id = sema.inner.waiter.next_ticket();
+        trace::requested("AsyncSemaphore", trace::lock_id(&*sema.inner), current_id);
+        self.pending.insert(AsyncSemaphoreFuture::new(
+          Arc::clone(&sema.inner),
+          Arc::clone(&sema.sema),
+          current_id,
+          1,
+        ))
+      }
+    };
+
+    match Pin::new(pending).poll(cx) {
+      Poll::Ready(()) => {
+        self.pending = None;
+        Poll::Ready(SemaphorePermit { sema, permits: 1 })
+      }
+      Poll::Pending => Poll::Pending,
+    }
+  }
+}
+
+#[cfg(feature = "stream")]
+impl<'a, const WAITERS: usize> futures_core::Stream for PermitStream<'a, WAITERS> {
+  type Item = SemaphorePermit<'a, WAITERS>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.get_mut().poll_permit(cx).map(Some)
+  }
+}
+
 /// The `Future` that represents an `await`able semaphore down request to an [AsyncSemaphore] and can only be created
 /// from functions of the [AsyncSemaphore]
-struct AsyncSemaphoreFuture {
-  inner: Arc<Mutex<AsyncSemaphoreInner>>,
+struct AsyncSemaphoreFuture<const WAITERS: usize> {
+  inner: Arc<AsyncSemaphoreInner<WAITERS>>,
   sema: Arc<Semaphore>,
   id: usize,
+  permits: u32,
+  /// The index of the request placed for the permits, if any
+  request: Option<usize>,
 }
 
-impl AsyncSemaphoreFuture {
-  fn new(inner: Arc<Mutex<AsyncSemaphoreInner>>, sema: Arc<Semaphore>, id: usize) -> Self {
-    Self { inner, sema, id }
+impl<const WAITERS: usize> AsyncSemaphoreFuture<WAITERS> {
+  fn new(
+    inner: Arc<AsyncSemaphoreInner<WAITERS>>,
+    sema: Arc<Semaphore>,
+    id: usize,
+    permits: u32,
+  ) -> Self {
+    Self {
+      inner,
+      sema,
+      id,
+      permits,
+      request: None,
+    }
+  }
+
+  /// Take the permits if they have been granted to the request placed
+  fn take_granted(&mut self, index: usize) -> bool {
+    if !self.inner.take_granted(index, self.id) {
+      return false;
+    }
+    self.request = None;
+    trace::acquired(
+      "AsyncSemaphore",
+      trace::lock_id(&*self.inner),
+      Some(self.id),
+    );
+    true
   }
 }
 
-impl Future for AsyncSemaphoreFuture {
+impl<const WAITERS: usize> Future for AsyncSemaphoreFuture<WAITERS> {
   type Output = ();
 
   fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
     let this = self.get_mut();
+    // hand out the permits released by an interrupt handler while we are in the context of the executor
+    this.inner.dispatch_deferred(&this.sema);
 
-    if this.sema.try_down().is_ok() {
-      Poll::Ready(())
-    } else {
-      let mut inner = this.inner.lock();
-      inner.waiter.insert(this.id, cx.waker().clone());
-      drop(inner);
+    let index = match this.request {
+      Some(index) => index,
+      None => match this.inner.request(this.id, this.permits) {
+        Some(index) => *this.request.insert(index),
+        None => {
+          // all request slots are occupied, so take the permits if no request is waiting or re-schedule ourself to
+          // try again
+          if this.inner.try_acquire(&this.sema, this.permits) {
+            trace::acquired(
+              "AsyncSemaphore",
+              trace::lock_id(&*this.inner),
+              Some(this.id),
+            );
+            return Poll::Ready(());
+          }
+          cx.waker().wake_by_ref();
+          return Poll::Pending;
+        }
+      },
+    };
+
+    if this.take_granted(index) {
+      this.inner.waiter.remove(this.id);
+      return Poll::Ready(());
+    }
+
+    let registered = this.inner.waiter.register(this.id, cx.waker());
+    // permits might have been released before our request has been placed or while we registered ourself, so hand
+    // them out now to not miss the wake up
+    this.inner.dispatch(&this.sema);
+    if this.take_granted(index) {
+      this.inner.waiter.remove(this.id);
+      return Poll::Ready(());
+    }
+
+    if !registered {
+      // all waiter slots are occupied, so re-schedule ourself to try again
+      cx.waker().wake_by_ref();
+    }
+
+    Poll::Pending
+  }
+}
 
-      Poll::Pending
+/// If the `Future` is dropped before it got its permits the request is withdrawn. If the permits have been granted
+/// already they are handed on to the next request.
+impl<const WAITERS: usize> Drop for AsyncSemaphoreFuture<WAITERS> {
+  fn drop(&mut self) {
+    if let Some(index) = self.request.take() {
+      self.inner.withdraw(index, self.id, &self.sema);
     }
+    self.inner.waiter.remove(self.id);
   }
 }
 
-struct AsyncSemaphoreInner {
-  /// If the lock could not be aquired we store the requestor id here to allow the next one
-  /// already waiting for the lock to retrieve it
-  waiter: BTreeMap<usize, Waker>,
-  /// The id of the next waiter that can be woken once the lock is released and someone else is already waiting for
-  /// the lock to be aquired
-  next_waiter: usize,
+/// The request slot is not used
+const FREE: usize = 0;
+/// The request slot is claimed by the `Future` placing its request
+const BUSY: usize = 1;
+/// The request is waiting for its permits
+const REQUESTED: usize = 2;
+/// The permits have been handed to the request and are not yet taken by its `Future`
+const GRANTED: usize = 3;
+/// The bits of the request state holding its status, the upper bits hold the ticket of the waiter
+const STATUS: usize = 0b11;
+/// The position of the ticket in the request state
+const TICKET_SHIFT: u32 = 2;
+
+/// The state of a request with the given ticket and status
+const fn state(ticket: usize, status: usize) -> usize {
+  (ticket << TICKET_SHIFT) | status
+}
+
+/// A request of a waiting `Future` for a number of permits
+struct Request {
+  state: AtomicUsize,
+  permits: AtomicU32,
 }
 
-impl AsyncSemaphoreInner {
+struct AsyncSemaphoreInner<const WAITERS: usize> {
+  /// If the semaphore could not be decreased we store the waker of the requestor here to allow it to be woken once
+  /// its request is granted
+  waiter: WaiterSlots<WAITERS>,
+  /// The requests of the waiting `Future`s, served in the order of their tickets
+  requests: [Request; WAITERS],
+  /// The number of request slots in use
+  queued: AtomicUsize,
+  /// Set if permits have been released by an interrupt handler that are not yet handed to the requests
+  deferred: AtomicBool,
+}
+
+impl<const WAITERS: usize> AsyncSemaphoreInner<WAITERS> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const EMPTY: Request = Request {
+    state: AtomicUsize::new(FREE),
+    permits: AtomicU32::new(0),
+  };
+
   fn new() -> Self {
     Self {
-      waiter: BTreeMap::new(),
-      next_waiter: 0,
+      waiter: WaiterSlots::new(),
+      requests: [Self::EMPTY; WAITERS],
+      queued: AtomicUsize::new(0),
+      deferred: AtomicBool::new(false),
+    }
+  }
+
+  /// Take the given number of permits right away. This fails while requests are waiting, so a new request never
+  /// overtakes them.
+  fn try_acquire(&self, sema: &Semaphore, permits: u32) -> bool {
+    self.queued.load(Ordering::SeqCst) == 0 && sema.try_acquire_n(permits).is_ok()
+  }
+
+  /// Place a request for the given number of permits. Returns the index of the request or `None` if all request slots
+  /// are in use.
+  fn request(&self, ticket: usize, permits: u32) -> Option<usize> {
+    let index = self.requests.iter().position(|request| {
+      request
+        .state
+        .compare_exchange(
+          FREE,
+          state(ticket, BUSY),
+          Ordering::Acquire,
+          Ordering::Relaxed,
+        )
+        .is_ok()
+    })?;
+    // count the request before it can be served, so it is counted as long as it can be granted
+    self.queued.fetch_add(1, Ordering::SeqCst);
+    let request = &self.requests[index];
+    request.permits.store(permits, Ordering::Relaxed);
+    request
+      .state
+      .store(state(ticket, REQUESTED), Ordering::Release);
+    Some(index)
+  }
+
+  /// Free the request if its permits have been granted. Returns `false` if the request is still waiting.
+  fn take_granted(&self, index: usize, ticket: usize) -> bool {
+    let request = &self.requests[index];
+    if request.state.load(Ordering::Acquire) != state(ticket, GRANTED) {
+      return false;
+    }
+    request.state.store(FREE, Ordering::Release);
+    self.queued.fetch_sub(1, Ordering::SeqCst);
+    true
+  }
+
+  /// Withdraw the request. Permits granted to it meanwhile are given back. As the request might have held off the
+  /// requests made after it, those are served now.
+  fn withdraw(&self, index: usize, ticket: usize, sema: &Semaphore) {
+    let request = &self.requests[index];
+    let permits = request.permits.load(Ordering::Relaxed);
+    if request
+      .state
+      .compare_exchange(
+        state(ticket, REQUESTED),
+        FREE,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+      )
+      .is_err()
+    {
+      // the permits have been granted already
+      request.state.store(FREE, Ordering::Release);
+      sema.up_n(permits);
+    }
+    self.queued.fetch_sub(1, Ordering::SeqCst);
+    self.dispatch(sema);
+  }
+
+  /// Hand the available permits to the waiting requests in the order of their tickets and wake each request served.
+  /// This stops at the first request that can not be served with the available permits.
+  fn dispatch(&self, sema: &Semaphore) {
+    // pairs with the fence in register, so either the waiter sees the released permits in its own dispatch or the
+    // request placed before registering is seen here
+    fence(Ordering::SeqCst);
+    loop {
+      let Some((request, ticket)) = self
+        .requests
+        .iter()
+        .map(|request| (request, request.state.load(Ordering::Acquire)))
+        .filter(|&(_, current)| current & STATUS == REQUESTED)
+        .map(|(request, current)| (request, current >> TICKET_SHIFT))
+        .min_by_key(|&(_, ticket)| ticket)
+      else {
+        return;
+      };
+
+      let permits = request.permits.load(Ordering::Relaxed);
+      if sema.try_acquire_n(permits).is_err() {
+        return;
+      }
+      if request
+        .state
+        .compare_exchange(
+          state(ticket, REQUESTED),
+          state(ticket, GRANTED),
+          Ordering::AcqRel,
+          Ordering::Relaxed,
+        )
+        .is_ok()
+      {
+        self.waiter.wake(ticket);
+      } else {
+        // the request has been withdrawn in the meantime, so give the permits back and serve the next one
+        sema.up_n(permits);
+      }
+    }
+  }
+
+  /// Hand out the permits released by an interrupt handler since the last call
+  fn dispatch_deferred(&self, sema: &Semaphore) {
+    if self.deferred.load(Ordering::Relaxed) && self.deferred.swap(false, Ordering::Acquire) {
+      self.dispatch(sema);
+    }
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use alloc::boxed::Box;
+  use core::task::Waker;
+
+  /// A waker recording whether it has been woken
+  struct WakeFlag(AtomicBool);
+
+  impl alloc::task::Wake for WakeFlag {
+    fn wake(self: Arc<Self>) {
+      self.0.store(true, Ordering::SeqCst);
     }
   }
+
+  impl WakeFlag {
+    fn new() -> (Arc<Self>, Waker) {
+      let flag = Arc::new(Self(AtomicBool::new(false)));
+      (Arc::clone(&flag), Waker::from(flag))
+    }
+
+    fn woken(&self) -> bool {
+      self.0.swap(false, Ordering::SeqCst)
+    }
+  }
+
+  fn poll_once<F: Future>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+    future.poll(&mut Context::from_waker(waker))
+  }
+
+  #[test]
+  fn burst_of_ups_grants_permits_in_request_order() {
+    let sema = AsyncSemaphore::new(0);
+    let waiters: Vec<_> = [1, 2, 1]
+      .into_iter()
+      .map(|permits| {
+        let (woken, waker) = WakeFlag::new();
+        let mut future = Box::pin(sema.acquire(permits));
+        assert!(poll_once(future.as_mut(), &waker).is_pending());
+        (future, woken, waker)
+      })
+      .collect();
+
+    // the second request holds off the third one until both of its permits are available
+    sema.up();
+    sema.up();
+    let woken: Vec<_> = waiters.iter().map(|(_, woken, _)| woken.woken()).collect();
+    assert_eq!(woken, [true, false, false]);
+    sema.up();
+    sema.up();
+    sema.up();
+    let woken: Vec<_> = waiters.iter().map(|(_, woken, _)| woken.woken()).collect();
+    assert_eq!(woken, [false, true, true]);
+
+    // the permits left over are not taken by the waiters, and a new request does not overtake them
+    assert!(sema.try_acquire_n(1).is_none());
+    for (mut future, _, waker) in waiters {
+      match poll_once(future.as_mut(), &waker) {
+        Poll::Ready(permit) => permit.forget(),
+        Poll::Pending => panic!("permits not granted"),
+      }
+    }
+    assert!(sema.try_acquire_n(1).is_some());
+  }
+
+  #[test]
+  fn dropped_request_hands_granted_permits_on() {
+    let sema = AsyncSemaphore::new(0);
+    let (first_woken, first_waker) = WakeFlag::new();
+    let mut first = Box::pin(sema.acquire(1));
+    assert!(poll_once(first.as_mut(), &first_waker).is_pending());
+    let (second_woken, second_waker) = WakeFlag::new();
+    let mut second = Box::pin(sema.acquire(1));
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+
+    sema.up();
+    assert!(first_woken.woken());
+    // the first waiter is cancelled after its permit has been granted
+    drop(first);
+    assert!(second_woken.woken());
+    assert!(poll_once(second.as_mut(), &second_waker).is_ready());
+  }
 }
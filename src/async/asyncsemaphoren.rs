@@ -0,0 +1,325 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Async Semaphore with fixed waiter capacity
+//!
+//! This version of the async semaphore does not require any heap allocation. The `Future`s waiting for the semaphore
+//! are kept in a fixed number of waiter slots.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::r#async::AsyncSemaphoreN;
+//!
+//! static SEMA: AsyncSemaphoreN<4> = AsyncSemaphoreN::new(1);
+//!
+//! async fn work() {
+//!     SEMA.down().await;
+//!     // do something
+//!     SEMA.up();
+//! }
+//! ```
+
+use super::trace;
+use super::waiters::WaiterSlots;
+use crate::sync::{Semaphore, SharedHandle};
+use crate::LockError;
+use core::{
+  future::Future,
+  pin::Pin,
+  task::{Context, Poll},
+};
+
+/// An async counting semaphore that does not require `alloc`. Up to `WAITERS` `Future`s can wait for the semaphore at
+/// the same time without busy polling.
+pub struct AsyncSemaphoreN<const WAITERS: usize> {
+  inner: WaiterSlots<WAITERS>,
+  sema: Semaphore,
+}
+
+impl<const WAITERS: usize> AsyncSemaphoreN<WAITERS> {
+  /// Create the [AsyncSemaphoreN] with the given initial value. As this does not require any allocation it can be
+  /// assigned to a static variable
+  pub const fn new(initial: u32) -> Self {
+    Self {
+      inner: WaiterSlots::new(),
+      sema: Semaphore::new(initial),
+    }
+  }
+
+  /// Decrease the [AsyncSemaphoreN]. The returned `Future` resolves as soon as the semaphore could be decreased.
+  pub async fn down(&self) {
+    self.inner.wake_deferred();
+    if self.sema.try_acquire().is_err() {
+      let ticket = self.inner.next_ticket();
+      trace::requested("AsyncSemaphoreN", trace::lock_id(&self.inner), ticket);
+      AsyncSemaphoreNFuture {
+        sema: self,
+        ticket,
+        done: false,
+      }
+      .await
+    } else {
+      trace::acquired("AsyncSemaphoreN", trace::lock_id(&self.inner), None);
+    }
+  }
+
+  /// Decrease the [AsyncSemaphoreN] accessed through the given [SharedHandle]. The returned [OwnedSemaphoreNPermit]
+  /// keeps the handle and increases the semaphore again once it is dropped.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::AsyncSemaphoreN;
+  /// # use ruspiro_lock::sync::Shared;
+  /// static CHANNELS: Shared<AsyncSemaphoreN<4>, 4> = Shared::new(AsyncSemaphoreN::new(2));
+  ///
+  /// async fn transfer() {
+  ///     let permit = AsyncSemaphoreN::acquire_owned(CHANNELS.handle().unwrap()).await;
+  ///     // the permit can be moved into the task serving the transfer
+  ///     drop(permit);
+  /// }
+  /// ```
+  pub async fn acquire_owned<'a, const MAX: usize>(
+    sema: SharedHandle<'a, Self, MAX>,
+  ) -> OwnedSemaphoreNPermit<'a, WAITERS, MAX> {
+    sema.down().await;
+    OwnedSemaphoreNPermit { sema }
+  }
+
+  /// Try to decrease the [AsyncSemaphoreN] without waiting. Returns [value@Ok] if the semaphore could be decreased
+  /// or [LockError::WouldBlock] otherwise.
+  pub fn try_acquire(&self) -> Result<(), LockError> {
+    self.sema.try_acquire()?;
+    trace::acquired("AsyncSemaphoreN", trace::lock_id(&self.inner), None);
+    Ok(())
+  }
+
+  /// Try to decrease the [AsyncSemaphoreN] without waiting. Returns [value@Ok] if the semaphore could be decreased.
+  #[deprecated(
+    since = "0.6.0",
+    note = "use `try_acquire` that reports the reason of a failure"
+  )]
+  #[allow(clippy::result_unit_err)]
+  pub fn try_down(&self) -> Result<(), ()> {
+    self.sema.try_acquire().map_err(|_| ())
+  }
+
+  /// Increase the [AsyncSemaphoreN] and wake the next waiter
+  pub fn up(&self) {
+    self.sema.up();
+    trace::released("AsyncSemaphoreN", trace::lock_id(&self.inner));
+    self.inner.wake_next();
+  }
+
+  /// Increase the [AsyncSemaphoreN] from an interrupt handler. The wake up of the next waiter is deferred until the
+  /// next call of [AsyncSemaphoreN::down] or [AsyncSemaphoreN::wake_pending], or until a waiting `Future` of this
+  /// semaphore is polled. This only updates atomic counters, so it never waits for another core.
+  pub fn up_from_isr(&self) {
+    self.sema.up_from_isr();
+    self.inner.defer_wakes(1);
+  }
+
+  /// Wake the waiters whose wake up has been deferred by [AsyncSemaphoreN::up_from_isr]. The executor shall call this
+  /// outside of the interrupt handler if the tasks waiting for the semaphore are not polled otherwise.
+  pub fn wake_pending(&self) {
+    self.inner.wake_deferred();
+  }
+}
+
+/// The permit of an [AsyncSemaphoreN] decreased through a [SharedHandle]. The semaphore is increased again before
+/// the handle is dropped once this goes out of scope.
+#[must_use = "if unused the permit is immediately released"]
+pub struct OwnedSemaphoreNPermit<'a, const WAITERS: usize, const MAX: usize> {
+  sema: SharedHandle<'a, AsyncSemaphoreN<WAITERS>, MAX>,
+}
+
+impl<const WAITERS: usize, const MAX: usize> Drop for OwnedSemaphoreNPermit<'_, WAITERS, MAX> {
+  fn drop(&mut self) {
+    self.sema.up();
+  }
+}
+
+/// The `Future` that represents an `await`able semaphore down request to an [AsyncSemaphoreN]
+struct AsyncSemaphoreNFuture<'a, const WAITERS: usize> {
+  sema: &'a AsyncSemaphoreN<WAITERS>,
+  ticket: usize,
+  done: bool,
+}
+
+impl<const WAITERS: usize> Future for AsyncSemaphoreNFuture<'_, WAITERS> {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    // pass on the wake ups deferred by an interrupt handler while we are in the context of the executor
+    this.sema.inner.wake_deferred();
+    if this.sema.sema.try_acquire().is_ok() {
+      this.done = true;
+      trace::acquired(
+        "AsyncSemaphoreN",
+        trace::lock_id(&this.sema.inner),
+        Some(this.ticket),
+      );
+      return Poll::Ready(());
+    }
+
+    let registered = this.sema.inner.register(this.ticket, cx.waker());
+    // the semaphore might have been increased while we registered ourself
+    if this.sema.sema.try_acquire().is_ok() {
+      this.sema.inner.remove(this.ticket);
+      this.done = true;
+      trace::acquired(
+        "AsyncSemaphoreN",
+        trace::lock_id(&this.sema.inner),
+        Some(this.ticket),
+      );
+      return Poll::Ready(());
+    }
+
+    if !registered {
+      // all waiter slots are occupied, so re-schedule ourself to try again
+      cx.waker().wake_by_ref();
+    }
+    Poll::Pending
+  }
+}
+
+impl<const WAITERS: usize> Drop for AsyncSemaphoreNFuture<'_, WAITERS> {
+  fn drop(&mut self) {
+    // free the waiter slot and pass a wake up we did not use on to the next waiter
+    let inner = &self.sema.inner;
+    if !inner.remove(self.ticket) && !self.done {
+      inner.wake_next();
+    }
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use crate::sync::Shared;
+  use core::sync::atomic::{AtomicBool, Ordering};
+  use core::task::Waker;
+  use std::sync::Arc;
+  use std::task::Wake;
+
+  /// A waker recording whether it has been woken
+  struct WakeFlag(AtomicBool);
+
+  impl Wake for WakeFlag {
+    fn wake(self: Arc<Self>) {
+      self.0.store(true, Ordering::SeqCst);
+    }
+  }
+
+  impl WakeFlag {
+    fn new() -> (Arc<Self>, Waker) {
+      let flag = Arc::new(Self(AtomicBool::new(false)));
+      (Arc::clone(&flag), Waker::from(flag))
+    }
+
+    fn woken(&self) -> bool {
+      self.0.swap(false, Ordering::SeqCst)
+    }
+  }
+
+  fn poll_once<F: Future>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+    future.poll(&mut Context::from_waker(waker))
+  }
+
+  #[test]
+  fn try_acquire_fails_once_exhausted() {
+    let sema: AsyncSemaphoreN<2> = AsyncSemaphoreN::new(2);
+    assert!(sema.try_acquire().is_ok());
+    assert!(sema.try_acquire().is_ok());
+    assert_eq!(sema.try_acquire(), Err(LockError::WouldBlock));
+    sema.up();
+    assert!(sema.try_acquire().is_ok());
+  }
+
+  #[test]
+  fn waiters_are_woken_in_request_order() {
+    let sema: AsyncSemaphoreN<2> = AsyncSemaphoreN::new(0);
+    let (first_flag, first_waker) = WakeFlag::new();
+    let (second_flag, second_waker) = WakeFlag::new();
+    let mut first = core::pin::pin!(sema.down());
+    let mut second = core::pin::pin!(sema.down());
+    assert!(poll_once(first.as_mut(), &first_waker).is_pending());
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+
+    sema.up();
+    assert!(first_flag.woken());
+    assert!(!second_flag.woken());
+    assert!(poll_once(first.as_mut(), &first_waker).is_ready());
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+    sema.up();
+    assert!(second_flag.woken());
+    assert!(poll_once(second.as_mut(), &second_waker).is_ready());
+  }
+
+  #[test]
+  fn wake_ups_from_an_interrupt_are_deferred() {
+    let sema: AsyncSemaphoreN<2> = AsyncSemaphoreN::new(0);
+    let (flag, waker) = WakeFlag::new();
+    let mut waiter = core::pin::pin!(sema.down());
+    assert!(poll_once(waiter.as_mut(), &waker).is_pending());
+
+    sema.up_from_isr();
+    assert!(!flag.woken());
+    sema.wake_pending();
+    assert!(flag.woken());
+    assert!(poll_once(waiter.as_mut(), &waker).is_ready());
+  }
+
+  #[test]
+  fn dropped_waiter_passes_its_wake_up_on() {
+    let sema: AsyncSemaphoreN<2> = AsyncSemaphoreN::new(0);
+    let (_, first_waker) = WakeFlag::new();
+    let (second_flag, second_waker) = WakeFlag::new();
+    let mut first = Box::pin(sema.down());
+    let mut second = core::pin::pin!(sema.down());
+    assert!(poll_once(first.as_mut(), &first_waker).is_pending());
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+
+    // the first waiter is woken, but cancelled before it decreased the semaphore
+    sema.up();
+    drop(first);
+    assert!(second_flag.woken());
+    assert!(poll_once(second.as_mut(), &second_waker).is_ready());
+  }
+
+  #[test]
+  fn waiters_beyond_the_slots_reschedule_themselves() {
+    let sema: AsyncSemaphoreN<1> = AsyncSemaphoreN::new(0);
+    let (first_flag, first_waker) = WakeFlag::new();
+    let (second_flag, second_waker) = WakeFlag::new();
+    let mut first = core::pin::pin!(sema.down());
+    let mut second = core::pin::pin!(sema.down());
+    assert!(poll_once(first.as_mut(), &first_waker).is_pending());
+    assert!(!first_flag.woken());
+    assert!(poll_once(second.as_mut(), &second_waker).is_pending());
+    assert!(second_flag.woken());
+    sema.up();
+    assert!(first_flag.woken());
+  }
+
+  #[test]
+  fn owned_permit_increases_the_semaphore_once_dropped() {
+    let shared: Shared<AsyncSemaphoreN<2>, 2> = Shared::new(AsyncSemaphoreN::new(1));
+    let (_, waker) = WakeFlag::new();
+    let mut acquire = core::pin::pin!(AsyncSemaphoreN::acquire_owned(shared.handle().unwrap()));
+    let permit = match poll_once(acquire.as_mut(), &waker) {
+      Poll::Ready(permit) => permit,
+      Poll::Pending => panic!("the semaphore is not exhausted"),
+    };
+    let sema = shared.handle().unwrap();
+    assert_eq!(sema.try_acquire(), Err(LockError::WouldBlock));
+    assert_eq!(shared.handles(), 2);
+    drop(permit);
+    assert_eq!(shared.handles(), 1);
+    assert!(sema.try_acquire().is_ok());
+  }
+}
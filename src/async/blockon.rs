@@ -0,0 +1,51 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Block On
+//!
+//! A minimal executor driving a single `Future` to completion on the current core. This allows code that has no
+//! executor available, like the early boot code or a panic handler, to use the async lock APIs. While the `Future` is
+//! pending the core waits for an event (`wfe`). The waker signals an event (`sev`), which wakes the waiting core even
+//! if the `Future` is woken from another core or from an interrupt handler.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::r#async::{block_on, AsyncMutex};
+//!
+//! fn main() {
+//!     let mutex = AsyncMutex::new(10);
+//!     let value = block_on(async {
+//!         let guard = mutex.lock().await;
+//!         **guard
+//!     });
+//!     assert_eq!(value, 10);
+//! }
+//! ```
+
+use super::waker;
+use crate::arch;
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll};
+
+/// Drive the given `Future` to completion on the current core and return its output. While the `Future` is pending
+/// the core waits for an event. As any event, not only the wake up of this `Future`, continues the core the `Future`
+/// might be polled more often than required, which the `Future`s of this crate are fine with.
+///
+/// With the `no_sev` feature no events are signalled and the `Future` is polled in a spin loop instead.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+  let mut future = pin!(future);
+  // the waker signals an event, so it continues this core regardless of the core the wake up is issued from
+  let waker = waker::from_sev();
+  let mut cx = Context::from_waker(&waker);
+  loop {
+    if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+      return output;
+    }
+    arch::wait_for_event();
+  }
+}
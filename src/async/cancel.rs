@@ -0,0 +1,152 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Cancellation Token
+//!
+//! A token that signals the cancellation of pending work to any number of tasks, e.g. to shut them down gracefully.
+//! The async locks provide `*_cancellable` variants of their lock functions that fail with [LockError::Cancelled]
+//! once the token is cancelled while they are waiting for the lock. The waiting `Future` is dropped in this case, so
+//! it is no longer woken by the lock.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::r#async::{AsyncMutex, CancellationToken};
+//! use ruspiro_lock::LockError;
+//!
+//! async fn worker(data: &AsyncMutex<u32>, token: &CancellationToken) -> Result<(), LockError> {
+//!     loop {
+//!         let mut data = data.lock_cancellable(token).await?;
+//!         **data += 1;
+//!     }
+//! }
+//!
+//! fn shutdown(token: &CancellationToken) {
+//!     token.cancel();
+//! }
+//! ```
+
+extern crate alloc;
+
+use crate::sync::Mutex;
+use crate::LockError;
+use alloc::collections::BTreeMap;
+use core::{
+  future::{poll_fn, Future},
+  pin::{pin, Pin},
+  sync::atomic::{AtomicBool, Ordering},
+  task::{Context, Poll, Waker},
+};
+
+/// A token to cancel any number of waiting tasks at once
+pub struct CancellationToken {
+  cancelled: AtomicBool,
+  inner: Mutex<CancellationTokenInner>,
+}
+
+impl CancellationToken {
+  /// Create a new [CancellationToken] that is not cancelled
+  pub fn new() -> Self {
+    Self {
+      cancelled: AtomicBool::new(false),
+      inner: Mutex::new(CancellationTokenInner {
+        waiter: BTreeMap::new(),
+        next_waiter: 0,
+      }),
+    }
+  }
+
+  /// Cancel the token and wake all tasks awaiting the cancellation
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Release);
+    let waiter = core::mem::take(&mut self.inner.lock().waiter);
+    for (_, waker) in waiter {
+      waker.wake();
+    }
+  }
+
+  /// Returns `true` if the token has been cancelled
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Acquire)
+  }
+
+  /// The returned `Future` resolves once the token has been cancelled
+  pub fn cancelled(&self) -> impl Future<Output = ()> + '_ {
+    let mut inner = self.inner.lock();
+    let id = inner.next_waiter;
+    inner.next_waiter += 1;
+    drop(inner);
+
+    CancelledFuture { token: self, id }
+  }
+
+  /// Run the given `Future` until it completes or the token is cancelled. If the token is cancelled first the `Future`
+  /// is dropped and this resolves to [LockError::Cancelled].
+  pub async fn run_until_cancelled<F: Future>(&self, future: F) -> Result<F::Output, LockError> {
+    let mut future = pin!(future);
+    let mut cancelled = pin!(self.cancelled());
+    poll_fn(|cx| {
+      // a cancelled token takes precedence, so no further lock is acquired once the shutdown has been started
+      if cancelled.as_mut().poll(cx).is_ready() {
+        return Poll::Ready(Err(LockError::Cancelled));
+      }
+      future.as_mut().poll(cx).map(Ok)
+    })
+    .await
+  }
+}
+
+impl Default for CancellationToken {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl core::fmt::Debug for CancellationToken {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("CancellationToken")
+      .field("cancelled", &self.is_cancelled())
+      .finish_non_exhaustive()
+  }
+}
+
+/// The `Future` awaiting the cancellation of a [CancellationToken]
+struct CancelledFuture<'a> {
+  token: &'a CancellationToken,
+  id: usize,
+}
+
+impl Future for CancelledFuture<'_> {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    if self.token.is_cancelled() {
+      return Poll::Ready(());
+    }
+
+    let mut inner = self.token.inner.lock();
+    // the token might have been cancelled while the waker is registered, so check once more while holding the lock
+    if self.token.is_cancelled() {
+      return Poll::Ready(());
+    }
+    inner.waiter.insert(self.id, cx.waker().clone());
+    Poll::Pending
+  }
+}
+
+/// If the `Future` is dropped before the token has been cancelled it shall no longer be woken
+impl Drop for CancelledFuture<'_> {
+  fn drop(&mut self) {
+    self.token.inner.lock().waiter.remove(&self.id);
+  }
+}
+
+struct CancellationTokenInner {
+  /// The wakers of the tasks awaiting the cancellation
+  waiter: BTreeMap<usize, Waker>,
+  /// The id of the next `Future` awaiting the cancellation
+  next_waiter: usize,
+}
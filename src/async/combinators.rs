@@ -0,0 +1,131 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Lock Combinators
+//!
+//! Waiting for a lock for a bounded time or for whichever of two locks is released first usually requires a futures
+//! utility crate and a timer of the runtime, which are not available on bare metal. The combinators of this module
+//! express these patterns with the `Future`s of the async locks only:
+//!
+//! - [with_deadline] gives up waiting once a deadline of the given [TimerDriver] has passed.
+//! - [race_lock] acquires whichever of two locks gets available first. The request for the other lock is withdrawn.
+//!
+//! # Example
+//! ```
+//! use core::sync::atomic::{AtomicU64, Ordering};
+//! use core::task::Waker;
+//! use ruspiro_lock::r#async::{block_on, race_lock, with_deadline, AsyncMutex, Either, TimerDriver};
+//! use ruspiro_lock::LockError;
+//!
+//! /// a timer advancing with each look at it, the system timer of the Raspberry Pi would be used instead
+//! struct Ticks(AtomicU64);
+//!
+//! impl TimerDriver for Ticks {
+//!     type Instant = u64;
+//!
+//!     fn now(&self) -> u64 {
+//!         self.0.fetch_add(1, Ordering::Relaxed)
+//!     }
+//!
+//!     fn wake_at(&self, _deadline: u64, waker: &Waker) {
+//!         // a real timer would wake the task from its interrupt handler once the deadline passed
+//!         waker.wake_by_ref();
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let timer = Ticks(AtomicU64::new(0));
+//!     let uart0 = AsyncMutex::new(0u8);
+//!     let uart1 = AsyncMutex::new(1u8);
+//!
+//!     let busy = block_on(uart0.lock());
+//!     // uart0 is busy, so waiting for it times out
+//!     let result = block_on(with_deadline(uart0.lock(), 10, &timer));
+//!     assert!(matches!(result, Err(LockError::TimedOut)));
+//!     // and the free uart1 is taken
+//!     let uart = block_on(race_lock(&uart0, &uart1));
+//!     assert!(matches!(uart, Either::Right(_)));
+//!     drop(busy);
+//! }
+//! ```
+
+use super::AsyncLock;
+use crate::LockError;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::{Poll, Waker};
+
+/// A timer that wakes waiting tasks at a deadline, e.g. backed by the system timer of the Raspberry Pi
+pub trait TimerDriver {
+  /// A point in time
+  type Instant: Copy + Ord;
+
+  /// The current point in time
+  fn now(&self) -> Self::Instant;
+
+  /// Wake the given waker once the deadline has passed. If the deadline has passed already it shall be woken right
+  /// away. This is called each time the waiting `Future` is polled, so it might replace a previous registration of
+  /// the same task.
+  fn wake_at(&self, deadline: Self::Instant, waker: &Waker);
+}
+
+/// One of two values, which is returned by [race] and [race_lock]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+  /// The value of the first `Future` or lock
+  Left(A),
+  /// The value of the second `Future` or lock
+  Right(B),
+}
+
+/// Run the given `Future` until it completes or the deadline of the given timer has passed. If the deadline passes
+/// first the `Future` is dropped, which withdraws the request of a lock `Future`, and this resolves to
+/// [LockError::TimedOut]. A `Future` that completes when the deadline is checked still succeeds.
+pub async fn with_deadline<F: Future, T: TimerDriver>(
+  future: F,
+  deadline: T::Instant,
+  timer: &T,
+) -> Result<F::Output, LockError> {
+  let mut future = pin!(future);
+  poll_fn(|cx| {
+    if let Poll::Ready(output) = future.as_mut().poll(cx) {
+      return Poll::Ready(Ok(output));
+    }
+    if timer.now() >= deadline {
+      return Poll::Ready(Err(LockError::TimedOut));
+    }
+    timer.wake_at(deadline, cx.waker());
+    Poll::Pending
+  })
+  .await
+}
+
+/// Run both `Future`s until one of them completes and return its output. The other `Future` is dropped. If both
+/// complete in the same poll the output of the first one is returned.
+pub async fn race<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+  let mut a = pin!(a);
+  let mut b = pin!(b);
+  poll_fn(|cx| {
+    if let Poll::Ready(output) = a.as_mut().poll(cx) {
+      return Poll::Ready(Either::Left(output));
+    }
+    b.as_mut().poll(cx).map(Either::Right)
+  })
+  .await
+}
+
+/// Lock whichever of the two locks gets available first and return its guard. The request for the other lock is
+/// withdrawn, so it is not kept from other tasks. If both locks are available the first one is locked.
+pub async fn race_lock<'a, TA, TB, A, B>(a: &'a A, b: &'a B) -> Either<A::Guard<'a>, B::Guard<'a>>
+where
+  TA: ?Sized,
+  TB: ?Sized,
+  A: AsyncLock<TA>,
+  B: AsyncLock<TB>,
+{
+  race(a.lock(), b.lock()).await
+}
@@ -0,0 +1,364 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Hybrid Mutex
+//!
+//! A resource that is accessed from blocking code, e.g. the bottom half of an interrupt handler, as well as from async
+//! tasks would otherwise need an [AsyncMutex](super::AsyncMutex) with a blocking [Mutex](crate::sync::Mutex) inside,
+//! where each side keeps track of its own waiters. The [HybridMutex] keeps the lock in a single state word and the
+//! waiters of both sides in one list. [HybridMutex::lock] blocks the core until the lock could be acquired and
+//! [HybridMutex::lock_async] returns a `Future` that resolves once it could be acquired. Both hand out the same
+//! [HybridMutexGuard].
+//!
+//! Releasing the lock wakes the waiter that requested it first, regardless of whether it blocks or `.await`s. The
+//! lock is not handed over to this waiter, though. A core calling [HybridMutex::lock] at the same time or a blocking
+//! waiter retrying after an event might acquire it first. The woken waiter then registers again with its ticket and
+//! stays at the head of the list, but the waiters are not strictly served in the order they requested the lock.
+//!
+//! A blocking waiter registers a waker that signals an event (`sev`) and waits for an event (`wfe`), depending on the
+//! selected [spin policy](crate::sync::spin), until it is woken. The [HybridMutex] does not require `alloc`, so it can
+//! be assigned to a static variable.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::r#async::{block_on, HybridMutex};
+//!
+//! static FIFO: HybridMutex<[u8; 16]> = HybridMutex::new([0; 16]);
+//!
+//! fn interrupt_bottom_half() {
+//!     FIFO.lock()[0] = 0xFF;
+//! }
+//!
+//! async fn drain() -> u8 {
+//!     let fifo = FIFO.lock_async().await;
+//!     fifo[0]
+//! }
+//!
+//! fn main() {
+//!     interrupt_bottom_half();
+//!     assert_eq!(block_on(drain()), 0xFF);
+//! }
+//! ```
+
+use super::waiters::WaiterSlots;
+use super::{trace, waker, AsyncLock};
+use crate::arch;
+use crate::sync::metrics::{self, LockKind};
+use crate::sync::{spin, BlockingLock};
+use core::{
+  cell::UnsafeCell,
+  fmt,
+  future::Future,
+  ops::{Deref, DerefMut},
+  pin::Pin,
+  sync::atomic::{AtomicBool, Ordering},
+  task::{Context, Poll},
+};
+
+/// A mutex that can be locked from blocking code with [HybridMutex::lock] and from async code with
+/// [HybridMutex::lock_async]. Up to `WAITERS` blocking and async waiters are queued and the one that requested the
+/// lock first is woken once it is released. If all waiter slots are occupied additional waiters keep trying until a
+/// slot gets available.
+pub struct HybridMutex<T, const WAITERS: usize = 32> {
+  /// The state of the lock, `true` while it is held
+  locked: AtomicBool,
+  /// The blocking and async waiters of the lock. They are registered and woken with atomic operations only, so the
+  /// async path never spins
+  waiters: WaiterSlots<WAITERS>,
+  data: UnsafeCell<T>,
+}
+
+impl<T, const WAITERS: usize> HybridMutex<T, WAITERS> {
+  /// Create the [HybridMutex]. As this does not require any allocation it can be assigned to a static variable
+  pub const fn new(value: T) -> Self {
+    Self {
+      locked: AtomicBool::new(false),
+      waiters: WaiterSlots::new(),
+      data: UnsafeCell::new(value),
+    }
+  }
+
+  /// Try to lock the data secured by the [HybridMutex] without waiting. Returns `None` if the lock is currently held.
+  pub fn try_lock(&self) -> Option<HybridMutexGuard<'_, T, WAITERS>> {
+    self.try_lock_as(None)
+  }
+
+  /// Try to lock the data on behalf of the given waiter
+  fn try_lock_as(&self, waiter: Option<usize>) -> Option<HybridMutexGuard<'_, T, WAITERS>> {
+    // if the lock is already held a plain read is sufficient to fail, this keeps the cache line shared
+    if self.locked.load(Ordering::Relaxed)
+      || self
+        .locked
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+      return None;
+    }
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    trace::acquired("HybridMutex", trace::lock_id(self), waiter);
+    Some(HybridMutexGuard { mutex: self })
+  }
+
+  /// Lock the data secured by the [HybridMutex]. This blocks the current core until the lock could be acquired. The
+  /// core tries to acquire the lock right away and after each event, so it might acquire it ahead of the async
+  /// waiters that requested it before.
+  pub fn lock(&self) -> HybridMutexGuard<'_, T, WAITERS> {
+    if let Some(guard) = self.try_lock() {
+      metrics::record(LockKind::Mutex, 0);
+      return guard;
+    }
+
+    // queue up with the async waiters, the waker signals the event this core waits for
+    let ticket = self.waiters.next_ticket();
+    let waker = waker::from_sev();
+    let mut attempt = 0;
+    loop {
+      // the registration is renewed after each wake up, as a woken waiter is removed from the list
+      self.waiters.register(ticket, &waker);
+      if let Some(guard) = self.try_lock_as(Some(ticket)) {
+        self.waiters.remove(ticket);
+        metrics::record(LockKind::Mutex, attempt);
+        return guard;
+      }
+      // to save energy and cpu consumption we can wait for an event beeing raised that indicates that the
+      // lock has likely been released, depending on the selected spin policy
+      spin::on_contention(&mut attempt, "HybridMutex::lock");
+    }
+  }
+
+  /// Locking the data secured by the [HybridMutex] will yield a `Future` that must be awaited to actually acquire the
+  /// lock. The `Future` tries to acquire the lock right away and, once it is waiting, each time it is woken. It is
+  /// woken ahead of the waiters that requested the lock later, but it might still lose the lock to a core that tries
+  /// at the same time.
+  pub async fn lock_async(&self) -> HybridMutexGuard<'_, T, WAITERS> {
+    if let Some(guard) = self.try_lock() {
+      guard
+    } else {
+      let ticket = self.waiters.next_ticket();
+      trace::requested("HybridMutex", trace::lock_id(self), ticket);
+      HybridMutexFuture {
+        mutex: self,
+        ticket,
+        done: false,
+      }
+      .await
+    }
+  }
+
+  /// Returns `true` if the [HybridMutex] is currently locked
+  pub fn is_locked(&self) -> bool {
+    self.locked.load(Ordering::Relaxed)
+  }
+
+  /// Consume the [HybridMutex] and return the inner value
+  pub fn into_inner(self) -> T {
+    self.data.into_inner()
+  }
+
+  /// Release the lock, signal this to the blocking waiters and wake the next waiter.
+  ///
+  /// # Safety
+  /// The caller need to own the lock
+  unsafe fn unlock(&self) {
+    self.locked.store(false, Ordering::Release);
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // also raise a signal to indicate the mutex has been changed, so the blocking waiters that could not register
+    // themself continue as well
+    arch::signal_event();
+    trace::released("HybridMutex", trace::lock_id(self));
+    self.waiters.wake_next();
+  }
+}
+
+impl<T: Default, const WAITERS: usize> Default for HybridMutex<T, WAITERS> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+/// The Debug implementation only reports the lock state and never acquires the lock
+impl<T, const WAITERS: usize> fmt::Debug for HybridMutex<T, WAITERS> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("HybridMutex")
+      .field("locked", &self.is_locked())
+      .finish_non_exhaustive()
+  }
+}
+
+impl<T, const WAITERS: usize> BlockingLock<T> for HybridMutex<T, WAITERS> {
+  type Guard<'a>
+    = HybridMutexGuard<'a, T, WAITERS>
+  where
+    Self: 'a;
+
+  fn lock(&self) -> Self::Guard<'_> {
+    HybridMutex::lock(self)
+  }
+
+  fn try_lock(&self) -> Option<Self::Guard<'_>> {
+    HybridMutex::try_lock(self)
+  }
+}
+
+impl<T, const WAITERS: usize> AsyncLock<T> for HybridMutex<T, WAITERS> {
+  type Guard<'a>
+    = HybridMutexGuard<'a, T, WAITERS>
+  where
+    Self: 'a;
+
+  fn lock(&self) -> impl Future<Output = Self::Guard<'_>> {
+    HybridMutex::lock_async(self)
+  }
+}
+
+/// The HybridMutex is always `Sync`, to make it `Send` as well it need to be wrapped into an `Arc`.
+unsafe impl<T: Send, const WAITERS: usize> Sync for HybridMutex<T, WAITERS> {}
+
+/// The guard of a successfully acquired [HybridMutex], regardless of whether it has been acquired blocking or async.
+/// If this goes out of scope the lock is released and the next waiter is woken.
+pub struct HybridMutexGuard<'a, T, const WAITERS: usize = 32> {
+  mutex: &'a HybridMutex<T, WAITERS>,
+}
+
+impl<'a, T, const WAITERS: usize> HybridMutexGuard<'a, T, WAITERS> {
+  /// Returns the [HybridMutex] this guard has been acquired from
+  pub fn mutex(&self) -> &'a HybridMutex<T, WAITERS> {
+    self.mutex
+  }
+}
+
+impl<T, const WAITERS: usize> Drop for HybridMutexGuard<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    // SAFETY: the guard does only exist if the lock is owned
+    unsafe { self.mutex.unlock() };
+  }
+}
+
+// the HybridMutexGuard does only exist if the exclusive access to the data could be ensured, which makes it safe to
+// return immutable and mutable references
+impl<T, const WAITERS: usize> Deref for HybridMutexGuard<'_, T, WAITERS> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    unsafe { &*self.mutex.data.get() }
+  }
+}
+
+impl<T, const WAITERS: usize> DerefMut for HybridMutexGuard<'_, T, WAITERS> {
+  fn deref_mut(&mut self) -> &mut T {
+    unsafe { &mut *self.mutex.data.get() }
+  }
+}
+
+impl<T, const WAITERS: usize> AsRef<T> for HybridMutexGuard<'_, T, WAITERS> {
+  fn as_ref(&self) -> &T {
+    self
+  }
+}
+
+impl<T, const WAITERS: usize> AsMut<T> for HybridMutexGuard<'_, T, WAITERS> {
+  fn as_mut(&mut self) -> &mut T {
+    self
+  }
+}
+
+/// implement debug trait to forward to the type wrapped within the guard
+impl<T: fmt::Debug, const WAITERS: usize> fmt::Debug for HybridMutexGuard<'_, T, WAITERS> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&**self, f)
+  }
+}
+
+// a shared guard only hands out shared references to the data
+unsafe impl<T: Sync, const WAITERS: usize> Sync for HybridMutexGuard<'_, T, WAITERS> {}
+
+/// The `Future` that represents an `await`able [HybridMutex]
+struct HybridMutexFuture<'a, T, const WAITERS: usize> {
+  mutex: &'a HybridMutex<T, WAITERS>,
+  ticket: usize,
+  done: bool,
+}
+
+impl<'a, T, const WAITERS: usize> Future for HybridMutexFuture<'a, T, WAITERS> {
+  type Output = HybridMutexGuard<'a, T, WAITERS>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    let mutex = this.mutex;
+    if let Some(guard) = mutex.try_lock_as(Some(this.ticket)) {
+      this.done = true;
+      return Poll::Ready(guard);
+    }
+
+    let registered = mutex.waiters.register(this.ticket, cx.waker());
+    // the lock might have been released while we registered ourself, so give it another try to not miss the wake up
+    if let Some(guard) = mutex.try_lock_as(Some(this.ticket)) {
+      mutex.waiters.remove(this.ticket);
+      this.done = true;
+      return Poll::Ready(guard);
+    }
+
+    if !registered {
+      // all waiter slots are occupied, so re-schedule ourself to try again
+      cx.waker().wake_by_ref();
+    }
+    Poll::Pending
+  }
+}
+
+impl<T, const WAITERS: usize> Drop for HybridMutexFuture<'_, T, WAITERS> {
+  fn drop(&mut self) {
+    // a future shall not occupy a waiter slot any longer. If it has been woken already while still waiting it has
+    // not used the chance to acquire the lock, so pass this on to the next waiter
+    let waiters = &self.mutex.waiters;
+    if !waiters.remove(self.ticket) && !self.done {
+      waiters.wake_next();
+    }
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  extern crate alloc;
+  use super::*;
+  use crate::r#async::block_on;
+  use alloc::sync::Arc;
+
+  #[test]
+  fn blocking_and_async_waiters_share_the_lock() {
+    let mutex: Arc<HybridMutex<u32, 4>> = Arc::new(HybridMutex::new(0));
+    let guard = mutex.lock();
+
+    let waiters: Vec<_> = (0..4)
+      .map(|waiter| {
+        let mutex = Arc::clone(&mutex);
+        std::thread::spawn(move || {
+          if waiter % 2 == 0 {
+            *mutex.lock() += 1;
+          } else {
+            *block_on(mutex.lock_async()) += 1;
+          }
+        })
+      })
+      .collect();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert!(mutex.try_lock().is_none());
+    drop(guard);
+
+    for waiter in waiters {
+      waiter.join().unwrap();
+    }
+    assert_eq!(*mutex.lock(), 4);
+    assert!(!mutex.is_locked());
+  }
+}
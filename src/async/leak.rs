@@ -0,0 +1,143 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Guard Leak Detection
+//!
+//! A task that holds an async guard across a long chain of `.await`s blocks all other tasks waiting for the lock,
+//! without anything hanging visibly. With the `leak_detection` feature the [AsyncRWLock](super::AsyncRWLock) counts
+//! its outstanding guards and how often waiting `Future`s have been polled without getting the lock since a guard has
+//! been released the last time. Once this exceeds the threshold set with [set_leak_hook] the registered hook is called
+//! with a [GuardLeak] describing the lock. With the `tracing` feature a warning event is emitted as well, whose `id`
+//! matches the lock id of the trace events, so the task that acquired the guard can be found in the trace.
+//!
+//! The detection is meant for debug builds. Without the feature it compiles to nothing.
+//!
+//! # Example
+//! ```
+//! # #[cfg(feature = "leak_detection")]
+//! # mod doc {
+//! use ruspiro_lock::r#async::{set_leak_hook, GuardLeak};
+//!
+//! fn report(leak: &GuardLeak) {
+//!     // eg. print the leak to the debug console
+//!     let _ = (leak.lock, leak.id, leak.guards, leak.polls);
+//! }
+//!
+//! fn init() {
+//!     // report guards held while waiting Futures have been polled more than 100 times
+//!     set_leak_hook(100, report);
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+
+#[cfg(feature = "leak_detection")]
+use crate::sync::AtomicCell;
+#[cfg(feature = "leak_detection")]
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// The description of a lock whose guards are held while waiting `Future`s have been polled more often than the
+/// threshold set with [set_leak_hook]
+#[cfg(feature = "leak_detection")]
+#[derive(Debug, Clone, Copy)]
+pub struct GuardLeak {
+  /// The kind of the lock
+  pub lock: &'static str,
+  /// The id of the lock, it is the same as in the trace events of the lock
+  pub id: usize,
+  /// The number of guards of the lock currently alive
+  pub guards: usize,
+  /// The number of polls of waiting `Future`s since a guard has been released the last time
+  pub polls: u32,
+}
+
+#[cfg(feature = "leak_detection")]
+static THRESHOLD: AtomicU32 = AtomicU32::new(u32::MAX);
+#[cfg(feature = "leak_detection")]
+static HOOK: AtomicCell<Option<fn(&GuardLeak)>> = AtomicCell::new(None);
+
+/// Call the given hook once waiting `Future`s have been polled more often than `threshold` times while the guards of
+/// a lock are held. The hook is called once per lock each time the threshold is exceeded and shall return quickly, as
+/// it runs in the context of the polled `Future`.
+#[cfg(feature = "leak_detection")]
+pub fn set_leak_hook(threshold: u32, hook: fn(&GuardLeak)) {
+  HOOK.store(Some(hook));
+  THRESHOLD.store(threshold, Ordering::Release);
+}
+
+/// Remove the hook registered with [set_leak_hook]
+#[cfg(feature = "leak_detection")]
+pub fn clear_leak_hook() {
+  THRESHOLD.store(u32::MAX, Ordering::Release);
+  HOOK.store(None);
+}
+
+/// The counters of a lock used to detect guards that are held too long
+#[derive(Default)]
+pub(crate) struct LeakDetector {
+  /// The number of guards alive
+  #[cfg(feature = "leak_detection")]
+  guards: AtomicUsize,
+  /// The number of polls of waiting `Future`s since a guard has been released the last time
+  #[cfg(feature = "leak_detection")]
+  polls: AtomicU32,
+}
+
+impl LeakDetector {
+  /// A guard of the lock has been created
+  #[inline(always)]
+  pub(crate) fn acquired(&self) {
+    #[cfg(feature = "leak_detection")]
+    self.guards.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// A guard of the lock has been dropped. Waiting `Future`s can make progress now, so the polls are counted anew.
+  #[inline(always)]
+  pub(crate) fn released(&self) {
+    #[cfg(feature = "leak_detection")]
+    {
+      self.guards.fetch_sub(1, Ordering::Relaxed);
+      self.polls.store(0, Ordering::Relaxed);
+    }
+  }
+
+  /// A waiting `Future` has been polled without getting the lock
+  #[inline(always)]
+  pub(crate) fn pending(&self, lock: &'static str, id: usize) {
+    #[cfg(feature = "leak_detection")]
+    {
+      let guards = self.guards.load(Ordering::Relaxed);
+      if guards == 0 {
+        return;
+      }
+      let threshold = THRESHOLD.load(Ordering::Acquire);
+      let polls = self.polls.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+      // report only the poll exceeding the threshold, so a leak is reported once until a guard is released
+      if threshold == u32::MAX || polls != threshold + 1 {
+        return;
+      }
+      #[cfg(feature = "tracing")]
+      tracing::warn!(target: "ruspiro_lock", lock, id, guards, polls, "lock guard held too long");
+      if let Some(hook) = HOOK.load() {
+        hook(&GuardLeak {
+          lock,
+          id,
+          guards,
+          polls,
+        });
+      }
+    }
+    #[cfg(not(feature = "leak_detection"))]
+    let _ = (lock, id);
+  }
+
+  /// The number of guards alive
+  #[cfg(feature = "leak_detection")]
+  pub(crate) fn guards(&self) -> usize {
+    self.guards.load(Ordering::Relaxed)
+  }
+}
@@ -0,0 +1,134 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Concurrency Limiter
+//!
+//! Limit the number of `Future`s that run at the same time, e.g. the commands queued to a device that can only
+//! process a few of them in parallel. Each `Future` passed to [ConcurrencyLimiter::run] first waits for a permit of an
+//! [AsyncSemaphore] and holds it until it completes or is dropped.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::r#async::ConcurrencyLimiter;
+//!
+//! async fn read_block(block: u32) -> [u8; 512] {
+//!     [0; 512]
+//! }
+//!
+//! async fn read(sdcard: &ConcurrencyLimiter, block: u32) -> [u8; 512] {
+//!     // at most 2 blocks are read at the same time
+//!     sdcard.run(read_block(block)).await
+//! }
+//!
+//! fn main() {
+//!     let sdcard = ConcurrencyLimiter::new(2);
+//!     let _ = read(&sdcard, 0);
+//! }
+//! ```
+
+use super::AsyncSemaphore;
+use core::future::Future;
+
+/// Run at most `max` `Future`s at the same time
+pub struct ConcurrencyLimiter<const WAITERS: usize = 32> {
+  sema: AsyncSemaphore<WAITERS>,
+  max: u32,
+}
+
+impl ConcurrencyLimiter {
+  /// Create the [ConcurrencyLimiter] running at most `max` `Future`s at the same time, with the default number of 32
+  /// waiter slots
+  pub fn new(max: u32) -> Self {
+    Self::with_waiter_slots(max)
+  }
+}
+
+impl<const WAITERS: usize> ConcurrencyLimiter<WAITERS> {
+  /// Create the [ConcurrencyLimiter] running at most `max` `Future`s at the same time, with `WAITERS` waiter slots
+  pub fn with_waiter_slots(max: u32) -> Self {
+    Self {
+      sema: AsyncSemaphore::with_waiter_slots(max),
+      max,
+    }
+  }
+
+  /// Run the given `Future` once less than the maximum number of `Future`s are running. The permit is released once
+  /// the `Future` completes or the returned `Future` is dropped.
+  pub async fn run<F: Future>(&self, future: F) -> F::Output {
+    let _permit = self.sema.acquire(1).await;
+    future.await
+  }
+
+  /// The maximum number of `Future`s running at the same time
+  pub fn max(&self) -> u32 {
+    self.max
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::pin::Pin;
+  use core::sync::atomic::{AtomicBool, Ordering};
+  use core::task::{Context, Poll, Waker};
+  use std::sync::Arc;
+  use std::task::Wake;
+
+  /// A waker recording whether it has been woken
+  struct WakeFlag(AtomicBool);
+
+  impl Wake for WakeFlag {
+    fn wake(self: Arc<Self>) {
+      self.0.store(true, Ordering::SeqCst);
+    }
+  }
+
+  impl WakeFlag {
+    fn new() -> (Arc<Self>, Waker) {
+      let flag = Arc::new(Self(AtomicBool::new(false)));
+      (Arc::clone(&flag), Waker::from(flag))
+    }
+
+    fn woken(&self) -> bool {
+      self.0.swap(false, Ordering::SeqCst)
+    }
+  }
+
+  fn poll_once<F: Future>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+    future.poll(&mut Context::from_waker(waker))
+  }
+
+  #[test]
+  fn completed_futures_release_their_permit() {
+    let limiter: ConcurrencyLimiter<2> = ConcurrencyLimiter::with_waiter_slots(1);
+    assert_eq!(limiter.max(), 1);
+    let (_, waker) = WakeFlag::new();
+    for value in 0..3 {
+      let mut run = core::pin::pin!(limiter.run(async move { value }));
+      assert_eq!(poll_once(run.as_mut(), &waker), Poll::Ready(value));
+    }
+  }
+
+  #[test]
+  fn futures_beyond_the_limit_wait_for_a_permit() {
+    let limiter: ConcurrencyLimiter<2> = ConcurrencyLimiter::with_waiter_slots(2);
+    let (_, waker) = WakeFlag::new();
+    let mut first = Box::pin(limiter.run(core::future::pending::<()>()));
+    let mut second = core::pin::pin!(limiter.run(core::future::pending::<()>()));
+    assert!(poll_once(first.as_mut(), &waker).is_pending());
+    assert!(poll_once(second.as_mut(), &waker).is_pending());
+
+    let (flag, third_waker) = WakeFlag::new();
+    let mut third = core::pin::pin!(limiter.run(async { 3 }));
+    assert!(poll_once(third.as_mut(), &third_waker).is_pending());
+    assert!(!flag.woken());
+    // dropping a running future releases its permit
+    drop(first);
+    assert!(flag.woken());
+    assert_eq!(poll_once(third.as_mut(), &third_waker), Poll::Ready(3));
+  }
+}
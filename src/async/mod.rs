@@ -7,16 +7,95 @@
 
 //! # Async Locking
 //!
+//! The async locks are available in two flavours. With the `async_locks` feature the [AsyncMutex], [AsyncSemaphore]
+//! and [AsyncRWLock] are provided. They require `alloc` to be available when they are created, but keep the wakers of
+//! waiting `Future`s in a fixed number of waiter slots (32 by default), so contended locks do not cause heap churn. The
+//! `async_locks_noalloc` feature provides the [AsyncMutexN] and [AsyncSemaphoreN] with a fixed number of waiter slots,
+//! usable on heap-less systems. The [HybridMutex] can be locked from blocking code and from async code alike and does
+//! not require `alloc` either.
 //!
+//! ## Executor requirements
+//! The `Future`s returned by the lock functions borrow the lock they are created from and are `Send` if the secured
+//! data is `Send`. They can therefore be spawned on a work stealing executor that moves tasks between cores. A waiting
+//! `Future` is woken by the task releasing the lock, which might run on another core, so the executor need to accept
+//! wake ups from any core. A `Future` that is dropped while waiting withdraws its request and passes a received wake up
+//! on to the next waiter.
+//!
+//! The wakers of the waiting `Future`s are registered and woken with atomic operations only. Polling a lock `Future`
+//! therefore never spins on a blocking lock and does not stall the executor, even if other cores access the same lock
+//! at the same time.
+//!
+//! Code without an executor, like the early boot code or a panic handler, can drive a single lock `Future` to
+//! completion with [block_on]. A minimal executor of its own can create the wakers of its tasks with the non allocating
+//! helpers of the [waker] module.
+
+mod trace;
+pub(crate) mod waiters;
+
+mod traits;
+#[doc(inline)]
+pub use traits::*;
+
+pub mod waker;
 
+mod blockon;
+#[doc(inline)]
+pub use blockon::*;
+
+mod combinators;
+#[doc(inline)]
+pub use combinators::*;
+
+#[cfg(any(feature = "async_locks", doc))]
 mod asyncmutex;
+#[cfg(any(feature = "async_locks", doc))]
 #[doc(inline)]
 pub use asyncmutex::*;
 
+#[cfg(any(feature = "async_locks", doc))]
 mod asyncsemaphore;
+#[cfg(any(feature = "async_locks", doc))]
 #[doc(inline)]
 pub use asyncsemaphore::*;
 
+#[cfg(any(feature = "async_locks", doc))]
 mod asyncrwlock;
+#[cfg(any(feature = "async_locks", doc))]
 #[doc(inline)]
 pub use asyncrwlock::*;
+
+#[cfg(any(feature = "async_locks", doc))]
+mod leak;
+#[cfg(feature = "leak_detection")]
+#[doc(inline)]
+pub use leak::*;
+
+#[cfg(any(feature = "async_locks", doc))]
+mod cancel;
+#[cfg(any(feature = "async_locks", doc))]
+#[doc(inline)]
+pub use cancel::*;
+
+#[cfg(any(feature = "async_locks", doc))]
+mod limiter;
+#[cfg(any(feature = "async_locks", doc))]
+#[doc(inline)]
+pub use limiter::*;
+
+#[cfg(feature = "stream")]
+mod mutexstream;
+#[cfg(feature = "stream")]
+#[doc(inline)]
+pub use mutexstream::*;
+
+mod asyncmutexn;
+#[doc(inline)]
+pub use asyncmutexn::*;
+
+mod asyncsemaphoren;
+#[doc(inline)]
+pub use asyncsemaphoren::*;
+
+mod hybridmutex;
+#[doc(inline)]
+pub use hybridmutex::*;
@@ -0,0 +1,139 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Mutex Stream
+//!
+//! A queue of values secured by an [AsyncMutex] that is consumed as a [Stream]. Producers, e.g. interrupt handlers,
+//! push values into the queue and wake the consumer task that is awaiting the next value.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::r#async::MutexStream;
+//!
+//! fn irq_handler(stream: &MutexStream<u8>) {
+//!     // an interrupt handler can not await, so it tries to push the value without blocking
+//!     let _ = stream.try_push(0x42);
+//! }
+//!
+//! async fn consumer(stream: &MutexStream<u8>) {
+//!     // any `StreamExt::next` could be used here as well
+//!     while let Some(value) = core::future::poll_fn(|cx| stream.poll_pop(cx)).await {
+//!         // process the value
+//!     }
+//! }
+//! ```
+
+extern crate alloc;
+
+use super::waiters::WaiterSlots;
+use super::AsyncMutex;
+use alloc::collections::VecDeque;
+use core::{
+  pin::Pin,
+  sync::atomic::{AtomicBool, Ordering},
+  task::{Context, Poll},
+};
+use futures_core::Stream;
+
+/// A stream of values pushed by any number of producers and consumed by a single async consumer. The [Stream] is
+/// implemented for a shared reference to the [MutexStream], so producers and the consumer can share the same instance.
+pub struct MutexStream<T> {
+  queue: AsyncMutex<VecDeque<T>>,
+  /// The waker of the consumer waiting for the next value
+  waker: WaiterSlots<1>,
+  /// Once closed the stream ends after the remaining values have been consumed
+  closed: AtomicBool,
+}
+
+impl<T> MutexStream<T> {
+  /// Create an empty [MutexStream]
+  pub fn new() -> Self {
+    Self {
+      queue: AsyncMutex::new(VecDeque::new()),
+      waker: WaiterSlots::new(),
+      closed: AtomicBool::new(false),
+    }
+  }
+
+  /// Push a value into the stream and wake the consumer. This waits for the queue to become available.
+  pub async fn push(&self, value: T) {
+    self.queue.lock().await.push_back(value);
+    self.wake();
+  }
+
+  /// Try to push a value into the stream without waiting and wake the consumer. This can be used from interrupt
+  /// handlers. If the queue is currently locked the value is given back in the `Err` variant.
+  pub fn try_push(&self, value: T) -> Result<(), T> {
+    match self.queue.try_lock_owned() {
+      Some(mut queue) => {
+        queue.push_back(value);
+        drop(queue);
+        self.wake();
+        Ok(())
+      }
+      None => Err(value),
+    }
+  }
+
+  /// Close the stream. The consumer receives the values that are already queued and the stream ends afterwards.
+  pub fn close(&self) {
+    self.closed.store(true, Ordering::Release);
+    self.wake();
+  }
+
+  /// Returns `true` if the stream has been closed
+  pub fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::Acquire)
+  }
+
+  /// Poll the next value of the stream. This is the same as polling the [Stream] implementation but does not require
+  /// to pin the stream.
+  pub fn poll_pop(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+    if let Some(ready) = self.try_pop() {
+      return ready;
+    }
+
+    self.waker.register(0, cx.waker());
+    // a producer might have pushed a value while the waker has been registered, so check once again before going to
+    // sleep. As producers push the value before they wake the consumer nothing can be missed
+    self.try_pop().unwrap_or(Poll::Pending)
+  }
+
+  /// Pop the next value if the queue could be locked. Returns `None` if the consumer need to wait.
+  fn try_pop(&self) -> Option<Poll<Option<T>>> {
+    // the closed flag need to be read before the queue is checked, otherwise a value pushed right before closing the
+    // stream might get lost
+    let closed = self.is_closed();
+    let mut queue = self.queue.try_lock_owned()?;
+    match queue.pop_front() {
+      Some(value) => Some(Poll::Ready(Some(value))),
+      None if closed => Some(Poll::Ready(None)),
+      None => None,
+    }
+  }
+
+  /// Wake the consumer if it is waiting for the next value
+  fn wake(&self) {
+    // if the waker is currently updated the consumer is about to register itself and will check the queue once more
+    // afterwards, so it does not need to be woken
+    self.waker.wake_next();
+  }
+}
+
+impl<T> Default for MutexStream<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T> Stream for &MutexStream<T> {
+  type Item = T;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+    self.poll_pop(cx)
+  }
+}
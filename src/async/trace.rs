@@ -0,0 +1,50 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Lock Tracing
+//!
+//! With the `tracing` feature the async locks emit [tracing](https://docs.rs/tracing) events with the target
+//! `ruspiro_lock` when a lock is requested, acquired and released. Each event contains the kind of the lock, an `id`
+//! identifying the lock instance and, if the lock had to be waited for, the `waiter` id of the requesting `Future`.
+//! This allows to follow the wait chains of the locks in the trace viewer of the executor. Without the feature the
+//! tracing compiles to nothing.
+
+/// The id of a lock used in the trace events, which is the address of the given part of the lock
+#[inline(always)]
+pub(crate) fn lock_id<T: ?Sized>(lock: &T) -> usize {
+  lock as *const T as *const () as usize
+}
+
+/// A `Future` could not acquire the lock immediately and starts waiting for it
+#[inline(always)]
+pub(crate) fn requested(lock: &'static str, id: usize, waiter: usize) {
+  #[cfg(feature = "tracing")]
+  tracing::trace!(target: "ruspiro_lock", lock, id, waiter, "lock requested");
+  #[cfg(not(feature = "tracing"))]
+  let _ = (lock, id, waiter);
+}
+
+/// The lock has been acquired, either immediately or by the waiting `Future` with the given waiter id
+#[inline(always)]
+pub(crate) fn acquired(lock: &'static str, id: usize, waiter: Option<usize>) {
+  #[cfg(feature = "tracing")]
+  tracing::trace!(target: "ruspiro_lock", lock, id, waiter, "lock acquired");
+  // the acquisitions are accounted by the fairness scenario of the stress tests
+  #[cfg(feature = "stress_tests")]
+  crate::stress::acquired(id);
+  #[cfg(not(feature = "tracing"))]
+  let _ = (lock, id, waiter);
+}
+
+/// The lock has been released
+#[inline(always)]
+pub(crate) fn released(lock: &'static str, id: usize) {
+  #[cfg(feature = "tracing")]
+  tracing::trace!(target: "ruspiro_lock", lock, id, "lock released");
+  #[cfg(not(feature = "tracing"))]
+  let _ = (lock, id);
+}
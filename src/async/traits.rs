@@ -0,0 +1,68 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Async Lock Traits
+//!
+//! Traits abstracting over the async locks, so driver code can be generic over the locking strategy and can be unit
+//! tested with a mock lock. The guards provide access to the secured data with `AsRef` and `AsMut`, as the guards of
+//! the async locks of this crate dereference to the guard of the wrapped blocking lock.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::r#async::{AsyncLock, AsyncMutex};
+//!
+//! struct Uart<L> {
+//!     fifo: L,
+//! }
+//!
+//! impl<L: AsyncLock<[u8; 16]>> Uart<L> {
+//!     async fn clear(&self) {
+//!         let mut fifo = self.fifo.lock().await;
+//!         fifo.as_mut().fill(0);
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let uart = Uart { fifo: AsyncMutex::new([0u8; 16]) };
+//!     let _ = uart.clear();
+//! }
+//! ```
+
+use core::future::Future;
+
+/// A lock providing mutual exclusive access to the secured data once the returned `Future` resolves
+pub trait AsyncLock<T: ?Sized> {
+  /// The guard providing access to the secured data as long as the lock is held
+  type Guard<'a>: AsRef<T> + AsMut<T>
+  where
+    Self: 'a;
+
+  /// Lock the secured data. The returned `Future` resolves once the lock could be acquired.
+  fn lock(&self) -> impl Future<Output = Self::Guard<'_>>;
+}
+
+/// A lock providing shared read access to the secured data once the returned `Future` resolves
+pub trait AsyncReadLock<T: ?Sized> {
+  /// The guard providing read access to the secured data as long as the lock is held
+  type ReadGuard<'a>: AsRef<T>
+  where
+    Self: 'a;
+
+  /// Lock the secured data for read access. The returned `Future` resolves once the lock could be acquired.
+  fn read(&self) -> impl Future<Output = Self::ReadGuard<'_>>;
+}
+
+/// A lock providing mutual exclusive write access to the secured data once the returned `Future` resolves
+pub trait AsyncWriteLock<T: ?Sized> {
+  /// The guard providing write access to the secured data as long as the lock is held
+  type WriteGuard<'a>: AsRef<T> + AsMut<T>
+  where
+    Self: 'a;
+
+  /// Lock the secured data for write access. The returned `Future` resolves once the lock could be acquired.
+  fn write(&self) -> impl Future<Output = Self::WriteGuard<'_>>;
+}
@@ -0,0 +1,222 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Waiter Slots
+//!
+//! Fixed capacity storage of the [Waker]s of `Future`s waiting for an async lock. This does not require any heap
+//! allocation and can therefore be used on heap-less systems.
+//!
+//! The slots are claimed and released with atomic operations only. Registering or waking a waiter never waits for
+//! another core, so polling an async lock does not execute a blocking spin loop that would stall the executor.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+use core::task::Waker;
+
+/// The slot does not contain a [Waker]
+const FREE: usize = 0;
+/// The slot is claimed by a core that writes or takes its [Waker]
+const BUSY: usize = 1;
+/// The slot contains the [Waker] of a waiting `Future`
+const WAITING: usize = 2;
+/// The bits of the slot state holding [FREE], [BUSY] or [WAITING], the upper bits hold the ticket of the waiter
+const STATUS: usize = 0b11;
+/// The position of the ticket in the slot state
+const TICKET_SHIFT: u32 = 2;
+
+/// Array backed list of waiting `Future`s. Each waiter is identified by a ticket that is handed out in increasing
+/// order. Waking the next waiter always wakes the one with the lowest ticket, so waiters are served in the order they
+/// have requested the lock.
+pub(crate) struct WaiterSlots<const N: usize> {
+  slots: [WaiterSlot; N],
+  next_ticket: AtomicUsize,
+  deferred: AtomicUsize,
+}
+
+/// A single waiter slot. The state combines the ticket of the waiter with its status, so a slot can only be claimed
+/// for the ticket it has been registered with. The [Waker] is only accessed by the core that claimed the slot.
+struct WaiterSlot {
+  state: AtomicUsize,
+  waker: UnsafeCell<Option<Waker>>,
+}
+
+impl<const N: usize> WaiterSlots<N> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const EMPTY: WaiterSlot = WaiterSlot {
+    state: AtomicUsize::new(FREE),
+    waker: UnsafeCell::new(None),
+  };
+
+  pub(crate) const fn new() -> Self {
+    Self {
+      slots: [Self::EMPTY; N],
+      next_ticket: AtomicUsize::new(0),
+      deferred: AtomicUsize::new(0),
+    }
+  }
+
+  /// Hand out the next ticket a waiter is identified with
+  pub(crate) fn next_ticket(&self) -> usize {
+    self.next_ticket.fetch_add(1, Ordering::Relaxed) & (usize::MAX >> TICKET_SHIFT)
+  }
+
+  /// Register the [Waker] for the given ticket. If the ticket is already registered the [Waker] will be updated.
+  /// Returns `false` if there is no free slot available to store the [Waker].
+  ///
+  /// The waiter shall check the lock once more after registering. Either this check sees the lock released or the
+  /// core releasing the lock sees the registration in [WaiterSlots::take_next], so no wake up is lost.
+  pub(crate) fn register(&self, ticket: usize, waker: &Waker) -> bool {
+    let registered = self.store(ticket, waker);
+    // pairs with the fence in take_next, so the registration and the release of the lock can not both be missed
+    fence(Ordering::SeqCst);
+    registered
+  }
+
+  /// Store the [Waker] for the given ticket in its current or in a free slot
+  fn store(&self, ticket: usize, waker: &Waker) -> bool {
+    if let Some(slot) = self.claim(ticket) {
+      // SAFETY: the slot has been claimed, so no other core accesses the waker
+      let current = unsafe { &mut *slot.waker.get() };
+      if !matches!(current, Some(current) if current.will_wake(waker)) {
+        *current = Some(waker.clone());
+      }
+      slot.state.store(state(ticket, WAITING), Ordering::Release);
+      return true;
+    }
+
+    for slot in self.slots.iter() {
+      if slot
+        .state
+        .compare_exchange(
+          FREE,
+          state(ticket, BUSY),
+          Ordering::Acquire,
+          Ordering::Relaxed,
+        )
+        .is_ok()
+      {
+        // SAFETY: the slot has been claimed, so no other core accesses the waker
+        unsafe { *slot.waker.get() = Some(waker.clone()) };
+        slot.state.store(state(ticket, WAITING), Ordering::Release);
+        return true;
+      }
+    }
+    false
+  }
+
+  /// Remove the [Waker] registered for the given ticket. Returns `false` if there was no [Waker] registered for this
+  /// ticket, which is the case if the waiter has been woken already.
+  pub(crate) fn remove(&self, ticket: usize) -> bool {
+    match self.claim(ticket) {
+      Some(slot) => {
+        Self::release(slot);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Remove the [Waker] with the lowest ticket from the list and return it. This shall be called after the lock has
+  /// been released.
+  pub(crate) fn take_next(&self) -> Option<Waker> {
+    // pairs with the fence in register, so the registration and the release of the lock can not both be missed
+    fence(Ordering::SeqCst);
+    loop {
+      let (slot, ticket) = self
+        .slots
+        .iter()
+        .map(|slot| (slot, slot.state.load(Ordering::Relaxed)))
+        .filter(|&(_, current)| current & STATUS == WAITING)
+        .map(|(slot, current)| (slot, current >> TICKET_SHIFT))
+        .min_by_key(|&(_, ticket)| ticket)?;
+
+      // another core might have taken or removed this waiter in the meantime, so look for the next one in this case
+      if slot
+        .state
+        .compare_exchange(
+          state(ticket, WAITING),
+          state(ticket, BUSY),
+          Ordering::Acquire,
+          Ordering::Relaxed,
+        )
+        .is_ok()
+      {
+        return Self::release(slot);
+      }
+    }
+  }
+
+  /// Wake the waiter with the lowest ticket
+  pub(crate) fn wake_next(&self) {
+    if let Some(waker) = self.take_next() {
+      waker.wake();
+    }
+  }
+
+  /// Wake the waiter with the given ticket, if its [Waker] is registered. This shall be called after the waiter has
+  /// been handed what it waits for.
+  #[cfg(any(feature = "async_locks", doc))]
+  pub(crate) fn wake(&self, ticket: usize) {
+    // pairs with the fence in register, so the registration and the hand over can not both be missed
+    fence(Ordering::SeqCst);
+    if let Some(waker) = self.claim(ticket).and_then(Self::release) {
+      waker.wake();
+    }
+  }
+
+  /// Defer waking the given number of waiters until [WaiterSlots::wake_deferred] is called. This only updates an
+  /// atomic counter and never runs the code behind a [Waker], so it can be called from an interrupt handler.
+  pub(crate) fn defer_wakes(&self, n: usize) {
+    self.deferred.fetch_add(n, Ordering::Release);
+  }
+
+  /// Wake as many waiters as wake ups have been deferred since the last call
+  pub(crate) fn wake_deferred(&self) {
+    if self.deferred.load(Ordering::Relaxed) == 0 {
+      return;
+    }
+
+    for _ in 0..self.deferred.swap(0, Ordering::Acquire) {
+      match self.take_next() {
+        Some(waker) => waker.wake(),
+        None => break,
+      }
+    }
+  }
+
+  /// Claim the slot the given ticket is registered in. Returns `None` if the ticket is not registered, which is also
+  /// the case while its [Waker] is taken by another core.
+  fn claim(&self, ticket: usize) -> Option<&WaiterSlot> {
+    self.slots.iter().find(|slot| {
+      slot
+        .state
+        .compare_exchange(
+          state(ticket, WAITING),
+          state(ticket, BUSY),
+          Ordering::Acquire,
+          Ordering::Relaxed,
+        )
+        .is_ok()
+    })
+  }
+
+  /// Take the [Waker] out of a claimed slot and free the slot
+  fn release(slot: &WaiterSlot) -> Option<Waker> {
+    // SAFETY: the slot has been claimed, so no other core accesses the waker
+    let waker = unsafe { (*slot.waker.get()).take() };
+    slot.state.store(FREE, Ordering::Release);
+    waker
+  }
+}
+
+/// The state of a slot holding the given ticket with the given status
+const fn state(ticket: usize, status: usize) -> usize {
+  (ticket << TICKET_SHIFT) | status
+}
+
+// the wakers are only accessed by the core that claimed their slot
+unsafe impl<const N: usize> Sync for WaiterSlots<N> {}
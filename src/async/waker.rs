@@ -0,0 +1,82 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Waker
+//!
+//! A minimal executor on bare metal needs a [Waker] to poll the `Future`s of the async locks. Building one usually
+//! requires a `RawWaker` with a vtable, or `alloc` to implement the `Wake` trait. The helpers of this module create
+//! wakers without any data to manage, so they do not allocate and can be cloned and kept by a lock for any time.
+//!
+//! - [from_sev] signals an event (`sev`), which continues all cores waiting for an event (`wfe`), like [block_on].
+//! - [from_fn_static] calls a function, e.g. one that marks a task as ready in a static run queue or raises a
+//!   software generated interrupt.
+//!
+//! [block_on]: super::block_on
+//!
+//! # Example
+//! ```
+//! use core::sync::atomic::{AtomicBool, Ordering};
+//! use ruspiro_lock::r#async::waker;
+//!
+//! static READY: AtomicBool = AtomicBool::new(false);
+//!
+//! fn mark_ready() {
+//!     READY.store(true, Ordering::Release);
+//! }
+//!
+//! fn main() {
+//!     let waker = waker::from_fn_static(mark_ready);
+//!     waker.clone().wake();
+//!     assert!(READY.load(Ordering::Acquire));
+//! }
+//! ```
+
+use crate::arch;
+use core::ptr;
+use core::task::{RawWaker, RawWakerVTable, Waker};
+
+/// The waker does not carry any data, so it can be cloned and kept by the lock for any time
+static SEV_VTABLE: RawWakerVTable = RawWakerVTable::new(sev_clone, sev_wake, sev_wake, noop_drop);
+
+fn sev_clone(_: *const ()) -> RawWaker {
+  RawWaker::new(ptr::null(), &SEV_VTABLE)
+}
+
+fn sev_wake(_: *const ()) {
+  // the event wakes the cores waiting for an event, regardless of the core the wake up is issued from
+  arch::signal_event();
+}
+
+/// The data of the waker is the function to call, which lives for the whole runtime
+static FN_VTABLE: RawWakerVTable = RawWakerVTable::new(fn_clone, fn_wake, fn_wake, noop_drop);
+
+fn fn_clone(data: *const ()) -> RawWaker {
+  RawWaker::new(data, &FN_VTABLE)
+}
+
+fn fn_wake(data: *const ()) {
+  // SAFETY: the data has been created from a `fn()` in `from_fn_static`
+  let wake = unsafe { core::mem::transmute::<*const (), fn()>(data) };
+  wake();
+}
+
+fn noop_drop(_: *const ()) {}
+
+/// Create a [Waker] that signals an event (`sev`) to all cores when woken. A core waiting for an event (`wfe`), e.g.
+/// an executor that has no task to poll, continues and polls its tasks again. With the `no_sev` feature no events are
+/// signalled, so the executor need to poll its tasks in a spin loop.
+pub fn from_sev() -> Waker {
+  // SAFETY: the vtable functions ignore the data pointer, so the waker is valid for any lifetime
+  unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &SEV_VTABLE)) }
+}
+
+/// Create a [Waker] that calls the given function when woken. The function might be called from any core and from
+/// within an interrupt handler that releases a lock, so it shall only do little work, like marking a task as ready.
+pub fn from_fn_static(wake: fn()) -> Waker {
+  // SAFETY: the data pointer is a function pointer that is valid for the whole runtime and only called by the vtable
+  unsafe { Waker::from_raw(RawWaker::new(wake as *const (), &FN_VTABLE)) }
+}
@@ -0,0 +1,66 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Barriers and Events
+//!
+//! The memory barriers and event instructions used by the locks of this crate. Driver crates can use them to place
+//! barriers around device accesses or to build their own waiting loops without embedding assembly, and stay
+//! consistent with the memory model of the locks. On other architectures than Arm the barriers compile to nothing and
+//! waiting for an event compiles to a spin loop hint, so the code can also run in host tests.
+//!
+//! With the `no_sev` feature [send_event] does not signal an event and [wait_for_event] does not wait for one, like
+//! the locks do.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::barrier;
+//! use core::sync::atomic::{AtomicBool, Ordering};
+//!
+//! static READY: AtomicBool = AtomicBool::new(false);
+//!
+//! fn main() {
+//!     // on the producing core
+//!     barrier::data_memory_barrier();
+//!     READY.store(true, Ordering::Release);
+//!     barrier::send_event();
+//!
+//!     // on the consuming core
+//!     while !READY.load(Ordering::Acquire) {
+//!         barrier::wait_for_event();
+//!     }
+//!     barrier::data_memory_barrier();
+//! }
+//! ```
+
+use crate::arch;
+
+/// Data memory barrier (`dmb sy`). Memory accesses before the barrier are observed before the ones after it.
+#[inline(always)]
+pub fn data_memory_barrier() {
+  arch::dmb();
+}
+
+/// Data synchronisation barrier (`dsb sy`). Memory accesses before the barrier are completed before any instruction
+/// after it is executed.
+#[inline(always)]
+pub fn data_sync_barrier() {
+  arch::dsb();
+}
+
+/// Signal an event (`sev`) to all cores to wake them from [wait_for_event]. A data synchronisation barrier is done
+/// upfront, so the updates to memory are visible to the woken cores.
+#[inline(always)]
+pub fn send_event() {
+  arch::signal_event();
+}
+
+/// Wait for an event (`wfe`) signalled with [send_event] or by releasing a lock. The core might also be woken by
+/// other events, so the condition waited for need to be checked again.
+#[inline(always)]
+pub fn wait_for_event() {
+  arch::wait_for_event();
+}
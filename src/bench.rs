@@ -0,0 +1,207 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Benchmarks
+//!
+//! Measures the latency of acquiring and releasing the locking primitives with 1 up to all cores contending for the
+//! same lock. Like the [stress](crate::stress) scenarios the benchmarks are intended to be run on all cores at the same
+//! time. The time is taken with a clock function provided by the caller, eg. reading the cycle counter of the core on
+//! bare metal. The bare-metal kernel in `integration-tests/qemu` runs them with `cargo make qemu-bench`, the host
+//! counterpart using criterion lives in `benches/contention.rs`.
+//!
+//! # Example
+//! ```no_run
+//! use ruspiro_lock::bench;
+//!
+//! fn cycles() -> u64 {
+//!     // read the cycle counter of the current core
+//!     0
+//! }
+//!
+//! fn kernel_main(core: usize) {
+//!     // every core runs the benchmarks
+//!     bench::run(core, 4, 1_000, cycles);
+//!     if core == 0 {
+//!         for measurement in bench::results(4, 1_000) {
+//!             println!("{}", measurement);
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::sync::{Mutex, RWLock, Semaphore, Spinlock};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// The maximum number of cores the benchmarks can be run with
+pub const MAX_CORES: usize = 4;
+
+/// The primitives that are benchmarked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+  Spinlock,
+  Mutex,
+  /// write access to a [RWLock]
+  RWLockWrite,
+  /// read access to a [RWLock]
+  RWLockRead,
+  /// a [Semaphore] with a single permit
+  Semaphore,
+  /// write access to a [RWLock] by the first core while the others read, so the readers pile up behind the writer
+  RWLockMixed,
+}
+
+impl Primitive {
+  /// All primitives in the order they are benchmarked
+  pub const ALL: [Primitive; 6] = [
+    Primitive::Spinlock,
+    Primitive::Mutex,
+    Primitive::RWLockWrite,
+    Primitive::RWLockRead,
+    Primitive::Semaphore,
+    Primitive::RWLockMixed,
+  ];
+
+  /// The name of the primitive
+  pub fn name(&self) -> &'static str {
+    match self {
+      Primitive::Spinlock => "Spinlock",
+      Primitive::Mutex => "Mutex",
+      Primitive::RWLockWrite => "RWLock (write)",
+      Primitive::RWLockRead => "RWLock (read)",
+      Primitive::Semaphore => "Semaphore",
+      Primitive::RWLockMixed => "RWLock (1 writer)",
+    }
+  }
+
+  /// Acquire and release the benchmarked lock once on the given core
+  fn cycle(&self, core: usize) {
+    match self {
+      Primitive::Spinlock => {
+        SPINLOCK.acquire();
+        SPINLOCK.release();
+      }
+      Primitive::Mutex => {
+        *MUTEX.lock() += 1;
+      }
+      Primitive::RWLockWrite => {
+        *RWLOCK.write() += 1;
+      }
+      Primitive::RWLockRead => {
+        let _ = *RWLOCK.read();
+      }
+      Primitive::Semaphore => {
+        SEMAPHORE.down();
+        SEMAPHORE.up();
+      }
+      Primitive::RWLockMixed if core == 0 => {
+        *RWLOCK.write() += 1;
+      }
+      Primitive::RWLockMixed => {
+        let _ = *RWLOCK.read();
+      }
+    }
+  }
+}
+
+/// The result of benchmarking a primitive with a number of contending cores
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+  /// The primitive that has been benchmarked
+  pub primitive: Primitive,
+  /// The number of cores that were contending for the lock
+  pub cores: usize,
+  /// The number of acquire/release cycles done across all cores
+  pub operations: u64,
+  /// The clock ticks the slowest core took for its cycles
+  pub ticks: u64,
+}
+
+impl Measurement {
+  /// The average clock ticks of one acquire/release cycle on each core, this is the latency seen by a single core
+  pub fn ticks_per_op(&self) -> u64 {
+    self.ticks * self.cores as u64 / self.operations.max(1)
+  }
+
+  /// The number of acquire/release cycles across all cores per 1000 clock ticks, this is the throughput of the lock
+  pub fn ops_per_kilotick(&self) -> u64 {
+    self.operations * 1000 / self.ticks.max(1)
+  }
+}
+
+impl fmt::Display for Measurement {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} on {} core(s): {} ticks/op, {} ops/1000 ticks",
+      self.primitive.name(),
+      self.cores,
+      self.ticks_per_op(),
+      self.ops_per_kilotick()
+    )
+  }
+}
+
+/// Run all benchmarks on the calling core. This is expected to be called once on each of the `cores` participating
+/// cores. Each primitive is benchmarked with 1 up to `cores` contending cores doing `iterations` acquire/release
+/// cycles each, while the other cores wait. The function returns once all cores have finished all benchmarks.
+pub fn run(core: usize, cores: usize, iterations: u32, clock: fn() -> u64) {
+  let cores = cores.min(MAX_CORES);
+  for (idx, primitive) in Primitive::ALL.iter().enumerate() {
+    for contending in 1..=cores {
+      sync_cores(cores);
+      if core < contending {
+        let start = clock();
+        for _ in 0..iterations {
+          primitive.cycle(core);
+        }
+        let ticks = clock().wrapping_sub(start);
+        TICKS[idx][contending - 1].fetch_max(ticks, Ordering::SeqCst);
+      }
+    }
+  }
+  sync_cores(cores);
+}
+
+/// Provide the measurements of all benchmarks once [run] has returned on all cores.
+pub fn results(cores: usize, iterations: u32) -> impl Iterator<Item = Measurement> {
+  let cores = cores.min(MAX_CORES);
+  Primitive::ALL
+    .iter()
+    .enumerate()
+    .flat_map(move |(idx, &primitive)| {
+      (1..=cores).map(move |contending| Measurement {
+        primitive,
+        cores: contending,
+        operations: contending as u64 * iterations as u64,
+        ticks: TICKS[idx][contending - 1].load(Ordering::SeqCst),
+      })
+    })
+}
+
+/// Wait until all cores have reached this point
+fn sync_cores(cores: usize) {
+  let generation = ARRIVED.fetch_add(1, Ordering::SeqCst) / cores + 1;
+  while ARRIVED.load(Ordering::SeqCst) < generation * cores {
+    core::hint::spin_loop();
+  }
+}
+
+static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const TICKS_INIT: AtomicU64 = AtomicU64::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const PRIMITIVE_INIT: [AtomicU64; MAX_CORES] = [TICKS_INIT; MAX_CORES];
+/// The ticks of the slowest core per primitive and number of contending cores
+static TICKS: [[AtomicU64; MAX_CORES]; Primitive::ALL.len()] =
+  [PRIMITIVE_INIT; Primitive::ALL.len()];
+
+static SPINLOCK: Spinlock = Spinlock::new();
+static MUTEX: Mutex<u64> = Mutex::new(0);
+static RWLOCK: RWLock<u64> = RWLock::new(0);
+static SEMAPHORE: Semaphore = Semaphore::new(1);
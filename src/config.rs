@@ -0,0 +1,110 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Core Configuration
+//!
+//! The primitives that keep per core state, like the [CriticalSection](crate::sync::CriticalSection) nesting or the
+//! locks tracked for `panic_release_all`, need to know the number of cores and the id of the current core. By default
+//! [MAX_SUPPORTED_CORES] cores are assumed and the core id is read from the affinity level 0 of the `MPIDR_EL1`
+//! register. Systems with a different core topology, or host tests running on threads, call [configure] once at boot
+//! before any core uses a core aware primitive.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::{configure, CoreConfig};
+//!
+//! fn core_id() -> usize {
+//!     // e.g. read the cluster and core affinity levels of MPIDR_EL1
+//!     0
+//! }
+//!
+//! fn main() {
+//!     configure(CoreConfig {
+//!         max_cores: 2,
+//!         core_id_fn: core_id,
+//!     });
+//!     assert_eq!(ruspiro_lock::config::max_cores(), 2);
+//! }
+//! ```
+
+use crate::arch;
+use crate::sync::AtomicCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The maximum number of cores the per core state of the primitives is reserved for
+pub const MAX_SUPPORTED_CORES: usize = arch::MAX_CORES;
+
+/// The core topology used by the core aware primitives
+#[derive(Debug, Clone, Copy)]
+pub struct CoreConfig {
+  /// The number of cores using the primitives, at most [MAX_SUPPORTED_CORES]
+  pub max_cores: usize,
+  /// Returns the id of the calling core, which need to be in the range `0..max_cores`. Ids outside of this range are
+  /// not folded into it, as two cores would share their per core state then, but let [core_id] panic.
+  pub core_id_fn: fn() -> usize,
+}
+
+impl CoreConfig {
+  /// The configuration used if [configure] is not called
+  pub const DEFAULT: CoreConfig = CoreConfig {
+    max_cores: MAX_SUPPORTED_CORES,
+    core_id_fn: arch::mpidr_core_id,
+  };
+}
+
+impl Default for CoreConfig {
+  fn default() -> Self {
+    Self::DEFAULT
+  }
+}
+
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+static MAX_CORES: AtomicUsize = AtomicUsize::new(MAX_SUPPORTED_CORES);
+static CORE_ID_FN: AtomicCell<fn() -> usize> = AtomicCell::new(arch::mpidr_core_id);
+
+/// Configure the core topology used by the core aware primitives. This shall be called once at boot before the
+/// primitives are used by more than one core. The `core_id_fn` need to return ids in the range `0..max_cores` for all
+/// cores using the primitives.
+///
+/// # Panics
+/// Panics if called more than once or if `max_cores` is `0` or exceeds [MAX_SUPPORTED_CORES].
+pub fn configure(config: CoreConfig) {
+  assert!(
+    config.max_cores > 0 && config.max_cores <= MAX_SUPPORTED_CORES,
+    "max_cores need to be in the range 1..={}",
+    MAX_SUPPORTED_CORES
+  );
+  assert!(
+    !CONFIGURED.swap(true, Ordering::AcqRel),
+    "the cores have been configured already"
+  );
+  MAX_CORES.store(config.max_cores, Ordering::Release);
+  CORE_ID_FN.store(config.core_id_fn);
+  // the configuration need to be visible to all cores before they use the primitives
+  arch::dmb();
+}
+
+/// The number of cores using the primitives
+pub fn max_cores() -> usize {
+  MAX_CORES.load(Ordering::Acquire)
+}
+
+/// The id of the calling core as provided by the configured [CoreConfig::core_id_fn]. It is always in the range
+/// `0..max_cores()`.
+///
+/// # Panics
+/// Panics if the configured [CoreConfig::core_id_fn] returns an id of `max_cores()` or above.
+#[inline]
+pub fn core_id() -> usize {
+  let id = (CORE_ID_FN.load())();
+  assert!(
+    id < max_cores(),
+    "the core id {} exceeds the configured number of cores",
+    id
+  );
+  id
+}
@@ -0,0 +1,90 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Lock Errors
+//!
+//! The error returned by the fallible lock operations. It implements [core::error::Error], so it can be wrapped into
+//! the errors of a driver crate and passed on with `?` without `std`.
+//!
+//! # Example
+//! ```
+//! use core::error::Error;
+//! use core::fmt;
+//! use ruspiro_lock::{sync::Semaphore, LockError};
+//!
+//! #[derive(Debug)]
+//! enum DriverError {
+//!     Busy(LockError),
+//! }
+//!
+//! impl fmt::Display for DriverError {
+//!     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+//!         f.write_str("the device is busy")
+//!     }
+//! }
+//!
+//! impl Error for DriverError {
+//!     fn source(&self) -> Option<&(dyn Error + 'static)> {
+//!         match self {
+//!             DriverError::Busy(error) => Some(error),
+//!         }
+//!     }
+//! }
+//!
+//! impl From<LockError> for DriverError {
+//!     fn from(error: LockError) -> Self {
+//!         DriverError::Busy(error)
+//!     }
+//! }
+//!
+//! static FIFO: Semaphore = Semaphore::new(0);
+//!
+//! fn send() -> Result<(), DriverError> {
+//!     FIFO.try_acquire()?;
+//!     Ok(())
+//! }
+//!
+//! fn main() {
+//!     let error = send().unwrap_err();
+//!     assert_eq!(error.source().unwrap().to_string(), LockError::WouldBlock.to_string());
+//! }
+//! ```
+
+use core::fmt;
+
+/// The reason why a lock operation failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LockError {
+  /// The lock is currently not available and the operation would need to block or wait
+  WouldBlock,
+  /// The lock has been closed and will never become available again
+  Closed,
+  /// The lock could not be acquired within the given time
+  TimedOut,
+  /// The lock holder panicked, so the secured data might be in an inconsistent state
+  Poisoned,
+  /// Waiting for the lock has been cancelled
+  Cancelled,
+  /// The current core already holds the lock, so waiting for it would never end
+  Deadlock,
+}
+
+impl fmt::Display for LockError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LockError::WouldBlock => f.write_str("the lock is not available without blocking"),
+      LockError::Closed => f.write_str("the lock has been closed"),
+      LockError::TimedOut => f.write_str("the lock could not be acquired in time"),
+      LockError::Poisoned => f.write_str("the lock has been poisoned"),
+      LockError::Cancelled => f.write_str("waiting for the lock has been cancelled"),
+      LockError::Deadlock => f.write_str("waiting for the lock would deadlock"),
+    }
+  }
+}
+
+impl core::error::Error for LockError {}
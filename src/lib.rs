@@ -6,6 +6,8 @@
  **********************************************************************************************************************/
 #![doc(html_root_url = "https://docs.rs/ruspiro-lock/||VERSION||")]
 #![cfg_attr(not(any(test, doctest)), no_std)]
+#![cfg_attr(feature = "must_not_suspend", feature(must_not_suspend))]
+#![cfg_attr(testing, feature(cfg_sanitize))]
 
 //! # Atomic locks for Raspberry Pi baremetal systems
 //!
@@ -15,13 +17,30 @@
 //!
 //! ## Usage Hint:
 //! As the locks depend on low level atomics they do only work on the Raspberry Pi if the MMU is properly configured.
-//! Otherwise using either of the lock functions will hang the core it has been used on.
+//! Otherwise using either of the lock functions will hang the core it has been used on. The `stall_detection` feature
+//! turns this hang into a panic.
 //!
 //! ## Features
 //!
 //! Feature | Usage
 //! --------|--------
-//! async_locks | allows usage of the `async` lock versions.
+//! alloc | provides the lock types that require `alloc`, like the `AtomicArc` and the `Rcu`.
+//! async_locks | allows usage of the `async` lock versions. Requires `alloc` and enables the `alloc` feature.
+//! async_locks_noalloc | allows usage of the `async` lock versions with a fixed number of waiter slots that do not require `alloc`.
+//! stream | implements the `futures_core::Stream` trait for the `MutexStream` and the `PermitStream`. Enables the `async_locks` feature.
+//! stress_tests | provides the multi core contention scenarios used by the QEMU based integration tests and, with `async_locks`, the fairness scenario of the async locks.
+//! benchmarks | provides the multi core latency benchmarks of the primitives used by the QEMU based bench kernel.
+//! no_sev | waiting cores spin instead of using `wfe`/`sev`. This avoids trapped `sev` instructions when running as a guest of a hypervisor (e.g. at EL1 below EL2) at the cost of a higher power consumption while waiting.
+//! stall_detection | a blocking call that does not succeed after a huge number of attempts calls the handler set with `sync::spin::set_stall_handler`, which panics by default. Meant for the bring-up of a board, where a misconfigured MMU lets the locks hang silently.
+//! reentrancy_detection | each core tracks the `RWLock`s it holds, so in debug builds a blocking call of a `RWLock` panics if the core already holds a lock of it that would block the call forever, e.g. a read lock requested while holding the write lock.
+//! panic_release | each core tracks the `Spinlock`s and `Mutex`es it holds, so a panic handler can release them with `panic_release_all`.
+//! must_not_suspend | marks the blocking lock guards with `#[must_not_suspend]`, so crates enabling the nightly `must_not_suspend` lint are warned if a guard is held across an `.await` point.
+//! unsend_guards | the blocking lock guards are not `Send`, so holding one across an `.await` point of a task that need to be `Send` is rejected by the compiler. The guards of the async locks remain `Send`.
+//! mocks | provides the `MockMutex` and `MockSemaphore` test doubles with scripted contention, to unit test the contention handling of driver crates.
+//! std | implements the `BlockingLock` and `BlockingRwLock` traits for the `std::sync` locks. Requires a target providing `std`.
+//! tracing | the async locks emit `tracing` events when a lock is requested, acquired and released.
+//! metrics | each blocking acquisition records its spins into a histogram of its lock kind, read with `sync::metrics::spins`, and the most spins into a watermark read with `sync::metrics::max_spins`.
+//! leak_detection | the `AsyncRWLock` counts its guards and calls the hook set with `set_leak_hook` if they are held while waiting `Future`s are polled more often than a threshold. Meant for debug builds, enables the `async_locks` feature.
 //!
 //!
 //! To share those locking primitives accross the Rasperry Pi cores they should be wrapped in an `Arc`.
@@ -34,8 +53,8 @@
 //!
 //! fn main() {
 //!     let spin = Spinlock::new();
-//!     spin.aquire();
-//!     // following code is only executed if the lock could be aquired, the executing core pause till then
+//!     spin.acquire();
+//!     // following code is only executed if the lock could be acquired, the executing core pause till then
 //!     let _ = 10 + 3;
 //!     spin.release();
 //! }
@@ -47,7 +66,7 @@
 //!
 //! fn main() {
 //!     let sema  = Semaphore::new(1);
-//!     if sema.try_down().is_ok() {
+//!     if sema.try_acquire().is_ok() {
 //!         // we gained access to the semaphore, do something
 //!         let _ = 20 /4;
 //!         sema.up();
@@ -78,9 +97,37 @@
 //! }
 //! ```
 
+mod arch;
+mod macros;
+
+// re-export the error type of the fallible lock operations
+mod error;
+pub use error::*;
+
+// the memory barriers and event instructions used by the locks
+pub mod barrier;
+
+// re-export the configuration of the core topology
+pub mod config;
+pub use config::{configure, CoreConfig};
+
 // re-export the sync lock types, always at root level and witin the sync module
 pub mod sync;
 pub use sync::*;
 
-#[cfg(any(feature = "async_locks", doc))]
+#[cfg(any(feature = "async_locks", feature = "async_locks_noalloc", doc))]
 pub mod r#async;
+
+#[cfg(any(feature = "stress_tests", doc))]
+pub mod stress;
+
+#[cfg(any(feature = "benchmarks", doc))]
+pub mod bench;
+
+#[cfg(any(feature = "mocks", doc))]
+pub mod testing;
+
+// the sanitizer cfg requires a nightly feature, so it is only evaluated while testing
+#[cfg(testing)]
+#[cfg_attr(testing, cfg(any(miri, sanitize = "thread")))]
+mod model_tests;
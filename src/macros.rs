@@ -0,0 +1,108 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Macros
+//!
+
+/// Generate methods for a struct with several [Mutex](crate::sync::Mutex) and [RWLock](crate::sync::RWLock) fields
+/// that acquire a subset of those fields at once and return a combined guard struct.
+///
+/// The locks are always acquired in the order of their memory address, independent of the order they are listed in
+/// the macro. So all methods generated with this macro acquire the locks in the same order and can not deadlock each
+/// other.
+///
+/// Each field is given with the access mode and the type of the data secured by the lock:
+/// - `lock` for a [Mutex](crate::sync::Mutex), providing a [MutexGuard](crate::sync::MutexGuard)
+/// - `write` for a [RWLock](crate::sync::RWLock), providing a [WriteLockGuard](crate::sync::WriteLockGuard)
+/// - `read` for a [RWLock](crate::sync::RWLock), providing a [ReadLockGuard](crate::sync::ReadLockGuard)
+///
+/// # Example
+/// ```
+/// use ruspiro_lock::lock_fields;
+/// use ruspiro_lock::sync::{Mutex, RWLock};
+///
+/// struct Board {
+///     uart: Mutex<u32>,
+///     gpio: RWLock<u64>,
+///     config: RWLock<bool>,
+/// }
+///
+/// lock_fields! {
+///     impl Board {
+///         /// Acquire the uart and the gpio for update while reading the config
+///         pub fn lock_io -> IoGuard {
+///             uart: lock u32,
+///             gpio: write u64,
+///             config: read bool,
+///         }
+///     }
+/// }
+///
+/// fn main() {
+///     let board = Board {
+///         uart: Mutex::new(0),
+///         gpio: RWLock::new(0),
+///         config: RWLock::new(true),
+///     };
+///
+///     let mut io = board.lock_io();
+///     if *io.config {
+///         *io.uart = 10;
+///         *io.gpio = 20;
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! lock_fields {
+  (
+    impl $target:ty {
+      $(
+        $(#[$meta:meta])*
+        $vis:vis fn $method:ident -> $guard:ident {
+          $( $field:ident : $mode:ident $data:ty ),+ $(,)?
+        }
+      )+
+    }
+  ) => {
+    $(
+      #[doc = concat!("Combined guards acquired with `", stringify!($method), "`")]
+      #[allow(dead_code)]
+      $vis struct $guard<'a> {
+        $( pub $field: $crate::lock_fields!(@guard $mode, 'a, $data), )+
+      }
+
+      impl $target {
+        $(#[$meta])*
+        $vis fn $method(&self) -> $guard<'_> {
+          // acquire the locks in the order of their addresses to guarantee a deterministic order
+          let mut addresses = [ $( &self.$field as *const _ as *const () as usize ),+ ];
+          addresses.sort_unstable();
+          $( let mut $field = None; )+
+          for address in addresses.iter() {
+            $(
+              if $field.is_none() && *address == &self.$field as *const _ as *const () as usize {
+                $field = Some($crate::lock_fields!(@acquire self.$field, $mode));
+              }
+            )+
+          }
+
+          $guard {
+            $( $field: $field.unwrap(), )+
+          }
+        }
+      }
+    )+
+  };
+
+  (@guard lock, $lt:lifetime, $data:ty) => { $crate::sync::MutexGuard<$lt, $data> };
+  (@guard write, $lt:lifetime, $data:ty) => { $crate::sync::WriteLockGuard<$lt, $data> };
+  (@guard read, $lt:lifetime, $data:ty) => { $crate::sync::ReadLockGuard<$lt, $data> };
+
+  (@acquire $lock:expr, lock) => { $lock.lock() };
+  (@acquire $lock:expr, write) => { $lock.write() };
+  (@acquire $lock:expr, read) => { $lock.read() };
+}
@@ -0,0 +1,608 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Model Tests
+//!
+//! The cores of the Raspberry Pi are weakly ordered. A write of one core becomes visible to another core only in the
+//! order the atomic operations and barriers establish, so each primitive relies on the following assumptions:
+//!
+//! - A lock is acquired with an atomic operation of at least `Acquire` ordering and released with one of at least
+//!   `Release` ordering. All accesses of the holder to the secured data therefore happen before the accesses of the
+//!   next holder. This applies to the `Spinlock`, `Mutex`, `SpinMutex`, `RWLock` and the slots of the `LockPool`.
+//! - A `Semaphore` publishes the data written before `up` to the core whose `down` takes the released count.
+//! - A `Flag` publishes the data written before `set` to all cores that see the flag set.
+//! - The `AtomicCell` accesses a value as atomic integer or atomic pointer of the same size. Values of pointer size,
+//!   like the function pointers of the hooks, are accessed as pointer, so they keep their provenance.
+//! - The `AtomicArc` keeps the previous value alive until all loads that might still take a reference to it finished.
+//! - The `Rcu` releases a retired version only once each core passed a quiescent point since or does not read at all.
+//!
+//! On ARM the locks additionally place a `dmb` after acquiring and before releasing them. This orders the accesses to
+//! memory mapped peripherals, that are not covered by the memory model of Rust. The `dmb` is no replacement for the
+//! orderings above, as the compiler is free to reorder the memory accesses across it otherwise.
+//!
+//! The tests in this module check these assumptions against the memory model of Rust by racing plain, unsynchronized
+//! data through each primitive. They are meant to run under Miri, which reports every data race and undefined
+//! behavior and explores weak memory effects, or with the ThreadSanitizer on the host:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg testing" cargo +nightly miri test --lib --target x86_64-unknown-linux-gnu --features async_locks
+//! RUSTFLAGS="--cfg testing -Zsanitizer=thread" \
+//!   cargo +nightly test --lib -Zbuild-std --target x86_64-unknown-linux-gnu --features async_locks
+//! ```
+//!
+//! The number of iterations is kept small, as Miri executes the tests a lot slower than the host.
+
+use crate::sync::*;
+use core::cell::UnsafeCell;
+use std::thread;
+
+/// The number of threads racing through a primitive
+const THREADS: usize = 3;
+/// The number of accesses of each thread
+const ITERATIONS: usize = 20;
+
+/// Data without any synchronization of its own. Each access races unless the primitive under test orders it.
+struct Unsynchronized<T>(UnsafeCell<T>);
+
+// the primitive under test is responsible to order the accesses
+unsafe impl<T: Send> Sync for Unsynchronized<T> {}
+
+impl<T> Unsynchronized<T> {
+  const fn new(value: T) -> Self {
+    Self(UnsafeCell::new(value))
+  }
+
+  /// # Safety
+  /// The caller need to ensure no other thread accesses the data at the same time
+  #[allow(clippy::mut_from_ref)]
+  unsafe fn get(&self) -> &mut T {
+    &mut *self.0.get()
+  }
+
+  /// Read the data without creating a mutable reference, so several threads can read at the same time
+  ///
+  /// # Safety
+  /// The caller need to ensure no other thread writes the data at the same time
+  unsafe fn read(&self) -> T
+  where
+    T: Copy,
+  {
+    *self.0.get()
+  }
+}
+
+std::thread_local! {
+  /// The id of the core a thread acts as, `0` unless the thread sets it
+  static CORE_ID: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+/// Configure the cores to take their id from [CORE_ID], so each thread can act as a different core. The
+/// configuration is shared by all tests, as it can only be set once.
+fn configure_thread_cores() {
+  use crate::{configure, CoreConfig};
+  static CONFIGURED: std::sync::Once = std::sync::Once::new();
+
+  fn core_id() -> usize {
+    CORE_ID.with(|id| id.get())
+  }
+  CONFIGURED.call_once(|| {
+    configure(CoreConfig {
+      max_cores: THREADS + 1,
+      core_id_fn: core_id,
+    })
+  });
+}
+
+/// Data written by the holder of a `Spinlock` is visible to the next holder
+#[test]
+fn spinlock_orders_the_secured_data() {
+  let lock = Spinlock::new();
+  let counter = Unsynchronized::new(0);
+  thread::scope(|s| {
+    for _ in 0..THREADS {
+      s.spawn(|| {
+        for _ in 0..ITERATIONS {
+          lock.acquire();
+          unsafe { *counter.get() += 1 };
+          lock.release();
+        }
+      });
+    }
+  });
+  assert_eq!(unsafe { *counter.get() }, THREADS * ITERATIONS);
+}
+
+/// The guards of `Mutex` and `SpinMutex` order the accesses to the secured data
+#[test]
+fn mutex_guards_order_the_secured_data() {
+  let mutex = Mutex::new(0);
+  let spin_mutex = SpinMutex::new(0);
+  thread::scope(|s| {
+    for _ in 0..THREADS {
+      s.spawn(|| {
+        for _ in 0..ITERATIONS {
+          *mutex.lock() += 1;
+          *spin_mutex.lock() += 1;
+        }
+      });
+    }
+  });
+  assert_eq!(mutex.into_inner(), THREADS * ITERATIONS);
+  assert_eq!(spin_mutex.into_inner(), THREADS * ITERATIONS);
+}
+
+/// Readers of a `RWLock` never see a partial update of a writer
+#[test]
+fn rwlock_readers_never_see_partial_writes() {
+  let rwlock = RWLock::new([0_usize; 4]);
+  thread::scope(|s| {
+    s.spawn(|| {
+      for i in 1..=ITERATIONS {
+        let mut data = rwlock.write();
+        for value in data.iter_mut() {
+          *value = i;
+        }
+      }
+    });
+    for _ in 0..THREADS {
+      s.spawn(|| {
+        for _ in 0..ITERATIONS {
+          let data = rwlock.read();
+          assert!(data.iter().all(|value| *value == data[0]));
+        }
+      });
+    }
+  });
+  assert_eq!(rwlock.into_inner(), [ITERATIONS; 4]);
+}
+
+/// Data written before `up` is visible after the matching `down`
+#[test]
+fn semaphore_publishes_data_written_before_up() {
+  let sema = Semaphore::new(0);
+  let message = Unsynchronized::new([0_usize; 4]);
+  thread::scope(|s| {
+    s.spawn(|| {
+      unsafe { *message.get() = [1, 2, 3, 4] };
+      sema.up();
+    });
+    s.spawn(|| {
+      sema.down();
+      assert_eq!(unsafe { *message.get() }, [1, 2, 3, 4]);
+    });
+  });
+}
+
+/// Data written before `Flag::set` is visible to the blocking and to the async waiters
+#[test]
+fn flag_publishes_data_written_before_set() {
+  let flag: Flag = Flag::new();
+  let message = Unsynchronized::new([0_usize; 4]);
+  thread::scope(|s| {
+    s.spawn(|| {
+      flag.wait_set();
+      assert_eq!(unsafe { message.read() }, [1, 2, 3, 4]);
+    });
+    #[cfg(feature = "async_locks")]
+    s.spawn(|| {
+      park_on(flag.wait_set_async());
+      assert_eq!(unsafe { message.read() }, [1, 2, 3, 4]);
+    });
+    s.spawn(|| {
+      unsafe { *message.get() = [1, 2, 3, 4] };
+      flag.set();
+    });
+  });
+}
+
+/// A `BinarySemaphore` given by several threads at the same time holds a single permit only
+#[test]
+fn binary_semaphore_clamps_at_one_permit() {
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  let sema = BinarySemaphore::new(false);
+  let given = AtomicUsize::new(0);
+  thread::scope(|s| {
+    for _ in 0..THREADS {
+      s.spawn(|| {
+        if sema.give() {
+          given.fetch_add(1, Ordering::Relaxed);
+        }
+      });
+    }
+  });
+  assert_eq!(given.load(Ordering::Relaxed), 1);
+  sema.take();
+  assert!(sema.try_take().is_err());
+}
+
+/// Permits released while another thread drains and resets the semaphore are never lost or counted twice
+#[test]
+fn semaphore_drain_and_set_permits_do_not_race_with_up() {
+  let sema = Semaphore::new(0);
+  let mut drained = 0;
+  thread::scope(|s| {
+    s.spawn(|| {
+      for _ in 0..ITERATIONS {
+        sema.up();
+      }
+    });
+    for _ in 0..ITERATIONS {
+      drained += sema.drain();
+    }
+  });
+  drained += sema.set_permits(0);
+  assert_eq!(drained, ITERATIONS as u32);
+}
+
+/// A function pointer stored in an `AtomicCell` can be called by another thread
+#[test]
+fn atomiccell_keeps_the_provenance_of_pointers() {
+  fn answer() -> usize {
+    42
+  }
+  static VALUE: usize = 7;
+
+  let hook: AtomicCell<fn() -> usize> = AtomicCell::new(|| 0);
+  let reference: AtomicCell<&'static usize> = AtomicCell::new(&0);
+  thread::scope(|s| {
+    s.spawn(|| {
+      hook.store(answer);
+      reference.store(&VALUE);
+    });
+    s.spawn(|| {
+      let _ = (hook.load())();
+      let _ = *reference.load();
+    });
+  });
+  assert_eq!((hook.load())(), 42);
+  assert_eq!(*reference.load(), 7);
+}
+
+/// Messages posted to a `Mailbox` are taken completely and in order
+#[test]
+fn mailbox_hands_over_messages_in_order() {
+  let mailbox: Mailbox<[usize; 2], 4> = Mailbox::new();
+  thread::scope(|s| {
+    s.spawn(|| {
+      for i in 0..ITERATIONS {
+        while mailbox.post([i, i]).is_err() {
+          thread::yield_now();
+        }
+      }
+    });
+    s.spawn(|| {
+      for i in 0..ITERATIONS {
+        let message = loop {
+          match mailbox.take() {
+            Some(message) => break message,
+            None => thread::yield_now(),
+          }
+        };
+        assert_eq!(message, [i, i]);
+      }
+    });
+  });
+}
+
+/// Entries locked through the same handle of a `LockPool` are accessed exclusively
+#[test]
+fn lockpool_orders_the_entries_of_a_slot() {
+  let pool: LockPool<2> = LockPool::new();
+  let entries = [(); 4].map(|_| Unsynchronized::new(0_usize));
+  thread::scope(|s| {
+    for _ in 0..THREADS {
+      s.spawn(|| {
+        for i in 0..ITERATIONS {
+          let entry = i % 4;
+          let _guard = pool.lock(pool.handle(entry % 2));
+          unsafe { *entries[entry].get() += 1 };
+        }
+      });
+    }
+  });
+  let total: usize = entries.iter().map(|entry| unsafe { *entry.get() }).sum();
+  assert_eq!(total, THREADS * ITERATIONS);
+}
+
+/// An `Exclusive` initialized by several threads at the same time runs only one initialization, that is visible to all
+#[test]
+fn exclusive_is_initialized_exactly_once() {
+  let exclusive: Exclusive<[usize; 4]> = Exclusive::new_uninit();
+  let initialized = thread::scope(|s| {
+    let threads: std::vec::Vec<_> = (0..THREADS)
+      .map(|i| {
+        let exclusive = &exclusive;
+        s.spawn(move || {
+          let initialized = exclusive.init_once(|| [i; 4]);
+          exclusive.with(|value| assert!(value.iter().all(|v| *v == value[0])));
+          initialized
+        })
+      })
+      .collect();
+    threads
+      .into_iter()
+      .map(|thread| thread.join().unwrap())
+      .filter(|initialized| *initialized)
+      .count()
+  });
+  assert_eq!(initialized, 1);
+}
+
+/// Closures shipped to the owner of a `CoreLocal` by other threads run on the owner and return their results
+#[test]
+fn corelocal_runs_the_closures_of_other_cores_on_the_owner() {
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  configure_thread_cores();
+
+  let local: CoreLocal<usize, 2> = CoreLocal::new(0, 0);
+  let finished = AtomicUsize::new(0);
+  thread::scope(|s| {
+    for core in 1..=THREADS {
+      let local = &local;
+      let finished = &finished;
+      s.spawn(move || {
+        CORE_ID.with(|id| id.set(core));
+        for _ in 0..ITERATIONS {
+          let before = local.call_on_owner(|value| {
+            *value += 1;
+            *value - 1
+          });
+          assert!(before < THREADS * ITERATIONS);
+        }
+        finished.fetch_add(1, Ordering::Release);
+      });
+    }
+    // the owner serves the closures until all other cores are done
+    while finished.load(Ordering::Acquire) < THREADS {
+      local.process();
+      thread::yield_now();
+    }
+  });
+  assert_eq!(local.call_on_owner(|value| *value), THREADS * ITERATIONS);
+}
+
+/// Mutations appended to a `Batched` by several threads while another one flushes are applied exactly once and in the
+/// order each thread appended them
+#[test]
+fn batched_applies_the_mutations_of_all_threads_in_order() {
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  fn apply(next: &mut [usize; THREADS], (thread, i): (usize, usize)) {
+    assert_eq!(next[thread], i);
+    next[thread] += 1;
+  }
+
+  let batched: Batched<[usize; THREADS], (usize, usize), 4> = Batched::new([0; THREADS], apply);
+  let finished = AtomicUsize::new(0);
+  thread::scope(|s| {
+    for thread in 0..THREADS {
+      let batched = &batched;
+      let finished = &finished;
+      s.spawn(move || {
+        for i in 0..ITERATIONS {
+          let mut mutation = (thread, i);
+          while let Err(rejected) = batched.push(mutation) {
+            mutation = rejected;
+            batched.try_flush();
+            thread::yield_now();
+          }
+        }
+        finished.fetch_add(1, Ordering::Release);
+      });
+    }
+    while finished.load(Ordering::Acquire) < THREADS {
+      batched.flush();
+      thread::yield_now();
+    }
+  });
+  assert_eq!(*batched.lock(), [ITERATIONS; THREADS]);
+}
+
+/// Values recorded into an `AtomicHistogram` by several threads at the same time are all counted
+#[test]
+fn histogram_counts_the_values_of_all_threads() {
+  let histogram: AtomicHistogram<4> = AtomicHistogram::new();
+  thread::scope(|s| {
+    for _ in 0..THREADS {
+      s.spawn(|| {
+        for i in 0..ITERATIONS {
+          histogram.record(i as u64);
+        }
+      });
+    }
+  });
+  assert_eq!(histogram.total(), (THREADS * ITERATIONS) as u64);
+  assert_eq!(histogram.count(0), THREADS as u64);
+}
+
+/// Handles of a `Shared` cloned and dropped by several threads never exceed the limit and are all counted
+#[test]
+fn shared_counts_the_handles_of_all_threads() {
+  let shared: Shared<usize, 2> = Shared::new(7);
+  thread::scope(|s| {
+    for _ in 0..THREADS {
+      s.spawn(|| {
+        for _ in 0..ITERATIONS {
+          if let Some(handle) = shared.handle() {
+            assert!(SharedHandle::count(&handle) <= 2);
+            assert_eq!(*handle, 7);
+          }
+        }
+      });
+    }
+  });
+  assert_eq!(shared.handles(), 0);
+}
+
+/// The value replaced in an `AtomicArc` is not released while another thread loads it
+#[cfg(feature = "alloc")]
+#[test]
+fn atomicarc_keeps_loaded_values_alive() {
+  extern crate alloc;
+  use alloc::sync::Arc;
+
+  let cell = AtomicArc::new(Arc::new([0_usize; 4]));
+  thread::scope(|s| {
+    s.spawn(|| {
+      for i in 1..=ITERATIONS {
+        cell.store(Arc::new([i; 4]));
+      }
+    });
+    s.spawn(|| {
+      for _ in 0..ITERATIONS {
+        let value = cell.load();
+        assert!(value.iter().all(|v| *v == value[0]));
+      }
+    });
+  });
+  assert_eq!(*cell.into_inner(), [ITERATIONS; 4]);
+}
+
+/// The guards of the `AsyncMutex` order the accesses to the secured data
+/// A version retired by an update of an `Rcu` is only released once no thread reads it any more
+#[cfg(feature = "alloc")]
+#[test]
+fn rcu_releases_versions_only_after_all_readers_finished() {
+  configure_thread_cores();
+
+  let rcu = Rcu::new((0, 0));
+  thread::scope(|s| {
+    for core in 1..=THREADS {
+      let rcu = &rcu;
+      s.spawn(move || {
+        CORE_ID.with(|id| id.set(core));
+        for _ in 0..ITERATIONS {
+          let version = rcu.read();
+          // give the updates the chance to retire the version while it is read
+          thread::yield_now();
+          assert_eq!(version.1, version.0 * 2);
+          drop(version);
+          rcu.quiescent();
+        }
+      });
+    }
+    for _ in 0..ITERATIONS {
+      rcu.update(|&(value, _)| (value + 1, (value + 1) * 2));
+      rcu.quiescent();
+      thread::yield_now();
+    }
+  });
+  // no thread reads any more, so all retired versions are released
+  rcu.quiescent();
+  assert_eq!(rcu.retired(), 0);
+  assert_eq!(*rcu.read(), (ITERATIONS, ITERATIONS * 2));
+}
+
+#[cfg(feature = "async_locks")]
+#[test]
+fn async_mutex_orders_the_secured_data() {
+  use crate::r#async::{block_on, AsyncMutex};
+
+  let mutex = AsyncMutex::new(0);
+  thread::scope(|s| {
+    for _ in 0..THREADS {
+      s.spawn(|| {
+        for _ in 0..ITERATIONS {
+          block_on(async { **mutex.lock().await += 1 });
+        }
+      });
+    }
+  });
+  assert_eq!(
+    block_on(async { **mutex.lock().await }),
+    THREADS * ITERATIONS
+  );
+}
+
+/// Drive the `Future` to completion, parking the thread until it is woken. A lost wake up leaves the thread parked.
+#[cfg(feature = "async_locks")]
+fn park_on<F: core::future::Future>(future: F) -> F::Output {
+  extern crate alloc;
+  use alloc::sync::Arc;
+  use core::task::{Context, Poll, Waker};
+
+  struct Unpark(thread::Thread);
+
+  impl alloc::task::Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+      self.0.unpark();
+    }
+  }
+
+  let waker = Waker::from(Arc::new(Unpark(thread::current())));
+  let mut cx = Context::from_waker(&waker);
+  let mut future = core::pin::pin!(future);
+  loop {
+    if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+      return output;
+    }
+    thread::park();
+  }
+}
+
+/// Waiters registered while the lock is released are not missed, even with more waiters than waiter slots
+#[cfg(feature = "async_locks")]
+#[test]
+fn async_locks_do_not_lose_wake_ups() {
+  use crate::r#async::{AsyncMutex, AsyncRWLock, AsyncSemaphore};
+
+  let mutex: AsyncMutex<usize, 2> = AsyncMutex::with_waiter_slots(0);
+  let rwlock: AsyncRWLock<usize, 2> = AsyncRWLock::with_waiter_slots(0);
+  let sema: AsyncSemaphore<2> = AsyncSemaphore::with_waiter_slots(1);
+  thread::scope(|s| {
+    for _ in 0..THREADS {
+      s.spawn(|| {
+        for i in 0..ITERATIONS {
+          park_on(async {
+            **mutex.lock().await += 1;
+            if i % 2 == 0 {
+              **rwlock.write().await += 1;
+            } else {
+              let _ = **rwlock.read().await;
+            }
+            let _permit = sema.acquire(1).await;
+          });
+        }
+      });
+    }
+  });
+  assert_eq!(
+    park_on(async { **mutex.lock().await }),
+    THREADS * ITERATIONS
+  );
+}
+
+/// The wake up deferred by `up_from_isr` reaches the waiter once the executor passes it on
+#[cfg(feature = "async_locks")]
+#[test]
+fn async_semaphore_passes_on_deferred_wake_ups() {
+  use crate::r#async::AsyncSemaphore;
+  use core::sync::atomic::{AtomicBool, Ordering};
+
+  let sema = AsyncSemaphore::new(0);
+  let message = Unsynchronized::new(0_usize);
+  let done = AtomicBool::new(false);
+  thread::scope(|s| {
+    s.spawn(|| {
+      park_on(sema.acquire(1)).forget();
+      assert_eq!(unsafe { *message.get() }, 42);
+      done.store(true, Ordering::Release);
+    });
+    s.spawn(|| {
+      // the interrupt handler
+      unsafe { *message.get() = 42 };
+      sema.up_from_isr();
+      // the idle loop of the executor
+      while !done.load(Ordering::Acquire) {
+        sema.wake_pending();
+        thread::yield_now();
+      }
+    });
+  });
+}
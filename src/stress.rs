@@ -0,0 +1,561 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Stress Tests
+//!
+//! Contention scenarios for the locking primitives that are intended to be run on all cores at the same time. Each
+//! scenario hammers one primitive and verifies its guarantees with invariant counters:
+//! - a plain (non atomic) counter is incremented while holding the lock. Its final value must match the number of
+//!   increments done across all cores
+//! - an [Occupancy] tracks how many cores are inside the protected section at the same time
+//!
+//! The scenarios are used by the bare-metal kernel in `integration-tests/qemu` that runs them on 4 emulated cores
+//! with `qemu-system-aarch64` to validate the primitives on the actual memory model. They can be used on the host with
+//! threads as well.
+//!
+//! With the `async_locks` feature [async_fairness] runs hundreds of tasks contending for the async locks on a single
+//! core and checks that no task starves.
+//!
+//! # Example
+//! ```no_run
+//! use ruspiro_lock::stress;
+//!
+//! fn kernel_main(core: usize) {
+//!     // every core runs the scenarios
+//!     stress::run(core, 4, 10_000);
+//!     if core == 0 {
+//!         for report in stress::reports(4, 10_000).iter() {
+//!             assert!(report.is_ok());
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::sync::{Mutex, RWLock, Semaphore, Spinlock};
+use core::cell::UnsafeCell;
+use core::fmt;
+#[cfg(feature = "async_locks")]
+use core::future::Future;
+#[cfg(feature = "async_locks")]
+use core::pin::Pin;
+#[cfg(feature = "async_locks")]
+use core::sync::atomic::AtomicBool;
+#[cfg(feature = "async_locks_noalloc")]
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+#[cfg(feature = "async_locks")]
+use core::task::{Context, Poll, Waker};
+
+/// The number of permits of the semaphore scenario
+pub const SEMAPHORE_PERMITS: u32 = 2;
+
+/// Tracks how many cores are within a protected section at the same time and counts any violation of the exclusion
+/// guarantees of the lock protecting this section.
+pub struct Occupancy {
+  exclusive: AtomicU32,
+  shared: AtomicU32,
+  violations: AtomicU32,
+}
+
+impl Occupancy {
+  /// Create a new empty [Occupancy]
+  pub const fn new() -> Self {
+    Self {
+      exclusive: AtomicU32::new(0),
+      shared: AtomicU32::new(0),
+      violations: AtomicU32::new(0),
+    }
+  }
+
+  /// Enter the section with exclusive access. Nobody else is allowed to be inside.
+  pub fn enter_exclusive(&self) {
+    if self.exclusive.fetch_add(1, Ordering::SeqCst) != 0 || self.shared.load(Ordering::SeqCst) != 0
+    {
+      self.violations.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  /// Leave the section entered with [Occupancy::enter_exclusive]
+  pub fn leave_exclusive(&self) {
+    self.exclusive.fetch_sub(1, Ordering::SeqCst);
+  }
+
+  /// Enter the section with shared access. Up to `limit` cores are allowed to be inside at the same time but nobody
+  /// with exclusive access.
+  pub fn enter_shared(&self, limit: u32) {
+    if self.shared.fetch_add(1, Ordering::SeqCst) >= limit
+      || self.exclusive.load(Ordering::SeqCst) != 0
+    {
+      self.violations.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  /// Leave the section entered with [Occupancy::enter_shared]
+  pub fn leave_shared(&self) {
+    self.shared.fetch_sub(1, Ordering::SeqCst);
+  }
+
+  /// The number of violations detected so far
+  pub fn violations(&self) -> u32 {
+    self.violations.load(Ordering::SeqCst)
+  }
+}
+
+impl Default for Occupancy {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// The result of a stress test scenario
+#[derive(Debug, Clone, Copy)]
+pub struct Report {
+  /// The primitive the scenario has been stressing
+  pub primitive: &'static str,
+  /// The expected value of the invariant counter
+  pub expected: u64,
+  /// The actual value of the invariant counter
+  pub counted: u64,
+  /// The number of exclusion violations detected
+  pub violations: u32,
+}
+
+impl Report {
+  /// Returns `true` if the scenario has passed
+  pub fn is_ok(&self) -> bool {
+    self.expected == self.counted && self.violations == 0
+  }
+}
+
+impl fmt::Display for Report {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}: {} (counted {} of {}, {} violations)",
+      self.primitive,
+      if self.is_ok() { "PASS" } else { "FAIL" },
+      self.counted,
+      self.expected,
+      self.violations
+    )
+  }
+}
+
+/// Run all stress test scenarios on the calling core. This is expected to be called once on each of the `cores`
+/// participating cores. The scenarios are run one after another and all cores wait for each other before the next
+/// scenario starts. The function returns once all cores have finished all scenarios.
+pub fn run(core: usize, cores: usize, iterations: u32) {
+  sync_cores(cores);
+  spinlock_scenario(iterations);
+  sync_cores(cores);
+  mutex_scenario(iterations);
+  sync_cores(cores);
+  rwlock_scenario(core, iterations);
+  sync_cores(cores);
+  semaphore_scenario(iterations);
+  sync_cores(cores);
+}
+
+/// Provide the reports of all scenarios once [run] has returned on all cores.
+pub fn reports(cores: usize, iterations: u32) -> [Report; 4] {
+  let expected = cores as u64 * iterations as u64;
+  [
+    Report {
+      primitive: "Spinlock",
+      expected,
+      counted: SPIN_COUNTER.get(),
+      violations: SPIN_OCCUPANCY.violations(),
+    },
+    Report {
+      primitive: "Mutex",
+      expected,
+      counted: *MUTEX.lock(),
+      violations: MUTEX_OCCUPANCY.violations(),
+    },
+    Report {
+      primitive: "RWLock",
+      // only every second iteration is a write access
+      expected: (0..cores)
+        .map(|core| (iterations as u64 + (core & 1 == 0) as u64) / 2)
+        .sum(),
+      counted: *RWLOCK.read(),
+      violations: RWLOCK_OCCUPANCY.violations(),
+    },
+    Report {
+      primitive: "Semaphore",
+      expected,
+      counted: SEMA_COUNTER.load(Ordering::SeqCst) as u64,
+      violations: SEMA_OCCUPANCY.violations(),
+    },
+  ]
+}
+
+fn spinlock_scenario(iterations: u32) {
+  for _ in 0..iterations {
+    SPINLOCK.acquire();
+    SPIN_OCCUPANCY.enter_exclusive();
+    // SAFETY: the counter is only accessed while holding the spinlock
+    unsafe { SPIN_COUNTER.increment() };
+    SPIN_OCCUPANCY.leave_exclusive();
+    SPINLOCK.release();
+  }
+}
+
+fn mutex_scenario(iterations: u32) {
+  for _ in 0..iterations {
+    let mut value = MUTEX.lock();
+    MUTEX_OCCUPANCY.enter_exclusive();
+    *value += 1;
+    MUTEX_OCCUPANCY.leave_exclusive();
+  }
+}
+
+fn rwlock_scenario(core: usize, iterations: u32) {
+  for i in 0..iterations {
+    // the cores alternate between read and write access with an offset, so there are readers and writers at the
+    // same time
+    if (i as usize + core) & 1 == 0 {
+      let mut value = RWLOCK.write();
+      RWLOCK_OCCUPANCY.enter_exclusive();
+      *value += 1;
+      RWLOCK_OCCUPANCY.leave_exclusive();
+    } else {
+      let value = RWLOCK.read();
+      RWLOCK_OCCUPANCY.enter_shared(u32::MAX);
+      let _ = *value;
+      RWLOCK_OCCUPANCY.leave_shared();
+    }
+  }
+}
+
+fn semaphore_scenario(iterations: u32) {
+  for _ in 0..iterations {
+    SEMAPHORE.down();
+    SEMA_OCCUPANCY.enter_shared(SEMAPHORE_PERMITS);
+    SEMA_COUNTER.fetch_add(1, Ordering::SeqCst);
+    SEMA_OCCUPANCY.leave_shared();
+    SEMAPHORE.up();
+  }
+}
+
+/// Wait until all cores have reached this point
+fn sync_cores(cores: usize) {
+  let generation = ARRIVED.fetch_add(1, Ordering::SeqCst) / cores + 1;
+  while ARRIVED.load(Ordering::SeqCst) < generation * cores {
+    core::hint::spin_loop();
+  }
+}
+
+/// A plain counter that is only safe to be incremented while holding a lock
+struct PlainCounter(UnsafeCell<u64>);
+
+impl PlainCounter {
+  const fn new() -> Self {
+    Self(UnsafeCell::new(0))
+  }
+
+  /// # Safety
+  /// The caller need to ensure exclusive access to the counter
+  unsafe fn increment(&self) {
+    let value = core::ptr::read_volatile(self.0.get());
+    core::ptr::write_volatile(self.0.get(), value + 1);
+  }
+
+  fn get(&self) -> u64 {
+    unsafe { core::ptr::read_volatile(self.0.get()) }
+  }
+}
+
+// the counter is only accessed while holding the lock of the scenario
+unsafe impl Sync for PlainCounter {}
+
+static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+
+static SPINLOCK: Spinlock = Spinlock::new();
+static SPIN_COUNTER: PlainCounter = PlainCounter::new();
+static SPIN_OCCUPANCY: Occupancy = Occupancy::new();
+
+static MUTEX: Mutex<u64> = Mutex::new(0);
+static MUTEX_OCCUPANCY: Occupancy = Occupancy::new();
+
+static RWLOCK: RWLock<u64> = RWLock::new(0);
+static RWLOCK_OCCUPANCY: Occupancy = Occupancy::new();
+
+static SEMAPHORE: Semaphore = Semaphore::new(SEMAPHORE_PERMITS);
+static SEMA_COUNTER: AtomicU32 = AtomicU32::new(0);
+static SEMA_OCCUPANCY: Occupancy = Occupancy::new();
+
+/// The id of the async lock whose acquisitions are counted by the [async_fairness] scenario
+#[cfg(feature = "async_locks_noalloc")]
+static ACCOUNTED_LOCK: AtomicUsize = AtomicUsize::new(0);
+/// The number of acquisitions of the accounted async lock
+#[cfg(feature = "async_locks_noalloc")]
+static ACCOUNTED_ACQUISITIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Account the acquisition of the async lock with the given id. This is called by the async locks each time they are
+/// acquired, but only the lock run by the [async_fairness] scenario is counted.
+#[cfg(feature = "async_locks_noalloc")]
+#[inline(always)]
+pub(crate) fn acquired(id: usize) {
+  if ACCOUNTED_LOCK.load(Ordering::Relaxed) == id {
+    ACCOUNTED_ACQUISITIONS.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+/// The result of an [async_fairness] scenario
+#[cfg(feature = "async_locks")]
+#[derive(Debug, Clone, Copy)]
+pub struct FairnessReport {
+  /// The primitive the scenario has been running
+  pub primitive: &'static str,
+  /// The number of tasks contending for the lock
+  pub tasks: usize,
+  /// The least number of acquisitions of a single task
+  pub min_acquisitions: u32,
+  /// The largest number of acquisitions of a single task
+  pub max_acquisitions: u32,
+  /// The largest number of acquisitions by other tasks while a single task has been waiting for the lock
+  pub max_overtaken: u64,
+  /// The least number of acquisitions of a single task allowed by the starvation bound of the primitive
+  pub min_acquisitions_bound: u32,
+  /// The largest number of acquisitions overtaking a waiting task allowed by the starvation bound of the primitive
+  pub max_overtaken_bound: u64,
+}
+
+#[cfg(feature = "async_locks")]
+impl FairnessReport {
+  /// Returns `true` if the scenario stayed within the starvation bounds of the primitive
+  pub fn is_ok(&self) -> bool {
+    self.min_acquisitions >= self.min_acquisitions_bound
+      && self.max_overtaken <= self.max_overtaken_bound
+  }
+}
+
+#[cfg(feature = "async_locks")]
+impl fmt::Display for FairnessReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}: {} ({} tasks, {} to {} acquisitions per task of at least {}, overtaken at most {} times of {})",
+      self.primitive,
+      if self.is_ok() { "PASS" } else { "FAIL" },
+      self.tasks,
+      self.min_acquisitions,
+      self.max_acquisitions,
+      self.min_acquisitions_bound,
+      self.max_overtaken,
+      self.max_overtaken_bound
+    )
+  }
+}
+
+/// Run `tasks` tasks on the calling core that hammer an [AsyncMutex](crate::async::AsyncMutex) and an
+/// [AsyncRWLock](crate::async::AsyncRWLock), until each lock has been acquired `tasks * rounds` times. The tasks
+/// hold the lock across a poll, so the other tasks queue up behind it, and are run by a round robin executor. The
+/// async locks account each of their acquisitions, so each task measures how many acquisitions of other tasks
+/// overtook its request.
+///
+/// The async locks wake their waiters in the order they started waiting, but a woken task competes with the tasks
+/// polled before it and with the tasks that found all waiter slots occupied. The starvation bounds checked by
+/// [FairnessReport::is_ok] are:
+/// - `AsyncMutex`: each task acquires the lock at least `rounds` times and a waiting task is overtaken by at most one
+///   acquisition of each other task
+/// - `AsyncRWLock`: the tasks alternate between read and write access. Readers share the lock, so they overtake a
+///   waiting writer as a group. Each task acquires the lock at least `rounds / 2` times and a waiting task is
+///   overtaken by at most three acquisitions of each other task
+#[cfg(feature = "async_locks")]
+pub fn async_fairness(tasks: usize, rounds: u32) -> [FairnessReport; 2] {
+  use crate::r#async::{AsyncMutex, AsyncRWLock};
+
+  let mutex = AsyncMutex::new(());
+  ACCOUNTED_LOCK.store(mutex.lock_id(), Ordering::Relaxed);
+  let mutex_report = fairness_scenario("AsyncMutex", tasks, rounds, |_| async {
+    let _guard = mutex.lock().await;
+    let acquired = ACCOUNTED_ACQUISITIONS.load(Ordering::Relaxed);
+    YieldNow(false).await;
+    acquired
+  });
+
+  let rwlock = AsyncRWLock::new(());
+  ACCOUNTED_LOCK.store(rwlock.lock_id(), Ordering::Relaxed);
+  let rwlock_report = fairness_scenario("AsyncRWLock", tasks, rounds, |turn| {
+    let rwlock = &rwlock;
+    async move {
+      // the tasks alternate between read and write access with an offset, so there are readers and writers at the
+      // same time
+      let (_read, _write) = if turn & 1 == 0 {
+        (None, Some(rwlock.write().await))
+      } else {
+        (Some(rwlock.read().await), None)
+      };
+      let acquired = ACCOUNTED_ACQUISITIONS.load(Ordering::Relaxed);
+      YieldNow(false).await;
+      acquired
+    }
+  });
+  ACCOUNTED_LOCK.store(0, Ordering::Relaxed);
+
+  let others = tasks.saturating_sub(1) as u64;
+  [
+    FairnessReport {
+      min_acquisitions_bound: rounds,
+      max_overtaken_bound: others,
+      ..mutex_report
+    },
+    FairnessReport {
+      min_acquisitions_bound: rounds / 2,
+      max_overtaken_bound: 3 * others,
+      ..rwlock_report
+    },
+  ]
+}
+
+/// Run the tasks of an [async_fairness] scenario. Each task repeatedly calls `acquire` with its turn, that is its
+/// index plus the number of its acquisitions so far, until the lock has been acquired `tasks * rounds` times. The
+/// future returned by `acquire` holds the lock across a poll and resolves to the number of accounted acquisitions at
+/// the time it acquired the lock.
+#[cfg(feature = "async_locks")]
+fn fairness_scenario<'a, F, Fut>(
+  primitive: &'static str,
+  tasks: usize,
+  rounds: u32,
+  acquire: F,
+) -> FairnessReport
+where
+  F: Fn(usize) -> Fut + 'a,
+  Fut: Future<Output = u64> + 'a,
+{
+  extern crate alloc;
+  use alloc::{boxed::Box, sync::Arc, task::Wake, vec::Vec};
+
+  /// The waker of a task marks it to be polled in the next round of the executor
+  struct TaskWaker(AtomicBool);
+
+  impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+      self.0.store(true, Ordering::Relaxed);
+    }
+  }
+
+  /// A task resolving to its number of acquisitions and the most acquisitions that overtook it
+  type Task<'a> = Pin<Box<dyn Future<Output = (u32, u64)> + 'a>>;
+
+  let target = tasks as u64 * rounds as u64;
+  let total = &AtomicU64::new(0);
+  let acquire = &acquire;
+  let mut futures: Vec<Task<'_>> = (0..tasks)
+    .map(|task| -> Task<'_> {
+      Box::pin(async move {
+        let mut acquisitions = 0_u32;
+        let mut max_overtaken = 0;
+        while total.load(Ordering::Relaxed) < target {
+          let requested = ACCOUNTED_ACQUISITIONS.load(Ordering::Relaxed);
+          let acquired = acquire(task + acquisitions as usize).await;
+          max_overtaken = max_overtaken.max(acquired - requested - 1);
+          acquisitions += 1;
+          total.fetch_add(1, Ordering::Relaxed);
+          // give the other tasks the chance to request the lock before this task requests it again
+          YieldNow(false).await;
+        }
+        (acquisitions, max_overtaken)
+      })
+    })
+    .collect();
+  let wakers: Vec<_> = (0..tasks)
+    .map(|_| Arc::new(TaskWaker(AtomicBool::new(true))))
+    .collect();
+  let mut results: Vec<Option<(u32, u64)>> = (0..tasks).map(|_| None).collect();
+
+  // poll the woken tasks round robin until all of them are done
+  while results.iter().any(Option::is_none) {
+    let mut polled = false;
+    for task in 0..tasks {
+      if results[task].is_some() || !wakers[task].0.swap(false, Ordering::Relaxed) {
+        continue;
+      }
+      polled = true;
+      let waker = Waker::from(Arc::clone(&wakers[task]));
+      if let Poll::Ready(result) = futures[task]
+        .as_mut()
+        .poll(&mut Context::from_waker(&waker))
+      {
+        results[task] = Some(result);
+      }
+    }
+    assert!(
+      polled,
+      "the {} scenario stalled without a woken task",
+      primitive
+    );
+  }
+
+  let results = results.iter().flatten();
+  FairnessReport {
+    primitive,
+    tasks,
+    min_acquisitions: results
+      .clone()
+      .map(|(acquisitions, _)| *acquisitions)
+      .min()
+      .unwrap_or(0),
+    max_acquisitions: results
+      .clone()
+      .map(|(acquisitions, _)| *acquisitions)
+      .max()
+      .unwrap_or(0),
+    max_overtaken: results.map(|(_, overtaken)| *overtaken).max().unwrap_or(0),
+    min_acquisitions_bound: 0,
+    max_overtaken_bound: u64::MAX,
+  }
+}
+
+/// A `Future` that is pending once and wakes itself, so the executor polls the other tasks in between
+#[cfg(feature = "async_locks")]
+struct YieldNow(bool);
+
+#[cfg(feature = "async_locks")]
+impl Future for YieldNow {
+  type Output = ();
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    if self.0 {
+      return Poll::Ready(());
+    }
+    self.0 = true;
+    cx.waker().wake_by_ref();
+    Poll::Pending
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  extern crate std;
+  use super::*;
+  use std::thread;
+
+  #[test]
+  fn stress_on_threads() {
+    let threads: std::vec::Vec<_> = (0..4)
+      .map(|core| thread::spawn(move || run(core, 4, 1_000)))
+      .collect();
+    for t in threads {
+      t.join().unwrap();
+    }
+
+    for report in reports(4, 1_000).iter() {
+      assert!(report.is_ok(), "{}", report);
+    }
+  }
+
+  #[test]
+  #[cfg(feature = "async_locks")]
+  fn async_locks_are_fair() {
+    for report in async_fairness(200, 20).iter() {
+      assert!(report.is_ok(), "{}", report);
+    }
+  }
+}
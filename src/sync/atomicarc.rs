@@ -0,0 +1,204 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # AtomicArc
+//!
+//! An atomic pointer to data shared with an `Arc` that can be swapped while other cores take snapshots of it. Loading
+//! the current value never blocks, which makes this a replacement for a [RWLock](crate::sync::RWLock) around an `Arc`
+//! in cases like configuration data that is updated rarely but read often.
+//!
+//! Replacing the value waits until the loads that are currently in progress have taken their reference to the
+//! previous value. This is only a short window, but if the value is loaded all the time on other cores a store might
+//! need to retry for a while. The core waits according to the selected [spin policy](crate::sync::spin) meanwhile, so
+//! an interrupt handler shall never replace a value that might be loaded by the code it interrupted.
+//!
+//! # Example
+//! ```
+//! extern crate alloc;
+//! use alloc::sync::Arc;
+//! use ruspiro_lock::sync::AtomicArc;
+//!
+//! struct Config {
+//!     baud_rate: u32,
+//! }
+//!
+//! fn main() {
+//!     let config = AtomicArc::new(Arc::new(Config { baud_rate: 115_200 }));
+//!     let snapshot = config.load();
+//!     config.store(Arc::new(Config { baud_rate: 9_600 }));
+//!     // the snapshot still refers to the previous config
+//!     assert_eq!(snapshot.baud_rate, 115_200);
+//!     assert_eq!(config.load().baud_rate, 9_600);
+//! }
+//! ```
+
+extern crate alloc;
+use super::spin;
+use crate::arch;
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// An `Arc` that can be atomically loaded and replaced
+pub struct AtomicArc<T> {
+  ptr: AtomicPtr<T>,
+  /// The number of loads currently in progress. The previous value is only released once there are none
+  loading: AtomicUsize,
+  /// The number of cores waiting for the loads in progress to finish, they need to be signalled once a load finished
+  swapping: AtomicUsize,
+}
+
+impl<T> AtomicArc<T> {
+  /// Create a new [AtomicArc] containing the given value
+  pub fn new(value: Arc<T>) -> Self {
+    Self {
+      ptr: AtomicPtr::new(Arc::into_raw(value) as *mut T),
+      loading: AtomicUsize::new(0),
+      swapping: AtomicUsize::new(0),
+    }
+  }
+
+  /// Take a snapshot of the current value. This never blocks.
+  pub fn load(&self) -> Arc<T> {
+    self.loading.fetch_add(1, Ordering::SeqCst);
+    let ptr = self.ptr.load(Ordering::SeqCst);
+    // SAFETY: the value the pointer refers to is not released while a load is in progress, so it is still alive
+    let value = unsafe {
+      Arc::increment_strong_count(ptr);
+      Arc::from_raw(ptr)
+    };
+    self.loading.fetch_sub(1, Ordering::SeqCst);
+    // a core replacing the value might wait for this load to finish. It announces itself before it checks the loads in
+    // progress, so either it sees this load finished or this sees it waiting
+    if self.swapping.load(Ordering::SeqCst) != 0 {
+      arch::signal_event();
+    }
+    value
+  }
+
+  /// Replace the current value with the given one
+  ///
+  /// # Deadlocks
+  /// This waits for the loads in progress like [AtomicArc::swap], so it shall not be called from an interrupt handler
+  /// that might interrupt a [AtomicArc::load] of the same [AtomicArc].
+  pub fn store(&self, value: Arc<T>) {
+    drop(self.swap(value));
+  }
+
+  /// Replace the current value with the given one and return the previous value. This waits until the loads in
+  /// progress have taken their reference to the previous value.
+  ///
+  /// # Deadlocks
+  /// A load that has been interrupted on the current core does not finish before the interrupt handler returns. So
+  /// this shall not be called from an interrupt handler or any other context that might preempt a [AtomicArc::load]
+  /// of the same [AtomicArc], otherwise the core waits forever.
+  pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+    let previous = self
+      .ptr
+      .swap(Arc::into_raw(value) as *mut T, Ordering::SeqCst);
+    // loads that have started before the swap might still be about to take their reference to the previous value,
+    // so wait for them to finish. Loads starting from now on will see the new value
+    if self.loading.load(Ordering::SeqCst) != 0 {
+      self.swapping.fetch_add(1, Ordering::SeqCst);
+      let mut attempt = 0;
+      while self.loading.load(Ordering::SeqCst) != 0 {
+        // to save energy and cpu consumption we can wait for the event signalled once a load finished, depending on
+        // the selected spin policy
+        spin::on_contention(&mut attempt, "AtomicArc::swap");
+      }
+      self.swapping.fetch_sub(1, Ordering::Release);
+    }
+    // SAFETY: the pointer has been created with Arc::into_raw and the reference it owned is handed back
+    unsafe { Arc::from_raw(previous) }
+  }
+
+  /// Consume the [AtomicArc] and return the contained value
+  pub fn into_inner(self) -> Arc<T> {
+    let ptr = self.ptr.load(Ordering::Relaxed);
+    core::mem::forget(self);
+    // SAFETY: the pointer has been created with Arc::into_raw and the reference it owned is handed back
+    unsafe { Arc::from_raw(ptr) }
+  }
+}
+
+impl<T> Drop for AtomicArc<T> {
+  fn drop(&mut self) {
+    // SAFETY: the pointer has been created with Arc::into_raw and no other reference to the AtomicArc exists
+    unsafe { drop(Arc::from_raw(*self.ptr.get_mut())) };
+  }
+}
+
+impl<T: Default> Default for AtomicArc<T> {
+  fn default() -> Self {
+    Self::new(Arc::new(T::default()))
+  }
+}
+
+impl<T: fmt::Debug> fmt::Debug for AtomicArc<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("AtomicArc")
+      .field("value", &self.load())
+      .finish()
+  }
+}
+
+// the AtomicArc hands out clones of the contained Arc to any core, so it has the same requirements as an Arc
+unsafe impl<T: Send + Sync> Sync for AtomicArc<T> {}
+unsafe impl<T: Send + Sync> Send for AtomicArc<T> {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn snapshots_outlive_the_replaced_value() {
+    let first = Arc::new(1u32);
+    let atomic = AtomicArc::new(Arc::clone(&first));
+    let snapshot = atomic.load();
+    assert_eq!(Arc::strong_count(&first), 3);
+
+    let previous = atomic.swap(Arc::new(2));
+    assert!(Arc::ptr_eq(&previous, &first));
+    drop(previous);
+    assert_eq!(*snapshot, 1);
+    assert_eq!(*atomic.load(), 2);
+    drop(snapshot);
+    assert_eq!(Arc::strong_count(&first), 1);
+
+    atomic.store(Arc::clone(&first));
+    let inner = atomic.into_inner();
+    assert!(Arc::ptr_eq(&inner, &first));
+    drop(inner);
+    assert_eq!(Arc::strong_count(&first), 1);
+  }
+
+  #[test]
+  fn dropping_releases_the_contained_value() {
+    let value = Arc::new(1u32);
+    drop(AtomicArc::new(Arc::clone(&value)));
+    assert_eq!(Arc::strong_count(&value), 1);
+  }
+
+  #[test]
+  fn swap_waits_for_the_loads_in_progress() {
+    let atomic = AtomicArc::new(Arc::new(1u32));
+    // simulate a load that has not taken its reference yet
+    atomic.loading.fetch_add(1, Ordering::SeqCst);
+    std::thread::scope(|s| {
+      let swapping = s.spawn(|| atomic.swap(Arc::new(2)));
+      while atomic.swapping.load(Ordering::SeqCst) == 0 {
+        std::thread::yield_now();
+      }
+      assert!(!swapping.is_finished());
+      atomic.loading.fetch_sub(1, Ordering::SeqCst);
+      arch::signal_event();
+      assert_eq!(*swapping.join().unwrap(), 1);
+    });
+    assert_eq!(atomic.swapping.load(Ordering::Relaxed), 0);
+    assert_eq!(*atomic.load(), 2);
+  }
+}
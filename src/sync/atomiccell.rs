@@ -0,0 +1,199 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # AtomicCell
+//!
+//! A cell storing a small `Copy` value that can be read and updated atomically without a lock. If the size and the
+//! alignment of the value match an atomic integer type the value is stored and accessed as this atomic integer. Values
+//! of pointer size are accessed as atomic pointer instead, so function pointers and references keep their provenance.
+//! Otherwise each access is secured by a [Spinlock].
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::AtomicCell;
+//!
+//! static BAUD_RATE: AtomicCell<u32> = AtomicCell::new(115_200);
+//!
+//! fn main() {
+//!     let old = BAUD_RATE.swap(9_600);
+//!     assert_eq!(old, 115_200);
+//!     assert_eq!(BAUD_RATE.load(), 9_600);
+//! }
+//! ```
+
+use super::spinlock::Spinlock;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::{align_of, size_of, transmute_copy};
+use core::sync::atomic::{AtomicPtr, AtomicU16, AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+/// A cell that allows atomic access to a small `Copy` value
+#[repr(C)]
+pub struct AtomicCell<T: Copy> {
+  value: UnsafeCell<T>,
+  /// secures the value if it can not be accessed as an atomic integer
+  lock: Spinlock,
+}
+
+/// Returns `true` if a `T` can be accessed as the atomic integer `A`
+const fn fits<T, A>() -> bool {
+  size_of::<T>() == size_of::<A>() && align_of::<T>() >= align_of::<A>()
+}
+
+/// Run the atomic expression with the value reinterpreted as the matching atomic integer or the fallback expression
+/// if there is no matching atomic integer
+macro_rules! dispatch {
+  ($cell:expr, |$atomic:ident: $int:ident| $op:expr, $fallback:expr) => {{
+    let ptr = $cell.value.get();
+    if fits::<T, AtomicPtr<()>>() {
+      // values of pointer size, like function pointers, are accessed as pointer to keep their provenance
+      type $int = *mut ();
+      // SAFETY: size and alignment of T match the atomic type
+      let $atomic = unsafe { &*(ptr as *const AtomicPtr<()>) };
+      $op
+    } else if fits::<T, AtomicU8>() {
+      type $int = u8;
+      // SAFETY: size and alignment of T match the atomic type
+      let $atomic = unsafe { &*(ptr as *const AtomicU8) };
+      $op
+    } else if fits::<T, AtomicU16>() {
+      type $int = u16;
+      // SAFETY: size and alignment of T match the atomic type
+      let $atomic = unsafe { &*(ptr as *const AtomicU16) };
+      $op
+    } else if fits::<T, AtomicU32>() {
+      type $int = u32;
+      // SAFETY: size and alignment of T match the atomic type
+      let $atomic = unsafe { &*(ptr as *const AtomicU32) };
+      $op
+    } else if fits::<T, AtomicU64>() {
+      type $int = u64;
+      // SAFETY: size and alignment of T match the atomic type
+      let $atomic = unsafe { &*(ptr as *const AtomicU64) };
+      $op
+    } else {
+      $cell.lock.acquire();
+      let result = $fallback;
+      $cell.lock.release();
+      result
+    }
+  }};
+}
+
+impl<T: Copy> AtomicCell<T> {
+  /// Create a new [AtomicCell] containing the given value
+  pub const fn new(value: T) -> Self {
+    Self {
+      value: UnsafeCell::new(value),
+      lock: Spinlock::new(),
+    }
+  }
+
+  /// Returns `true` if the value is accessed as an atomic integer without using the fallback lock
+  pub const fn is_lock_free() -> bool {
+    fits::<T, AtomicPtr<()>>()
+      || fits::<T, AtomicU8>()
+      || fits::<T, AtomicU16>()
+      || fits::<T, AtomicU32>()
+      || fits::<T, AtomicU64>()
+  }
+
+  /// Load the current value
+  pub fn load(&self) -> T {
+    dispatch!(
+      self,
+      |atomic: Int| unsafe { transmute_copy::<Int, T>(&atomic.load(Ordering::Acquire)) },
+      unsafe { *self.value.get() }
+    )
+  }
+
+  /// Store a new value
+  pub fn store(&self, value: T) {
+    dispatch!(
+      self,
+      |atomic: Int| atomic.store(
+        unsafe { transmute_copy::<T, Int>(&value) },
+        Ordering::Release
+      ),
+      unsafe { *self.value.get() = value }
+    )
+  }
+
+  /// Store a new value and return the previous one
+  pub fn swap(&self, value: T) -> T {
+    dispatch!(
+      self,
+      |atomic: Int| unsafe {
+        transmute_copy::<Int, T>(&atomic.swap(transmute_copy::<T, Int>(&value), Ordering::AcqRel))
+      },
+      unsafe { core::ptr::replace(self.value.get(), value) }
+    )
+  }
+
+  /// Store the new value if the current value equals `current`. Returns the previous value in `Ok` if it has been
+  /// replaced or the actual current value in `Err` otherwise.
+  ///
+  /// If the value is accessed as an atomic integer the comparison is done on the bit representation. So this shall
+  /// only be used with types that do not contain padding bytes.
+  pub fn compare_exchange(&self, current: T, new: T) -> Result<T, T>
+  where
+    T: PartialEq,
+  {
+    dispatch!(
+      self,
+      |atomic: Int| unsafe {
+        atomic
+          .compare_exchange(
+            transmute_copy::<T, Int>(&current),
+            transmute_copy::<T, Int>(&new),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+          )
+          .map(|value| transmute_copy::<Int, T>(&value))
+          .map_err(|value| transmute_copy::<Int, T>(&value))
+      },
+      unsafe {
+        let value = *self.value.get();
+        if value == current {
+          *self.value.get() = new;
+          Ok(value)
+        } else {
+          Err(value)
+        }
+      }
+    )
+  }
+
+  /// Mutable access to the value. This does not require any atomic operation as the mutable borrow guarantees
+  /// exclusive access.
+  pub fn get_mut(&mut self) -> &mut T {
+    self.value.get_mut()
+  }
+
+  /// Consume the [AtomicCell] and return the contained value
+  pub fn into_inner(self) -> T {
+    self.value.into_inner()
+  }
+}
+
+impl<T: Copy + Default> Default for AtomicCell<T> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for AtomicCell<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("AtomicCell")
+      .field("value", &self.load())
+      .finish()
+  }
+}
+
+// the value is only accessed atomically or while holding the lock
+unsafe impl<T: Copy + Send> Sync for AtomicCell<T> {}
+unsafe impl<T: Copy + Send> Send for AtomicCell<T> {}
@@ -0,0 +1,264 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Batched
+//!
+//! Producers that update shared data often but with low priority, like cores appending to a shared log buffer, take
+//! the lock for each small update and cause a lot of lock traffic. A [Batched] lets them append their mutations to a
+//! small fixed queue without taking the lock. The mutations are applied in the order they have been appended with a
+//! single acquisition of the lock once the queue is flushed, e.g. by a low priority task or when the data is read.
+//!
+//! Appending a mutation only claims a slot of the queue with an atomic operation, so it never waits for another core
+//! and can be used from interrupt handlers.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::Batched;
+//!
+//! struct Log {
+//!     lines: usize,
+//!     bytes: usize,
+//! }
+//!
+//! fn append(log: &mut Log, line: &'static str) {
+//!     log.lines += 1;
+//!     log.bytes += line.len();
+//! }
+//!
+//! static LOG: Batched<Log, &'static str> = Batched::new(Log { lines: 0, bytes: 0 }, append);
+//!
+//! fn main() {
+//!     // the producers append their lines without taking the lock
+//!     LOG.push("core 1 started").unwrap();
+//!     LOG.push("core 2 started").unwrap();
+//!     assert_eq!(LOG.pending(), 2);
+//!     // reading the log applies the pending lines first
+//!     assert_eq!(LOG.lock().lines, 2);
+//! }
+//! ```
+
+use super::{Mutex, MutexGuard};
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Data secured by a [Mutex] whose mutations of type `M` are queued without taking the lock and applied in batches.
+/// Up to `N` mutations can be queued, `N` need to be a power of two of at least 2.
+pub struct Batched<T, M, const N: usize = 16> {
+  data: Mutex<T>,
+  /// Apply a mutation to the data
+  apply: fn(&mut T, M),
+  slots: [Slot<M>; N],
+  /// The position the next mutation is appended at
+  tail: AtomicUsize,
+  /// The position of the oldest mutation, only changed while the lock is held
+  head: AtomicUsize,
+}
+
+/// A slot of the queue. Its lap tells the position that can be written or read next, so the producers and the
+/// flushing core agree on the state of the slot without a lock.
+struct Slot<M> {
+  /// The position this slot can be written at minus the index of the slot. Once the mutation has been written it is
+  /// one more, telling the flushing core that the mutation at this position can be read
+  lap: AtomicUsize,
+  mutation: UnsafeCell<MaybeUninit<M>>,
+}
+
+impl<M> Slot<M> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const EMPTY: Slot<M> = Slot {
+    lap: AtomicUsize::new(0),
+    mutation: UnsafeCell::new(MaybeUninit::uninit()),
+  };
+}
+
+impl<T, M, const N: usize> Batched<T, M, N> {
+  /// Create a [Batched] with the given data and the function applying a mutation to it. As this does not require any
+  /// allocation it can be assigned to a static variable
+  ///
+  /// # Panics
+  /// Panics if `N` is less than 2 or not a power of two. With a single slot the lap of a written slot could not be
+  /// told apart from the next free position, so a pending mutation would be overwritten.
+  pub const fn new(value: T, apply: fn(&mut T, M)) -> Self {
+    assert!(
+      N >= 2 && N.is_power_of_two(),
+      "the queue of a Batched need a power of two slots of at least 2"
+    );
+    Self {
+      data: Mutex::new(value),
+      apply,
+      slots: [Slot::EMPTY; N],
+      tail: AtomicUsize::new(0),
+      head: AtomicUsize::new(0),
+    }
+  }
+
+  /// Append a mutation to the queue without taking the lock. It is applied with the next flush. If the queue is full
+  /// the mutation is given back in the `Err` variant, the caller might [flush](Batched::flush) the queue and try again.
+  pub fn push(&self, mutation: M) -> Result<(), M> {
+    let mut pos = self.tail.load(Ordering::Relaxed);
+    let slot = loop {
+      let slot = &self.slots[pos % N];
+      let lap = slot.lap.load(Ordering::Acquire).wrapping_add(pos % N);
+      match lap.wrapping_sub(pos) as isize {
+        // the slot is free for this position, so claim the position
+        0 => match self.tail.compare_exchange_weak(
+          pos,
+          pos.wrapping_add(1),
+          Ordering::Relaxed,
+          Ordering::Relaxed,
+        ) {
+          Ok(_) => break slot,
+          Err(current) => pos = current,
+        },
+        // the slot still contains the mutation appended one lap before, so the queue is full
+        diff if diff < 0 => return Err(mutation),
+        // another core claimed the position already
+        _ => pos = self.tail.load(Ordering::Relaxed),
+      }
+    };
+
+    // SAFETY: the position has been claimed exclusively, the flushing core does not read it before the lap is updated
+    unsafe { (*slot.mutation.get()).write(mutation) };
+    slot
+      .lap
+      .store(pos.wrapping_add(1).wrapping_sub(pos % N), Ordering::Release);
+    Ok(())
+  }
+
+  /// Take the oldest mutation of the queue. Returns `None` if there is none or the oldest one is still being written.
+  /// This shall only be called while the lock is held.
+  fn pop(&self) -> Option<M> {
+    let pos = self.head.load(Ordering::Relaxed);
+    let slot = &self.slots[pos % N];
+    let lap = slot.lap.load(Ordering::Acquire).wrapping_add(pos % N);
+    if lap != pos.wrapping_add(1) {
+      return None;
+    }
+    // SAFETY: the mutation has been written completely and only the core holding the lock reads it
+    let mutation = unsafe { (*slot.mutation.get()).assume_init_read() };
+    self.head.store(pos.wrapping_add(1), Ordering::Relaxed);
+    // the slot can be written again one lap later
+    slot
+      .lap
+      .store(pos.wrapping_add(N).wrapping_sub(pos % N), Ordering::Release);
+    Some(mutation)
+  }
+
+  /// Apply all queued mutations to the data of the given guard and return their number
+  fn apply_pending(&self, data: &mut T) -> usize {
+    let mut applied = 0;
+    while let Some(mutation) = self.pop() {
+      (self.apply)(data, mutation);
+      applied += 1;
+    }
+    applied
+  }
+
+  /// Lock the data, apply all queued mutations with this single acquisition and return their number. A mutation that
+  /// is still being appended by another core is applied with the next flush, together with the ones appended after it.
+  pub fn flush(&self) -> usize {
+    let mut data = self.data.lock();
+    self.apply_pending(&mut data)
+  }
+
+  /// Flush the queued mutations like [Batched::flush] if the lock is available without waiting. Returns `None` if the
+  /// lock is held, e.g. by the core currently flushing the queue.
+  pub fn try_flush(&self) -> Option<usize> {
+    let mut data = self.data.try_lock()?;
+    Some(self.apply_pending(&mut data))
+  }
+
+  /// Lock the data for mutual exclusive access. The queued mutations are applied first, so the guard provides the
+  /// data with all mutations appended so far.
+  pub fn lock(&self) -> MutexGuard<'_, T> {
+    let mut data = self.data.lock();
+    self.apply_pending(&mut data);
+    data
+  }
+
+  /// The number of mutations waiting in the queue. The result might already be outdated when it is returned.
+  pub fn pending(&self) -> usize {
+    self
+      .tail
+      .load(Ordering::Relaxed)
+      .wrapping_sub(self.head.load(Ordering::Relaxed))
+      .min(N)
+  }
+
+  /// Consume the [Batched], apply the queued mutations and return the data
+  pub fn into_inner(self) -> T {
+    self.flush();
+    // the queue is empty, as no mutation can be appended any more
+    let this = core::mem::ManuallyDrop::new(self);
+    // SAFETY: the data is read once and the Batched is not dropped
+    unsafe { core::ptr::read(&this.data) }.into_inner()
+  }
+}
+
+/// Drop the mutations that have not been applied
+impl<T, M, const N: usize> Drop for Batched<T, M, N> {
+  fn drop(&mut self) {
+    while self.pop().is_some() {}
+  }
+}
+
+/// The Debug implementation only reports the number of pending mutations and never acquires the lock
+impl<T, M, const N: usize> fmt::Debug for Batched<T, M, N> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Batched")
+      .field("pending", &self.pending())
+      .finish_non_exhaustive()
+  }
+}
+
+// the mutations are moved from the appending core to the core flushing the queue
+unsafe impl<T: Send, M: Send, const N: usize> Sync for Batched<T, M, N> {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  fn append(log: &mut [u32; 8], value: u32) {
+    log.rotate_left(1);
+    log[7] = value;
+  }
+
+  #[test]
+  fn push_fails_once_the_queue_is_full() {
+    let batched: Batched<[u32; 8], u32, 4> = Batched::new([0; 8], append);
+    for value in 1..=4 {
+      assert!(batched.push(value).is_ok());
+    }
+    assert_eq!(batched.pending(), 4);
+    assert_eq!(batched.push(5), Err(5));
+    assert_eq!(batched.flush(), 4);
+    assert!(batched.push(5).is_ok());
+    assert_eq!(batched.pending(), 1);
+  }
+
+  #[test]
+  fn mutations_are_applied_in_the_order_they_were_pushed() {
+    let batched: Batched<[u32; 8], u32, 4> = Batched::new([0; 8], append);
+    // wrap around the queue a few times to cover reused slots
+    for value in 1..=10 {
+      if batched.push(value).is_err() {
+        batched.flush();
+        batched.push(value).unwrap();
+      }
+    }
+    assert_eq!(*batched.lock(), [3, 4, 5, 6, 7, 8, 9, 10]);
+    assert_eq!(batched.pending(), 0);
+  }
+
+  #[test]
+  #[should_panic]
+  fn a_single_slot_queue_is_rejected() {
+    let _: Batched<[u32; 8], u32, 1> = Batched::new([0; 8], append);
+  }
+}
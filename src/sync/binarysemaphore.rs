@@ -0,0 +1,155 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Binary Semaphore
+//!
+//! A [Semaphore](super::Semaphore) counts each `up`, so giving it twice before it is taken lets two takers through.
+//! Drivers ported from FreeRTOS expect the classic binary semaphore instead, that only remembers whether it has been
+//! given. A [BinarySemaphore] clamps at one permit, so an event signalled twice, e.g. by two interrupts before the
+//! waiting core got the chance to run, is only handled once.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::BinarySemaphore;
+//!
+//! static RX_READY: BinarySemaphore = BinarySemaphore::new(false);
+//!
+//! fn uart_irq_handler() {
+//!     // an interrupt handler gives the semaphore with a single atomic operation
+//!     RX_READY.give_from_isr();
+//! }
+//!
+//! fn main() {
+//!     uart_irq_handler();
+//!     uart_irq_handler();
+//!     // the semaphore has been given twice, but is only taken once
+//!     RX_READY.take();
+//!     assert!(RX_READY.try_take().is_err());
+//! }
+//! ```
+
+use super::registry::{InspectLock, LockState};
+use super::Semaphore;
+use crate::LockError;
+use core::fmt;
+
+/// A semaphore that is either given or taken. Giving it while it is given already has no effect.
+///
+/// Cores blocking in [BinarySemaphore::take] are served in the order they started waiting.
+pub struct BinarySemaphore {
+  sema: Semaphore,
+}
+
+impl BinarySemaphore {
+  /// Create a [BinarySemaphore] that is initially given or taken
+  pub const fn new(given: bool) -> Self {
+    Self {
+      sema: Semaphore::new(given as u32),
+    }
+  }
+
+  /// Give the semaphore, so the next core taking it can proceed. Returns `false` if it is given already, in which case
+  /// this has no effect.
+  pub fn give(&self) -> bool {
+    self.sema.up_bounded(1)
+  }
+
+  /// Give the semaphore from an interrupt handler. This only updates the state with a single atomic operation and
+  /// raises an event if a core is waiting. It never waits for another core, so it is safe to be called while the
+  /// interrupted code on the same core is waiting in [BinarySemaphore::take]. Returns `false` if the semaphore is
+  /// given already.
+  pub fn give_from_isr(&self) -> bool {
+    self.sema.up_bounded(1)
+  }
+
+  /// Take the semaphore. This blocks the current core until it has been given.
+  pub fn take(&self) {
+    self.sema.down();
+  }
+
+  /// Take the semaphore like [BinarySemaphore::take], but call `keep_waiting` with the number of failed attempts each
+  /// time the core is about to wait again. If it returns `false` waiting is aborted and this fails with
+  /// [LockError::Cancelled].
+  pub fn take_while<F: FnMut(u32) -> bool>(&self, keep_waiting: F) -> Result<(), LockError> {
+    self.sema.down_while(keep_waiting)
+  }
+
+  /// Try to take the semaphore without waiting. This fails with [LockError::WouldBlock] if it has not been given or
+  /// other cores are waiting in [BinarySemaphore::take].
+  pub fn try_take(&self) -> Result<(), LockError> {
+    self.sema.try_acquire()
+  }
+
+  /// Returns `true` if the semaphore is currently given. The result might already be outdated when it is returned, so
+  /// it shall only be used for diagnostics.
+  pub fn is_given(&self) -> bool {
+    matches!(self.sema.fmt_state(), LockState::Semaphore { permits: 1 })
+  }
+}
+
+impl InspectLock for BinarySemaphore {
+  fn lock_state(&self) -> LockState {
+    self.sema.fmt_state()
+  }
+}
+
+impl Default for BinarySemaphore {
+  fn default() -> Self {
+    Self::new(false)
+  }
+}
+
+impl fmt::Debug for BinarySemaphore {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("BinarySemaphore")
+      .field("given", &self.is_given())
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn giving_twice_is_taken_once() {
+    let sema = BinarySemaphore::new(false);
+    assert!(!sema.is_given());
+    assert!(sema.give());
+    assert!(!sema.give_from_isr());
+    assert!(sema.is_given());
+    assert_eq!(sema.try_take(), Ok(()));
+    assert_eq!(sema.try_take(), Err(LockError::WouldBlock));
+  }
+
+  #[test]
+  fn take_while_is_cancelled_by_the_predicate() {
+    let sema = BinarySemaphore::new(false);
+    assert_eq!(
+      sema.take_while(|attempt| attempt < 3),
+      Err(LockError::Cancelled)
+    );
+    sema.give();
+    assert_eq!(sema.take_while(|_| false), Ok(()));
+    assert!(!sema.is_given());
+  }
+
+  #[test]
+  fn take_waits_until_given() {
+    let sema = BinarySemaphore::new(false);
+    std::thread::scope(|s| {
+      let taker = s.spawn(|| sema.take());
+      while !taker.is_finished() {
+        sema.give();
+        std::thread::yield_now();
+      }
+    });
+    // the taker might have been given a second time after it took the semaphore
+    let _ = sema.try_take();
+    assert!(!sema.is_given());
+  }
+}
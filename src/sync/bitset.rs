@@ -0,0 +1,159 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # AtomicBitset
+//!
+//! A set of up to 64 flags that can be set and cleared atomically from any core, e.g. to ring doorbells from one core
+//! to another or to collect event masks. Setting a flag signals an event, so a core waiting for any flag with
+//! [AtomicBitset::wait_for_any] is woken from `wfe` without the need to busy spin. The required barriers are placed
+//! by the bitset, so data written before setting a flag is visible to the core that observes the flag.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::AtomicBitset;
+//!
+//! /// one doorbell flag per core
+//! static DOORBELL: AtomicBitset<4> = AtomicBitset::new();
+//!
+//! fn main() {
+//!     // on the sending core
+//!     DOORBELL.set(2);
+//!     // on core 2
+//!     let pending = DOORBELL.wait_for_any(1 << 2);
+//!     assert_eq!(pending, 1 << 2);
+//!     DOORBELL.clear(2);
+//! }
+//! ```
+
+use super::spin;
+use crate::arch;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// A set of `BITS` flags that can be accessed atomically. `BITS` shall not exceed 64.
+#[repr(C, align(16))]
+pub struct AtomicBitset<const BITS: usize> {
+  bits: AtomicU64,
+}
+
+impl<const BITS: usize> AtomicBitset<BITS> {
+  /// The mask of all flags of this bitset. Evaluating this fails to compile if `BITS` exceeds 64.
+  pub const MASK: u64 = {
+    assert!(
+      BITS > 0 && BITS <= 64,
+      "an AtomicBitset supports 1 to 64 bits"
+    );
+    u64::MAX >> (64 - BITS)
+  };
+
+  /// Create a new [AtomicBitset] with all flags cleared
+  pub const fn new() -> Self {
+    // reference the mask to reject invalid sizes at compile time
+    let _ = Self::MASK;
+    Self {
+      bits: AtomicU64::new(0),
+    }
+  }
+
+  /// Set the given flag and signal an event to wake cores waiting for it. Returns `true` if the flag has been set
+  /// already.
+  pub fn set(&self, bit: usize) -> bool {
+    self.set_mask(Self::bit(bit)) & Self::bit(bit) != 0
+  }
+
+  /// Set all flags of the given mask and signal an event to wake cores waiting for them. Returns the flags that have
+  /// been set before.
+  pub fn set_mask(&self, mask: u64) -> u64 {
+    // dmb required before the flag is set, so the data written upfront is visible to the observer, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    let previous = self.bits.fetch_or(mask & Self::MASK, Ordering::AcqRel);
+    arch::dmb();
+    // raise a signal to wake the cores waiting for a flag
+    arch::signal_event();
+    previous
+  }
+
+  /// Clear the given flag. Returns `true` if the flag has been set.
+  pub fn clear(&self, bit: usize) -> bool {
+    self.clear_mask(Self::bit(bit)) & Self::bit(bit) != 0
+  }
+
+  /// Clear all flags of the given mask. Returns the flags that have been set before.
+  pub fn clear_mask(&self, mask: u64) -> u64 {
+    let previous = self.bits.fetch_and(!mask, Ordering::AcqRel);
+    // dmb required after atomic operations, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    previous
+  }
+
+  /// Returns `true` if the given flag is set
+  pub fn test(&self, bit: usize) -> bool {
+    self.load() & Self::bit(bit) != 0
+  }
+
+  /// Set the given flag and return whether it has been set already. As only one caller observes the flag as not set
+  /// this can be used to claim a flag. Other than [AtomicBitset::set] this does not signal an event if the flag has
+  /// been set already.
+  pub fn test_and_set(&self, bit: usize) -> bool {
+    let mask = Self::bit(bit);
+    if self.load() & mask != 0 {
+      return true;
+    }
+    self.set_mask(mask) & mask != 0
+  }
+
+  /// Returns all flags that are currently set
+  pub fn load(&self) -> u64 {
+    let bits = self.bits.load(Ordering::Acquire);
+    arch::dmb();
+    bits
+  }
+
+  /// Block the current core until any flag of the given mask is set and return the flags of the mask that are set.
+  /// The flags are not cleared. Between two checks the core waits as given by the selected
+  /// [SpinPolicy](super::spin::SpinPolicy), which waits for an event by default.
+  pub fn wait_for_any(&self, mask: u64) -> u64 {
+    let mut attempt = 0;
+    loop {
+      let bits = self.load() & mask;
+      if bits != 0 {
+        return bits;
+      }
+      spin::on_contention(&mut attempt, "AtomicBitset::wait_for_any");
+    }
+  }
+
+  /// The mask of a single flag
+  fn bit(bit: usize) -> u64 {
+    assert!(
+      bit < BITS,
+      "bit {} out of range of an AtomicBitset<{}>",
+      bit,
+      BITS
+    );
+    1 << bit
+  }
+}
+
+impl<const BITS: usize> Default for AtomicBitset<BITS> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<const BITS: usize> fmt::Debug for AtomicBitset<BITS> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("AtomicBitset")
+      .field(
+        "bits",
+        &format_args!("{:#0width$b}", self.load(), width = BITS + 2),
+      )
+      .finish()
+  }
+}
@@ -0,0 +1,90 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Lock Budget
+//!
+//! Code with a deadline, like the deferred work of a real-time interrupt, can not afford to block on a contended lock
+//! for an unbounded time. [Mutex::lock_with_budget](super::Mutex::lock_with_budget) gives up after a given number of
+//! failed attempts and returns a [LockBudgetExceeded] error instead. Each exceeded budget is also reported to the
+//! hook selected with [set_budget_hook], so the contention can be logged or counted at a single place.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::{set_budget_hook, LockBudgetExceeded, Mutex};
+//!
+//! static DATA: Mutex<u32> = Mutex::new_named(0, "data");
+//!
+//! fn report(exceeded: &LockBudgetExceeded) {
+//!     // the report names the lock, e.g. "core 0 could not acquire the lock data at 0x1000 within 10 spins"
+//!     println!("{}", exceeded);
+//! }
+//!
+//! fn main() {
+//!     set_budget_hook(report);
+//!     let _guard = DATA.lock();
+//!     // the lock is held, so the budget of the second attempt is exceeded
+//!     assert!(DATA.lock_with_budget(10).is_err());
+//! }
+//! ```
+
+use super::AtomicCell;
+use core::fmt;
+
+/// The diagnostic error returned if a lock could not be acquired within the given budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockBudgetExceeded {
+  /// The address of the contended lock
+  pub lock: usize,
+  /// The name of the contended lock, if it has been labeled with one, e.g. with
+  /// [Mutex::new_named](super::Mutex::new_named)
+  pub name: Option<&'static str>,
+  /// The number of failed attempts after which the core gave up
+  pub spins: u32,
+  /// The id of the core that gave up
+  pub core: usize,
+}
+
+impl fmt::Display for LockBudgetExceeded {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "core {} could not acquire the lock ", self.core)?;
+    if let Some(name) = self.name {
+      write!(f, "{} ", name)?;
+    }
+    write!(f, "at {:#x} within {} spins", self.lock, self.spins)
+  }
+}
+
+impl core::error::Error for LockBudgetExceeded {}
+
+/// The hook called each time a lock budget is exceeded
+static HOOK: AtomicCell<fn(&LockBudgetExceeded)> =
+  AtomicCell::new(ignore as fn(&LockBudgetExceeded));
+
+/// The default hook that does not report anything
+fn ignore(_: &LockBudgetExceeded) {}
+
+/// Select the hook that is called each time a lock budget is exceeded. It is called on the core that gave up, before
+/// the error is returned to the caller, and shall therefore not acquire the lock that could not be acquired.
+pub fn set_budget_hook(hook: fn(&LockBudgetExceeded)) {
+  HOOK.store(hook);
+}
+
+/// Report an exceeded lock budget to the selected hook and return the error
+pub(crate) fn exceeded<T: ?Sized>(
+  lock: &T,
+  name: Option<&'static str>,
+  spins: u32,
+) -> LockBudgetExceeded {
+  let exceeded = LockBudgetExceeded {
+    lock: lock as *const T as *const () as usize,
+    name,
+    spins,
+    core: crate::config::core_id(),
+  };
+  (HOOK.load())(&exceeded);
+  exceeded
+}
@@ -0,0 +1,92 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Priority Ceiling
+//!
+//! A task holding a [Spinlock](super::Spinlock) might be preempted by a task of medium priority, while a task of high
+//! priority waits for the lock. A Spinlock created with [Spinlock::with_ceiling](super::Spinlock::with_ceiling)
+//! prevents this priority inversion with the immediate priority ceiling protocol. The ceiling is the highest priority
+//! of all tasks using the lock. Before the lock is acquired the priority of the acquiring core is raised to the
+//! ceiling and once it is released the previous priority is restored. So no task that uses the lock can preempt its
+//! holder, without the bookkeeping of a full priority inheritance.
+//!
+//! The priority of a core is managed by the scheduler, so it is changed through the [CeilingHooks] registered with
+//! [set_ceiling_hooks]. Without hooks the ceiling is ignored.
+//!
+//! # Example
+//! ```
+//! use core::sync::atomic::{AtomicU32, Ordering};
+//! use ruspiro_lock::sync::{ceiling, Spinlock};
+//!
+//! // the highest priority of the tasks accessing the timer is 3
+//! static TIMER_LOCK: Spinlock = Spinlock::with_ceiling(3);
+//! // the priority of the current task as maintained by the scheduler
+//! static PRIORITY: AtomicU32 = AtomicU32::new(1);
+//!
+//! fn raise(ceiling: u32) -> u32 {
+//!     PRIORITY.fetch_max(ceiling, Ordering::Relaxed)
+//! }
+//!
+//! fn restore(priority: u32) {
+//!     PRIORITY.store(priority, Ordering::Relaxed);
+//! }
+//!
+//! fn main() {
+//!     ceiling::set_ceiling_hooks(ceiling::CeilingHooks { raise, restore });
+//!
+//!     TIMER_LOCK.acquire();
+//!     // the timer is accessed with the ceiling priority
+//!     assert_eq!(PRIORITY.load(Ordering::Relaxed), 3);
+//!     TIMER_LOCK.release();
+//!     assert_eq!(PRIORITY.load(Ordering::Relaxed), 1);
+//! }
+//! ```
+
+use super::RWLock;
+
+/// The functions changing the priority of the current core for the priority ceiling of a [Spinlock](super::Spinlock)
+#[derive(Debug, Clone, Copy)]
+pub struct CeilingHooks {
+  /// Raise the priority of the current core to at least the given ceiling and return the previous priority. This is
+  /// called before the Spinlock is acquired. If the current priority is higher than the ceiling already, e.g. because
+  /// the core holds a lock with a higher ceiling, it shall be kept. The priority `u32::MAX` is reserved and shall not
+  /// be returned.
+  pub raise: fn(u32) -> u32,
+  /// Restore the given priority returned by `raise`. This is called after the Spinlock has been released.
+  pub restore: fn(u32),
+}
+
+static CEILING_HOOKS: RWLock<Option<CeilingHooks>> = RWLock::new(None);
+
+/// Register the functions changing the priority of the current core for the priority ceiling of a
+/// [Spinlock](super::Spinlock). This replaces any functions registered before and shall be called before a Spinlock
+/// with a ceiling is used, as a priority raised without hooks will not be restored.
+pub fn set_ceiling_hooks(hooks: CeilingHooks) {
+  CEILING_HOOKS.replace(Some(hooks));
+}
+
+/// Remove the registered ceiling hooks
+pub fn clear_ceiling_hooks() {
+  CEILING_HOOKS.replace(None);
+}
+
+/// Raise the priority of the current core to the given ceiling. Returns the priority to restore or `None` if there
+/// are no hooks registered
+#[inline]
+pub(crate) fn raise(ceiling: u32) -> Option<u32> {
+  let hooks = *CEILING_HOOKS.read();
+  hooks.map(|hooks| (hooks.raise)(ceiling))
+}
+
+/// Restore the priority of the current core returned by [raise]
+#[inline]
+pub(crate) fn restore(priority: u32) {
+  let hooks = *CEILING_HOOKS.read();
+  if let Some(hooks) = hooks {
+    (hooks.restore)(priority);
+  }
+}
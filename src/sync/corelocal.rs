@@ -0,0 +1,326 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Core Local
+//!
+//! Some data is affine to a single core, like the state of the core's timer or its interrupt controller interface.
+//! Instead of locking it, a [CoreLocal] is owned by one core and all other cores ship their access as a closure to
+//! the owner with [CoreLocal::call_on_owner]. The closure is posted to a [Mailbox] whose doorbell notifies the owner,
+//! e.g. by raising a software generated interrupt, whose handler runs the closures with [CoreLocal::process]. The
+//! calling core waits until its closure has been run and receives the result. So all accesses are serialized by the
+//! owner without a lock that could be contended.
+//!
+//! On the owner core the closure is called directly.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::CoreLocal;
+//!
+//! struct Timer {
+//!     ticks: u64,
+//! }
+//!
+//! // the timer state is owned by core 0
+//! static TIMER: CoreLocal<Timer> = CoreLocal::new(0, Timer { ticks: 0 });
+//!
+//! fn raise_sgi() {
+//!     // notify core 0, e.g. by raising a software generated interrupt
+//! }
+//!
+//! // the handler of the software generated interrupt on core 0
+//! fn sgi_handler() {
+//!     TIMER.process();
+//! }
+//!
+//! fn main() {
+//!     TIMER.set_doorbell(Some(raise_sgi));
+//!     // on core 0 the closure is called directly, on any other core it runs on core 0
+//!     let ticks = TIMER.call_on_owner(|timer| {
+//!         timer.ticks += 1;
+//!         timer.ticks
+//!     });
+//!     assert_eq!(ticks, 1);
+//! }
+//! ```
+
+use super::{spin, Mailbox};
+use crate::arch;
+use crate::config::core_id;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A value owned by a single core that all other cores access by shipping closures to the owner. Up to `N` closures
+/// can wait to be run by the owner at the same time.
+pub struct CoreLocal<T, const N: usize = 4> {
+  /// The id of the core owning the value
+  owner: usize,
+  /// Set while the owner core accesses the value
+  busy: AtomicBool,
+  value: UnsafeCell<T>,
+  /// The closures posted by other cores waiting to be run by the owner
+  calls: Mailbox<Call<T>, N>,
+}
+
+/// A closure posted to the owner core. It lives on the stack of the calling core, which waits until the closure has
+/// been run.
+struct Call<T> {
+  /// The closure and the slot for its result
+  closure: *mut (),
+  /// Run the closure with the value of the [CoreLocal]
+  run: unsafe fn(*mut (), &mut T),
+  /// Set once the closure has been run
+  done: *const AtomicBool,
+}
+
+// SAFETY: the calling core does not access the closure until the owner has set the done flag
+unsafe impl<T> Send for Call<T> {}
+
+impl<T, const N: usize> CoreLocal<T, N> {
+  /// Create a [CoreLocal] with the given value that is owned by the core with the given id. As this does not require
+  /// any allocation it can be assigned to a static variable
+  pub const fn new(owner: usize, value: T) -> Self {
+    Self {
+      owner,
+      busy: AtomicBool::new(false),
+      value: UnsafeCell::new(value),
+      calls: Mailbox::new(),
+    }
+  }
+
+  /// The id of the core owning the value
+  pub fn owner(&self) -> usize {
+    self.owner
+  }
+
+  /// Register the doorbell that is called each time another core posted a closure. It shall notify the owner core to
+  /// call [CoreLocal::process]. Passing `None` removes the doorbell, then the owner need to call
+  /// [CoreLocal::process] regularly.
+  pub fn set_doorbell(&self, doorbell: Option<fn()>) {
+    self.calls.set_doorbell(doorbell);
+  }
+
+  /// Call the given function with mutual exclusive access to the value on the owner core and return its result. On
+  /// the owner core the function is called directly. Any other core posts it to the owner and waits until it has been
+  /// run. If the function panics on the owner core the calling core waits forever.
+  ///
+  /// # Panics
+  /// Panics if called on the owner core from within a function accessing the value, e.g. from an interrupt handler
+  /// that interrupted [CoreLocal::call_on_owner] or from within the function itself.
+  pub fn call_on_owner<R, F>(&self, f: F) -> R
+  where
+    F: FnOnce(&mut T) -> R + Send,
+    R: Send,
+  {
+    if core_id() == self.owner {
+      assert!(
+        !self.busy.swap(true, Ordering::Acquire),
+        "the CoreLocal is accessed re-entrant on its owner core"
+      );
+      // SAFETY: only the owner core accesses the value and the busy flag prevents a nested access
+      let result = f(unsafe { &mut *self.value.get() });
+      self.busy.store(false, Ordering::Release);
+      // run the closures posted while the value has been accessed
+      self.process();
+      return result;
+    }
+
+    struct Closure<F, R> {
+      f: Option<F>,
+      result: Option<R>,
+    }
+
+    unsafe fn run<T, F: FnOnce(&mut T) -> R, R>(closure: *mut (), value: &mut T) {
+      let closure = &mut *(closure as *mut Closure<F, R>);
+      if let Some(f) = closure.f.take() {
+        closure.result = Some(f(value));
+      }
+    }
+
+    let mut closure = Closure {
+      f: Some(f),
+      result: None,
+    };
+    let done = AtomicBool::new(false);
+    let mut call = Call {
+      closure: &mut closure as *mut Closure<F, R> as *mut (),
+      run: run::<T, F, R>,
+      done: &done,
+    };
+    // the owner signals an event each time it has run closures, so a slot might have been freed
+    let mut attempt = 0;
+    while let Err(rejected) = self.calls.post(call) {
+      call = rejected;
+      spin::on_contention(&mut attempt, "CoreLocal::call_on_owner");
+    }
+
+    let mut attempt = 0;
+    while !done.load(Ordering::Acquire) {
+      spin::on_contention(&mut attempt, "CoreLocal::call_on_owner");
+    }
+    closure
+      .result
+      .take()
+      .expect("the owner core has run the closure")
+  }
+
+  /// Run the closures posted by other cores and return their number. This shall be called on the owner core, e.g.
+  /// from the interrupt handler raised by the doorbell. If the owner core is accessing the value already, e.g. the
+  /// interrupt handler interrupted [CoreLocal::call_on_owner], this returns `0` and the closures are run once the
+  /// access has finished.
+  ///
+  /// # Panics
+  /// Panics if not called on the owner core
+  pub fn process(&self) -> usize {
+    assert_eq!(
+      core_id(),
+      self.owner,
+      "the CoreLocal is processed on a core that does not own it"
+    );
+
+    let mut processed = 0;
+    loop {
+      if self.busy.swap(true, Ordering::Acquire) {
+        return processed;
+      }
+      let mut ran = 0;
+      while let Some(call) = self.calls.take() {
+        // SAFETY: the calling core waits until the done flag is set, so its closure and the flag are alive. Only the
+        // owner core accesses the value and the busy flag prevents a nested access
+        unsafe {
+          (call.run)(call.closure, &mut *self.value.get());
+          (*call.done).store(true, Ordering::Release);
+        }
+        ran += 1;
+      }
+      self.busy.store(false, Ordering::Release);
+
+      if ran > 0 {
+        // wake the cores waiting for their closures or for a free slot
+        arch::signal_event();
+      }
+      processed += ran;
+      // a closure posted while the value has been accessed found the busy flag set, so it is run here
+      if self.calls.is_empty() {
+        return processed;
+      }
+    }
+  }
+
+  /// The number of closures waiting to be run by the owner core
+  pub fn pending(&self) -> usize {
+    self.calls.len()
+  }
+
+  /// Returns a mutable reference to the value. As this requires a mutable borrow of the [CoreLocal] no other core can
+  /// access it at the same time.
+  pub fn get_mut(&mut self) -> &mut T {
+    self.value.get_mut()
+  }
+
+  /// Consume the [CoreLocal] and return the value
+  pub fn into_inner(self) -> T {
+    self.value.into_inner()
+  }
+}
+
+/// The Debug implementation only reports the owner and the pending closures and never accesses the value
+impl<T, const N: usize> fmt::Debug for CoreLocal<T, N> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("CoreLocal")
+      .field("owner", &self.owner)
+      .field("pending", &self.pending())
+      .finish_non_exhaustive()
+  }
+}
+
+unsafe impl<T: Send, const N: usize> Sync for CoreLocal<T, N> {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn owner_core_calls_directly() {
+    // all host threads report core 0
+    let local: CoreLocal<u32> = CoreLocal::new(0, 1);
+    assert_eq!(local.call_on_owner(|value| core::mem::replace(value, 2)), 1);
+    assert_eq!(local.call_on_owner(|value| *value), 2);
+    assert_eq!(local.process(), 0);
+    assert_eq!(local.into_inner(), 2);
+  }
+
+  #[test]
+  #[should_panic(expected = "re-entrant")]
+  fn nested_access_on_the_owner_panics() {
+    let local: CoreLocal<u32> = CoreLocal::new(0, 0);
+    local.call_on_owner(|_| local.call_on_owner(|value| *value));
+  }
+
+  #[test]
+  #[should_panic(expected = "does not own it")]
+  fn processing_on_another_core_panics() {
+    let local: CoreLocal<u32> = CoreLocal::new(1, 0);
+    local.process();
+  }
+
+  #[test]
+  fn posted_closures_are_run_by_the_owner() {
+    unsafe fn add(closure: *mut (), value: &mut u32) {
+      *value += *(closure as *const u32);
+    }
+
+    let local: CoreLocal<u32, 2> = CoreLocal::new(0, 0);
+    let mut increments = [1u32, 2];
+    let done = [AtomicBool::new(false), AtomicBool::new(false)];
+    // simulate the closures another core posts
+    for (increment, done) in increments.iter_mut().zip(done.iter()) {
+      let call = Call {
+        closure: increment as *mut u32 as *mut (),
+        run: add,
+        done,
+      };
+      assert!(local.calls.post(call).is_ok());
+    }
+    assert_eq!(local.pending(), 2);
+    // further closures have to wait for a free slot
+    let call = Call {
+      closure: core::ptr::null_mut(),
+      run: |_, _| unreachable!(),
+      done: &done[0],
+    };
+    assert!(local.calls.post(call).is_err());
+
+    assert_eq!(local.process(), 2);
+    assert!(done.iter().all(|done| done.load(Ordering::Acquire)));
+    assert_eq!(local.pending(), 0);
+    assert_eq!(local.into_inner(), 3);
+  }
+
+  #[test]
+  fn closures_posted_during_an_access_are_run_afterwards() {
+    unsafe fn set(_: *mut (), value: &mut u32) {
+      *value = 10;
+    }
+
+    let local: CoreLocal<u32> = CoreLocal::new(0, 0);
+    let done = AtomicBool::new(false);
+    local.call_on_owner(|value| {
+      let call = Call {
+        closure: core::ptr::null_mut(),
+        run: set,
+        done: &done,
+      };
+      assert!(local.calls.post(call).is_ok());
+      // the owner is busy, so processing the closure is deferred
+      assert_eq!(local.process(), 0);
+      *value = 1;
+    });
+    assert!(done.load(Ordering::Acquire));
+    assert_eq!(local.into_inner(), 10);
+  }
+}
@@ -0,0 +1,158 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Critical Section
+//!
+//! A critical section disables the interrupts on the current core. Critical sections can be nested. Each core keeps
+//! track of its nesting level and only the outermost critical section restores the interrupt state that was active
+//! when it was entered. A critical section can be combined with a [Spinlock] to also guarantee exclusive access
+//! across cores.
+//!
+//! # Example
+//! ```no_run
+//! use ruspiro_lock::sync::{CriticalSection, Spinlock};
+//!
+//! static LOCK: Spinlock = Spinlock::new();
+//!
+//! fn main() {
+//!     let _cs = CriticalSection::enter();
+//!     {
+//!         // nested critical section that also acquires the spinlock
+//!         let _locked = CriticalSection::enter_locked(&LOCK);
+//!     }
+//!     // interrupts are still disabled here and restored once `_cs` is dropped
+//! }
+//! ```
+
+use super::spinlock::Spinlock;
+use crate::arch::MAX_CORES;
+use crate::config::core_id;
+#[cfg(target_arch = "aarch64")]
+use core::arch::asm;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Token of an entered critical section. Interrupts on the current core stay disabled as long as this or any other
+/// critical section on this core exists. The token is bound to the core that entered the critical section and can
+/// therefore not be send to another core.
+#[must_use = "if unused the critical section is immediately left"]
+pub struct CriticalSection<'a> {
+  lock: Option<&'a Spinlock>,
+  core: usize,
+  _not_send: PhantomData<*const ()>,
+}
+
+impl CriticalSection<'static> {
+  /// Enter a critical section on the current core by disabling the interrupts.
+  pub fn enter() -> Self {
+    let core = enter_section();
+    CriticalSection {
+      lock: None,
+      core,
+      _not_send: PhantomData,
+    }
+  }
+}
+
+impl<'a> CriticalSection<'a> {
+  /// Enter a critical section on the current core and acquire the given [Spinlock]. The lock is released before the
+  /// interrupt state is restored.
+  pub fn enter_locked(lock: &'a Spinlock) -> Self {
+    let core = enter_section();
+    lock.acquire();
+    CriticalSection {
+      lock: Some(lock),
+      core,
+      _not_send: PhantomData,
+    }
+  }
+
+  /// The current nesting level of critical sections on the current core
+  pub fn nesting_level() -> u32 {
+    NESTING[core_id()].load(Ordering::Relaxed)
+  }
+}
+
+impl Drop for CriticalSection<'_> {
+  fn drop(&mut self) {
+    if let Some(lock) = self.lock {
+      lock.release();
+    }
+    // as interrupts are disabled nothing else could interfere with the state of this core
+    if NESTING[self.core].fetch_sub(1, Ordering::Relaxed) == 1 {
+      restore_irq(SAVED_STATE[self.core].load(Ordering::Relaxed));
+    }
+  }
+}
+
+/// Disable the interrupts and increase the nesting level of the current core. Returns the core id.
+fn enter_section() -> usize {
+  let state = disable_irq();
+  let core = core_id();
+  if NESTING[core].fetch_add(1, Ordering::Relaxed) == 0 {
+    SAVED_STATE[core].store(state, Ordering::Relaxed);
+  }
+  core
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NESTING_INIT: AtomicU32 = AtomicU32::new(0);
+static NESTING: [AtomicU32; MAX_CORES] = [NESTING_INIT; MAX_CORES];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const STATE_INIT: AtomicUsize = AtomicUsize::new(0);
+static SAVED_STATE: [AtomicUsize; MAX_CORES] = [STATE_INIT; MAX_CORES];
+
+/// Disable the interrupts and return the previous interrupt state
+fn disable_irq() -> usize {
+  #[cfg(target_arch = "aarch64")]
+  unsafe {
+    let daif: usize;
+    asm!(
+      "mrs {}, daif",
+      "msr daifset, #2",
+      out(reg) daif
+    );
+    daif
+  }
+  #[cfg(not(target_arch = "aarch64"))]
+  0
+}
+
+/// Restore the interrupt state returned by [disable_irq]
+fn restore_irq(state: usize) {
+  #[cfg(target_arch = "aarch64")]
+  unsafe {
+    asm!("msr daif, {}", in(reg) state);
+  }
+  #[cfg(not(target_arch = "aarch64"))]
+  let _ = state;
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  // all host threads report the same core, so the nesting level is only checked within a single test
+  #[test]
+  fn nested_sections_track_their_level_and_release_the_lock() {
+    let lock = Spinlock::new();
+    let level = CriticalSection::nesting_level();
+    let outer = CriticalSection::enter();
+    assert_eq!(CriticalSection::nesting_level(), level + 1);
+    {
+      let _locked = CriticalSection::enter_locked(&lock);
+      assert_eq!(CriticalSection::nesting_level(), level + 2);
+      assert!(lock.try_acquire().is_err());
+    }
+    assert_eq!(CriticalSection::nesting_level(), level + 1);
+    assert!(lock.try_acquire().is_ok());
+    lock.release();
+    drop(outer);
+    assert_eq!(CriticalSection::nesting_level(), level);
+  }
+}
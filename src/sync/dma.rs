@@ -0,0 +1,177 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # DMA Access
+//!
+//! Buffers secured by a [RWLock] can be handed to a DMA engine while the write lock guarantees exclusive access. The
+//! data caches need to be maintained around such an access. As this is platform specific the cache maintenance
+//! functions can be registered with [set_cache_maintenance]. They are called when a [DmaBuffer] is created from a
+//! [WriteLockGuard] and when it is dropped.
+//!
+//! Cache maintenance works on whole cache lines. To not discard data the CPU wrote to a line shared with the buffer, the
+//! buffer need to start at a multiple of [CACHE_LINE_SIZE] and its length need to be a multiple of it.
+//!
+//! # Example
+//! ```no_run
+//! use ruspiro_lock::sync::{dma, RWLock};
+//!
+//! // the buffer covers whole cache lines
+//! #[repr(align(64))]
+//! struct Frame([u8; 64]);
+//!
+//! impl AsMut<[u8]> for Frame {
+//!     fn as_mut(&mut self) -> &mut [u8] {
+//!         &mut self.0
+//!     }
+//! }
+//!
+//! static BUFFER: RWLock<Frame> = RWLock::new(Frame([0; 64]));
+//!
+//! fn clean(addr: *const u8, len: usize) { /* clean data cache for the range */ }
+//! fn invalidate(addr: *const u8, len: usize) { /* invalidate data cache for the range */ }
+//!
+//! fn main() {
+//!     dma::set_cache_maintenance(dma::CacheMaintenance { clean, invalidate });
+//!
+//!     let mut guard = BUFFER.write();
+//!     let buffer = guard.as_dma_buffer();
+//!     // hand buffer.as_mut_ptr() and buffer.len() to the DMA engine and wait for the transfer to finish
+//!     drop(buffer);
+//! }
+//! ```
+
+use super::rwlock::{RWLock, WriteLockGuard};
+use core::marker::PhantomData;
+
+/// The size of a data cache line of the Cortex-A53 in bytes. The buffers handed to a DMA engine need to be aligned to
+/// and a multiple of it, as the cache maintenance of a partial line would also affect the data next to the buffer.
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// The cache maintenance functions called for the memory range of a [DmaBuffer]. Both functions get the start address
+/// and the length of the memory range. They can also be given to a [GuardedRegion](super::GuardedRegion) with
+/// [GuardedRegion::with_cache_maintenance](super::GuardedRegion::with_cache_maintenance).
+#[derive(Debug, Clone, Copy)]
+pub struct CacheMaintenance {
+  /// Clean the data cache for the memory range, so that the DMA engine sees all writes of the CPU. This is called when
+  /// the [DmaBuffer] is created.
+  pub clean: fn(*const u8, usize),
+  /// Invalidate the data cache for the memory range, so that the CPU sees all writes of the DMA engine. This is called
+  /// when the [DmaBuffer] is dropped.
+  pub invalidate: fn(*const u8, usize),
+}
+
+static CACHE_MAINTENANCE: RWLock<Option<CacheMaintenance>> = RWLock::new(None);
+
+/// Register the cache maintenance functions called around each DMA access. This replaces any functions registered
+/// before.
+pub fn set_cache_maintenance(maintenance: CacheMaintenance) {
+  CACHE_MAINTENANCE.replace(Some(maintenance));
+}
+
+/// Remove the registered cache maintenance functions
+pub fn clear_cache_maintenance() {
+  CACHE_MAINTENANCE.replace(None);
+}
+
+fn cache_maintenance() -> Option<CacheMaintenance> {
+  *CACHE_MAINTENANCE.read()
+}
+
+/// Raw access to a buffer secured by a [RWLock] that can be handed to a DMA engine. The exclusive access is guaranteed
+/// as long as this exists, as it borrows the [WriteLockGuard] it was created from.
+pub struct DmaBuffer<'a> {
+  ptr: *mut u8,
+  len: usize,
+  _guard: PhantomData<&'a mut [u8]>,
+}
+
+impl DmaBuffer<'_> {
+  /// The start address of the buffer
+  pub fn as_ptr(&self) -> *const u8 {
+    self.ptr
+  }
+
+  /// The start address of the buffer the DMA engine may write to
+  pub fn as_mut_ptr(&mut self) -> *mut u8 {
+    self.ptr
+  }
+
+  /// The length of the buffer in bytes
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  /// Returns `true` if the buffer has a length of 0
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+}
+
+impl Drop for DmaBuffer<'_> {
+  fn drop(&mut self) {
+    if let Some(maintenance) = cache_maintenance() {
+      (maintenance.invalidate)(self.ptr, self.len);
+    }
+  }
+}
+
+impl<T: ?Sized + AsMut<[u8]>> WriteLockGuard<'_, T> {
+  /// Provide the raw access to the locked buffer for a DMA transfer. The registered clean cache maintenance function
+  /// is called before this returns and the invalidate function is called once the [DmaBuffer] is dropped. This
+  /// requires `T: AsMut<[u8]>` as the DMA engine might write to the buffer.
+  ///
+  /// # Panics
+  /// Panics if the buffer does not start at a multiple of [CACHE_LINE_SIZE] or its length is not a multiple of it.
+  /// Invalidating the partial first or last line would discard writes of the CPU to the data sharing the line.
+  pub fn as_dma_buffer(&mut self) -> DmaBuffer<'_> {
+    let buffer = (**self).as_mut();
+    let ptr = buffer.as_mut_ptr();
+    let len = buffer.len();
+    assert!(
+      (ptr as usize).is_multiple_of(CACHE_LINE_SIZE) && len.is_multiple_of(CACHE_LINE_SIZE),
+      "a DMA buffer need to cover whole cache lines"
+    );
+    if let Some(maintenance) = cache_maintenance() {
+      (maintenance.clean)(ptr, len);
+    }
+
+    DmaBuffer {
+      ptr,
+      len,
+      _guard: PhantomData,
+    }
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  #[repr(align(64))]
+  struct Lines<const N: usize>([u8; N]);
+
+  impl<const N: usize> AsMut<[u8]> for Lines<N> {
+    fn as_mut(&mut self) -> &mut [u8] {
+      &mut self.0
+    }
+  }
+
+  #[test]
+  fn buffer_covering_whole_cache_lines_is_handed_out() {
+    let rwlock = RWLock::new(Lines([0u8; 128]));
+    let mut guard = rwlock.write();
+    let buffer = guard.as_dma_buffer();
+    assert_eq!(buffer.len(), 128);
+  }
+
+  #[test]
+  #[should_panic]
+  fn buffer_with_a_partial_cache_line_is_rejected() {
+    let rwlock = RWLock::new(Lines([0u8; 96]));
+    let _ = rwlock.write().as_dma_buffer();
+  }
+}
@@ -0,0 +1,288 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Event Bus
+//!
+//! Distribute events, like GPIO edges or interrupts, from any number of publishing cores to up to `SUBS` subscribers.
+//! A subscriber either registers a callback that is called on the publishing core, or a [Subscription] with its own
+//! queue of `DEPTH` events that can be polled or awaited in an async task.
+//!
+//! The subscribers are kept in a [RWLock]. Publishing only acquires the read lock, so events can be published from
+//! several cores at the same time, while subscribing and unsubscribing acquire the write lock. The callbacks are called
+//! once the read lock has been released, so they can subscribe and unsubscribe as well. A callback that is removed
+//! while an event is published might therefore still be called with this event. The queues are [Mailbox]es, if the
+//! queue of a subscriber is full the event is dropped for this subscriber.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::EventBus;
+//!
+//! #[derive(Clone, Copy, Debug, PartialEq)]
+//! struct Edge {
+//!     pin: u8,
+//!     rising: bool,
+//! }
+//!
+//! static GPIO_EVENTS: EventBus<Edge, 4> = EventBus::new();
+//!
+//! fn log_edge(edge: &Edge) {
+//!     println!("{:?}", edge);
+//! }
+//!
+//! fn main() {
+//!     let _logger = GPIO_EVENTS.subscribe(log_edge);
+//!     let button = GPIO_EVENTS.subscribe_queue().unwrap();
+//!
+//!     // in the GPIO interrupt handler
+//!     GPIO_EVENTS.publish(Edge { pin: 17, rising: true });
+//!
+//!     assert_eq!(button.try_recv(), Some(Edge { pin: 17, rising: true }));
+//! }
+//! ```
+
+use super::{Mailbox, RWLock};
+use core::fmt;
+
+/// Publish events of type `E` to up to `SUBS` subscribers, each subscription queueing up to `DEPTH` events
+pub struct EventBus<E, const SUBS: usize, const DEPTH: usize = 4> {
+  subscribers: RWLock<[Option<Subscriber<E>>; SUBS]>,
+  queues: [Mailbox<E, DEPTH>; SUBS],
+}
+
+/// The id of a subscriber of an [EventBus]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriberId(usize);
+
+/// A subscriber of an [EventBus] receiving the events through its queue. If this goes out of scope the subscriber is
+/// removed and the events left in its queue are dropped.
+pub struct Subscription<'a, E, const SUBS: usize, const DEPTH: usize = 4> {
+  bus: &'a EventBus<E, SUBS, DEPTH>,
+  id: SubscriberId,
+}
+
+enum Subscriber<E> {
+  /// The callback called with each published event
+  Callback(fn(&E)),
+  /// The events are posted to the queue of this subscriber
+  Queue,
+}
+
+// the subscriber only contains a function pointer, so it can be copied for any event type
+impl<E> Clone for Subscriber<E> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<E> Copy for Subscriber<E> {}
+
+impl<E, const SUBS: usize, const DEPTH: usize> EventBus<E, SUBS, DEPTH> {
+  /// An unused subscriber slot, used to initialize the slots in a `const fn`
+  const EMPTY: Option<Subscriber<E>> = None;
+  /// An empty subscriber queue, used to initialize the queues in a `const fn`
+  #[allow(clippy::declare_interior_mutable_const)]
+  const QUEUE: Mailbox<E, DEPTH> = Mailbox::new();
+
+  /// Create an [EventBus] without subscribers
+  pub const fn new() -> Self {
+    Self {
+      subscribers: RWLock::new([Self::EMPTY; SUBS]),
+      queues: [Self::QUEUE; SUBS],
+    }
+  }
+
+  /// Register a callback that is called with each published event on the publishing core. Returns `None` if all
+  /// `SUBS` subscriber slots are occupied.
+  pub fn subscribe(&self, callback: fn(&E)) -> Option<SubscriberId> {
+    self.register(Subscriber::Callback(callback))
+  }
+
+  /// Register a subscriber receiving the published events through its own queue. Returns `None` if all `SUBS`
+  /// subscriber slots are occupied.
+  pub fn subscribe_queue(&self) -> Option<Subscription<'_, E, SUBS, DEPTH>> {
+    let id = self.register(Subscriber::Queue)?;
+    Some(Subscription { bus: self, id })
+  }
+
+  /// Remove the callback subscriber with the given id. A [Subscription] is removed once it is dropped and shall not
+  /// be removed with this function, as its id might be handed out again afterwards.
+  pub fn unsubscribe(&self, id: SubscriberId) {
+    let mut subscribers = self.subscribers.write();
+    subscribers[id.0] = None;
+    // no event can be published while the write lock is held, so the queue is empty once the next subscriber uses it
+    while self.queues[id.0].take().is_some() {}
+  }
+
+  /// The number of registered subscribers
+  pub fn subscriber_count(&self) -> usize {
+    self
+      .subscribers
+      .read()
+      .iter()
+      .filter(|subscriber| subscriber.is_some())
+      .count()
+  }
+
+  fn register(&self, subscriber: Subscriber<E>) -> Option<SubscriberId> {
+    let mut subscribers = self.subscribers.write();
+    let id = subscribers.iter().position(Option::is_none)?;
+    subscribers[id] = Some(subscriber);
+    Some(SubscriberId(id))
+  }
+}
+
+impl<E: Clone, const SUBS: usize, const DEPTH: usize> EventBus<E, SUBS, DEPTH> {
+  /// Publish an event to all subscribers. The callbacks are called on the current core, the event is posted to the
+  /// queues of the other subscribers and wakes the task awaiting it. Returns the number of subscribers the event has
+  /// been delivered to, which excludes the subscribers with a full queue.
+  ///
+  /// The event is posted to the queues while the read lock is held, so it never reaches a subscriber registered after
+  /// it has been published. The callbacks are called after the read lock has been released.
+  pub fn publish(&self, event: E) -> usize {
+    let mut delivered = 0;
+    let callbacks = {
+      let subscribers = self.subscribers.read();
+      let mut callbacks = [None; SUBS];
+      for (id, subscriber) in subscribers.iter().enumerate() {
+        match subscriber {
+          Some(Subscriber::Callback(callback)) => callbacks[id] = Some(*callback),
+          Some(Subscriber::Queue) if self.queues[id].post(event.clone()).is_ok() => delivered += 1,
+          // the slot is unused or the queue of the subscriber is full
+          _ => (),
+        }
+      }
+      callbacks
+    };
+    // a callback might subscribe or unsubscribe, which requires the write lock
+    for callback in callbacks.iter().flatten() {
+      callback(&event);
+      delivered += 1;
+    }
+    delivered
+  }
+}
+
+impl<E, const SUBS: usize, const DEPTH: usize> Subscription<'_, E, SUBS, DEPTH> {
+  /// The id of this subscriber
+  pub fn id(&self) -> SubscriberId {
+    self.id
+  }
+
+  /// Take the oldest event from the queue of this subscriber. Returns `None` if there is no event.
+  pub fn try_recv(&self) -> Option<E> {
+    self.bus.queues[self.id.0].take()
+  }
+
+  /// Take the oldest event from the queue of this subscriber. The returned `Future` resolves once an event has been
+  /// published. Only one task shall await the events of a [Subscription] at a time.
+  pub async fn recv(&self) -> E {
+    self.bus.queues[self.id.0].take_async().await
+  }
+}
+
+// when the Subscription is dropped the subscriber is removed from the bus
+impl<E, const SUBS: usize, const DEPTH: usize> Drop for Subscription<'_, E, SUBS, DEPTH> {
+  fn drop(&mut self) {
+    self.bus.unsubscribe(self.id);
+  }
+}
+
+impl<E, const SUBS: usize, const DEPTH: usize> Default for EventBus<E, SUBS, DEPTH> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<E, const SUBS: usize, const DEPTH: usize> fmt::Debug for EventBus<E, SUBS, DEPTH> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("EventBus")
+      .field("subscribers", &self.subscriber_count())
+      .field("capacity", &SUBS)
+      .finish_non_exhaustive()
+  }
+}
+
+impl<E, const SUBS: usize, const DEPTH: usize> fmt::Debug for Subscription<'_, E, SUBS, DEPTH> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Subscription")
+      .field("id", &self.id)
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  #[test]
+  fn published_events_reach_all_subscribers() {
+    static CALLED: AtomicUsize = AtomicUsize::new(0);
+    fn count(event: &usize) {
+      CALLED.fetch_add(*event, Ordering::Relaxed);
+    }
+
+    let bus: EventBus<usize, 4, 2> = EventBus::new();
+    let callback = bus.subscribe(count).unwrap();
+    let queue = bus.subscribe_queue().unwrap();
+    assert_eq!(bus.subscriber_count(), 2);
+
+    assert_eq!(bus.publish(1), 2);
+    assert_eq!(bus.publish(2), 2);
+    // the queue of the subscriber is full, so the event is only delivered to the callback
+    assert_eq!(bus.publish(3), 1);
+    assert_eq!(CALLED.load(Ordering::Relaxed), 6);
+    assert_eq!(queue.try_recv(), Some(1));
+    assert_eq!(queue.try_recv(), Some(2));
+    assert_eq!(queue.try_recv(), None);
+
+    bus.unsubscribe(callback);
+    drop(queue);
+    assert_eq!(bus.subscriber_count(), 0);
+    assert_eq!(bus.publish(4), 0);
+    assert_eq!(CALLED.load(Ordering::Relaxed), 6);
+  }
+
+  #[test]
+  fn subscribers_are_limited_and_slots_reused() {
+    fn ignore(_: &u32) {}
+
+    let bus: EventBus<u32, 2> = EventBus::new();
+    let first = bus.subscribe(ignore).unwrap();
+    let _second = bus.subscribe(ignore).unwrap();
+    assert!(bus.subscribe(ignore).is_none());
+    assert!(bus.subscribe_queue().is_none());
+    bus.unsubscribe(first);
+    let queue = bus.subscribe_queue().unwrap();
+    assert_eq!(queue.id(), first);
+  }
+
+  #[test]
+  fn callbacks_can_subscribe_and_unsubscribe() {
+    static BUS: EventBus<u32, 4> = EventBus::new();
+    static SUBSCRIBED: AtomicUsize = AtomicUsize::new(usize::MAX);
+    fn follow_up(_: &u32) {}
+    fn toggle(_: &u32) {
+      match SUBSCRIBED.load(Ordering::Relaxed) {
+        usize::MAX => {
+          let id = BUS.subscribe(follow_up).unwrap();
+          SUBSCRIBED.store(id.0, Ordering::Relaxed);
+        }
+        id => {
+          BUS.unsubscribe(SubscriberId(id));
+          SUBSCRIBED.store(usize::MAX, Ordering::Relaxed);
+        }
+      }
+    }
+
+    BUS.subscribe(toggle).unwrap();
+    assert_eq!(BUS.publish(1), 1);
+    assert_eq!(BUS.subscriber_count(), 2);
+    assert_eq!(BUS.publish(2), 2);
+    assert_eq!(BUS.subscriber_count(), 1);
+  }
+}
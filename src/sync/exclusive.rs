@@ -0,0 +1,222 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Exclusive
+//!
+//! Each peripheral of the Raspberry Pi is represented by a single global instance, like the UART or the GPIO, that
+//! can only be created at runtime and is then accessed from any core. An [Exclusive] is placed in a static variable
+//! without a value and initialized exactly once with [Exclusive::init_once]. If several cores try to initialize it at
+//! the same time only the first one runs its initialization, while the others wait until it has finished. Afterwards
+//! the value is accessed mutual exclusive with [Exclusive::with].
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::Exclusive;
+//!
+//! struct Uart {
+//!     baud_rate: u32,
+//! }
+//!
+//! static UART: Exclusive<Uart> = Exclusive::new_uninit();
+//!
+//! fn main() {
+//!     // each core might call this at boot, only the first call initializes the UART
+//!     UART.init_once(|| Uart { baud_rate: 115_200 });
+//!     UART.init_once(|| Uart { baud_rate: 9_600 });
+//!
+//!     let baud_rate = UART.with(|uart| uart.baud_rate);
+//!     assert_eq!(baud_rate, 115_200);
+//! }
+//! ```
+
+use super::spin;
+use super::Mutex;
+use crate::arch;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// The [Exclusive] does not contain a value yet
+const UNINIT: u8 = 0;
+/// A core is running the initialization of the [Exclusive]
+const INITIALIZING: u8 = 1;
+/// The value of the [Exclusive] is initialized
+const READY: u8 = 2;
+
+/// A global value that is initialized exactly once at runtime and accessed mutual exclusive afterwards
+pub struct Exclusive<T> {
+  state: AtomicU8,
+  value: Mutex<MaybeUninit<T>>,
+}
+
+impl<T> Exclusive<T> {
+  /// Create the [Exclusive] without a value. As this does not require any allocation it can be assigned to a static
+  /// variable
+  pub const fn new_uninit() -> Self {
+    Self {
+      state: AtomicU8::new(UNINIT),
+      value: Mutex::new(MaybeUninit::uninit()),
+    }
+  }
+
+  /// Initialize the [Exclusive] with the value returned by the given function, unless it has been initialized
+  /// already. If another core is running its initialization at the same time, this waits until it has finished.
+  /// Returns `true` if the value has been initialized by this call.
+  ///
+  /// If the function panics the [Exclusive] stays uninitialized and can be initialized by another call.
+  pub fn init_once<F: FnOnce() -> T>(&self, init: F) -> bool {
+    let mut attempt = 0;
+    loop {
+      match self
+        .state
+        .compare_exchange(UNINIT, INITIALIZING, Ordering::Acquire, Ordering::Acquire)
+      {
+        Ok(_) => break,
+        Err(READY) => return false,
+        // another core is running the initialization, wait according to the selected spin policy
+        Err(_) => spin::on_contention(&mut attempt, "Exclusive::init_once"),
+      }
+    }
+
+    // reset the state if the initialization panics, so other cores do not wait forever
+    struct Reset<'a>(&'a AtomicU8);
+    impl Drop for Reset<'_> {
+      fn drop(&mut self) {
+        self.0.store(UNINIT, Ordering::Release);
+      }
+    }
+
+    let reset = Reset(&self.state);
+    let value = init();
+    self.value.lock().write(value);
+    core::mem::forget(reset);
+    self.state.store(READY, Ordering::Release);
+    // wake the cores waiting for the initialization
+    arch::signal_event();
+    true
+  }
+
+  /// Returns `true` if the [Exclusive] has been initialized
+  pub fn is_initialized(&self) -> bool {
+    self.state.load(Ordering::Acquire) == READY
+  }
+
+  /// Call the given function with mutual exclusive access to the value. This blocks while another core accesses the
+  /// value.
+  ///
+  /// # Panics
+  /// Panics if the [Exclusive] has not been initialized with [Exclusive::init_once]
+  pub fn with<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+    self
+      .try_with(f)
+      .expect("the Exclusive is accessed before it has been initialized")
+  }
+
+  /// Call the given function with mutual exclusive access to the value. Returns `None` if the [Exclusive] has not
+  /// been initialized yet.
+  pub fn try_with<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Option<R> {
+    if !self.is_initialized() {
+      return None;
+    }
+
+    let mut value = self.value.lock();
+    // SAFETY: the state is only set to READY after the value has been written
+    Some(f(unsafe { value.assume_init_mut() }))
+  }
+}
+
+impl<T> Default for Exclusive<T> {
+  fn default() -> Self {
+    Self::new_uninit()
+  }
+}
+
+impl<T> Drop for Exclusive<T> {
+  fn drop(&mut self) {
+    if *self.state.get_mut() == READY {
+      // SAFETY: the state is only set to READY after the value has been written
+      unsafe { self.value.lock().assume_init_drop() };
+    }
+  }
+}
+
+/// The Debug implementation only reports the state and never acquires the lock
+impl<T> fmt::Debug for Exclusive<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Exclusive")
+      .field("initialized", &self.is_initialized())
+      .field("locked", &self.value.is_locked())
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::sync::atomic::AtomicUsize;
+
+  #[test]
+  fn value_is_only_accessible_once_initialized() {
+    let exclusive: Exclusive<u32> = Exclusive::new_uninit();
+    assert!(!exclusive.is_initialized());
+    assert!(exclusive.try_with(|value| *value).is_none());
+    assert!(exclusive.init_once(|| 1));
+    assert!(!exclusive.init_once(|| 2));
+    exclusive.with(|value| *value += 10);
+    assert_eq!(exclusive.try_with(|value| *value), Some(11));
+  }
+
+  #[test]
+  #[should_panic]
+  fn with_panics_before_the_initialization() {
+    let exclusive: Exclusive<u32> = Exclusive::new_uninit();
+    exclusive.with(|_| ());
+  }
+
+  #[test]
+  fn panicking_initialization_can_be_retried() {
+    let exclusive: Exclusive<u32> = Exclusive::new_uninit();
+    let result = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+      exclusive.init_once(|| panic!("initialization failed"))
+    }));
+    assert!(result.is_err());
+    assert!(!exclusive.is_initialized());
+    assert!(exclusive.init_once(|| 1));
+    assert_eq!(exclusive.with(|value| *value), 1);
+  }
+
+  #[test]
+  fn only_one_of_concurrent_initializations_runs() {
+    const THREADS: usize = 4;
+
+    struct Counted<'a>(&'a AtomicUsize);
+    impl Drop for Counted<'_> {
+      fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+
+    let dropped = AtomicUsize::new(0);
+    let initialized = AtomicUsize::new(0);
+    let exclusive = Exclusive::new_uninit();
+    std::thread::scope(|s| {
+      for _ in 0..THREADS {
+        s.spawn(|| {
+          if exclusive.init_once(|| Counted(&dropped)) {
+            initialized.fetch_add(1, Ordering::Relaxed);
+          }
+          // the value is initialized once init_once returned, regardless of the core that initialized it
+          assert!(exclusive.is_initialized());
+        });
+      }
+    });
+    assert_eq!(initialized.load(Ordering::Relaxed), 1);
+    assert_eq!(dropped.load(Ordering::Relaxed), 0);
+    drop(exclusive);
+    assert_eq!(dropped.load(Ordering::Relaxed), 1);
+  }
+}
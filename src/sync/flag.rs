@@ -0,0 +1,270 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Flag
+//!
+//! Waiting until an interrupt handler reports that a DMA transfer is done is usually written as an `AtomicBool` set by
+//! the handler, followed by `sev`, and a loop of `wfe` on the waiting core. The [Flag] provides this pattern with the
+//! ordering and the events done right. [Flag::wait_set] blocks the core until the flag is set and, with the
+//! `async_locks_noalloc` feature, [Flag::wait_set_async] returns a `Future` that resolves once the flag is set. Both
+//! kinds of waiters can wait for the same flag at the same time.
+//!
+//! The data written before [Flag::set] is visible to the waiters once they see the flag set. The flag stays set until
+//! it is cleared with [Flag::clear], so a waiter starting after it has been set does not wait at all.
+//!
+//! The code behind a [Waker](core::task::Waker) might block or allocate, so an interrupt handler shall set the flag
+//! with [Flag::set_from_isr]. It only signals an event and defers waking the waiting `Future`s until one of them is
+//! polled or [Flag::wake_pending] is called.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::Flag;
+//!
+//! static DMA_DONE: Flag = Flag::new();
+//!
+//! fn dma_irq_handler() {
+//!     DMA_DONE.set_from_isr();
+//! }
+//!
+//! fn main() {
+//!     # dma_irq_handler();
+//!     // start the transfer and wait for the interrupt
+//!     DMA_DONE.wait_set();
+//!     DMA_DONE.clear();
+//! }
+//! ```
+
+use super::spin;
+use crate::arch;
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+use crate::r#async::waiters::WaiterSlots;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+use core::{
+  future::Future,
+  pin::Pin,
+  task::{Context, Poll},
+};
+
+/// A flag that can be waited for from blocking and from async code. Up to `WAITERS` `Future`s can wait for it at the
+/// same time without busy polling. If all waiter slots are occupied additional `Future`s re-schedule themself when
+/// polled until a slot gets available.
+pub struct Flag<const WAITERS: usize = 32> {
+  set: AtomicBool,
+  /// The flag has been set from an interrupt handler and the waiting `Future`s have not been woken yet
+  #[cfg(any(feature = "async_locks_noalloc", doc))]
+  deferred: AtomicBool,
+  #[cfg(any(feature = "async_locks_noalloc", doc))]
+  waiters: WaiterSlots<WAITERS>,
+}
+
+impl<const WAITERS: usize> Flag<WAITERS> {
+  /// Create a new [Flag] that is not set. As this does not require any allocation it can be assigned to a static
+  /// variable
+  pub const fn new() -> Self {
+    Self {
+      set: AtomicBool::new(false),
+      #[cfg(any(feature = "async_locks_noalloc", doc))]
+      deferred: AtomicBool::new(false),
+      #[cfg(any(feature = "async_locks_noalloc", doc))]
+      waiters: WaiterSlots::new(),
+    }
+  }
+
+  /// Set the flag and wake all cores and `Future`s waiting for it
+  pub fn set(&self) {
+    self.raise();
+    self.wake_waiters();
+  }
+
+  /// Set the flag from an interrupt handler. This wakes the cores waiting for the flag, but defers waking the waiting
+  /// `Future`s until one of them is polled or [Flag::wake_pending] is called. This only updates atomic values, so it
+  /// never waits for another core.
+  pub fn set_from_isr(&self) {
+    self.raise();
+    #[cfg(any(feature = "async_locks_noalloc", doc))]
+    self.deferred.store(true, Ordering::Release);
+  }
+
+  /// Clear the flag, so the next waiters wait until it is set again
+  pub fn clear(&self) {
+    self.set.store(false, Ordering::Relaxed);
+  }
+
+  /// Returns `true` if the flag is set. The data written before the flag has been set is visible afterwards.
+  pub fn is_set(&self) -> bool {
+    if self.set.load(Ordering::Acquire) {
+      // dmb required before allow access to the protected resource, see:
+      // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+      arch::dmb();
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Block until the flag is set. The core waits according to the selected [spin policy](super::spin) and is woken
+  /// by the event signalled when the flag is set.
+  pub fn wait_set(&self) {
+    let mut attempt = 0;
+    while !self.is_set() {
+      spin::on_contention(&mut attempt, "Flag::wait_set");
+    }
+  }
+
+  /// Returns a `Future` that resolves once the flag is set
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Flag;
+  /// async fn transfer(done: &Flag) {
+  ///     // start the transfer and wait for the interrupt handler to set the flag
+  ///     done.wait_set_async().await;
+  ///     done.clear();
+  /// }
+  /// ```
+  #[cfg(any(feature = "async_locks_noalloc", doc))]
+  pub fn wait_set_async(&self) -> FlagFuture<'_, WAITERS> {
+    FlagFuture {
+      flag: self,
+      ticket: None,
+    }
+  }
+
+  /// Wake the `Future`s whose wake up has been deferred by [Flag::set_from_isr]. The executor shall call this outside
+  /// of the interrupt handler, eg. in its idle loop, if the tasks waiting for the flag are not polled otherwise.
+  #[cfg(any(feature = "async_locks_noalloc", doc))]
+  pub fn wake_pending(&self) {
+    if self.deferred.load(Ordering::Relaxed) && self.deferred.swap(false, Ordering::Acquire) {
+      self.wake_waiters();
+    }
+  }
+
+  fn raise(&self) {
+    // dmb required before the waiters are allowed to access the data written before, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    self.set.store(true, Ordering::Release);
+    // raise a signal to continue the cores waiting for an event
+    arch::signal_event();
+  }
+
+  /// Wake all `Future`s waiting for the flag
+  fn wake_waiters(&self) {
+    #[cfg(any(feature = "async_locks_noalloc", doc))]
+    while let Some(waker) = self.waiters.take_next() {
+      waker.wake();
+    }
+  }
+}
+
+impl<const WAITERS: usize> Default for Flag<WAITERS> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<const WAITERS: usize> fmt::Debug for Flag<WAITERS> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Flag")
+      .field(&self.set.load(Ordering::Relaxed))
+      .finish()
+  }
+}
+
+/// The `Future` returned by [Flag::wait_set_async] that resolves once the [Flag] is set
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+pub struct FlagFuture<'a, const WAITERS: usize> {
+  flag: &'a Flag<WAITERS>,
+  /// The ticket the waker is registered with, once the `Future` had to wait
+  ticket: Option<usize>,
+}
+
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+impl<const WAITERS: usize> Future for FlagFuture<'_, WAITERS> {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    // the Future does not contain any self references, so it is fine to access it mutably
+    let this = self.get_mut();
+    // pass on the wake ups deferred by an interrupt handler while we are in the context of the executor
+    this.flag.wake_pending();
+    if this.flag.is_set() {
+      return Poll::Ready(());
+    }
+
+    let ticket = *this
+      .ticket
+      .get_or_insert_with(|| this.flag.waiters.next_ticket());
+    let registered = this.flag.waiters.register(ticket, cx.waker());
+    // the flag might have been set while we registered ourself, so check once more to not miss the wake up
+    if this.flag.is_set() {
+      this.flag.waiters.remove(ticket);
+      return Poll::Ready(());
+    }
+    if !registered {
+      // all waiter slots are occupied, so re-schedule ourself to try again
+      cx.waker().wake_by_ref();
+    }
+    Poll::Pending
+  }
+}
+
+/// A `Future` dropped while waiting shall no longer be woken
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+impl<const WAITERS: usize> Drop for FlagFuture<'_, WAITERS> {
+  fn drop(&mut self) {
+    if let Some(ticket) = self.ticket {
+      self.flag.waiters.remove(ticket);
+    }
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn waiters_continue_once_the_flag_is_set() {
+    let flag: Flag = Flag::new();
+    std::thread::scope(|s| {
+      let waiter = s.spawn(|| flag.wait_set());
+      assert!(!flag.is_set());
+      flag.set();
+      waiter.join().unwrap();
+    });
+    // the flag stays set until it is cleared
+    flag.wait_set();
+    flag.clear();
+    assert!(!flag.is_set());
+  }
+
+  #[test]
+  #[cfg(feature = "async_locks_noalloc")]
+  fn deferred_wake_up_reaches_the_waiting_future() {
+    use core::sync::atomic::AtomicUsize;
+    use core::task::Waker;
+
+    static WOKEN: AtomicUsize = AtomicUsize::new(0);
+    fn wake() {
+      WOKEN.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let flag: Flag<2> = Flag::new();
+    let waker: Waker = crate::r#async::waker::from_fn_static(wake);
+    let mut cx = Context::from_waker(&waker);
+    let mut future = flag.wait_set_async();
+    assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+
+    flag.set_from_isr();
+    assert_eq!(WOKEN.load(Ordering::SeqCst), 0);
+    flag.wake_pending();
+    assert_eq!(WOKEN.load(Ordering::SeqCst), 1);
+    assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+  }
+}
@@ -0,0 +1,167 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Held Locks
+//!
+//! With the `panic_release` feature each core keeps track of the [Spinlock](crate::sync::Spinlock)s and
+//! [Mutex](crate::sync::Mutex)es it currently holds. A panic handler can call [panic_release_all] to release them.
+//! Otherwise the panic reporting path would deadlock if it needs a lock, eg. the one of the UART, the panicking core
+//! already holds.
+//!
+//! Without the feature the tracking compiles to nothing.
+//!
+//! # Example
+//! ```no_run
+//! # #[cfg(feature = "panic_release")]
+//! # mod doc {
+//! use core::panic::PanicInfo;
+//!
+//! fn panic(info: &PanicInfo) -> ! {
+//!     // SAFETY: the panicking core will never continue to use the data secured by the released locks
+//!     let released = unsafe { ruspiro_lock::panic_release_all() };
+//!     // now it is safe to acquire the lock of the UART to report the panic
+//!     loop {}
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+
+#[cfg(feature = "panic_release")]
+use crate::arch::{self, MAX_CORES};
+#[cfg(feature = "panic_release")]
+use crate::config::core_id;
+use core::sync::atomic::AtomicBool;
+#[cfg(feature = "panic_release")]
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// The maximum number of locks tracked per core. Locks acquired while this number of locks is held are not tracked.
+#[cfg(feature = "panic_release")]
+pub const MAX_HELD_LOCKS: usize = 8;
+
+#[cfg(feature = "panic_release")]
+#[allow(clippy::declare_interior_mutable_const)]
+const SLOT_INIT: AtomicPtr<AtomicBool> = AtomicPtr::new(core::ptr::null_mut());
+#[cfg(feature = "panic_release")]
+#[allow(clippy::declare_interior_mutable_const)]
+const CORE_INIT: [AtomicPtr<AtomicBool>; MAX_HELD_LOCKS] = [SLOT_INIT; MAX_HELD_LOCKS];
+/// The lock flags of the locks currently held by each core
+#[cfg(feature = "panic_release")]
+static HELD: [[AtomicPtr<AtomicBool>; MAX_HELD_LOCKS]; MAX_CORES] = [CORE_INIT; MAX_CORES];
+
+/// Track the lock flag of a lock that has been acquired by the current core
+#[inline(always)]
+pub(crate) fn track(flag: &AtomicBool) {
+  #[cfg(feature = "panic_release")]
+  {
+    let flag = flag as *const AtomicBool as *mut AtomicBool;
+    // an interrupt on this core might acquire a lock in between, so the slot is claimed atomically
+    for slot in HELD[core_id()].iter() {
+      if slot
+        .compare_exchange(
+          core::ptr::null_mut(),
+          flag,
+          Ordering::Relaxed,
+          Ordering::Relaxed,
+        )
+        .is_ok()
+      {
+        return;
+      }
+    }
+  }
+  #[cfg(not(feature = "panic_release"))]
+  let _ = flag;
+}
+
+/// Stop tracking the lock flag of a lock that is released by the current core. The flag is looked up at the current
+/// core first, as the guard releasing it might have been sent to another core.
+#[inline(always)]
+pub(crate) fn untrack(flag: &AtomicBool) {
+  #[cfg(feature = "panic_release")]
+  untrack_from(core_id(), flag);
+  #[cfg(not(feature = "panic_release"))]
+  let _ = flag;
+}
+
+/// Stop tracking the lock flag released by the given core, looking it up at this core first and at the others after
+#[cfg(feature = "panic_release")]
+pub(crate) fn untrack_from(current: usize, flag: &AtomicBool) {
+  let flag = flag as *const AtomicBool as *mut AtomicBool;
+  let slots = (0..MAX_CORES).flat_map(|core| HELD[(current + core) % MAX_CORES].iter());
+  for slot in slots {
+    if slot
+      .compare_exchange(
+        flag,
+        core::ptr::null_mut(),
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+      )
+      .is_ok()
+    {
+      return;
+    }
+  }
+}
+
+/// Returns `true` if the lock flag is tracked as held by the current core. Without the `panic_release` feature no lock
+/// is tracked and this always returns `false`.
+#[inline(always)]
+pub(crate) fn is_held(flag: &AtomicBool) -> bool {
+  #[cfg(feature = "panic_release")]
+  {
+    let flag = flag as *const AtomicBool as *mut AtomicBool;
+    HELD[core_id()]
+      .iter()
+      .any(|slot| slot.load(Ordering::Relaxed) == flag)
+  }
+  #[cfg(not(feature = "panic_release"))]
+  {
+    let _ = flag;
+    false
+  }
+}
+
+/// Release all [Spinlock](crate::sync::Spinlock)s and [Mutex](crate::sync::Mutex)es held by the current core and
+/// return the number of released locks. This is intended to be called from a panic handler before it acquires any
+/// lock to report the panic.
+///
+/// # Safety
+/// The locks are released while their owner still believes to hold them. So the current core shall never return to
+/// the code that acquired them. In addition the held locks shall not have been moved or dropped, which is the case
+/// for locks in `static`s or ones held with a guard.
+#[cfg(feature = "panic_release")]
+pub unsafe fn panic_release_all() -> usize {
+  let mut released = 0;
+  for slot in HELD[core_id()].iter() {
+    let flag = slot.swap(core::ptr::null_mut(), Ordering::Relaxed);
+    if !flag.is_null() {
+      (*flag).store(false, Ordering::SeqCst);
+      released += 1;
+    }
+  }
+
+  if released > 0 {
+    arch::dmb();
+    arch::signal_event();
+  }
+  released
+}
+
+#[cfg(all(testing, feature = "panic_release"))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lock_released_on_another_core_is_no_longer_tracked() {
+    let flag = AtomicBool::new(true);
+    track(&flag);
+    assert!(is_held(&flag));
+    // the guard has been sent to the next core and is dropped there
+    untrack_from((core_id() + 1) % MAX_CORES, &flag);
+    assert!(!is_held(&flag));
+  }
+}
@@ -0,0 +1,171 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Atomic Histogram
+//!
+//! Counters only tell how often something happened, while the tail of a distribution, like the 99th percentile of
+//! the spins needed to acquire a lock, tells how bad it can get. The [AtomicHistogram] records values into buckets
+//! with a single atomic add, so it can be updated by all cores at the same time, even from interrupt handlers, without
+//! a lock.
+//!
+//! The buckets grow in powers of two. Bucket `0` counts the value `0` and bucket `i` the values from `2^(i-1)` up to
+//! `2^i - 1`. The last bucket counts all values that do not fit into the other buckets.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::AtomicHistogram;
+//!
+//! static LATENCY: AtomicHistogram<8> = AtomicHistogram::new();
+//!
+//! fn main() {
+//!     for value in [0, 1, 2, 3, 5, 100] {
+//!         LATENCY.record(value);
+//!     }
+//!     assert_eq!(LATENCY.total(), 6);
+//!     // half of the values are at most 2^2 - 1
+//!     assert_eq!(LATENCY.percentile(50), Some(3));
+//!     // print the buckets, eg. to the debug UART
+//!     println!("{}", LATENCY);
+//! }
+//! ```
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// A histogram of `BUCKETS` buckets whose size grows in powers of two, recorded with atomic operations only
+pub struct AtomicHistogram<const BUCKETS: usize> {
+  buckets: [AtomicU64; BUCKETS],
+}
+
+impl<const BUCKETS: usize> AtomicHistogram<BUCKETS> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const EMPTY: AtomicU64 = AtomicU64::new(0);
+
+  /// Create an empty [AtomicHistogram]. As this does not require any allocation it can be assigned to a static
+  /// variable
+  ///
+  /// # Panics
+  /// Panics if `BUCKETS` is 0 or exceeds the 65 buckets needed to cover all values of an `u64`
+  pub const fn new() -> Self {
+    assert!(
+      BUCKETS > 0 && BUCKETS <= 65,
+      "an AtomicHistogram requires 1 to 65 buckets"
+    );
+    Self {
+      buckets: [Self::EMPTY; BUCKETS],
+    }
+  }
+
+  /// The index of the bucket the given value is recorded in
+  pub const fn bucket_of(value: u64) -> usize {
+    let bucket = (u64::BITS - value.leading_zeros()) as usize;
+    if bucket < BUCKETS {
+      bucket
+    } else {
+      BUCKETS - 1
+    }
+  }
+
+  /// The largest value recorded in the bucket with the given index
+  ///
+  /// # Panics
+  /// Panics if the index is not less than `BUCKETS`
+  pub const fn upper_bound(bucket: usize) -> u64 {
+    assert!(bucket < BUCKETS, "the bucket does not exist");
+    if bucket == BUCKETS - 1 || bucket == 64 {
+      u64::MAX
+    } else {
+      (1 << bucket) - 1
+    }
+  }
+
+  /// Record the given value
+  #[inline]
+  pub fn record(&self, value: u64) {
+    self.buckets[Self::bucket_of(value)].fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// The number of values recorded in the bucket with the given index
+  ///
+  /// # Panics
+  /// Panics if the index is not less than `BUCKETS`
+  pub fn count(&self, bucket: usize) -> u64 {
+    self.buckets[bucket].load(Ordering::Relaxed)
+  }
+
+  /// A copy of the counts of all buckets. Values recorded while the copy is taken might be missing.
+  pub fn counts(&self) -> [u64; BUCKETS] {
+    core::array::from_fn(|bucket| self.count(bucket))
+  }
+
+  /// The number of values recorded in all buckets
+  pub fn total(&self) -> u64 {
+    self.counts().iter().sum()
+  }
+
+  /// The upper bound of the bucket containing the given percentile of the recorded values, eg. `99` for the value
+  /// that 99 percent of the recorded values do not exceed. Returns `None` if no value has been recorded.
+  ///
+  /// # Panics
+  /// Panics if the percentile exceeds `100`
+  pub fn percentile(&self, percentile: u32) -> Option<u64> {
+    assert!(percentile <= 100, "the percentile need to be at most 100");
+    let counts = self.counts();
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+      return None;
+    }
+
+    // the number of values that shall not exceed the percentile, rounded up
+    let required = (total as u128 * percentile as u128).div_ceil(100);
+    let mut recorded = 0_u128;
+    for (bucket, count) in counts.iter().enumerate() {
+      recorded += *count as u128;
+      if recorded >= required {
+        return Some(Self::upper_bound(bucket));
+      }
+    }
+    Some(u64::MAX)
+  }
+
+  /// Clear all buckets
+  pub fn reset(&self) {
+    for bucket in self.buckets.iter() {
+      bucket.store(0, Ordering::Relaxed);
+    }
+  }
+}
+
+impl<const BUCKETS: usize> Default for AtomicHistogram<BUCKETS> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<const BUCKETS: usize> fmt::Debug for AtomicHistogram<BUCKETS> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("AtomicHistogram")
+      .field("counts", &self.counts())
+      .finish()
+  }
+}
+
+/// Print one line with the upper bound and the count of each bucket that is not empty
+impl<const BUCKETS: usize> fmt::Display for AtomicHistogram<BUCKETS> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (bucket, count) in self.counts().iter().enumerate() {
+      if *count == 0 {
+        continue;
+      }
+      match Self::upper_bound(bucket) {
+        u64::MAX if bucket > 0 => writeln!(f, "> {}: {}", Self::upper_bound(bucket - 1), count)?,
+        bound => writeln!(f, "<= {}: {}", bound, count)?,
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,528 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # KeyedOnce
+//!
+//! If the block cache of the SD card misses a block that several cores request at the same time, each of them would
+//! read the same block from the card. A [KeyedOnce] lets only the first caller compute the value for a key, while the
+//! concurrent callers for the same key wait for it and all receive a clone of the value. Once the last of them took it,
+//! the key is forgotten, so the next request for the key computes the value again. Keeping the value is up to the
+//! cache the [KeyedOnce] is put in front of.
+//!
+//! Up to `N` keys can be computed at the same time. If all of them are in use, a caller for another key computes its
+//! value right away without sharing it. The keys and the callers waiting for them are kept in a table secured by a
+//! [Mutex], that is only held to update the table and never while a value is computed.
+//!
+//! If the computation panics or, with the `async_locks_noalloc` feature, the computing `Future` is dropped, the
+//! waiting callers do not receive a value. One of them takes over and computes it instead.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::KeyedOnce;
+//!
+//! static BLOCKS: KeyedOnce<u32, [u8; 16], 4> = KeyedOnce::new();
+//!
+//! fn read_block(block: u32) -> [u8; 16] {
+//!     // the concurrent requests of the same block share a single read from the card
+//!     BLOCKS.get_or_init(block, || [block as u8; 16])
+//! }
+//!
+//! fn main() {
+//!     assert_eq!(read_block(7)[0], 7);
+//! }
+//! ```
+
+use super::{spin, Mutex};
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+use crate::r#async::waiters::WaiterSlots;
+use core::fmt;
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+use core::{
+  future::Future,
+  pin::Pin,
+  task::{Context, Poll},
+};
+
+/// Computes the value of a key once for all concurrent callers requesting this key
+pub struct KeyedOnce<K, V, const N: usize> {
+  table: Mutex<Table<K, V, N>>,
+  /// The wakers of the `Future`s waiting for a value
+  #[cfg(any(feature = "async_locks_noalloc", doc))]
+  waiters: WaiterSlots<N>,
+}
+
+/// The keys currently computed or waiting to be taken by the callers
+struct Table<K, V, const N: usize> {
+  slots: [Slot<K, V>; N],
+  /// Identifies each computation, so a caller notices if the computation it waits for has been abandoned and the slot
+  /// reused meanwhile
+  next_flight: u32,
+}
+
+enum Slot<K, V> {
+  Free,
+  Computing {
+    key: K,
+    flight: u32,
+    waiting: u32,
+  },
+  Ready {
+    key: K,
+    flight: u32,
+    waiting: u32,
+    value: V,
+  },
+}
+
+/// The way a caller takes part in the computation of a key
+enum Join {
+  /// The caller computes the value in the given slot
+  Compute(usize, u32),
+  /// The caller waits for the value computed in the given slot
+  Wait(usize, u32),
+  /// All slots are in use by other keys, so the caller computes the value without sharing it
+  Uncached,
+}
+
+/// The state of the computation a caller waits for
+enum Status<V> {
+  Pending,
+  Ready(V),
+  /// The computation has been abandoned without providing a value
+  Abandoned,
+}
+
+impl<K, V, const N: usize> KeyedOnce<K, V, N> {
+  /// Create a new [KeyedOnce]. As this does not require any allocation it can be assigned to a static variable
+  pub const fn new() -> Self {
+    Self {
+      table: Mutex::new(Table {
+        slots: [Table::<K, V, N>::FREE; N],
+        next_flight: 0,
+      }),
+      #[cfg(any(feature = "async_locks_noalloc", doc))]
+      waiters: WaiterSlots::new(),
+    }
+  }
+
+  /// The number of keys that are currently computed or whose value has not been taken by all callers yet
+  pub fn in_flight(&self) -> usize {
+    self
+      .table
+      .lock()
+      .slots
+      .iter()
+      .filter(|slot| !matches!(slot, Slot::Free))
+      .count()
+  }
+}
+
+impl<K: Eq + Clone, V: Clone, const N: usize> KeyedOnce<K, V, N> {
+  /// Return the value of the given key. The first caller for a key computes it with the given function, the callers
+  /// requesting the same key meanwhile block until it is computed and receive a clone of it.
+  ///
+  /// The function shall not request a key of the same [KeyedOnce] while the value is shared with other callers, as
+  /// they might wait for each other forever.
+  pub fn get_or_init<F: FnOnce() -> V>(&self, key: K, f: F) -> V {
+    let mut attempt = 0;
+    loop {
+      let joined = self.table.lock().join(&key);
+      match joined {
+        Join::Uncached => return f(),
+        Join::Compute(index, flight) => {
+          let flight = Flight {
+            once: self,
+            index,
+            flight,
+          };
+          return flight.complete(f());
+        }
+        Join::Wait(index, flight) => loop {
+          let status = self.table.lock().take(index, flight);
+          match status {
+            Status::Ready(value) => return value,
+            // take over the computation that has been abandoned
+            Status::Abandoned => break,
+            // the event raised when the table is unlocked after the value has been stored wakes the core, depending
+            // on the selected spin policy
+            Status::Pending => spin::on_contention(&mut attempt, "KeyedOnce::get_or_init"),
+          }
+        },
+      }
+    }
+  }
+
+  /// Return the value of the given key like [KeyedOnce::get_or_init], but compute it with the `Future` returned by the
+  /// given function and `.await` the value computed by another caller. Up to `N` waiting `Future`s are woken once the
+  /// value is available, further ones re-schedule themself when polled.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::r#async::block_on;
+  /// # use ruspiro_lock::sync::KeyedOnce;
+  /// static BLOCKS: KeyedOnce<u32, [u8; 16], 4> = KeyedOnce::new();
+  ///
+  /// async fn read_from_card(block: u32) -> [u8; 16] {
+  ///     [block as u8; 16]
+  /// }
+  ///
+  /// # fn main() {
+  /// let block = block_on(BLOCKS.get_or_init_async(3, || read_from_card(3)));
+  /// assert_eq!(block[0], 3);
+  /// # }
+  /// ```
+  #[cfg(any(feature = "async_locks_noalloc", doc))]
+  pub async fn get_or_init_async<F, Fut>(&self, key: K, f: F) -> V
+  where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = V>,
+  {
+    loop {
+      let joined = self.table.lock().join(&key);
+      match joined {
+        Join::Uncached => return f().await,
+        Join::Compute(index, flight) => {
+          // the flight is abandoned if the `Future` is dropped while computing
+          let flight = Flight {
+            once: self,
+            index,
+            flight,
+          };
+          let value = f().await;
+          return flight.complete(value);
+        }
+        Join::Wait(index, flight) => {
+          let waiting = WaitFuture {
+            once: self,
+            index,
+            flight,
+            ticket: self.waiters.next_ticket(),
+            done: false,
+          };
+          if let Some(value) = waiting.await {
+            return value;
+          }
+        }
+      }
+    }
+  }
+
+  /// Wake the callers waiting for a value, as it became available or the computation has been abandoned
+  fn wake_waiters(&self) {
+    #[cfg(any(feature = "async_locks_noalloc", doc))]
+    while let Some(waker) = self.waiters.take_next() {
+      waker.wake();
+    }
+  }
+}
+
+impl<K, V, const N: usize> Default for KeyedOnce<K, V, N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<K, V, const N: usize> fmt::Debug for KeyedOnce<K, V, N> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("KeyedOnce")
+      .field("in_flight", &self.in_flight())
+      .field("capacity", &N)
+      .finish()
+  }
+}
+
+impl<K, V, const N: usize> Table<K, V, N> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const FREE: Slot<K, V> = Slot::Free;
+}
+
+impl<K: Eq + Clone, V: Clone, const N: usize> Table<K, V, N> {
+  /// Join the computation of the given key or start a new one in a free slot
+  fn join(&mut self, key: &K) -> Join {
+    let current = self
+      .slots
+      .iter_mut()
+      .enumerate()
+      .find_map(|(index, slot)| match slot {
+        Slot::Computing {
+          key: current,
+          flight,
+          waiting,
+        }
+        | Slot::Ready {
+          key: current,
+          flight,
+          waiting,
+          ..
+        } if current == key => {
+          *waiting += 1;
+          Some(Join::Wait(index, *flight))
+        }
+        _ => None,
+      });
+    if let Some(joined) = current {
+      return joined;
+    }
+
+    match self
+      .slots
+      .iter()
+      .position(|slot| matches!(slot, Slot::Free))
+    {
+      Some(index) => {
+        let flight = self.next_flight;
+        self.next_flight = self.next_flight.wrapping_add(1);
+        self.slots[index] = Slot::Computing {
+          key: key.clone(),
+          flight,
+          waiting: 0,
+        };
+        Join::Compute(index, flight)
+      }
+      None => Join::Uncached,
+    }
+  }
+
+  /// Take the value of the computation the caller waits for. The last waiting caller frees the slot.
+  fn take(&mut self, index: usize, flight: u32) -> Status<V> {
+    let slot = &mut self.slots[index];
+    match slot {
+      Slot::Computing {
+        flight: current, ..
+      } if *current == flight => Status::Pending,
+      Slot::Ready {
+        flight: current,
+        waiting,
+        value,
+        ..
+      } if *current == flight => {
+        *waiting -= 1;
+        if *waiting > 0 {
+          return Status::Ready(value.clone());
+        }
+        match core::mem::replace(slot, Slot::Free) {
+          Slot::Ready { value, .. } => Status::Ready(value),
+          _ => unreachable!(),
+        }
+      }
+      _ => Status::Abandoned,
+    }
+  }
+
+  /// Stop waiting for the computation without taking its value
+  #[cfg(any(feature = "async_locks_noalloc", doc))]
+  fn leave(&mut self, index: usize, flight: u32) {
+    let slot = &mut self.slots[index];
+    match slot {
+      Slot::Computing {
+        flight: current,
+        waiting,
+        ..
+      } if *current == flight => *waiting -= 1,
+      Slot::Ready {
+        flight: current,
+        waiting,
+        ..
+      } if *current == flight => {
+        *waiting -= 1;
+        if *waiting == 0 {
+          *slot = Slot::Free;
+        }
+      }
+      _ => (),
+    }
+  }
+
+  /// Provide the computed value to the waiting callers and return it to the computing one
+  fn complete(&mut self, index: usize, flight: u32, value: V) -> V {
+    let slot = &mut self.slots[index];
+    match core::mem::replace(slot, Slot::Free) {
+      Slot::Computing {
+        key,
+        flight: current,
+        waiting,
+      } if current == flight => {
+        if waiting > 0 {
+          *slot = Slot::Ready {
+            key,
+            flight,
+            waiting,
+            value: value.clone(),
+          };
+        }
+        value
+      }
+      other => {
+        *slot = other;
+        value
+      }
+    }
+  }
+
+  /// Free the slot of a computation that has been given up, so a waiting caller takes over
+  fn abandon(&mut self, index: usize, flight: u32) {
+    if matches!(self.slots[index], Slot::Computing { flight: current, .. } if current == flight) {
+      self.slots[index] = Slot::Free;
+    }
+  }
+}
+
+/// The computation of a key by the current caller. If it is dropped without being completed, eg. as the computation
+/// panicked, it is abandoned.
+struct Flight<'a, K: Eq + Clone, V: Clone, const N: usize> {
+  once: &'a KeyedOnce<K, V, N>,
+  index: usize,
+  flight: u32,
+}
+
+impl<K: Eq + Clone, V: Clone, const N: usize> Flight<'_, K, V, N> {
+  fn complete(self, value: V) -> V {
+    let value = self
+      .once
+      .table
+      .lock()
+      .complete(self.index, self.flight, value);
+    self.once.wake_waiters();
+    core::mem::forget(self);
+    value
+  }
+}
+
+impl<K: Eq + Clone, V: Clone, const N: usize> Drop for Flight<'_, K, V, N> {
+  fn drop(&mut self) {
+    self.once.table.lock().abandon(self.index, self.flight);
+    self.once.wake_waiters();
+  }
+}
+
+/// The `Future` waiting for the value computed by another caller. It resolves to `None` if the computation has been
+/// abandoned.
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+struct WaitFuture<'a, K: Eq + Clone, V: Clone, const N: usize> {
+  once: &'a KeyedOnce<K, V, N>,
+  index: usize,
+  flight: u32,
+  ticket: usize,
+  done: bool,
+}
+
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+impl<K: Eq + Clone, V: Clone, const N: usize> WaitFuture<'_, K, V, N> {
+  fn status(&mut self) -> Poll<Option<V>> {
+    let status = self.once.table.lock().take(self.index, self.flight);
+    let value = match status {
+      Status::Pending => return Poll::Pending,
+      Status::Ready(value) => Some(value),
+      Status::Abandoned => None,
+    };
+    self.done = true;
+    self.once.waiters.remove(self.ticket);
+    Poll::Ready(value)
+  }
+}
+
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+impl<K: Eq + Clone, V: Clone, const N: usize> Future for WaitFuture<'_, K, V, N> {
+  type Output = Option<V>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    // the Future does not contain any self references, so it is fine to access it mutably
+    let this = self.get_mut();
+    if let Poll::Ready(value) = this.status() {
+      return Poll::Ready(value);
+    }
+
+    let registered = this.once.waiters.register(this.ticket, cx.waker());
+    // the value might have been provided while we registered ourself, so check once more to not miss the wake up
+    if let Poll::Ready(value) = this.status() {
+      return Poll::Ready(value);
+    }
+    if !registered {
+      // all waiter slots are occupied, so re-schedule ourself to try again
+      cx.waker().wake_by_ref();
+    }
+    Poll::Pending
+  }
+}
+
+/// A waiting `Future` that is dropped no longer counts as waiting caller, so the value is not kept for it
+#[cfg(any(feature = "async_locks_noalloc", doc))]
+impl<K: Eq + Clone, V: Clone, const N: usize> Drop for WaitFuture<'_, K, V, N> {
+  fn drop(&mut self) {
+    if !self.done {
+      self.once.table.lock().leave(self.index, self.flight);
+      self.once.waiters.remove(self.ticket);
+    }
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  #[test]
+  fn concurrent_callers_share_a_single_computation() {
+    let once: KeyedOnce<u32, u32, 2> = KeyedOnce::new();
+    let computed = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+      for _ in 0..3 {
+        s.spawn(|| {
+          let value = once.get_or_init(7, || {
+            computed.fetch_add(1, Ordering::SeqCst);
+            // keep computing until the other callers joined
+            while !matches!(
+              once.table.lock().slots[0],
+              Slot::Computing { waiting: 2, .. }
+            ) {
+              std::thread::yield_now();
+            }
+            42
+          });
+          assert_eq!(value, 42);
+        });
+      }
+    });
+    assert_eq!(computed.load(Ordering::SeqCst), 1);
+    assert_eq!(once.in_flight(), 0);
+  }
+
+  #[test]
+  fn abandoned_computation_is_taken_over() {
+    let once: KeyedOnce<u32, u32, 2> = KeyedOnce::new();
+    let joined = once.table.lock().join(&7);
+    let index = match joined {
+      Join::Compute(index, flight) => {
+        // a caller joins before the computation panics
+        assert!(matches!(once.table.lock().join(&7), Join::Wait(..)));
+        drop(Flight {
+          once: &once,
+          index,
+          flight,
+        });
+        index
+      }
+      _ => panic!("computation not started"),
+    };
+    assert!(matches!(once.table.lock().slots[index], Slot::Free));
+    // the waiting caller notices the abandoned computation and computes the value itself
+    assert!(matches!(
+      once.table.lock().take(index, 0),
+      Status::Abandoned
+    ));
+    assert_eq!(once.get_or_init(7, || 42), 42);
+    assert_eq!(once.in_flight(), 0);
+  }
+
+  #[test]
+  fn callers_compute_uncached_if_all_slots_are_in_use() {
+    let once: KeyedOnce<u32, u32, 1> = KeyedOnce::new();
+    let value = once.get_or_init(1, || once.get_or_init(2, || 2) + 40);
+    assert_eq!(value, 42);
+    assert_eq!(once.in_flight(), 0);
+  }
+}
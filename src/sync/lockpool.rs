@@ -0,0 +1,286 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # LockPool
+//!
+//! Data structures with thousands of tiny entries, like an inode cache, can not afford an aligned lock in each entry.
+//! A [LockPool] manages a fixed number of spinlock slots instead and hands out [LockHandle]s to them, similar to the
+//! futex table of an operating system. An entry only stores its small [LockHandle], or derives it from its key with
+//! [LockPool::handle_of] each time it is accessed.
+//!
+//! Entries sharing a slot exclude each other, so the pool shall have enough slots to keep this rare. A core shall never
+//! hold the locks of two handles at the same time unless it acquires them in the order of their index, as two entries
+//! might share a slot or different cores acquiring them in a different order can deadlock.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::{LockHandle, LockPool};
+//!
+//! static INODE_LOCKS: LockPool<64> = LockPool::new();
+//!
+//! struct Inode {
+//!     number: u32,
+//!     size: u64,
+//! }
+//!
+//! fn main() {
+//!     let inode = Inode { number: 4711, size: 0 };
+//!     let handle: LockHandle = INODE_LOCKS.handle_of(&inode.number);
+//!     {
+//!         let _guard = INODE_LOCKS.lock(handle);
+//!         // the inode is exclusively accessed while the guard is alive
+//!     }
+//!     assert!(!INODE_LOCKS.is_locked(handle));
+//! }
+//! ```
+
+use super::held;
+use super::marker::GuardMarker;
+use super::metrics::{self, LockKind};
+use super::sharded::Fnv1a;
+use super::spin;
+use crate::arch;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A fixed set of `N` spinlock slots that are locked through [LockHandle]s
+pub struct LockPool<const N: usize> {
+  slots: [AtomicBool; N],
+}
+
+/// The index of a slot of a [LockPool]. It is small enough to be stored in each entry of a data structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LockHandle(u32);
+
+/// The guard of a locked slot of a [LockPool]. If this goes out of scope the slot is released.
+#[cfg_attr(
+  feature = "must_not_suspend",
+  must_not_suspend = "holding a LockPoolGuard across a suspend point blocks the other cores waiting for the slot"
+)]
+pub struct LockPoolGuard<'a> {
+  slot: &'a AtomicBool,
+  handle: LockHandle,
+  _marker: GuardMarker,
+}
+
+impl<const N: usize> LockPool<N> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const UNLOCKED: AtomicBool = AtomicBool::new(false);
+
+  /// Create a new [LockPool] with all slots unlocked
+  ///
+  /// # Panics
+  /// Panics if `N` is 0 or does not fit into a [LockHandle]
+  pub const fn new() -> Self {
+    assert!(
+      N > 0 && N <= u32::MAX as usize,
+      "a LockPool requires 1 to u32::MAX slots"
+    );
+    Self {
+      slots: [Self::UNLOCKED; N],
+    }
+  }
+
+  /// The number of slots of this pool
+  pub const fn slots(&self) -> usize {
+    N
+  }
+
+  /// The handle of the slot at the given index
+  ///
+  /// # Panics
+  /// Panics if the index is not less than `N`
+  pub fn handle(&self, index: usize) -> LockHandle {
+    assert!(index < N, "the LockPool has only {} slots", N);
+    LockHandle(index as u32)
+  }
+
+  /// The handle of the slot the given key is mapped to. The same key is always mapped to the same slot.
+  pub fn handle_of<K: Hash + ?Sized>(&self, key: &K) -> LockHandle {
+    let mut hasher = Fnv1a::default();
+    key.hash(&mut hasher);
+    LockHandle((hasher.finish() % N as u64) as u32)
+  }
+
+  /// Lock the slot of the given handle. This blocks until the slot could be locked.
+  ///
+  /// # Panics
+  /// Panics if the handle has been created by a pool with less slots
+  pub fn lock(&self, handle: LockHandle) -> LockPoolGuard<'_> {
+    let slot = &self.slots[handle.index()];
+    let mut attempt = 0;
+    while slot
+      .compare_exchange(false, true, Ordering::SeqCst, Ordering::Acquire)
+      .is_err()
+    {
+      // the slot is held by another core, wait according to the selected spin policy
+      spin::on_contention(&mut attempt, "LockPool::lock");
+    }
+    metrics::record(LockKind::LockPool, attempt);
+
+    Self::guard(slot, handle)
+  }
+
+  /// Try to lock the slot of the given handle. Returns `None` if the slot is currently locked.
+  ///
+  /// # Panics
+  /// Panics if the handle has been created by a pool with less slots
+  pub fn try_lock(&self, handle: LockHandle) -> Option<LockPoolGuard<'_>> {
+    let slot = &self.slots[handle.index()];
+    slot
+      .compare_exchange(false, true, Ordering::SeqCst, Ordering::Acquire)
+      .ok()?;
+
+    Some(Self::guard(slot, handle))
+  }
+
+  /// Whether the slot of the given handle is currently locked. The answer might be outdated already when it is
+  /// returned, so this is only meant for diagnostics.
+  ///
+  /// # Panics
+  /// Panics if the handle has been created by a pool with less slots
+  pub fn is_locked(&self, handle: LockHandle) -> bool {
+    self.slots[handle.index()].load(Ordering::Relaxed)
+  }
+
+  /// The number of slots currently locked
+  pub fn locked(&self) -> usize {
+    self
+      .slots
+      .iter()
+      .filter(|slot| slot.load(Ordering::Relaxed))
+      .count()
+  }
+
+  fn guard(slot: &AtomicBool, handle: LockHandle) -> LockPoolGuard<'_> {
+    held::track(slot);
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    LockPoolGuard {
+      slot,
+      handle,
+      _marker: PhantomData,
+    }
+  }
+}
+
+impl<const N: usize> Default for LockPool<N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<const N: usize> fmt::Debug for LockPool<N> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("LockPool")
+      .field("slots", &N)
+      .field("locked", &self.locked())
+      .finish()
+  }
+}
+
+impl LockHandle {
+  /// The index of the slot this handle refers to
+  pub const fn index(self) -> usize {
+    self.0 as usize
+  }
+}
+
+impl LockPoolGuard<'_> {
+  /// The handle of the slot locked by this guard
+  pub fn handle(&self) -> LockHandle {
+    self.handle
+  }
+}
+
+// when the LockPoolGuard is dropped release the slot
+impl Drop for LockPoolGuard<'_> {
+  fn drop(&mut self) {
+    held::untrack(self.slot);
+    // dmb required to finish all accesses to the protected resource before the slot is released, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    self.slot.store(false, Ordering::Release);
+    // wake the cores waiting for the slot
+    arch::signal_event();
+  }
+}
+
+impl fmt::Debug for LockPoolGuard<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("LockPoolGuard")
+      .field("handle", &self.handle)
+      .finish()
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::sync::atomic::AtomicUsize;
+
+  #[test]
+  fn slots_are_locked_independently() {
+    let pool: LockPool<4> = LockPool::new();
+    let first = pool.handle(0);
+    let second = pool.handle(3);
+    let guard = pool.lock(first);
+    assert_eq!(guard.handle(), first);
+    assert!(pool.is_locked(first));
+    assert!(pool.try_lock(first).is_none());
+    let other = pool.try_lock(second).unwrap();
+    assert_eq!(pool.locked(), 2);
+    drop(guard);
+    drop(other);
+    assert_eq!(pool.locked(), 0);
+    assert!(pool.try_lock(first).is_some());
+  }
+
+  #[test]
+  fn keys_are_mapped_to_the_same_slot() {
+    let pool: LockPool<8> = LockPool::new();
+    for key in 0..32u32 {
+      let handle = pool.handle_of(&key);
+      assert_eq!(handle, pool.handle_of(&key));
+      assert!(handle.index() < pool.slots());
+    }
+  }
+
+  #[test]
+  #[should_panic]
+  fn handles_beyond_the_slots_are_rejected() {
+    let pool: LockPool<4> = LockPool::new();
+    let _ = pool.handle(4);
+  }
+
+  #[test]
+  fn slot_excludes_concurrent_holders() {
+    const THREADS: usize = 4;
+    const UPDATES: usize = 1000;
+
+    let pool: LockPool<2> = LockPool::new();
+    let counter = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+      for _ in 0..THREADS {
+        s.spawn(|| {
+          for _ in 0..UPDATES {
+            let _guard = pool.lock(pool.handle(1));
+            // not an atomic increment, so updates get lost without mutual exclusion
+            let value = counter.load(Ordering::Relaxed);
+            counter.store(value + 1, Ordering::Relaxed);
+          }
+        });
+      }
+    });
+    assert_eq!(counter.load(Ordering::Relaxed), THREADS * UPDATES);
+    assert_eq!(pool.locked(), 0);
+  }
+}
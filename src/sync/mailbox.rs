@@ -0,0 +1,226 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Mailbox
+//!
+//! A fixed number of message slots to pass messages from one core to another. Posting a message optionally rings a
+//! doorbell, a function registered by the user that notifies the receiving core, e.g. by raising a software generated
+//! interrupt. The receiving core takes the messages either by polling or by awaiting them in an async task.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::Mailbox;
+//!
+//! static MAILBOX: Mailbox<u32, 4> = Mailbox::new();
+//!
+//! fn raise_sgi() {
+//!     // notify the receiving core, e.g. by raising a software generated interrupt
+//! }
+//!
+//! fn main() {
+//!     MAILBOX.set_doorbell(Some(raise_sgi));
+//!     // on the sending core
+//!     assert!(MAILBOX.post(42).is_ok());
+//!     // on the receiving core
+//!     assert_eq!(MAILBOX.take(), Some(42));
+//! }
+//! ```
+
+use super::{AtomicCell, Mutex};
+use core::fmt;
+use core::future::poll_fn;
+use core::task::{Context, Poll, Waker};
+
+/// A cross core mailbox with `N` message slots. The messages are taken in the order they have been posted.
+pub struct Mailbox<T, const N: usize> {
+  inner: Mutex<MailboxInner<T, N>>,
+  /// The function called after a message has been posted
+  doorbell: AtomicCell<Option<fn()>>,
+}
+
+struct MailboxInner<T, const N: usize> {
+  slots: [Option<T>; N],
+  /// The slot containing the oldest message
+  head: usize,
+  /// The number of messages in the mailbox
+  len: usize,
+  /// The waker of the task awaiting the next message
+  waker: Option<Waker>,
+}
+
+impl<T, const N: usize> Mailbox<T, N> {
+  /// An empty message slot, used to initialize the slots in a `const fn`
+  const EMPTY: Option<T> = None;
+
+  /// Create an empty [Mailbox] without a doorbell
+  pub const fn new() -> Self {
+    Self {
+      inner: Mutex::new(MailboxInner {
+        slots: [Self::EMPTY; N],
+        head: 0,
+        len: 0,
+        waker: None,
+      }),
+      doorbell: AtomicCell::new(None),
+    }
+  }
+
+  /// Register the doorbell that is called each time a message has been posted. Passing `None` removes the doorbell.
+  pub fn set_doorbell(&self, doorbell: Option<fn()>) {
+    self.doorbell.store(doorbell);
+  }
+
+  /// Post a message to the [Mailbox] and ring the doorbell. If all slots are occupied the message is given back in the
+  /// `Err` variant.
+  pub fn post(&self, message: T) -> Result<(), T> {
+    let mut inner = self.inner.lock();
+    if inner.len == N {
+      return Err(message);
+    }
+    let slot = (inner.head + inner.len) % N;
+    inner.slots[slot] = Some(message);
+    inner.len += 1;
+    let waker = inner.waker.take();
+    // release the lock before the receiver is notified, so it can immediately take the message
+    drop(inner);
+
+    if let Some(waker) = waker {
+      waker.wake();
+    }
+    if let Some(doorbell) = self.doorbell.load() {
+      doorbell();
+    }
+    Ok(())
+  }
+
+  /// Take the oldest message from the [Mailbox]. Returns `None` if there is no message.
+  pub fn take(&self) -> Option<T> {
+    self.inner.lock().pop()
+  }
+
+  /// Take the oldest message from the [Mailbox]. The returned `Future` resolves once a message has been posted. Only
+  /// one task shall await the messages of a [Mailbox] at a time.
+  pub async fn take_async(&self) -> T {
+    poll_fn(|cx| self.poll_take(cx)).await
+  }
+
+  /// Poll the oldest message from the [Mailbox]. If there is no message the waker of the given context is woken once
+  /// a message has been posted.
+  pub fn poll_take(&self, cx: &mut Context<'_>) -> Poll<T> {
+    let mut inner = self.inner.lock();
+    match inner.pop() {
+      Some(message) => Poll::Ready(message),
+      None => {
+        // the waker is stored while holding the lock, so a message posted afterwards will wake it
+        inner.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+
+  /// The number of messages in the [Mailbox]
+  pub fn len(&self) -> usize {
+    self.inner.lock().len
+  }
+
+  /// Returns `true` if there is no message in the [Mailbox]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl<T, const N: usize> MailboxInner<T, N> {
+  fn pop(&mut self) -> Option<T> {
+    if self.len == 0 {
+      return None;
+    }
+    let message = self.slots[self.head].take();
+    self.head = (self.head + 1) % N;
+    self.len -= 1;
+    message
+  }
+}
+
+impl<T, const N: usize> Default for Mailbox<T, N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T, const N: usize> fmt::Debug for Mailbox<T, N> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Mailbox")
+      .field("capacity", &N)
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+  use std::sync::Arc;
+  use std::task::Wake;
+
+  /// A waker recording whether it has been woken
+  struct WakeFlag(AtomicBool);
+
+  impl Wake for WakeFlag {
+    fn wake(self: Arc<Self>) {
+      self.0.store(true, Ordering::SeqCst);
+    }
+  }
+
+  #[test]
+  fn messages_are_taken_in_order_until_full() {
+    let mailbox: Mailbox<u32, 2> = Mailbox::new();
+    // wrap around the slots a few times
+    for message in 0..6 {
+      assert!(mailbox.post(message).is_ok());
+      if message % 2 == 1 {
+        assert_eq!(mailbox.post(99), Err(99));
+        assert_eq!(mailbox.len(), 2);
+        assert_eq!(mailbox.take(), Some(message - 1));
+        assert_eq!(mailbox.take(), Some(message));
+      }
+    }
+    assert!(mailbox.is_empty());
+    assert_eq!(mailbox.take(), None);
+  }
+
+  #[test]
+  fn doorbell_is_rung_for_each_posted_message() {
+    static RUNG: AtomicUsize = AtomicUsize::new(0);
+    fn doorbell() {
+      RUNG.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mailbox: Mailbox<u32, 1> = Mailbox::new();
+    mailbox.set_doorbell(Some(doorbell));
+    assert!(mailbox.post(1).is_ok());
+    // a message that does not fit does not ring the doorbell
+    assert!(mailbox.post(2).is_err());
+    assert_eq!(RUNG.load(Ordering::Relaxed), 1);
+    mailbox.set_doorbell(None);
+    mailbox.take();
+    assert!(mailbox.post(3).is_ok());
+    assert_eq!(RUNG.load(Ordering::Relaxed), 1);
+  }
+
+  #[test]
+  fn waiting_task_is_woken_by_a_posted_message() {
+    let mailbox: Mailbox<u32, 2> = Mailbox::new();
+    let flag = Arc::new(WakeFlag(AtomicBool::new(false)));
+    let waker = Waker::from(Arc::clone(&flag));
+    let mut cx = Context::from_waker(&waker);
+    assert_eq!(mailbox.poll_take(&mut cx), Poll::Pending);
+    assert!(!flag.0.load(Ordering::SeqCst));
+    assert!(mailbox.post(42).is_ok());
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert_eq!(mailbox.poll_take(&mut cx), Poll::Ready(42));
+  }
+}
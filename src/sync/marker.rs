@@ -0,0 +1,30 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Guard Marker
+//!
+//! The guards of the blocking locks carry a [GuardMarker]. With the `unsend_guards` feature it makes the guards
+//! `!Send`, so the compiler rejects holding a guard across an `.await` point of a task that need to be `Send`. Without
+//! the feature the marker has no effect.
+
+use core::marker::PhantomData;
+
+/// A type that is `Sync` but not `Send`
+#[cfg(feature = "unsend_guards")]
+pub(crate) struct NotSend(PhantomData<*const ()>);
+
+// the marker does not contain any data, it only shall not be `Send`
+#[cfg(feature = "unsend_guards")]
+unsafe impl Sync for NotSend {}
+
+/// The marker of the blocking lock guards
+#[cfg(feature = "unsend_guards")]
+pub(crate) type GuardMarker = PhantomData<NotSend>;
+
+/// The marker of the blocking lock guards
+#[cfg(not(feature = "unsend_guards"))]
+pub(crate) type GuardMarker = PhantomData<()>;
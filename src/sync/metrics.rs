@@ -0,0 +1,230 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Lock Metrics
+//!
+//! With the `metrics` feature each blocking acquisition of a lock records the number of failed attempts, the spins,
+//! it needed into an [AtomicHistogram] of its [LockKind]. The histograms show the distribution of the contention, so
+//! the worst cases are visible and not averaged away. An uncontended acquisition records `0` spins. The exact worst
+//! case of each kind is kept in a [Watermark] read with [max_spins].
+//!
+//! The spins of a lock labeled with a name, like a [Mutex](super::Mutex) created with
+//! [Mutex::new_named](super::Mutex::new_named), are additionally recorded into a histogram of its own that is looked
+//! up with [named_spins]. Up to [MAX_NAMED] names are tracked.
+//!
+//! Without the feature the recording compiles to nothing.
+//!
+//! # Example
+//! ```
+//! # #[cfg(feature = "metrics")]
+//! # mod doc {
+//! use ruspiro_lock::sync::metrics::{self, LockKind};
+//! use ruspiro_lock::sync::Mutex;
+//!
+//! static DATA: Mutex<u32> = Mutex::new_named(0, "data");
+//!
+//! fn report() {
+//!     *DATA.lock() += 1;
+//!     for kind in LockKind::ALL {
+//!         let spins = metrics::spins(kind);
+//!         let max = metrics::max_spins(kind).get();
+//!         // eg. print this to the debug UART
+//!         println!("{}: p99 {:?} spins, max {} spins\n{}", kind.name(), spins.percentile(99), max, spins);
+//!     }
+//!     for (name, spins) in metrics::named() {
+//!         println!("{}: p99 {:?} spins", name, spins.percentile(99));
+//!     }
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+
+#[cfg(feature = "metrics")]
+use super::{AtomicHistogram, Watermark};
+#[cfg(feature = "metrics")]
+use core::cell::UnsafeCell;
+#[cfg(feature = "metrics")]
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The number of buckets of the spin histograms. The last bucket counts acquisitions with 16384 spins or more.
+#[cfg(feature = "metrics")]
+pub const SPIN_BUCKETS: usize = 16;
+
+/// The maximum number of named locks whose spins are recorded into a histogram of their own
+#[cfg(feature = "metrics")]
+pub const MAX_NAMED: usize = 16;
+
+/// The kind of lock acquisition the spins are recorded for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockKind {
+  /// [Spinlock::acquire](super::Spinlock::acquire)
+  Spinlock,
+  /// [Mutex::lock](super::Mutex::lock) and [Mutex::lock_while](super::Mutex::lock_while)
+  Mutex,
+  /// [RWLock::read](super::RWLock::read) and [RWLock::upgradable_read](super::RWLock::upgradable_read)
+  RWLockRead,
+  /// [RWLock::write](super::RWLock::write), [RWLock::write_while](super::RWLock::write_while) and the upgrade of an
+  /// [UpgradableReadGuard](super::UpgradableReadGuard)
+  RWLockWrite,
+  /// [Semaphore::down](super::Semaphore::down) and [Semaphore::down_while](super::Semaphore::down_while)
+  Semaphore,
+  /// [LockPool::lock](super::LockPool::lock)
+  LockPool,
+}
+
+#[cfg(feature = "metrics")]
+impl LockKind {
+  /// All kinds of lock acquisitions
+  pub const ALL: [LockKind; 6] = [
+    LockKind::Spinlock,
+    LockKind::Mutex,
+    LockKind::RWLockRead,
+    LockKind::RWLockWrite,
+    LockKind::Semaphore,
+    LockKind::LockPool,
+  ];
+
+  /// The name of the kind of lock acquisition
+  pub const fn name(self) -> &'static str {
+    match self {
+      LockKind::Spinlock => "Spinlock",
+      LockKind::Mutex => "Mutex",
+      LockKind::RWLockRead => "RWLock::read",
+      LockKind::RWLockWrite => "RWLock::write",
+      LockKind::Semaphore => "Semaphore",
+      LockKind::LockPool => "LockPool",
+    }
+  }
+}
+
+#[cfg(feature = "metrics")]
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicHistogram<SPIN_BUCKETS> = AtomicHistogram::new();
+#[cfg(feature = "metrics")]
+static SPINS: [AtomicHistogram<SPIN_BUCKETS>; LockKind::ALL.len()] = [EMPTY; LockKind::ALL.len()];
+
+#[cfg(feature = "metrics")]
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_SPINS: Watermark = Watermark::new();
+#[cfg(feature = "metrics")]
+static MAX_SPINS: [Watermark; LockKind::ALL.len()] = [NO_SPINS; LockKind::ALL.len()];
+
+/// The histogram of the spins needed to acquire the given kind of lock
+#[cfg(feature = "metrics")]
+pub fn spins(kind: LockKind) -> &'static AtomicHistogram<SPIN_BUCKETS> {
+  &SPINS[kind as usize]
+}
+
+/// The most spins a single acquisition of the given kind of lock needed. Other than the histogram this is the exact
+/// worst case and not the bound of a bucket.
+#[cfg(feature = "metrics")]
+pub fn max_spins(kind: LockKind) -> &'static Watermark {
+  &MAX_SPINS[kind as usize]
+}
+
+/// The spins recorded for the locks with a name
+#[cfg(feature = "metrics")]
+struct NamedSpins {
+  /// The number of names that have been added. The entries up to this count are never changed again
+  count: AtomicUsize,
+  /// Set while a core adds a name
+  adding: AtomicBool,
+  names: [UnsafeCell<&'static str>; MAX_NAMED],
+  spins: [AtomicHistogram<SPIN_BUCKETS>; MAX_NAMED],
+}
+
+// a name is written only once before it is published with the count, so it is safe to share them
+#[cfg(feature = "metrics")]
+unsafe impl Sync for NamedSpins {}
+
+#[cfg(feature = "metrics")]
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_NAME: UnsafeCell<&'static str> = UnsafeCell::new("");
+#[cfg(feature = "metrics")]
+static NAMED: NamedSpins = NamedSpins {
+  count: AtomicUsize::new(0),
+  adding: AtomicBool::new(false),
+  names: [NO_NAME; MAX_NAMED],
+  spins: [EMPTY; MAX_NAMED],
+};
+
+/// The histogram of the spins needed to acquire the lock with the given name. Returns `None` if the lock with this
+/// name has not been acquired yet.
+#[cfg(feature = "metrics")]
+pub fn named_spins(name: &str) -> Option<&'static AtomicHistogram<SPIN_BUCKETS>> {
+  named().find_map(|(named, spins)| (named == name).then_some(spins))
+}
+
+/// Provide an iterator over the names and the histograms of the spins of all named locks recorded so far
+#[cfg(feature = "metrics")]
+pub fn named() -> impl Iterator<Item = (&'static str, &'static AtomicHistogram<SPIN_BUCKETS>)> {
+  let count = NAMED.count.load(Ordering::Acquire);
+  // SAFETY: the names up to the count are published and never changed again
+  (0..count).map(|idx| (unsafe { *NAMED.names[idx].get() }, &NAMED.spins[idx]))
+}
+
+/// Clear the histograms and the maximum spins of all kinds of locks and the histograms of all named locks. The names
+/// are kept.
+#[cfg(feature = "metrics")]
+pub fn reset() {
+  for spins in SPINS.iter().chain(NAMED.spins.iter()) {
+    spins.reset();
+  }
+  for max_spins in MAX_SPINS.iter() {
+    max_spins.reset();
+  }
+}
+
+/// The histogram of the lock with the given name. The name is added if it has not been recorded before. Returns
+/// `None` if all names are taken or another core is adding a name at the same time. Waiting for that core could
+/// deadlock if this interrupted it, so the spins are not recorded for the name in this case.
+#[cfg(feature = "metrics")]
+fn named_or_add(name: &'static str) -> Option<&'static AtomicHistogram<SPIN_BUCKETS>> {
+  if let Some(spins) = named_spins(name) {
+    return Some(spins);
+  }
+  if NAMED.adding.swap(true, Ordering::Acquire) {
+    return None;
+  }
+  // the name might have been added while this core checked the existing ones
+  let spins = named_spins(name).or_else(|| {
+    let idx = NAMED.count.load(Ordering::Relaxed);
+    if idx == MAX_NAMED {
+      return None;
+    }
+    // SAFETY: only the core that set the adding flag writes the name, which is not published yet
+    unsafe { *NAMED.names[idx].get() = name };
+    NAMED.count.store(idx + 1, Ordering::Release);
+    Some(&NAMED.spins[idx])
+  });
+  NAMED.adding.store(false, Ordering::Release);
+  spins
+}
+
+/// Record the number of failed attempts of a blocking acquisition that succeeded
+#[inline(always)]
+pub(crate) fn record(kind: LockKind, spins: u32) {
+  #[cfg(feature = "metrics")]
+  {
+    SPINS[kind as usize].record(spins as u64);
+    MAX_SPINS[kind as usize].record(spins as u64);
+  }
+  #[cfg(not(feature = "metrics"))]
+  let _ = (kind, spins);
+}
+
+/// Record the number of failed attempts of a blocking acquisition of a lock that might be labeled with a name
+#[inline(always)]
+pub(crate) fn record_named(kind: LockKind, name: Option<&'static str>, spins: u32) {
+  record(kind, spins);
+  #[cfg(feature = "metrics")]
+  if let Some(spins_of_name) = name.and_then(named_or_add) {
+    spins_of_name.record(spins as u64);
+  }
+  #[cfg(not(feature = "metrics"))]
+  let _ = name;
+}
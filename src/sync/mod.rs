@@ -9,6 +9,9 @@
 //!
 //!
 
+// the marker of the blocking lock guards
+mod marker;
+
 mod spinlock;
 #[doc(inline)]
 pub use spinlock::*;
@@ -18,11 +21,178 @@ mod semaphore;
 #[doc(inline)]
 pub use semaphore::*;
 
+// re-export the semaphore clamped at a single permit
+mod binarysemaphore;
+#[doc(inline)]
+pub use binarysemaphore::*;
+
 // re-export the data-lock
 mod mutex;
 #[doc(inline)]
 pub use mutex::*;
 
+// re-export the data carrying spinlock
+mod spinmutex;
+#[doc(inline)]
+pub use spinmutex::*;
+
 // re-export the data read/write lock
 mod rwlock;
 pub use rwlock::*;
+
+// re-export the global value initialized once and accessed mutual exclusive
+mod exclusive;
+#[doc(inline)]
+pub use exclusive::*;
+
+// re-export the locks securing pinned data
+mod pinned;
+#[doc(inline)]
+pub use pinned::*;
+
+// re-export the read/write lock updated with transactions
+mod txlock;
+#[doc(inline)]
+pub use txlock::*;
+
+// re-export the read/write lock split into shards
+mod sharded;
+#[doc(inline)]
+pub use sharded::*;
+
+// re-export the pool of spinlock slots locked through handles
+mod lockpool;
+#[doc(inline)]
+pub use lockpool::*;
+
+// re-export the iterators over collections secured by a read/write lock
+mod rwlockiter;
+#[doc(inline)]
+pub use rwlockiter::*;
+
+// re-export the bounded lock aquisition diagnostics
+mod budget;
+#[doc(inline)]
+pub use budget::*;
+
+// re-export the fixup of secured data on unwind
+mod unwind;
+#[doc(inline)]
+pub use unwind::*;
+
+// re-export the critical section
+mod critical;
+#[doc(inline)]
+pub use critical::*;
+
+// re-export the volatile lock for memory mapped registers
+mod volatile;
+#[doc(inline)]
+pub use volatile::*;
+
+// re-export the lock for MMIO register blocks
+mod register;
+#[doc(inline)]
+pub use register::*;
+
+// re-export the read/write lock for raw memory regions
+mod region;
+#[doc(inline)]
+pub use region::*;
+
+// re-export the computation of a value once for all concurrent callers of the same key
+mod keyedonce;
+#[doc(inline)]
+pub use keyedonce::*;
+
+// re-export the atomic cell
+mod atomiccell;
+#[doc(inline)]
+pub use atomiccell::*;
+
+// re-export the reference counted access to data without allocation
+mod shared;
+#[doc(inline)]
+pub use shared::*;
+
+// re-export the histogram recorded with atomic operations
+mod histogram;
+#[doc(inline)]
+pub use histogram::*;
+
+// re-export the largest and smallest values recorded with atomic operations
+mod watermark;
+#[doc(inline)]
+pub use watermark::*;
+
+// re-export the flag waited for by blocking and async code
+mod flag;
+#[doc(inline)]
+pub use flag::*;
+
+// re-export the atomic bitset
+mod bitset;
+#[doc(inline)]
+pub use bitset::*;
+
+// re-export the data updated with queued mutations applied in batches
+mod batched;
+#[doc(inline)]
+pub use batched::*;
+
+// re-export the cross core mailbox
+mod mailbox;
+#[doc(inline)]
+pub use mailbox::*;
+
+// re-export the value owned by a single core accessed by shipping closures to it
+mod corelocal;
+#[doc(inline)]
+pub use corelocal::*;
+
+// re-export the event bus distributing events to subscribers
+mod eventbus;
+#[doc(inline)]
+pub use eventbus::*;
+
+// re-export the traits abstracting over the blocking locks
+mod traits;
+#[doc(inline)]
+pub use traits::*;
+
+// re-export the spinlock shared with a peer that might die while holding it
+mod robust;
+#[doc(inline)]
+pub use robust::*;
+
+// re-export the atomic arc
+#[cfg(any(feature = "alloc", doc))]
+mod atomicarc;
+#[cfg(any(feature = "alloc", doc))]
+#[doc(inline)]
+pub use atomicarc::*;
+
+// re-export the read-copy-update
+#[cfg(any(feature = "alloc", doc))]
+mod rcu;
+#[cfg(any(feature = "alloc", doc))]
+#[doc(inline)]
+pub use rcu::*;
+
+// re-export the release of the locks held by the current core
+mod held;
+#[cfg(feature = "panic_release")]
+#[doc(inline)]
+pub use held::*;
+
+// the tracking of the RWLocks held by the current core
+mod reentrancy;
+
+pub mod ceiling;
+pub mod dma;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(not(feature = "metrics"))]
+pub(crate) mod metrics;
+pub mod registry;
+pub mod spin;
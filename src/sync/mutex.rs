@@ -9,7 +9,7 @@
 //!
 //! Enable exclusive access to data guarded by a cross core atomic lock. In contrast to a ``Singleton``
 //! the data access lock could also be non-blocking and might fail. But exclusive access is guaranteed
-//! across cores if the lock could be aquired.
+//! across cores if the lock could be acquired.
 //!
 //! # Example
 //! ```
@@ -34,24 +34,79 @@
 //! The data might also be wrapped in an ``Arc<Mutex<T>>`` and shared between cores using clones
 //! of the ``Arc``.
 //!
+//! # Trait objects
+//! A Mutex can secure a dynamically sized value like a trait object. As the data is the last field of the Mutex any
+//! pointer to a `Mutex<T>` coerces into a pointer to a `Mutex<dyn Trait>` if `T` implements the trait. This works for
+//! plain references, e.g. to build a static registry of drivers, as well as for `Box` and `Arc`. With the `alloc`
+//! feature [Mutex::new_unsized] allocates a Mutex that is meant to be coerced into a trait object.
+//! ```
+//! use ruspiro_lock::sync::Mutex;
+//!
+//! trait Serial {
+//!     fn send(&mut self, byte: u8);
+//! }
+//!
+//! struct Uart(u32);
+//!
+//! impl Serial for Uart {
+//!     fn send(&mut self, byte: u8) {
+//!         self.0 += byte as u32;
+//!     }
+//! }
+//!
+//! static UART0: Mutex<Uart> = Mutex::new(Uart(0));
+//! static UART1: Mutex<Uart> = Mutex::new(Uart(1));
+//! static SERIALS: [&Mutex<dyn Serial + Send>; 2] = [&UART0, &UART1];
+//!
+//! fn main() {
+//!     for serial in SERIALS.iter() {
+//!         serial.lock().send(b'a');
+//!     }
+//! }
+//! ```
+//!
+
+#[cfg(any(feature = "alloc", doc))]
+extern crate alloc;
 
-use core::arch::asm;
+use super::budget::{self, LockBudgetExceeded};
+use super::held;
+use super::marker::GuardMarker;
+use super::metrics::{self, LockKind};
+use super::registry::{InspectLock, LockState};
+use super::spin;
+use super::unwind::UnwindGuard;
+use super::BlockingLock;
+use crate::{arch, LockError};
+#[cfg(any(feature = "alloc", doc))]
+use alloc::boxed::Box;
+use core::any::Any;
 use core::cell::UnsafeCell;
 use core::fmt;
+use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
 /// An mutual exclusive access lock for the interior data
 #[repr(C, align(16))]
 pub struct Mutex<T: ?Sized> {
   locked: AtomicBool,
+  /// Increased each time the lock is released, so observers can detect whether the lock changed hands
+  generation: AtomicU32,
+  /// The label of the lock reported in diagnostics
+  name: Option<&'static str>,
   data: UnsafeCell<T>,
 }
 
-/// The MutexGuard is the result of successfully aquiring the mutual exclusive lock for the interior
+/// The MutexGuard is the result of successfully acquiring the mutual exclusive lock for the interior
 /// data. If this guard goes ot of scope the lock will be released
+#[cfg_attr(
+  feature = "must_not_suspend",
+  must_not_suspend = "holding a MutexGuard across a suspend point blocks the other cores waiting for the lock"
+)]
 pub struct MutexGuard<'a, T: ?Sized + 'a> {
   _data: &'a Mutex<T>,
+  _marker: GuardMarker,
 }
 
 impl<T> Mutex<T> {
@@ -59,9 +114,56 @@ impl<T> Mutex<T> {
   pub const fn new(value: T) -> Self {
     Mutex {
       locked: AtomicBool::new(false),
+      generation: AtomicU32::new(0),
+      name: None,
+      data: UnsafeCell::new(value),
+    }
+  }
+
+  /// Create a new data access guarding lock labeled with the given name. The name is reported by the Debug
+  /// implementation, an exceeded [lock budget](Mutex::lock_with_budget), the [metrics](super::metrics) and the
+  /// [registry](super::registry), so the lock can be identified in diagnostics without knowing its address.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Mutex;
+  /// static UART0: Mutex<u32> = Mutex::new_named(0, "uart0");
+  /// # fn main() {
+  ///     assert_eq!(UART0.name(), Some("uart0"));
+  ///     println!("{:?}", UART0);
+  /// # }
+  /// ```
+  pub const fn new_named(value: T, name: &'static str) -> Self {
+    Mutex {
+      locked: AtomicBool::new(false),
+      generation: AtomicU32::new(0),
+      name: Some(name),
       data: UnsafeCell::new(value),
     }
   }
+
+  /// Create a new data access guarding lock on the heap that is meant to be coerced into a Mutex of a dynamically
+  /// sized type, like a trait object.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Mutex;
+  /// # use core::fmt::Debug;
+  /// # fn main() {
+  ///     let data: Box<Mutex<dyn Debug + Send>> = Mutex::new_unsized(10u32);
+  ///     println!("{:?}", data.debug_value());
+  /// # }
+  /// ```
+  #[cfg(any(feature = "alloc", doc))]
+  pub fn new_unsized(value: T) -> Box<Self> {
+    Box::new(Self::new(value))
+  }
+}
+
+impl<T> From<T> for Mutex<T> {
+  fn from(value: T) -> Self {
+    Self::new(value)
+  }
 }
 
 impl<T: ?Sized> Mutex<T> {
@@ -79,22 +181,70 @@ impl<T: ?Sized> Mutex<T> {
   ///     }
   /// # }
   /// ```
-  pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+  pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+    // if the lock is already held a plain read is sufficient to fail. This keeps the cache line shared across the
+    // contending cores instead of invalidating it with a write on each attempt
+    if self.locked.load(Ordering::Relaxed) {
+      return None;
+    }
+
     // do the atomic operation to set the lock
-    if !self.locked.swap(true, Ordering::Acquire) {
-      // has been false previously means we now have the lock
-
-      #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-      unsafe {
-        // dmb required before allow access to the protected resource, see:
-        // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
-        asm!("dmb sy");
+    loop {
+      match self
+        .locked
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+      {
+        // has been false previously means we now have the lock
+        Ok(_) => break,
+        // we couldn't set the lock
+        Err(true) => return None,
+        // the weak exchange failed spuriously, eg. due to an interrupt between the exclusive load and store, so retry
+        Err(false) => continue,
       }
+    }
+    Some(self.acquired())
+  }
+
+  /// Try to lock the interior data with a single attempt like [Mutex::try_lock], but without retrying a spurious
+  /// failure. This might return ``None`` even though the Mutex is not locked, eg. if an interrupt occurs between the
+  /// exclusive load and store of the lock state. It is intended for custom spin loops, like the one of a scheduler,
+  /// that retry anyway. Each attempt then executes a single exclusive load/store pair.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Mutex;
+  /// static DATA: Mutex<u32> = Mutex::new(10);
+  /// # fn main() {
+  ///     let data = loop {
+  ///         if let Some(data) = DATA.try_lock_weak() {
+  ///             break data;
+  ///         }
+  ///         // run another task while the lock is held
+  ///     };
+  /// # }
+  /// ```
+  pub fn try_lock_weak(&self) -> Option<MutexGuard<'_, T>> {
+    if self.locked.load(Ordering::Relaxed) {
+      return None;
+    }
+    self
+      .locked
+      .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+      .ok()?;
+    Some(self.acquired())
+  }
+
+  /// Create the guard for the lock that has just been acquired by the current core
+  fn acquired(&self) -> MutexGuard<'_, T> {
+    held::track(&self.locked);
 
-      Some(MutexGuard { _data: self })
-    } else {
-      // we couldn't set the lock
-      None
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+
+    MutexGuard {
+      _data: self,
+      _marker: PhantomData,
     }
   }
 
@@ -113,20 +263,186 @@ impl<T: ?Sized> Mutex<T> {
   ///
   /// # }
   /// ```
-  pub fn lock(&self) -> MutexGuard<T> {
+  pub fn lock(&self) -> MutexGuard<'_, T> {
+    let mut attempt = 0;
     loop {
-      if let Some(data) = self.try_lock() {
+      if let Some(data) = self.try_lock_weak() {
+        metrics::record_named(LockKind::Mutex, self.name, attempt);
         return data;
       }
+      // a spurious failure is retried right away, as no core would raise the event the policy might wait for
+      if !self.locked.load(Ordering::Relaxed) {
+        continue;
+      }
       // to save energy and cpu consumption we can wait for an event beeing raised that indicates that the
-      // mutex lock have liekly been released
-      #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-      unsafe {
-        asm!("wfe");
+      // mutex lock have liekly been released, depending on the selected spin policy
+      spin::on_contention(&mut attempt, self.name.unwrap_or("Mutex::lock"));
+    }
+  }
+
+  /// Lock the guarded data like [Mutex::lock], but report the conditions that would prevent [Mutex::lock] from ever
+  /// returning as error instead of blocking forever. With the `panic_release` feature each core tracks the locks it
+  /// holds, so locking a Mutex again on the core that already holds it fails with [LockError::Deadlock]. Without the
+  /// feature this cannot be detected and this blocks like [Mutex::lock].
+  ///
+  /// Further conditions, like [LockError::Poisoned] or [LockError::Closed], are reported here once the Mutex supports
+  /// them, while [Mutex::lock] stays infallible.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::{sync::Mutex, LockError};
+  /// static DATA: Mutex<u32> = Mutex::new(10);
+  /// # fn main() {
+  ///     match DATA.lock_checked() {
+  ///         Ok(mut data) => *data = 15,
+  ///         Err(LockError::Deadlock) => println!("DATA is already locked by this core"),
+  ///         Err(error) => println!("{}", error),
+  ///     }
+  /// # }
+  /// ```
+  pub fn lock_checked(&self) -> Result<MutexGuard<'_, T>, LockError> {
+    if held::is_held(&self.locked) {
+      return Err(LockError::Deadlock);
+    }
+    Ok(self.lock())
+  }
+
+  /// Lock the guarded data like [Mutex::lock], but give up once the lock could not be acquired after `max_spins`
+  /// failed attempts. The exceeded budget is reported to the hook selected with
+  /// [set_budget_hook](super::set_budget_hook) and returned as error, so code with a deadline can report the
+  /// contention instead of silently missing it.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Mutex;
+  /// static DATA: Mutex<u32> = Mutex::new(10);
+  /// # fn main() {
+  ///     match DATA.lock_with_budget(100) {
+  ///         Ok(mut data) => *data = 15,
+  ///         Err(exceeded) => println!("{}", exceeded),
+  ///     }
+  /// # }
+  /// ```
+  pub fn lock_with_budget(&self, max_spins: u32) -> Result<MutexGuard<'_, T>, LockBudgetExceeded> {
+    self
+      .lock_while(|attempt| attempt < max_spins)
+      .ok_or_else(|| budget::exceeded(self, self.name, max_spins))
+  }
+
+  /// Lock the guarded data like [Mutex::lock], but call `keep_waiting` with the number of failed attempts each time
+  /// the core is about to wait again. If it returns `false` waiting is aborted and `None` is returned. This allows to
+  /// stop waiting on external conditions, like a shutdown flag or a deadline, without a global time source.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Mutex;
+  /// # use core::sync::atomic::{AtomicBool, Ordering};
+  /// static DATA: Mutex<u32> = Mutex::new(10);
+  /// static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+  /// # fn main() {
+  ///     if let Some(mut data) = DATA.lock_while(|_| !SHUTDOWN.load(Ordering::Relaxed)) {
+  ///         *data = 15;
+  ///     }
+  /// # }
+  /// ```
+  pub fn lock_while<F: FnMut(u32) -> bool>(
+    &self,
+    mut keep_waiting: F,
+  ) -> Option<MutexGuard<'_, T>> {
+    let mut attempt = 0;
+    loop {
+      if let Some(data) = self.try_lock() {
+        metrics::record_named(LockKind::Mutex, self.name, attempt);
+        return Some(data);
+      }
+      if !keep_waiting(attempt) {
+        return None;
       }
+      spin::on_contention(&mut attempt, self.name.unwrap_or("Mutex::lock_while"));
+    }
+  }
+
+  /// Lock the data, replace it with the given value and return the previous one. This blocks until the lock could be
+  /// acquired and releases it before returning.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Mutex;
+  /// static DATA: Mutex<u32> = Mutex::new(10);
+  /// # fn main() {
+  ///     let old = DATA.set(20);
+  ///     assert_eq!(old, 10);
+  ///     assert_eq!(*DATA.lock(), 20);
+  /// # }
+  /// ```
+  pub fn set(&self, value: T) -> T
+  where
+    T: Sized,
+  {
+    let mut data = self.lock();
+    core::mem::replace(&mut *data, value)
+  }
+
+  /// Returns `true` if the Mutex is currently locked. This only reads the lock flag and never acquires the lock. The
+  /// result might already be outdated when it is returned, so it shall only be used for diagnostics.
+  pub fn is_locked(&self) -> bool {
+    self.locked.load(Ordering::Relaxed)
+  }
+
+  /// The name the Mutex has been labeled with by [Mutex::new_named]
+  pub fn name(&self) -> Option<&'static str> {
+    self.name
+  }
+
+  /// Subscribe to the changes of the lock state. The returned [MutexWatch] reports whether the lock has been released
+  /// since it was last checked without acquiring the lock itself. This allows a watchdog to detect a lock that is held
+  /// forever.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Mutex;
+  /// static DATA: Mutex<u32> = Mutex::new(10);
+  /// # fn main() {
+  ///     let mut watch = DATA.subscribe();
+  ///     // ... some time later, eg. in a timer interrupt
+  ///     if DATA.is_locked() && !watch.changed() {
+  ///         // the lock is held by the same owner since the last check
+  ///     }
+  /// # }
+  /// ```
+  pub fn subscribe(&self) -> MutexWatch<'_, T> {
+    MutexWatch {
+      mutex: self,
+      generation: self.generation.load(Ordering::Acquire),
     }
   }
 
+  /// Raw pointer to the data secured by the Mutex
+  pub(crate) fn data_ptr(&self) -> *mut T {
+    self.data.get()
+  }
+
+  /// Release the lock and signal this to waiting cores.
+  ///
+  /// # Safety
+  /// The caller need to own the lock, which is the case if it has forgotten the [MutexGuard]
+  pub(crate) unsafe fn unlock(&self) {
+    held::untrack(&self.locked);
+    // only the owner of the lock updates the generation, so there is no need for an atomic increment
+    self.generation.store(
+      self.generation.load(Ordering::Relaxed).wrapping_add(1),
+      Ordering::Release,
+    );
+    self.locked.swap(false, Ordering::Release);
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // also raise a signal to indicate the mutex has been changed (this trigger all WFE's to continue
+    // processing) but do data syncronisation barrier upfront to ensure any data updates has been finished
+    arch::signal_event();
+  }
+
   /// Consume the Mutex and return the inner value
   pub fn into_inner(self) -> T
   where
@@ -134,40 +450,157 @@ impl<T: ?Sized> Mutex<T> {
   {
     self.data.into_inner()
   }
+
+  /// Release the Mutex regardless of which core holds it and signal this to the waiting cores. This is intended for
+  /// exception recovery code that need to reclaim a lock held by a crashed core.
+  ///
+  /// # Safety
+  /// No [MutexGuard] of this Mutex shall be used or dropped afterwards, e.g. because the core holding it has been
+  /// halted or reset. The secured data might be left in an inconsistent state by the former holder, so the caller need
+  /// to restore a consistent state before the data is used again.
+  pub unsafe fn force_unlock(&self) {
+    self.unlock();
+  }
+
+  /// The current state of the Mutex. This only reads the lock flag and never acquires the lock, so it is safe to be
+  /// used while the lock is held by the current core, eg. from a panic handler.
+  pub fn fmt_state(&self) -> LockState {
+    LockState::Mutex {
+      locked: self.is_locked(),
+    }
+  }
+
+  /// Provide a [fmt::Debug] representation of the secured data. Formatting it tries to acquire the lock and prints
+  /// `<locked>` if this fails. In contrast to the [fmt::Debug] implementation of the Mutex this does acquire the lock
+  /// and shall therefore only be used where this is known to be safe.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Mutex;
+  /// static DATA: Mutex<u32> = Mutex::new(10);
+  /// # fn main() {
+  ///     println!("{:?}", DATA.debug_value());
+  /// # }
+  /// ```
+  pub fn debug_value(&self) -> impl fmt::Debug + '_
+  where
+    T: fmt::Debug,
+  {
+    DebugValue(self)
+  }
 }
 
-impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+/// The Debug implementation only reports the lock state and never acquires the lock. Use [Mutex::debug_value] to
+/// print the secured data.
+impl<T: ?Sized> fmt::Debug for Mutex<T> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    let mut dbg = f.debug_struct("Mutex");
-    match self.try_lock() {
-      Some(guard) => {
-        dbg.field("Value", &&*guard);
-      }
-      _ => {
-        dbg.field("Value", &"unable to lock");
-      }
+    let mut debug = f.debug_struct("Mutex");
+    if let Some(name) = self.name {
+      debug.field("name", &name);
     }
-    dbg.finish_non_exhaustive()
+    debug
+      .field("locked", &self.is_locked())
+      .finish_non_exhaustive()
+  }
+}
+
+struct DebugValue<'a, T: ?Sized>(&'a Mutex<T>);
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for DebugValue<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.0.try_lock() {
+      Some(guard) => fmt::Debug::fmt(&*guard, f),
+      None => f.write_str("<locked>"),
+    }
+  }
+}
+
+/// Observes the lock state of a [Mutex] without acquiring it. It is created with [Mutex::subscribe].
+pub struct MutexWatch<'a, T: ?Sized> {
+  mutex: &'a Mutex<T>,
+  generation: u32,
+}
+
+impl<T: ?Sized> MutexWatch<'_, T> {
+  /// Returns `true` if the [Mutex] has been released at least once since the last call or since the subscription.
+  pub fn changed(&mut self) -> bool {
+    let generation = self.mutex.generation.load(Ordering::Acquire);
+    let changed = generation != self.generation;
+    self.generation = generation;
+    changed
+  }
+}
+
+impl<T: ?Sized> fmt::Debug for MutexWatch<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MutexWatch")
+      .field("generation", &self.generation)
+      .finish_non_exhaustive()
+  }
+}
+
+impl<T: ?Sized> BlockingLock<T> for Mutex<T> {
+  type Guard<'a>
+    = MutexGuard<'a, T>
+  where
+    Self: 'a;
+
+  fn lock(&self) -> Self::Guard<'_> {
+    Mutex::lock(self)
+  }
+
+  fn try_lock(&self) -> Option<Self::Guard<'_>> {
+    Mutex::try_lock(self)
+  }
+}
+
+impl<T: ?Sized + Send> InspectLock for Mutex<T> {
+  fn lock_state(&self) -> LockState {
+    self.fmt_state()
+  }
+
+  fn lock_name(&self) -> Option<&'static str> {
+    self.name
+  }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+  /// Returns the [Mutex] this guard has been acquired from. This allows a function that is handed the guard to
+  /// temporarily release the lock and acquire it again without the need to pass the lock separately.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::{Mutex, MutexGuard};
+  /// fn let_others_in(guard: MutexGuard<'_, u32>) -> MutexGuard<'_, u32> {
+  ///     let mutex = guard.mutex();
+  ///     drop(guard);
+  ///     // other cores may acquire the lock here
+  ///     mutex.lock()
+  /// }
+  ///
+  /// # fn main() {
+  ///     let data = Mutex::new(10);
+  ///     let guard = let_others_in(data.lock());
+  ///     assert_eq!(*guard, 10);
+  /// # }
+  /// ```
+  pub fn mutex(&self) -> &'a Mutex<T> {
+    self._data
+  }
+
+  /// Register a fixup closure that is called with the secured data if the returned guard is dropped while the core
+  /// unwinds from a panic. The fixup runs before the lock is released, so other cores never see the data it resets.
+  /// See [set_panicking_hook](super::set_panicking_hook) on how unwinding is detected.
+  pub fn on_unwind<F: FnOnce(&mut T)>(self, fixup: F) -> UnwindGuard<'a, T, F> {
+    UnwindGuard::new(self, fixup)
   }
 }
 
 // when the MutexGuard is dropped release the owning lock
 impl<T: ?Sized> Drop for MutexGuard<'_, T> {
   fn drop(&mut self) {
-    self._data.locked.swap(false, Ordering::Release);
-
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    unsafe {
-      // dmb required before allow access to the protected resource, see:
-      // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
-      asm!("dmb sy");
-      // also raise a signal to indicate the mutex has been changed (this trigger all WFE's to continue
-      // processing) but do data syncronisation barrier upfront to ensure any data updates has been finished
-      asm!(
-        "dsb sy
-         sev"
-      );
-    }
+    // SAFETY: the guard does only exist if the lock is owned
+    unsafe { self._data.unlock() };
   }
 }
 
@@ -179,16 +612,70 @@ impl<T: ?Sized> Deref for MutexGuard<'_, T> {
   type Target = T;
 
   fn deref(&self) -> &T {
-    unsafe { &*self._data.data.get() }
+    unsafe { &*self._data.data_ptr() }
   }
 }
 
 impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
   fn deref_mut(&mut self) -> &mut T {
-    unsafe { &mut *self._data.data.get() }
+    unsafe { &mut *self._data.data_ptr() }
   }
 }
 
+/// Implement the downcast functions for guards of a [Mutex] securing a type erased value. `$value` is the path from the
+/// guard to the `dyn Any` value.
+macro_rules! impl_downcast {
+  ($(#[$attr:meta])* $any:ty, |$guard:ident| $value:expr) => {
+    $(#[$attr])*
+    impl MutexGuard<'_, $any> {
+      /// Returns a reference to the secured value if it is of type `U`
+      pub fn downcast_ref<U: Any>(&self) -> Option<&U> {
+        let $guard = self;
+        $value.downcast_ref::<U>()
+      }
+
+      /// Returns a mutable reference to the secured value if it is of type `U`
+      pub fn downcast_mut<U: Any>(&mut self) -> Option<&mut U> {
+        let $guard = self;
+        $value.downcast_mut::<U>()
+      }
+    }
+  };
+}
+
+impl_downcast!(dyn Any, |guard| (**guard));
+impl_downcast!(dyn Any + Send, |guard| (**guard));
+impl_downcast!(
+  #[cfg(any(feature = "alloc", doc))]
+  Box<dyn Any>,
+  |guard| (***guard)
+);
+impl_downcast!(
+  /// The guards of a `Mutex<Box<dyn Any + Send>>` allow a registry of type erased driver states to hand out the typed
+  /// state under the lock.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Mutex;
+  /// # use core::any::Any;
+  /// struct UartState {
+  ///     baud_rate: u32,
+  /// }
+  ///
+  /// # fn main() {
+  ///     let slot: Mutex<Box<dyn Any + Send>> = Mutex::new(Box::new(UartState { baud_rate: 115_200 }));
+  ///     let mut guard = slot.lock();
+  ///     if let Some(state) = guard.downcast_mut::<UartState>() {
+  ///         state.baud_rate = 9_600;
+  ///     }
+  ///     assert!(guard.downcast_ref::<u32>().is_none());
+  /// # }
+  /// ```
+  #[cfg(any(feature = "alloc", doc))]
+  Box<dyn Any + Send>,
+  |guard| (***guard)
+);
+
 /// implement debug trait to forward to the type wrapped within the guard
 impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -198,3 +685,25 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
 
 /// The Mutex is always `Sync`, to make it `Send` as well it need to be wrapped into an `Arc`.
 unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+#[cfg(all(testing, feature = "panic_release"))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lock_checked_succeeds_once_released_on_another_core() {
+    let mutex = Mutex::new(10);
+    let guard = mutex.lock_checked().unwrap();
+    assert_eq!(mutex.lock_checked().err(), Some(LockError::Deadlock));
+
+    // the lock is held by a guard that can be sent to another core, like the one of an AsyncMutex, and released there
+    core::mem::forget(guard);
+    held::untrack_from(
+      (crate::config::core_id() + 1) % arch::MAX_CORES,
+      &mutex.locked,
+    );
+    mutex.locked.store(false, Ordering::Release);
+
+    assert_eq!(*mutex.lock_checked().unwrap(), 10);
+  }
+}
@@ -0,0 +1,232 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Pinned Locks
+//!
+//! A self-referential state machine, like a `Future` that is polled by the interrupt handler of a device, must not
+//! move once it has been polled. The [Mutex] and [RWLock] hand out mutable references to the secured data, so the
+//! data could be swapped with another value while a guard is held and can therefore not be pinned inside them.
+//!
+//! The [PinMutex] and [PinRWLock] only hand out their guards wrapped in a [Pin] once they are pinned themself. The
+//! secured data is never moved until the lock is dropped, so it can be accessed as `Pin<&mut T>` and polled without
+//! any unsafe code. If the data is [Unpin] the pinned guards can be used like the guards of the [Mutex] and [RWLock].
+//!
+//! # Example
+//! ```
+//! use core::future::Future;
+//! use core::pin::Pin;
+//! use core::task::{Context, Poll};
+//! use ruspiro_lock::sync::PinMutex;
+//!
+//! async fn transfer() -> u32 {
+//!     42
+//! }
+//!
+//! fn poll_transfer<F: Future<Output = u32>>(
+//!     transfer: Pin<&PinMutex<F>>,
+//!     cx: &mut Context<'_>,
+//! ) -> Poll<u32> {
+//!     transfer.lock().as_mut().poll(cx)
+//! }
+//!
+//! fn main() {
+//!     let transfer = core::pin::pin!(PinMutex::new(transfer()));
+//!     let mut cx = Context::from_waker(core::task::Waker::noop());
+//!     assert_eq!(poll_transfer(transfer.as_ref(), &mut cx), Poll::Ready(42));
+//! }
+//! ```
+
+use super::{Mutex, MutexGuard, RWLock, ReadLockGuard, WriteLockGuard};
+use core::fmt;
+use core::pin::Pin;
+
+/// A [Mutex] whose secured data is pinned as long as the lock itself is pinned
+pub struct PinMutex<T: ?Sized> {
+  inner: Mutex<T>,
+}
+
+/// A [RWLock] whose secured data is pinned as long as the lock itself is pinned
+pub struct PinRWLock<T: ?Sized> {
+  inner: RWLock<T>,
+}
+
+impl<T> PinMutex<T> {
+  /// Create a new [PinMutex]. The data is pinned once the [PinMutex] is pinned, eg. with [core::pin::pin!] or by
+  /// placing it into a static variable and accessing it through [Pin::static_ref].
+  pub const fn new(value: T) -> Self {
+    Self {
+      inner: Mutex::new(value),
+    }
+  }
+
+  /// Consume the [PinMutex] and return the inner value. As this requires to own the [PinMutex] it has never been
+  /// pinned.
+  pub fn into_inner(self) -> T {
+    self.inner.into_inner()
+  }
+}
+
+impl<T: ?Sized> PinMutex<T> {
+  /// Lock the pinned data for mutual exclusive access. This blocks until the lock could be acquired.
+  ///
+  /// # Example
+  /// ```
+  /// # use core::pin::Pin;
+  /// # use ruspiro_lock::sync::PinMutex;
+  /// static DATA: PinMutex<u32> = PinMutex::new(10);
+  /// # fn main() {
+  ///     let mut data = Pin::static_ref(&DATA).lock();
+  ///     data.set(20);
+  /// # }
+  /// ```
+  pub fn lock(self: Pin<&Self>) -> Pin<MutexGuard<'_, T>> {
+    let guard = self.get_ref().inner.lock();
+    // SAFETY: the PinMutex is pinned and never hands out the secured data unpinned, so it is not moved until dropped
+    unsafe { Pin::new_unchecked(guard) }
+  }
+
+  /// Try to lock the pinned data for mutual exclusive access. Returns `None` if the lock is currently held.
+  pub fn try_lock(self: Pin<&Self>) -> Option<Pin<MutexGuard<'_, T>>> {
+    let guard = self.get_ref().inner.try_lock()?;
+    // SAFETY: the PinMutex is pinned and never hands out the secured data unpinned, so it is not moved until dropped
+    Some(unsafe { Pin::new_unchecked(guard) })
+  }
+
+  /// Returns `true` if the [PinMutex] is currently locked
+  pub fn is_locked(&self) -> bool {
+    self.inner.is_locked()
+  }
+}
+
+impl<T: ?Sized> fmt::Debug for PinMutex<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PinMutex")
+      .field("locked", &self.inner.is_locked())
+      .finish_non_exhaustive()
+  }
+}
+
+impl<T> PinRWLock<T> {
+  /// Create a new [PinRWLock]. The data is pinned once the [PinRWLock] is pinned, eg. with [core::pin::pin!] or by
+  /// placing it into a static variable and accessing it through [Pin::static_ref].
+  pub const fn new(value: T) -> Self {
+    Self {
+      inner: RWLock::new(value),
+    }
+  }
+
+  /// Consume the [PinRWLock] and return the inner value. As this requires to own the [PinRWLock] it has never been
+  /// pinned.
+  pub fn into_inner(self) -> T {
+    self.inner.into_inner()
+  }
+}
+
+impl<T: ?Sized> PinRWLock<T> {
+  /// Lock the pinned data for exclusive write access. This blocks until the lock could be acquired.
+  pub fn write(self: Pin<&Self>) -> Pin<WriteLockGuard<'_, T>> {
+    let guard = self.get_ref().inner.write();
+    // SAFETY: the PinRWLock is pinned and never hands out the secured data unpinned, so it is not moved until dropped
+    unsafe { Pin::new_unchecked(guard) }
+  }
+
+  /// Try to lock the pinned data for exclusive write access. Returns `None` if the lock is currently held.
+  pub fn try_write(self: Pin<&Self>) -> Option<Pin<WriteLockGuard<'_, T>>> {
+    let guard = self.get_ref().inner.try_write()?;
+    // SAFETY: the PinRWLock is pinned and never hands out the secured data unpinned, so it is not moved until dropped
+    Some(unsafe { Pin::new_unchecked(guard) })
+  }
+
+  /// Lock the pinned data for shared read access. This blocks until no writer holds the lock.
+  pub fn read(self: Pin<&Self>) -> Pin<ReadLockGuard<'_, T>> {
+    let guard = self.get_ref().inner.read();
+    // SAFETY: the PinRWLock is pinned and never hands out the secured data unpinned, so it is not moved until dropped
+    unsafe { Pin::new_unchecked(guard) }
+  }
+
+  /// Try to lock the pinned data for shared read access. Returns `None` if a writer currently holds the lock.
+  pub fn try_read(self: Pin<&Self>) -> Option<Pin<ReadLockGuard<'_, T>>> {
+    let guard = self.get_ref().inner.try_read()?;
+    // SAFETY: the PinRWLock is pinned and never hands out the secured data unpinned, so it is not moved until dropped
+    Some(unsafe { Pin::new_unchecked(guard) })
+  }
+}
+
+impl<T: ?Sized> fmt::Debug for PinRWLock<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PinRWLock")
+      .field("state", &self.inner.fmt_state())
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::future::Future;
+  use core::task::{Context, Poll, Waker};
+
+  /// A `Future` that is pending on its first poll
+  struct YieldOnce(bool);
+
+  impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+      if self.0 {
+        return Poll::Ready(());
+      }
+      self.0 = true;
+      Poll::Pending
+    }
+  }
+
+  #[test]
+  fn pinned_future_is_polled_through_the_mutex() {
+    let transfer = core::pin::pin!(PinMutex::new(async {
+      YieldOnce(false).await;
+      42
+    }));
+    let mut cx = Context::from_waker(Waker::noop());
+    assert_eq!(
+      transfer.as_ref().lock().as_mut().poll(&mut cx),
+      Poll::Pending
+    );
+    assert!(!transfer.is_locked());
+    assert_eq!(
+      transfer.as_ref().lock().as_mut().poll(&mut cx),
+      Poll::Ready(42)
+    );
+  }
+
+  #[test]
+  fn mutex_guard_releases_the_lock_once_dropped() {
+    let mutex = core::pin::pin!(PinMutex::new(0u32));
+    let mut guard = mutex.as_ref().lock();
+    guard.set(10);
+    assert!(mutex.is_locked());
+    assert!(mutex.as_ref().try_lock().is_none());
+    drop(guard);
+    assert_eq!(*mutex.as_ref().try_lock().unwrap(), 10);
+  }
+
+  #[test]
+  fn rwlock_guards_exclude_each_other() {
+    let rwlock = core::pin::pin!(PinRWLock::new(0u32));
+    let reader = rwlock.as_ref().read();
+    assert!(rwlock.as_ref().try_write().is_none());
+    assert!(rwlock.as_ref().try_read().is_some());
+    drop(reader);
+
+    let mut writer = rwlock.as_ref().write();
+    writer.set(10);
+    assert!(rwlock.as_ref().try_read().is_none());
+    assert!(rwlock.as_ref().try_write().is_none());
+    drop(writer);
+    assert_eq!(*rwlock.as_ref().try_read().unwrap(), 10);
+  }
+}
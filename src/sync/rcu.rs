@@ -0,0 +1,337 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Read-Copy-Update
+//!
+//! Data that is read all the time but updated rarely, like the dispatch table of the interrupt handlers, does not
+//! need a [RWLock](super::RWLock). With an [Rcu] a reader only announces itself on its own core and loads the pointer
+//! to the current version of the data, so reading is wait-free and the readers of different cores never contend on
+//! the same lock state. An update copies the current version with [Rcu::update], publishes the new version and
+//! retires the previous one.
+//!
+//! A retired version can only be released once no reader refers to it any more. Each core reports a quiescent point
+//! with [Rcu::quiescent], e.g. from its scheduler loop or timer interrupt, when it does not read the data. Once all
+//! cores passed a quiescent point after a version has been retired, or do not read at the moment, no reader can refer
+//! to it and it is released by the next call to [Rcu::quiescent]. A core that never reads the data does not delay the
+//! release.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::Rcu;
+//!
+//! fn default_handler() {}
+//! fn timer_handler() {}
+//!
+//! fn main() {
+//!     let handlers: Rcu<[fn(); 4]> = Rcu::new([default_handler; 4]);
+//!
+//!     // the interrupt dispatcher reads the table without any lock
+//!     (handlers.read()[1])();
+//!
+//!     handlers.update(|table| {
+//!         let mut table = *table;
+//!         table[1] = timer_handler;
+//!         table
+//!     });
+//!     assert_eq!(handlers.retired(), 1);
+//!     // the core reached a quiescent point, so the previous table is released
+//!     handlers.quiescent();
+//!     assert_eq!(handlers.retired(), 0);
+//! }
+//! ```
+
+extern crate alloc;
+use super::Mutex;
+use crate::config::{core_id, max_cores, MAX_SUPPORTED_CORES};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// Data read without locking and updated by replacing it with a new version
+pub struct Rcu<T> {
+  /// The current version of the data
+  current: AtomicPtr<T>,
+  /// Incremented with each update, so a retired version is tagged with the epoch it has been replaced in
+  epoch: AtomicUsize,
+  /// The number of [RcuReadGuard]s existing on each core
+  readers: [AtomicUsize; MAX_SUPPORTED_CORES],
+  /// The epoch each core has seen at its last quiescent point
+  quiescent: [AtomicUsize; MAX_SUPPORTED_CORES],
+  /// The versions replaced by an update that might still be read. This also serializes the updates
+  retired: Mutex<Vec<Retired<T>>>,
+}
+
+/// A version of the data replaced in the given epoch
+struct Retired<T> {
+  epoch: usize,
+  version: *mut T,
+}
+
+// SAFETY: the retired version is owned by the Rcu, which only releases it once no reader refers to it
+unsafe impl<T: Send> Send for Retired<T> {}
+
+/// Read access to the version of the data of an [Rcu] that has been current when the read started. The version is
+/// not released while the guard exists. The guard belongs to the core it has been created on, so it cannot be sent to
+/// another core.
+pub struct RcuReadGuard<'a, T> {
+  rcu: &'a Rcu<T>,
+  version: &'a T,
+  /// The core the reader has been announced on
+  core: usize,
+  _marker: PhantomData<*const ()>,
+}
+
+impl<T> Rcu<T> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const NONE: AtomicUsize = AtomicUsize::new(0);
+
+  /// Create a new [Rcu] with the given value as its first version
+  pub fn new(value: T) -> Self {
+    Self {
+      current: AtomicPtr::new(Box::into_raw(Box::new(value))),
+      epoch: AtomicUsize::new(0),
+      readers: [Self::NONE; MAX_SUPPORTED_CORES],
+      quiescent: [Self::NONE; MAX_SUPPORTED_CORES],
+      retired: Mutex::new(Vec::new()),
+    }
+  }
+
+  /// Read the current version of the data. This never blocks and does not contend with the readers of other cores.
+  /// The guard shall not be held across a quiescent point of the current core, as it delays the release of the
+  /// versions retired meanwhile.
+  pub fn read(&self) -> RcuReadGuard<'_, T> {
+    let core = core_id();
+    // announce the reader before loading the version, so a core releasing the retired versions either sees the reader
+    // or this reader loads the version published before
+    self.readers[core].fetch_add(1, Ordering::SeqCst);
+    let version = self.current.load(Ordering::SeqCst);
+    RcuReadGuard {
+      rcu: self,
+      // SAFETY: the version is not released while a reader is announced on this core
+      version: unsafe { &*version },
+      core,
+      _marker: PhantomData,
+    }
+  }
+
+  /// Replace the current version of the data with the one returned by the given function from the current version.
+  /// Concurrent updates are serialized, so none of them gets lost. The readers that started before see the previous
+  /// version until they are finished, the readers starting afterwards see the new version. The previous version is
+  /// retired and released by [Rcu::quiescent] once no reader refers to it any more.
+  ///
+  /// The function shall not update the same [Rcu], as this would block forever.
+  pub fn update<F>(&self, f: F)
+  where
+    F: FnOnce(&T) -> T,
+  {
+    let mut retired = self.retired.lock();
+    let previous = self.current.load(Ordering::Acquire);
+    // SAFETY: only updates replace the current version and they are serialized by the lock
+    let version = Box::into_raw(Box::new(f(unsafe { &*previous })));
+    self.current.store(version, Ordering::SeqCst);
+    // a core that sees this epoch at a quiescent point will not read the previous version any more
+    let epoch = self.epoch.fetch_add(1, Ordering::SeqCst) + 1;
+    retired.push(Retired {
+      epoch,
+      version: previous,
+    });
+  }
+
+  /// Report a quiescent point of the current core, at which it does not read the data, and release the retired
+  /// versions no reader refers to any more. Returns the number of released versions. This shall be called regularly
+  /// by each core reading the data, e.g. from its scheduler loop, but never while a [RcuReadGuard] of the current
+  /// core exists. If it exists nevertheless the quiescent point is ignored.
+  ///
+  /// If another core is releasing versions at the same time this does not wait for it and returns `0`.
+  pub fn quiescent(&self) -> usize {
+    let core = core_id();
+    if self.readers[core].load(Ordering::SeqCst) == 0 {
+      let epoch = self.epoch.load(Ordering::SeqCst);
+      self.quiescent[core].store(epoch, Ordering::SeqCst);
+    }
+
+    let Some(mut retired) = self.retired.try_lock() else {
+      return 0;
+    };
+    // the oldest epoch a core might still read a version of. A core that does not read at the moment will load the
+    // current version with its next read
+    let epoch = self.epoch.load(Ordering::SeqCst);
+    let oldest = (0..max_cores())
+      .map(|core| {
+        if self.readers[core].load(Ordering::SeqCst) == 0 {
+          epoch
+        } else {
+          self.quiescent[core].load(Ordering::SeqCst)
+        }
+      })
+      .min()
+      .unwrap_or(epoch);
+
+    let count = retired.len();
+    retired.retain(|retired| {
+      if retired.epoch > oldest {
+        return true;
+      }
+      // SAFETY: all cores passed a quiescent point or did not read since the version has been retired, so no reader
+      // refers to it
+      unsafe { drop(Box::from_raw(retired.version)) };
+      false
+    });
+    count - retired.len()
+  }
+
+  /// The number of versions replaced by an update that have not been released yet
+  pub fn retired(&self) -> usize {
+    self.retired.lock().len()
+  }
+
+  /// Returns a mutable reference to the current version. As this requires a mutable borrow of the [Rcu] no reader can
+  /// exist at the same time.
+  pub fn get_mut(&mut self) -> &mut T {
+    // SAFETY: the current version is owned by the Rcu and no reader exists
+    unsafe { &mut **self.current.get_mut() }
+  }
+
+  /// Consume the [Rcu] and return the current version. The retired versions are released.
+  pub fn into_inner(mut self) -> T {
+    let current = core::mem::take(self.current.get_mut());
+    // SAFETY: the current version has been created with Box::into_raw and is no longer referred to by the Rcu
+    *unsafe { Box::from_raw(current) }
+  }
+}
+
+impl<T> Drop for Rcu<T> {
+  fn drop(&mut self) {
+    let current = *self.current.get_mut();
+    if !current.is_null() {
+      // SAFETY: the current version has been created with Box::into_raw and no reader exists any more
+      unsafe { drop(Box::from_raw(current)) };
+    }
+    for retired in self.retired.lock().drain(..) {
+      // SAFETY: the retired version has been created with Box::into_raw and no reader exists any more
+      unsafe { drop(Box::from_raw(retired.version)) };
+    }
+  }
+}
+
+impl<T: Default> Default for Rcu<T> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Rcu<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Rcu")
+      .field("value", &&*self.read())
+      .finish_non_exhaustive()
+  }
+}
+
+// the Rcu hands out references to its versions to any core and releases them on any core
+unsafe impl<T: Send + Sync> Sync for Rcu<T> {}
+unsafe impl<T: Send> Send for Rcu<T> {}
+
+impl<T> Deref for RcuReadGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    self.version
+  }
+}
+
+impl<T> Drop for RcuReadGuard<'_, T> {
+  fn drop(&mut self) {
+    self.rcu.readers[self.core].fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RcuReadGuard<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(self.version, f)
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  /// Counts the versions released
+  struct Version<'a>(usize, &'a AtomicUsize);
+
+  impl Drop for Version<'_> {
+    fn drop(&mut self) {
+      self.1.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  #[test]
+  fn readers_keep_the_version_they_started_with() {
+    let rcu = Rcu::new(0u32);
+    let reader = rcu.read();
+    rcu.update(|value| value + 1);
+    assert_eq!(*reader, 0);
+    assert_eq!(*rcu.read(), 1);
+    // the reader of the current core still refers to the retired version
+    assert_eq!(rcu.quiescent(), 0);
+    assert_eq!(rcu.retired(), 1);
+    drop(reader);
+    assert_eq!(rcu.quiescent(), 1);
+    assert_eq!(rcu.retired(), 0);
+  }
+
+  #[test]
+  fn retired_versions_are_released_once() {
+    let released = AtomicUsize::new(0);
+    let rcu = Rcu::new(Version(0, &released));
+    rcu.update(|version| Version(version.0 + 1, version.1));
+    rcu.update(|version| Version(version.0 + 1, version.1));
+    assert_eq!(released.load(Ordering::Relaxed), 0);
+    assert_eq!(rcu.quiescent(), 2);
+    assert_eq!(released.load(Ordering::Relaxed), 2);
+
+    rcu.update(|version| Version(version.0 + 1, version.1));
+    // the retired version is released with the Rcu, the current one is handed out
+    let current = rcu.into_inner();
+    assert_eq!(current.0, 3);
+    assert_eq!(released.load(Ordering::Relaxed), 3);
+  }
+
+  #[test]
+  fn quiescent_does_not_wait_for_another_releasing_core() {
+    let rcu = Rcu::new(0u32);
+    rcu.update(|value| value + 1);
+    let releasing = rcu.retired.lock();
+    assert_eq!(rcu.quiescent(), 0);
+    drop(releasing);
+    assert_eq!(rcu.quiescent(), 1);
+  }
+
+  #[test]
+  fn concurrent_updates_are_serialized() {
+    const THREADS: usize = 4;
+    const UPDATES: usize = 100;
+
+    let rcu = Rcu::new(0usize);
+    std::thread::scope(|s| {
+      for _ in 0..THREADS {
+        s.spawn(|| {
+          for _ in 0..UPDATES {
+            rcu.update(|value| value + 1);
+            assert!(*rcu.read() > 0);
+          }
+        });
+      }
+    });
+    assert_eq!(rcu.retired(), THREADS * UPDATES);
+    assert_eq!(rcu.quiescent(), THREADS * UPDATES);
+    assert_eq!(rcu.into_inner(), THREADS * UPDATES);
+  }
+}
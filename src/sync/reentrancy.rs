@@ -0,0 +1,217 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # RWLock Re-entrancy
+//!
+//! A core that requests a read lock of a [RWLock](super::RWLock) while it already holds the write lock, or the other
+//! way round, waits for itself forever. Deep in the call chain of a driver this shows up as a hang that is hard to
+//! explain. With the `reentrancy_detection` feature each core keeps track of the RWLocks it holds and in which mode,
+//! so the blocking calls of the RWLock panic with a clear message instead. The tracking is only done in debug builds,
+//! in release builds and without the feature it compiles to nothing.
+//!
+//! A guard dropped on another core than the one it has been acquired on is untracked from the core that holds the
+//! lock. A guard passed to `core::mem::forget` stays tracked, while [WriteLockGuard::leak](super::WriteLockGuard::leak)
+//! stops the tracking, as the RWLock might be dropped and its memory reused afterwards.
+
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+use crate::arch::MAX_CORES;
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+use crate::config::core_id;
+use core::fmt;
+use core::sync::atomic::AtomicU32;
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// The way a [RWLock](super::RWLock) is accessed by a core
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+  Read,
+  Upgradable,
+  Write,
+}
+
+impl fmt::Display for Access {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Access::Read => write!(f, "a read lock"),
+      Access::Upgradable => write!(f, "the upgradable read lock"),
+      Access::Write => write!(f, "the write lock"),
+    }
+  }
+}
+
+/// The maximum number of RWLocks tracked per core. RWLocks acquired while this number of RWLocks is held are not
+/// tracked.
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+const MAX_TRACKED_LOCKS: usize = 8;
+
+/// The bit of a held access marking the write lock
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+const WRITE: u32 = 1 << 31;
+/// The bit of a held access marking the upgradable read lock
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+const UPGRADABLE: u32 = 1 << 30;
+/// The bits of a held access counting the read locks
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+const READS: u32 = UPGRADABLE - 1;
+
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+impl Access {
+  /// The change of the held access when this access is acquired or released
+  fn bits(self) -> u32 {
+    match self {
+      Access::Read => 1,
+      Access::Upgradable => UPGRADABLE,
+      Access::Write => WRITE,
+    }
+  }
+}
+
+/// A RWLock held by a core, identified by the address of its state, and the access the core holds
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+struct Slot {
+  lock: AtomicPtr<AtomicU32>,
+  held: AtomicU32,
+}
+
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+#[allow(clippy::declare_interior_mutable_const)]
+const SLOT_INIT: Slot = Slot {
+  lock: AtomicPtr::new(core::ptr::null_mut()),
+  held: AtomicU32::new(0),
+};
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+#[allow(clippy::declare_interior_mutable_const)]
+const CORE_INIT: [Slot; MAX_TRACKED_LOCKS] = [SLOT_INIT; MAX_TRACKED_LOCKS];
+/// The RWLocks currently held by each core
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+static HELD: [[Slot; MAX_TRACKED_LOCKS]; MAX_CORES] = [CORE_INIT; MAX_CORES];
+
+#[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+fn as_ptr(lock: &AtomicU32) -> *mut AtomicU32 {
+  lock as *const AtomicU32 as *mut AtomicU32
+}
+
+/// Track the access to the RWLock with the given state that has been acquired by the current core
+#[inline(always)]
+pub(crate) fn track(lock: &AtomicU32, access: Access) {
+  #[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+  {
+    let lock = as_ptr(lock);
+    let slots = &HELD[core_id()];
+    let slot = slots
+      .iter()
+      .find(|slot| slot.lock.load(Ordering::Relaxed) == lock)
+      .or_else(|| {
+        // an interrupt on this core might acquire a RWLock in between, so the slot is claimed atomically
+        slots.iter().find(|slot| {
+          slot
+            .lock
+            .compare_exchange(
+              core::ptr::null_mut(),
+              lock,
+              Ordering::Relaxed,
+              Ordering::Relaxed,
+            )
+            .is_ok()
+        })
+      });
+    if let Some(slot) = slot {
+      slot.held.fetch_add(access.bits(), Ordering::Relaxed);
+    }
+  }
+  #[cfg(not(all(feature = "reentrancy_detection", debug_assertions)))]
+  let _ = (lock, access);
+}
+
+/// Stop tracking the access to the RWLock with the given state that is released. The access is looked up at the
+/// current core first, as the guard releasing it might have been sent to another core.
+#[inline(always)]
+pub(crate) fn untrack(lock: &AtomicU32, access: Access) {
+  #[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+  {
+    let lock = as_ptr(lock);
+    let bits = access.bits();
+    let mask = if access == Access::Read { READS } else { bits };
+    let current = core_id();
+    let slot = (0..MAX_CORES)
+      .map(|core| &HELD[(current + core) % MAX_CORES])
+      .flat_map(|slots| slots.iter())
+      .find(|slot| {
+        slot.lock.load(Ordering::Relaxed) == lock && slot.held.load(Ordering::Relaxed) & mask != 0
+      });
+    if let Some(slot) = slot {
+      if slot.held.fetch_sub(bits, Ordering::Relaxed) == bits {
+        // the core does not hold any access to the RWLock any more, so free the slot
+        let _ = slot.lock.compare_exchange(
+          lock,
+          core::ptr::null_mut(),
+          Ordering::Relaxed,
+          Ordering::Relaxed,
+        );
+      }
+    }
+  }
+  #[cfg(not(all(feature = "reentrancy_detection", debug_assertions)))]
+  let _ = (lock, access);
+}
+
+/// Stop tracking any access to the RWLock with the given state on all cores, as it has been released regardless of
+/// the cores holding it
+#[inline(always)]
+pub(crate) fn untrack_all(lock: &AtomicU32) {
+  #[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+  {
+    let lock = as_ptr(lock);
+    for slot in HELD.iter().flat_map(|slots| slots.iter()) {
+      if slot.lock.load(Ordering::Relaxed) == lock {
+        slot.held.store(0, Ordering::Relaxed);
+        let _ = slot.lock.compare_exchange(
+          lock,
+          core::ptr::null_mut(),
+          Ordering::Relaxed,
+          Ordering::Relaxed,
+        );
+      }
+    }
+  }
+  #[cfg(not(all(feature = "reentrancy_detection", debug_assertions)))]
+  let _ = lock;
+}
+
+/// Returns the access the current core holds to the RWLock with the given state that blocks the requested access
+/// forever. The write lock is reported first, then the read locks and then the upgradable read lock. Without the
+/// `reentrancy_detection` feature or in release builds nothing is tracked and this always returns `None`.
+#[inline(always)]
+pub(crate) fn blocking(lock: &AtomicU32, requested: Access) -> Option<Access> {
+  #[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+  {
+    let lock = as_ptr(lock);
+    let held = HELD[core_id()]
+      .iter()
+      .find(|slot| slot.lock.load(Ordering::Relaxed) == lock)
+      .map_or(0, |slot| slot.held.load(Ordering::Relaxed));
+    // a read lock is only blocked by the write lock, the upgradable read lock is blocked by another one as well and
+    // the write lock is blocked by any access
+    let blocked_by = match requested {
+      Access::Read => WRITE,
+      Access::Upgradable => WRITE | UPGRADABLE,
+      Access::Write => WRITE | UPGRADABLE | READS,
+    };
+    match held & blocked_by {
+      0 => None,
+      blocking if blocking & WRITE != 0 => Some(Access::Write),
+      blocking if blocking & READS != 0 => Some(Access::Read),
+      _ => Some(Access::Upgradable),
+    }
+  }
+  #[cfg(not(all(feature = "reentrancy_detection", debug_assertions)))]
+  {
+    let _ = (lock, requested);
+    None
+  }
+}
@@ -0,0 +1,255 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # GuardedRegion
+//!
+//! A read/write lock for a memory region that is not owned by the lock, like the framebuffer allocated through the
+//! mailbox of the VideoCore. Its address and length are only known at runtime, so it cannot be expressed as a Rust
+//! value secured by a [RWLock](super::RWLock). The [GuardedRegion] pairs the raw region with a RWLock and hands out
+//! the region as `&[u8]` to any number of readers or as `&mut [u8]` to a single writer.
+//!
+//! The region might be accessed by a bus master that does not share the data caches of the cores, like the GPU
+//! scanning out the framebuffer. The cache maintenance functions given with [GuardedRegion::with_cache_maintenance]
+//! are called for the whole region. The data cache is invalidated when a guard is acquired, so the core sees the
+//! writes of the bus master, and cleaned when a write guard is released, so the bus master sees the writes of the core.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::GuardedRegion;
+//!
+//! fn main() {
+//!     // eg. the framebuffer allocated through the mailbox
+//!     let mut memory = [0u8; 64];
+//!     let framebuffer = unsafe { GuardedRegion::new(memory.as_mut_ptr(), memory.len()) };
+//!
+//!     framebuffer.write().fill(0xFF);
+//!     assert_eq!(framebuffer.read()[0], 0xFF);
+//! }
+//! ```
+
+use super::dma::CacheMaintenance;
+use super::rwlock::{RWLock, ReadLockGuard, WriteLockGuard};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+/// A read/write lock securing a raw memory region
+pub struct GuardedRegion {
+  lock: RWLock<()>,
+  ptr: *mut u8,
+  len: usize,
+  /// The cache maintenance functions called for the region
+  maintenance: Option<CacheMaintenance>,
+}
+
+/// Shared read access to the memory region secured by the [GuardedRegion]
+pub struct RegionReadGuard<'a> {
+  _guard: ReadLockGuard<'a, ()>,
+  region: &'a GuardedRegion,
+}
+
+/// Exclusive access to the memory region secured by the [GuardedRegion]. The data cache is cleaned for the region
+/// once this goes out of scope.
+pub struct RegionWriteGuard<'a> {
+  _guard: WriteLockGuard<'a, ()>,
+  region: &'a GuardedRegion,
+}
+
+impl GuardedRegion {
+  /// Create a new [GuardedRegion] for the `len` bytes of memory starting at the given address.
+  ///
+  /// # Safety
+  /// The memory region need to be valid for reads and writes for the whole lifetime of the lock and shall only be
+  /// accessed by the cores through this lock.
+  pub const unsafe fn new(ptr: *mut u8, len: usize) -> Self {
+    Self {
+      lock: RWLock::new(()),
+      ptr,
+      len,
+      maintenance: None,
+    }
+  }
+
+  /// Call the given cache maintenance functions for the region. The data cache is invalidated when a guard is acquired
+  /// and cleaned when a [RegionWriteGuard] is released.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::{dma::CacheMaintenance, GuardedRegion};
+  /// fn clean(addr: *const u8, len: usize) { /* clean data cache for the range */ }
+  /// fn invalidate(addr: *const u8, len: usize) { /* invalidate data cache for the range */ }
+  ///
+  /// # fn main() {
+  /// let mut memory = [0u8; 64];
+  /// let framebuffer = unsafe { GuardedRegion::new(memory.as_mut_ptr(), memory.len()) }
+  ///     .with_cache_maintenance(CacheMaintenance { clean, invalidate });
+  /// # }
+  /// ```
+  pub const fn with_cache_maintenance(mut self, maintenance: CacheMaintenance) -> Self {
+    self.maintenance = Some(maintenance);
+    self
+  }
+
+  /// Try to acquire shared read access. Returns `None` if there is a write access active.
+  pub fn try_read(&self) -> Option<RegionReadGuard<'_>> {
+    self.lock.try_read().map(|guard| self.read_guard(guard))
+  }
+
+  /// Acquire shared read access. This blocks until there is no write access active.
+  pub fn read(&self) -> RegionReadGuard<'_> {
+    self.read_guard(self.lock.read())
+  }
+
+  /// Try to acquire exclusive write access. Returns `None` if there is any other access active.
+  pub fn try_write(&self) -> Option<RegionWriteGuard<'_>> {
+    self.lock.try_write().map(|guard| self.write_guard(guard))
+  }
+
+  /// Acquire exclusive write access. This blocks until there is no other access active.
+  pub fn write(&self) -> RegionWriteGuard<'_> {
+    self.write_guard(self.lock.write())
+  }
+
+  /// The start address of the memory region secured by this lock
+  pub fn as_ptr(&self) -> *mut u8 {
+    self.ptr
+  }
+
+  /// The length of the memory region in bytes
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  /// Returns `true` if the memory region has a length of 0
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  fn read_guard<'a>(&'a self, guard: ReadLockGuard<'a, ()>) -> RegionReadGuard<'a> {
+    self.invalidate();
+    RegionReadGuard {
+      _guard: guard,
+      region: self,
+    }
+  }
+
+  fn write_guard<'a>(&'a self, guard: WriteLockGuard<'a, ()>) -> RegionWriteGuard<'a> {
+    self.invalidate();
+    RegionWriteGuard {
+      _guard: guard,
+      region: self,
+    }
+  }
+
+  /// Invalidate the data cache for the region, so the core sees the writes of other bus masters
+  fn invalidate(&self) {
+    if let Some(maintenance) = self.maintenance {
+      (maintenance.invalidate)(self.ptr, self.len);
+    }
+  }
+}
+
+impl fmt::Debug for GuardedRegion {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("GuardedRegion")
+      .field("ptr", &self.ptr)
+      .field("len", &self.len)
+      .field("lock", &self.lock)
+      .finish()
+  }
+}
+
+impl Deref for RegionReadGuard<'_> {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    // SAFETY: the caller of `GuardedRegion::new` guaranteed the region is valid and only accessed through the lock,
+    // which prevents any write access while the read lock is held
+    unsafe { core::slice::from_raw_parts(self.region.ptr, self.region.len) }
+  }
+}
+
+impl Deref for RegionWriteGuard<'_> {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    // SAFETY: the caller of `GuardedRegion::new` guaranteed the region is valid and the write lock is held
+    unsafe { core::slice::from_raw_parts(self.region.ptr, self.region.len) }
+  }
+}
+
+impl DerefMut for RegionWriteGuard<'_> {
+  fn deref_mut(&mut self) -> &mut [u8] {
+    // SAFETY: the caller of `GuardedRegion::new` guaranteed the region is valid and the write lock is held
+    unsafe { core::slice::from_raw_parts_mut(self.region.ptr, self.region.len) }
+  }
+}
+
+// clean the data cache before the write lock is released, so the other bus masters see the writes of this core
+impl Drop for RegionWriteGuard<'_> {
+  fn drop(&mut self) {
+    if let Some(maintenance) = self.region.maintenance {
+      (maintenance.clean)(self.region.ptr, self.region.len);
+    }
+  }
+}
+
+// the GuardedRegion does only hand out accesses to the memory region secured by the RWLock, so it is safe to be
+// shared across cores
+unsafe impl Sync for GuardedRegion {}
+unsafe impl Send for GuardedRegion {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  #[test]
+  fn guards_exclude_each_other() {
+    let mut memory = [0u8; 16];
+    let region = unsafe { GuardedRegion::new(memory.as_mut_ptr(), memory.len()) };
+    let mut writer = region.write();
+    writer[1] = 0xFF;
+    assert!(region.try_read().is_none());
+    assert!(region.try_write().is_none());
+    drop(writer);
+
+    let reader = region.try_read().unwrap();
+    assert_eq!(reader.len(), 16);
+    assert_eq!(reader[1], 0xFF);
+    assert!(region.try_write().is_none());
+    assert!(region.try_read().is_some());
+    drop(reader);
+    assert!(region.try_write().is_some());
+  }
+
+  #[test]
+  fn cache_is_maintained_around_the_accesses() {
+    static CLEANED: AtomicUsize = AtomicUsize::new(0);
+    static INVALIDATED: AtomicUsize = AtomicUsize::new(0);
+    fn clean(_: *const u8, len: usize) {
+      CLEANED.fetch_add(len, Ordering::Relaxed);
+    }
+    fn invalidate(_: *const u8, len: usize) {
+      INVALIDATED.fetch_add(len, Ordering::Relaxed);
+    }
+
+    let mut memory = [0u8; 16];
+    let region = unsafe { GuardedRegion::new(memory.as_mut_ptr(), memory.len()) }
+      .with_cache_maintenance(CacheMaintenance { clean, invalidate });
+    let reader = region.read();
+    assert_eq!(INVALIDATED.load(Ordering::Relaxed), 16);
+    drop(reader);
+    // only the writes of the core need to be cleaned
+    assert_eq!(CLEANED.load(Ordering::Relaxed), 0);
+
+    let writer = region.write();
+    assert_eq!(INVALIDATED.load(Ordering::Relaxed), 32);
+    assert_eq!(CLEANED.load(Ordering::Relaxed), 0);
+    drop(writer);
+    assert_eq!(CLEANED.load(Ordering::Relaxed), 16);
+  }
+}
@@ -0,0 +1,205 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # RegisterLock
+//!
+//! A [Spinlock](super::Spinlock) paired with the base address of a MMIO register block. Peripheral registers are
+//! frequently updated with a read-modify-write, e.g. to change the function of a single GPIO pin. If two cores do this
+//! at the same time one of the updates is lost. The [RegisterLock] performs the volatile read-modify-write of a
+//! register while holding the lock, so drivers do not need to pair a lock with raw pointer accesses on their own.
+//!
+//! The registers are accessed by their byte offset from the base address and have the width `T`, which defaults to
+//! `u32`. Register accessors like the ones of `ruspiro-register` can be used while the lock is held by creating them
+//! from [RegisterGuard::as_ptr].
+//!
+//! # Example
+//! ```no_run
+//! use ruspiro_lock::sync::RegisterLock;
+//!
+//! // the GPIO register block of the Raspberry Pi 3
+//! static GPIO: RegisterLock = unsafe { RegisterLock::new(0x3F20_0000) };
+//!
+//! fn main() {
+//!     // set GPIO 14 to alternative function 0 with a single locked read-modify-write
+//!     GPIO.modify(0x04, |value| (value & !(0b111 << 12)) | (0b100 << 12));
+//!
+//!     // several accesses while the lock is held
+//!     let gpio = GPIO.lock();
+//!     gpio.write(0x1C, 1 << 14);
+//!     let level = gpio.read(0x34);
+//! }
+//! ```
+
+use super::marker::GuardMarker;
+use super::Spinlock;
+use core::fmt;
+use core::marker::PhantomData;
+
+/// A [Spinlock] securing volatile accesses to the registers of a MMIO register block
+pub struct RegisterLock<T: Copy = u32> {
+  lock: Spinlock,
+  base: usize,
+  _width: PhantomData<T>,
+}
+
+/// Exclusive access to the registers secured by the [RegisterLock]. If this goes out of scope the lock is released.
+#[cfg_attr(
+  feature = "must_not_suspend",
+  must_not_suspend = "holding a RegisterGuard across a suspend point blocks the other cores waiting for the lock"
+)]
+pub struct RegisterGuard<'a, T: Copy = u32> {
+  _data: &'a RegisterLock<T>,
+  _marker: GuardMarker,
+}
+
+impl<T: Copy> RegisterLock<T> {
+  /// Create a new [RegisterLock] for the register block at the given base address.
+  ///
+  /// # Safety
+  /// Each offset used with this lock need to address a register valid for volatile reads and writes of `T` for the
+  /// whole lifetime of the lock, and the registers shall only be accessed through this lock.
+  pub const unsafe fn new(base: usize) -> Self {
+    Self {
+      lock: Spinlock::new(),
+      base,
+      _width: PhantomData,
+    }
+  }
+
+  /// Acquire exclusive access to the register block. This blocks until the lock could be acquired.
+  pub fn lock(&self) -> RegisterGuard<'_, T> {
+    self.lock.acquire();
+    RegisterGuard {
+      _data: self,
+      _marker: PhantomData,
+    }
+  }
+
+  /// Volatile read-modify-write of the register at the given offset while holding the lock. Returns the value written
+  /// to the register.
+  pub fn modify<F: FnOnce(T) -> T>(&self, offset: usize, f: F) -> T {
+    self.lock().modify(offset, f)
+  }
+
+  /// The base address of the register block
+  pub fn base(&self) -> usize {
+    self.base
+  }
+}
+
+impl<T: Copy> RegisterGuard<'_, T> {
+  /// Read the register at the given offset with a volatile read
+  pub fn read(&self, offset: usize) -> T {
+    // SAFETY: the caller of `RegisterLock::new` guaranteed the offset addresses a valid register
+    unsafe { self.as_ptr(offset).read_volatile() }
+  }
+
+  /// Write the register at the given offset with a volatile write
+  pub fn write(&self, offset: usize, value: T) {
+    // SAFETY: the caller of `RegisterLock::new` guaranteed the offset addresses a valid register
+    unsafe { self.as_ptr(offset).write_volatile(value) }
+  }
+
+  /// Volatile read-modify-write of the register at the given offset. Returns the value written to the register.
+  pub fn modify<F: FnOnce(T) -> T>(&self, offset: usize, f: F) -> T {
+    let value = f(self.read(offset));
+    self.write(offset, value);
+    value
+  }
+
+  /// The address of the register at the given offset. The pointer shall only be used while this guard is alive.
+  pub fn as_ptr(&self, offset: usize) -> *mut T {
+    debug_assert!(
+      offset.is_multiple_of(core::mem::align_of::<T>()),
+      "register offset {:#x} is not aligned",
+      offset
+    );
+    (self._data.base + offset) as *mut T
+  }
+}
+
+// when the RegisterGuard is dropped release the owning lock
+impl<T: Copy> Drop for RegisterGuard<'_, T> {
+  fn drop(&mut self) {
+    self._data.lock.release();
+  }
+}
+
+impl<T: Copy> fmt::Debug for RegisterLock<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("RegisterLock")
+      .field("base", &format_args!("{:#x}", self.base))
+      .field("lock", &self.lock)
+      .finish()
+  }
+}
+
+// the RegisterLock does only hand out volatile accesses to the registers while holding the Spinlock, so it is safe to
+// be shared across cores
+unsafe impl<T: Copy + Send> Sync for RegisterLock<T> {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::cell::UnsafeCell;
+
+  /// A register block in memory standing in for the MMIO registers
+  struct Registers(UnsafeCell<[u32; 4]>);
+
+  // the registers are only accessed through the RegisterLock
+  unsafe impl Sync for Registers {}
+
+  impl Registers {
+    fn lock(&self) -> RegisterLock {
+      // SAFETY: the offsets used by the tests address the registers, which outlive the lock
+      unsafe { RegisterLock::new(self.0.get() as usize) }
+    }
+  }
+
+  #[test]
+  fn registers_are_accessed_at_their_offset() {
+    let registers = Registers(UnsafeCell::new([0; 4]));
+    let lock = registers.lock();
+    let guard = lock.lock();
+    guard.write(0x04, 0b1010);
+    assert_eq!(guard.modify(0x04, |value| value | 1), 0b1011);
+    assert_eq!(guard.read(0x04), 0b1011);
+    assert_eq!(guard.read(0x00), 0);
+    drop(guard);
+    assert_eq!(unsafe { (*registers.0.get())[1] }, 0b1011);
+  }
+
+  #[test]
+  fn guard_holds_the_lock_until_dropped() {
+    let registers = Registers(UnsafeCell::new([0; 4]));
+    let lock = registers.lock();
+    let guard = lock.lock();
+    assert!(lock.lock.try_acquire().is_err());
+    drop(guard);
+    assert!(lock.lock.try_acquire().is_ok());
+    lock.lock.release();
+  }
+
+  #[test]
+  fn concurrent_modifications_are_not_lost() {
+    const THREADS: u32 = 4;
+    const UPDATES: u32 = 1000;
+
+    let registers = Registers(UnsafeCell::new([0; 4]));
+    let lock = registers.lock();
+    std::thread::scope(|s| {
+      for _ in 0..THREADS {
+        s.spawn(|| {
+          for _ in 0..UPDATES {
+            lock.modify(0x08, |value| value + 1);
+          }
+        });
+      }
+    });
+    assert_eq!(lock.lock().read(0x08), THREADS * UPDATES);
+  }
+}
@@ -0,0 +1,192 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Lock Registry
+//!
+//! A global registry where locks can be registered with a name. A [snapshot] of the registry reports the current
+//! state of each registered lock. This is intended for diagnostic purposes, eg. a panic handler that would like to
+//! report which locks were held at the time of the crash.
+//!
+//! The registry does not use any lock itself. Registering a lock claims a free slot with an atomic operation and
+//! taking a snapshot only reads the atomic state of the registered locks. It is therefore safe to be used from a panic
+//! handler even if the panicking core is in the middle of a lock operation.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::{registry, Mutex};
+//!
+//! static UART: Mutex<u32> = Mutex::new(0);
+//! static TIMER: Mutex<u64> = Mutex::new_named(0, "timer");
+//!
+//! fn main() {
+//!     registry::register("uart", &UART);
+//!     // a lock labeled with a name is registered with it
+//!     registry::register_named(&TIMER);
+//!
+//!     let _guard = UART.lock();
+//!     for (name, state) in registry::snapshot() {
+//!         println!("{}: {}", name, state);
+//!     }
+//! }
+//! ```
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The maximum number of locks that can be registered
+pub const MAX_LOCKS: usize = 64;
+
+/// The state of a lock as it can be reported without acquiring it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+  /// State of a [Spinlock](crate::sync::Spinlock)
+  Spinlock { locked: bool },
+  /// State of a [Mutex](crate::sync::Mutex)
+  Mutex { locked: bool },
+  /// State of a [RWLock](crate::sync::RWLock)
+  RWLock { write_locked: bool, readers: u32 },
+  /// State of a [Semaphore](crate::sync::Semaphore)
+  Semaphore { permits: u32 },
+}
+
+impl LockState {
+  /// Returns `true` if the lock is currently held in any way
+  pub fn is_held(&self) -> bool {
+    match *self {
+      LockState::Spinlock { locked } | LockState::Mutex { locked } => locked,
+      LockState::RWLock {
+        write_locked,
+        readers,
+      } => write_locked || readers > 0,
+      LockState::Semaphore { permits } => permits == 0,
+    }
+  }
+}
+
+impl fmt::Display for LockState {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      LockState::Spinlock { locked } => write!(f, "Spinlock (locked: {})", locked),
+      LockState::Mutex { locked } => write!(f, "Mutex (locked: {})", locked),
+      LockState::RWLock {
+        write_locked,
+        readers,
+      } => write!(
+        f,
+        "RWLock (write locked: {}, readers: {})",
+        write_locked, readers
+      ),
+      LockState::Semaphore { permits } => write!(f, "Semaphore (permits: {})", permits),
+    }
+  }
+}
+
+/// Locks that can report their current [LockState] without acquiring them
+pub trait InspectLock: Sync {
+  /// Report the current state of the lock
+  fn lock_state(&self) -> LockState;
+
+  /// The name the lock has been labeled with, e.g. by [Mutex::new_named](crate::sync::Mutex::new_named)
+  fn lock_name(&self) -> Option<&'static str> {
+    None
+  }
+}
+
+/// Register a lock with the given name. Returns `false` if the registry is full and the lock could not be registered.
+pub fn register(name: &'static str, lock: &'static dyn InspectLock) -> bool {
+  let idx = match REGISTRY
+    .count
+    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+      if count < MAX_LOCKS {
+        Some(count + 1)
+      } else {
+        None
+      }
+    }) {
+    Ok(idx) => idx,
+    Err(_) => return false,
+  };
+
+  let slot = &REGISTRY.slots[idx];
+  // SAFETY: the slot has been exclusively claimed with the atomic counter update and will not be read before it is
+  // marked as ready
+  unsafe {
+    *slot.entry.get() = Some((name, lock));
+  }
+  slot.ready.store(true, Ordering::Release);
+
+  true
+}
+
+/// Register a lock with the name it has been labeled with, e.g. by
+/// [Mutex::new_named](crate::sync::Mutex::new_named). Returns `false` if the lock has no name or the registry is full
+/// and the lock could not be registered.
+pub fn register_named(lock: &'static dyn InspectLock) -> bool {
+  match lock.lock_name() {
+    Some(name) => register(name, lock),
+    None => false,
+  }
+}
+
+/// Provide an iterator over the name and current [LockState] of all registered locks
+pub fn snapshot() -> Snapshot {
+  Snapshot {
+    idx: 0,
+    count: REGISTRY.count.load(Ordering::Acquire),
+  }
+}
+
+/// Iterator over the registered locks, created with [snapshot]
+pub struct Snapshot {
+  idx: usize,
+  count: usize,
+}
+
+impl Iterator for Snapshot {
+  type Item = (&'static str, LockState);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while self.idx < self.count {
+      let slot = &REGISTRY.slots[self.idx];
+      self.idx += 1;
+      if slot.ready.load(Ordering::Acquire) {
+        // SAFETY: the entry is written only once before the slot is marked as ready
+        if let Some((name, lock)) = unsafe { *slot.entry.get() } {
+          return Some((name, lock.lock_state()));
+        }
+      }
+    }
+    None
+  }
+}
+
+struct Slot {
+  ready: AtomicBool,
+  entry: UnsafeCell<Option<(&'static str, &'static dyn InspectLock)>>,
+}
+
+impl Slot {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const EMPTY: Slot = Slot {
+    ready: AtomicBool::new(false),
+    entry: UnsafeCell::new(None),
+  };
+}
+
+struct Registry {
+  count: AtomicUsize,
+  slots: [Slot; MAX_LOCKS],
+}
+
+// the slots are only written once after they have been claimed atomically, so it is safe to share them
+unsafe impl Sync for Registry {}
+
+static REGISTRY: Registry = Registry {
+  count: AtomicUsize::new(0),
+  slots: [Slot::EMPTY; MAX_LOCKS],
+};
@@ -0,0 +1,287 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # RobustSpinlock
+//!
+//! A spinlock coordinating the access to a resource shared with a peer that does not run this crate, like the
+//! VideoCore firmware or code at another exception level. The lock state is placed in memory shared with the peer
+//! which is typically mapped uncached. Atomic instructions are not reliable on such memory, so the lock uses
+//! Peterson's algorithm that only requires volatile reads and writes separated by data memory barriers.
+//!
+//! The algorithm arbitrates between exactly two sides. The cores of this side are serialized with a regular lock
+//! before they compete with the peer. As the peer might die while holding the lock, the owner records a tag. Once the
+//! peer is known to be dead its lock can be recovered with [RobustSpinlock::force_release_if_owner].
+//!
+//! The lock state consists of 5 consecutive `u32` words the peer need to implement the same protocol on:
+//!
+//! Word | Content
+//! -----|--------
+//! 0 | `1` if side 0 is interested in the lock, `0` otherwise
+//! 1 | `1` if side 1 is interested in the lock, `0` otherwise
+//! 2 | the side that has to wait if both are interested
+//! 3 | the tag of the current owner, `0` if the lock is free
+//! 4 | the side of the current owner
+//!
+//! To acquire the lock a side writes `1` to its interest word, then a data memory barrier, then the other side to the
+//! turn word, followed by another data memory barrier before it reads the state of the other side. The peer need to
+//! keep the same order. If the turn became visible ahead of the interest, both sides could enter the lock.
+//!
+//! # Example
+//! ```no_run
+//! use ruspiro_lock::sync::{RobustSpinlock, Side};
+//!
+//! // the lock state in the memory shared with the firmware
+//! static LOCK: RobustSpinlock = unsafe { RobustSpinlock::new(0x3C00_0000 as *mut u32, Side::Primary) };
+//!
+//! fn main() {
+//!     LOCK.acquire(1);
+//!     // access the shared resource
+//!     LOCK.release();
+//! }
+//! ```
+
+use super::Mutex;
+use crate::arch;
+use core::fmt;
+
+/// The side of the [RobustSpinlock] a party is using. Both parties sharing the lock need to use a different side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+  /// uses word 0 to announce it's interest in the lock
+  Primary = 0,
+  /// uses word 1 to announce it's interest in the lock
+  Secondary = 1,
+}
+
+/// The words of the shared lock state
+const INTERESTED: usize = 0;
+const TURN: usize = 2;
+const OWNER: usize = 3;
+const OWNER_SIDE: usize = 4;
+
+/// A spinlock with it's state in memory shared with a peer that might die while holding the lock
+pub struct RobustSpinlock {
+  /// The lock state in the shared memory
+  state: *mut u32,
+  side: Side,
+  /// Serializes the cores of this side
+  local: Mutex<()>,
+}
+
+impl RobustSpinlock {
+  /// Create a new [RobustSpinlock] using the lock state at the given address for the given side. The lock state need
+  /// to be initialized with zeros before any side uses the lock.
+  ///
+  /// # Safety
+  /// The address need to be valid for volatile reads and writes of 5 `u32` words for the whole lifetime of the lock
+  /// and this side shall only use this single [RobustSpinlock] for it.
+  pub const unsafe fn new(state: *mut u32, side: Side) -> Self {
+    Self {
+      state,
+      side,
+      local: Mutex::new(()),
+    }
+  }
+
+  /// Acquire the lock and record the given tag as owner. This blocks the current core until the lock could be acquired.
+  /// The tag shall not be `0`.
+  pub fn acquire(&self, tag: u32) {
+    // as the peer does not signal an event when releasing the lock, waiting always need to spin
+    core::mem::forget(self.local.lock());
+    self.announce();
+    while !self.may_enter() {
+      core::hint::spin_loop();
+    }
+    self.set_owner(tag);
+  }
+
+  /// Try to acquire the lock and record the given tag as owner. Returns `false` if the lock is currently held. The tag
+  /// shall not be `0`.
+  pub fn try_acquire(&self, tag: u32) -> bool {
+    let local = match self.local.try_lock() {
+      Some(local) => local,
+      None => return false,
+    };
+    self.announce();
+    if !self.may_enter() {
+      // withdraw the interest, so the peer is not blocked
+      self.write(INTERESTED + self.side as usize, 0);
+      arch::dmb();
+      return false;
+    }
+    core::mem::forget(local);
+    self.set_owner(tag);
+    true
+  }
+
+  /// Acquire the lock and record the given tag as owner. The tag shall not be `0`.
+  #[deprecated(since = "0.6.0", note = "use the correctly spelled `acquire`")]
+  pub fn aquire(&self, tag: u32) {
+    self.acquire(tag);
+  }
+
+  /// Try to acquire the lock and record the given tag as owner. The tag shall not be `0`.
+  #[deprecated(since = "0.6.0", note = "use the correctly spelled `try_acquire`")]
+  pub fn try_aquire(&self, tag: u32) -> bool {
+    self.try_acquire(tag)
+  }
+
+  /// Release the lock acquired by this side
+  pub fn release(&self) {
+    arch::dmb();
+    self.write(OWNER, 0);
+    self.write(INTERESTED + self.side as usize, 0);
+    arch::dmb();
+    // SAFETY: the local lock has been acquired and forgotten when this side acquired the lock
+    unsafe { self.local.unlock() };
+  }
+
+  /// The tag of the current owner of the lock. Returns `None` if the lock is free.
+  pub fn owner(&self) -> Option<u32> {
+    arch::dmb();
+    match self.read(OWNER) {
+      0 => None,
+      tag => Some(tag),
+    }
+  }
+
+  /// Release the lock if it is held by the owner with the given tag. This is used to recover the lock from a peer
+  /// that died while holding it. Returns `true` if the lock has been released.
+  ///
+  /// This shall only be called once the owner is known to be dead, otherwise it would loose the lock while still
+  /// accessing the shared resource.
+  pub fn force_release_if_owner(&self, tag: u32) -> bool {
+    arch::dmb();
+    if tag == 0 || self.read(OWNER) != tag {
+      return false;
+    }
+    let side = self.read(OWNER_SIDE) as usize & 1;
+    self.write(OWNER, 0);
+    self.write(INTERESTED + side, 0);
+    arch::dmb();
+    if side == self.side as usize {
+      // SAFETY: the lock has been acquired by a core of this side that also acquired the local lock
+      unsafe { self.local.unlock() };
+    }
+    true
+  }
+
+  /// Announce the interest of this side in the lock and give the peer precedence if it is interested as well
+  fn announce(&self) {
+    let side = self.side as usize;
+    self.write(INTERESTED + side, 1);
+    // the interest need to be visible before the turn is given away, otherwise the peer might read it as `0` once it
+    // sees the turn and enter together with this side
+    arch::dmb();
+    self.write(TURN, (side ^ 1) as u32);
+    // the writes need to be visible to the peer before it's state is read
+    arch::dmb();
+  }
+
+  /// Returns `true` if this side can enter after it announced it's interest
+  fn may_enter(&self) -> bool {
+    let other = self.side as usize ^ 1;
+    let entered = self.read(INTERESTED + other) == 0 || self.read(TURN) != other as u32;
+    arch::dmb();
+    entered
+  }
+
+  fn set_owner(&self, tag: u32) {
+    self.write(OWNER_SIDE, self.side as u32);
+    self.write(OWNER, tag);
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+  }
+
+  fn read(&self, word: usize) -> u32 {
+    // SAFETY: the creator of the lock guarantees the lock state is valid for volatile reads
+    unsafe { self.state.add(word).read_volatile() }
+  }
+
+  fn write(&self, word: usize, value: u32) {
+    // SAFETY: the creator of the lock guarantees the lock state is valid for volatile writes
+    unsafe { self.state.add(word).write_volatile(value) }
+  }
+}
+
+impl fmt::Debug for RobustSpinlock {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("RobustSpinlock")
+      .field("side", &self.side)
+      .field("owner", &self.owner())
+      .finish()
+  }
+}
+
+// the shared lock state is only accessed with volatile reads and writes
+unsafe impl Sync for RobustSpinlock {}
+unsafe impl Send for RobustSpinlock {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::cell::UnsafeCell;
+
+  /// The lock state shared by both sides
+  fn shared_state() -> UnsafeCell<[u32; 5]> {
+    UnsafeCell::new([0; 5])
+  }
+
+  #[test]
+  fn sides_exclude_each_other() {
+    let state = shared_state();
+    let primary = unsafe { RobustSpinlock::new(state.get().cast(), Side::Primary) };
+    let secondary = unsafe { RobustSpinlock::new(state.get().cast(), Side::Secondary) };
+
+    primary.acquire(1);
+    assert_eq!(primary.owner(), Some(1));
+    assert!(!secondary.try_acquire(2));
+    // the failed attempt withdrew its interest, so the primary side is not blocked by it
+    assert_eq!(secondary.read(INTERESTED + Side::Secondary as usize), 0);
+    // the cores of the same side are serialized as well
+    assert!(!primary.try_acquire(3));
+    primary.release();
+    assert_eq!(secondary.owner(), None);
+
+    assert!(secondary.try_acquire(2));
+    assert_eq!(primary.owner(), Some(2));
+    secondary.release();
+    assert_eq!(
+      unsafe { *state.get() },
+      [0, 0, 0, 0, Side::Secondary as u32]
+    );
+  }
+
+  #[test]
+  fn lock_of_a_dead_peer_is_recovered() {
+    let state = shared_state();
+    let primary = unsafe { RobustSpinlock::new(state.get().cast(), Side::Primary) };
+    let peer = unsafe { RobustSpinlock::new(state.get().cast(), Side::Secondary) };
+
+    // the peer dies while holding the lock
+    peer.acquire(7);
+    assert!(!primary.try_acquire(1));
+    assert!(!primary.force_release_if_owner(0));
+    assert!(!primary.force_release_if_owner(8));
+    assert!(primary.force_release_if_owner(7));
+    assert_eq!(primary.owner(), None);
+    assert!(primary.try_acquire(1));
+    primary.release();
+  }
+
+  #[test]
+  fn own_lock_is_recovered_with_the_local_lock() {
+    let state = shared_state();
+    let primary = unsafe { RobustSpinlock::new(state.get().cast(), Side::Primary) };
+
+    primary.acquire(1);
+    assert!(primary.force_release_if_owner(1));
+    assert!(primary.try_acquire(2));
+    primary.release();
+  }
+}
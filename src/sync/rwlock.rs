@@ -7,129 +7,772 @@
 
 //! # RWLock
 //!
+//! The state of the lock is kept in a single atomic word. The highest bit marks an existing write lock, the next bit
+//! marks an existing upgradable read lock, the next 6 bits count the pending writers and the remaining bits count the
+//! plain read locks. This allows each lock operation to be done with a single atomic update.
+//!
+//! An upgradable read lock coexists with plain read locks but excludes writers and other upgradable readers. When it
+//! is upgraded the write bit is set right away, so no new readers are let in and the upgrade only waits for the
+//! existing readers to leave. As only one upgradable read lock can exist the upgrade can not race with another
+//! writer.
+//!
+//! The number of plain read locks is limited to [MAX_READERS]. Once it is reached no further read lock is handed out,
+//! so leaked read guards can not overflow the counter into the other state bits and let a writer in while readers
+//! exist.
+//!
+//! A writer waiting in [RWLock::write] or [RWLock::try_write_spins] registers itself in the pending writer count of
+//! the state before it starts to wait. As long as writers are pending no new read locks are handed out, so a stream
+//! of overlapping readers can not starve a writer. Releasing the last read lock signals an event, so the waiting
+//! writer is woken once the existing readers have left. A core holding a read lock shall therefore not acquire another
+//! one with [RWLock::read] as this blocks forever once a writer is pending. Locks created with
+//! [RWLock::new_read_preferring] ignore pending writers and hand out read locks whenever no write lock exists.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::RWLock;
+//!
+//! static LAYERS: RWLock<[u32; 4]> = RWLock::new([0; 4]);
+//!
+//! fn main() {
+//!     let layers = LAYERS.upgradable_read();
+//!     // other readers can still access the data
+//!     assert!(LAYERS.try_read().is_some());
+//!     if layers[0] == 0 {
+//!         let mut layers = layers.upgrade();
+//!         layers[0] = 1;
+//!     }
+//! }
+//! ```
+//!
+//! # Trait objects
+//! Like the [Mutex](crate::sync::Mutex) a RWLock can secure a dynamically sized value like a trait object. Any
+//! reference, `Box` or `Arc` to a `RWLock<T>` coerces into one to a `RWLock<dyn Trait>` if `T` implements the trait.
+//! With the `alloc` feature [RWLock::new_unsized] allocates a RWLock that is meant to be coerced into a trait object.
+//! ```
+//! use ruspiro_lock::sync::RWLock;
+//! use core::fmt::Display;
+//!
+//! static NAME: RWLock<&str> = RWLock::new("uart0");
+//! static ID: RWLock<u32> = RWLock::new(0);
+//! static LABELS: [&RWLock<dyn Display + Send + Sync>; 2] = [&NAME, &ID];
+//!
+//! fn main() {
+//!     for label in LABELS.iter() {
+//!         println!("{}", &*label.read());
+//!     }
+//! }
+//! ```
 
-use core::arch::asm;
+#[cfg(any(feature = "alloc", doc))]
+extern crate alloc;
+
+use super::marker::GuardMarker;
+use super::metrics::{self, LockKind};
+use super::reentrancy::{self, Access};
+use super::registry::{InspectLock, LockState};
+use super::spin;
+use super::BlockingRwLock;
+use crate::config::core_id;
+use crate::{arch, LockError};
+#[cfg(any(feature = "alloc", doc))]
+use alloc::boxed::Box;
+#[cfg(any(feature = "alloc", doc))]
+use alloc::sync::Arc;
 use core::cell::UnsafeCell;
 use core::fmt;
+use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::sync::atomic::{AtomicU32, Ordering};
 
 /// An exclusive access lock around the given data
 #[repr(C, align(16))]
 pub struct RWLock<T: ?Sized> {
-  /// indicates whether a mutual exclusive write lock exists
-  write_lock: AtomicBool,
-  /// counts existing read-locks, this could be used in future to mark the data as "dirty" if a write lock is aquired
-  /// whiled read access is also handed out. Should a write access request fail with existing read access ?
-  read_locks: AtomicU32,
+  /// the lock state containing the `WRITER` and `UPGRADABLE` bits, the number of pending writers and the number of
+  /// read locks
+  state: AtomicU32,
+  /// new read locks are not handed out while writers are pending
+  prefer_writers: bool,
+  /// the number of plain read locks that can exist at the same time
+  max_readers: u32,
+  /// the number of readers waiting in [RWLock::read]
+  waiting_readers: AtomicU32,
+  /// the number of waiting readers a released writer admits although writers are pending, so all readers that waited
+  /// for the writer get through in one pass
+  read_batch: AtomicU32,
   data: UnsafeCell<T>,
 }
 
+/// The state bit indicating that a mutual exclusive write lock exists
+const WRITER: u32 = 1 << 31;
+/// The state bit indicating that an upgradable read lock exists
+const UPGRADABLE: u32 = 1 << 30;
+/// A single writer waiting in [RWLock::write] or [RWLock::try_write_spins] in the pending writer count
+const PENDING_ONE: u32 = 1 << 24;
+/// The state bits counting the pending writers. No new read locks are handed out while writers are pending.
+const PENDING: u32 = UPGRADABLE - PENDING_ONE;
+/// The state bits counting the existing plain read locks
+const READERS: u32 = PENDING_ONE - 1;
+/// The maximum number of plain read locks that can exist at the same time for one [RWLock]. [RWLock::try_read] fails
+/// and [RWLock::read] panics once this number of read locks exist.
+pub const MAX_READERS: u32 = READERS;
+/// The spins per core id a woken reader waits before it retries, so the readers woken by the same event do not all
+/// update the lock state at once
+const READER_STAGGER_SPINS: usize = 16;
+
 /// Result of trying to access the data using ``try_lock`` or ``lock`` on the data lock. If the
 /// result goes out of scope the write lock is released.
+///
+/// If the guard is passed to `core::mem::forget` the write lock is never released and any further attempt to acquire
+/// a read or write lock will fail or block forever. This is safe but should only be done on purpose, see
+/// [WriteLockGuard::leak].
+#[cfg_attr(
+  feature = "must_not_suspend",
+  must_not_suspend = "holding a WriteLockGuard across a suspend point blocks the other cores waiting for the lock"
+)]
 pub struct WriteLockGuard<'a, T: ?Sized + 'a> {
   _data: &'a RWLock<T>,
+  _marker: GuardMarker,
 }
 
-/// Result of aquiring read access to the data using ``read`` on the data lock. If the
+/// Result of acquiring read access to the data using ``read`` on the data lock. If the
 /// result goes out of scope the read lock is released.
+///
+/// If the guard is passed to `core::mem::forget` the read lock is never released. Further read locks can still be
+/// acquired but any attempt to acquire a write lock will fail or block forever.
+#[cfg_attr(
+  feature = "must_not_suspend",
+  must_not_suspend = "holding a ReadLockGuard across a suspend point blocks the other cores waiting for the lock"
+)]
 pub struct ReadLockGuard<'a, T: ?Sized + 'a> {
   _data: &'a RWLock<T>,
+  _marker: GuardMarker,
+}
+
+/// Result of acquiring upgradable read access to the data using ``upgradable_read`` on the data lock. It can be
+/// upgraded to a [WriteLockGuard]. If the result goes out of scope the upgradable read lock is released.
+#[cfg_attr(
+  feature = "must_not_suspend",
+  must_not_suspend = "holding an UpgradableReadGuard across a suspend point blocks the other cores waiting for the lock"
+)]
+pub struct UpgradableReadGuard<'a, T: ?Sized + 'a> {
+  _data: &'a RWLock<T>,
+  _marker: GuardMarker,
 }
 
 impl<T> RWLock<T> {
   /// Create a new data access guarding lock.
   pub const fn new(value: T) -> Self {
     RWLock {
-      write_lock: AtomicBool::new(false),
-      read_locks: AtomicU32::new(0),
+      state: AtomicU32::new(0),
+      prefer_writers: true,
+      max_readers: MAX_READERS,
+      waiting_readers: AtomicU32::new(0),
+      read_batch: AtomicU32::new(0),
       data: UnsafeCell::new(value),
     }
   }
+
+  /// Create a new data access guarding lock that hands out read locks even if writers are pending. This allows a
+  /// core to acquire nested read locks but a continuous stream of readers can starve the writers.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static TABLE: RWLock<[u32; 4]> = RWLock::new_read_preferring([0; 4]);
+  /// # fn main() {
+  ///     let outer = TABLE.read();
+  ///     // a nested read lock never waits for a pending writer
+  ///     let inner = TABLE.read();
+  ///     assert_eq!(outer[0], inner[0]);
+  /// # }
+  /// ```
+  pub const fn new_read_preferring(value: T) -> Self {
+    RWLock {
+      state: AtomicU32::new(0),
+      prefer_writers: false,
+      max_readers: MAX_READERS,
+      waiting_readers: AtomicU32::new(0),
+      read_batch: AtomicU32::new(0),
+      data: UnsafeCell::new(value),
+    }
+  }
+
+  /// Limit the number of plain read locks that can exist at the same time. Once this number of read locks exist
+  /// [RWLock::try_read] fails and [RWLock::read] blocks until a read lock is released. This bounds the time a writer
+  /// has to wait for the existing read locks to be released.
+  ///
+  /// # Panics
+  /// Panics if `max_readers` is `0` or exceeds [MAX_READERS].
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static FRAME: RWLock<[u8; 16]> = RWLock::new([0; 16]).with_max_readers(2);
+  /// # fn main() {
+  ///     let first = FRAME.read();
+  ///     let second = FRAME.read();
+  ///     assert!(FRAME.try_read().is_none());
+  /// # }
+  /// ```
+  pub const fn with_max_readers(mut self, max_readers: u32) -> Self {
+    assert!(
+      max_readers > 0 && max_readers <= MAX_READERS,
+      "max_readers need to be in the range 1..=MAX_READERS"
+    );
+    self.max_readers = max_readers;
+    self
+  }
+
+  /// Create a new data access guarding lock on the heap that is meant to be coerced into a RWLock of a dynamically
+  /// sized type, like a trait object.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// # use core::fmt::Debug;
+  /// # fn main() {
+  ///     let data: Box<RWLock<dyn Debug + Send + Sync>> = RWLock::new_unsized(10u32);
+  ///     println!("{:?}", data.debug_value());
+  /// # }
+  /// ```
+  #[cfg(any(feature = "alloc", doc))]
+  pub fn new_unsized(value: T) -> Box<Self> {
+    Box::new(Self::new(value))
+  }
+}
+
+impl<T> From<T> for RWLock<T> {
+  fn from(value: T) -> Self {
+    Self::new(value)
+  }
 }
 
 impl<T: ?Sized> RWLock<T> {
   /// Try to provide a Writelock for mutual exclusive access. Returns ``None`` if the lock fails
   /// or ``Some(WriteLockGuard)``. The actual data, the [WriteLockGuard] wraps could be conviniently accessed by
   /// dereferencing it.
-  pub fn try_write(&self) -> Option<WriteLockGuard<T>> {
-    if self.read_locks.load(Ordering::Relaxed) > 0 {
-      // write lock can only be given if there is no concurrent ReadLock already
-      // existing
-      return None;
-    }
-    // do the atomic operation to set the lock
-    if !self.write_lock.swap(true, Ordering::Acquire) {
-      // has been false previously means we now have the lock
+  pub fn try_write(&self) -> Option<WriteLockGuard<'_, T>> {
+    self.try_write_as(false)
+  }
 
-      #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-      unsafe {
-        // dmb required before allow access to the protected resource, see:
-        // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
-        asm!("dmb sy");
-      }
+  /// Try to acquire the write lock on behalf of a writer that is registered in the pending writer count if `pending`
+  /// is `true`. The writer is removed from the pending writers once it acquired the lock.
+  fn try_write_as(&self, pending: bool) -> Option<WriteLockGuard<'_, T>> {
+    let registered = if pending { PENDING_ONE } else { 0 };
+    // write lock can only be given if there is no concurrent lock of any kind existing, so do the atomic operation to
+    // set the lock only if the state is unlocked. Other pending writers keep their registration
+    if self
+      .state
+      .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+        if state & (WRITER | UPGRADABLE | READERS) != 0 {
+          None
+        } else {
+          Some((state - registered) | WRITER)
+        }
+      })
+      .is_ok()
+    {
+      // dmb required before allow access to the protected resource, see:
+      // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+      arch::dmb();
+      reentrancy::track(&self.state, Access::Write);
 
-      Some(WriteLockGuard { _data: self })
+      Some(WriteLockGuard {
+        _data: self,
+        _marker: PhantomData,
+      })
     } else {
       // we couldn't set the lock
       None
     }
   }
 
+  /// Register a writer in the pending writer count to hold off new readers. Returns `false` if the maximum number of
+  /// pending writers is reached. The writer then competes for the lock without being registered.
+  fn register_writer(&self) -> bool {
+    let registered = self
+      .state
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+        if state & PENDING == PENDING {
+          None
+        } else {
+          Some(state + PENDING_ONE)
+        }
+      })
+      .is_ok();
+    // the registration need to be visible to the readers before the writer starts to wait for them to leave
+    arch::dmb();
+    registered
+  }
+
+  /// Remove a registered writer that gave up waiting from the pending writer count
+  fn unregister_writer(&self) {
+    self.state.fetch_sub(PENDING_ONE, Ordering::Relaxed);
+    // readers might wait for the pending writer to give up, so raise a signal to wake them
+    arch::dmb();
+    arch::signal_event();
+  }
+
+  /// Try to provide a WriteLock for mutual exclusive access, spinning at most `max_spins` times while there are other
+  /// locks existing. While spinning the writer is pending and no new read locks are handed out, so the writer only
+  /// waits for the existing ones to be released. This bounds the latency of a writer without the need of a time
+  /// source. Returns ``None`` if the lock could not be acquired within the given number of spins.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static FRAME: RWLock<[u8; 16]> = RWLock::new([0; 16]);
+  /// # fn main() {
+  ///     if let Some(mut frame) = FRAME.try_write_spins(1_000) {
+  ///         frame[0] = 0xFF;
+  ///     }
+  /// # }
+  /// ```
+  pub fn try_write_spins(&self, max_spins: u32) -> Option<WriteLockGuard<'_, T>> {
+    if let Some(guard) = self.try_write() {
+      return Some(guard);
+    }
+
+    // hold off new readers while spinning
+    let registered = self.register_writer();
+    for _ in 0..max_spins {
+      if let Some(guard) = self.try_write_as(registered) {
+        return Some(guard);
+      }
+      core::hint::spin_loop();
+    }
+
+    if registered {
+      self.unregister_writer();
+    }
+    None
+  }
+
+  /// Lock the data for write access like [RWLock::write], but call `keep_waiting` with the number of failed attempts
+  /// each time the core is about to wait again. If it returns `false` waiting is aborted and `None` is returned. This
+  /// allows to stop waiting on external conditions, like a shutdown flag or a deadline, without a global time source.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static FRAME: RWLock<[u8; 16]> = RWLock::new([0; 16]);
+  /// # fn main() {
+  ///     if let Some(mut frame) = FRAME.write_while(|attempt| attempt < 100) {
+  ///         frame[0] = 0xFF;
+  ///     }
+  /// # }
+  /// ```
+  pub fn write_while<F: FnMut(u32) -> bool>(
+    &self,
+    mut keep_waiting: F,
+  ) -> Option<WriteLockGuard<'_, T>> {
+    self.assert_not_reentrant(Access::Write, "RWLock::write_while");
+    if let Some(write_guard) = self.try_write() {
+      metrics::record(LockKind::RWLockWrite, 0);
+      return Some(write_guard);
+    }
+
+    // hold off new readers while waiting
+    let registered = self.register_writer();
+    let mut attempt = 0;
+    loop {
+      if let Some(write_guard) = self.try_write_as(registered) {
+        metrics::record(LockKind::RWLockWrite, attempt);
+        return Some(write_guard);
+      }
+      if !keep_waiting(attempt) {
+        if registered {
+          self.unregister_writer();
+        }
+        return None;
+      }
+      spin::on_contention(&mut attempt, "RWLock::write_while");
+    }
+  }
+
   /// Provide a WriteLock for mutual exclusive access. This blocks until the data could be
   /// successfully locked. This also implies that there is no concurrent [ReadLockGuard] existing.
   /// The locked data will be returned as [WriteLockGuard]. Simply derefrencing
   /// this allows access to the contained data value.
   ///
-  pub fn write(&self) -> WriteLockGuard<T> {
+  /// While waiting the writer is pending and no new read locks are handed out, unless the lock has been created with
+  /// [RWLock::new_read_preferring].
+  ///
+  /// # Panics
+  /// With the `reentrancy_detection` feature in debug builds this panics if the current core already holds a lock of
+  /// any kind of this RWLock, as this would block forever.
+  pub fn write(&self) -> WriteLockGuard<'_, T> {
+    self.assert_not_reentrant(Access::Write, "RWLock::write");
+    if let Some(write_guard) = self.try_write() {
+      metrics::record(LockKind::RWLockWrite, 0);
+      return write_guard;
+    }
+
+    // announce the writer before waiting, so the last reader leaving signals the event the writer waits for
+    let registered = self.register_writer();
+    let mut attempt = 0;
     loop {
-      if let Some(write_guard) = self.try_write() {
-        //println!("write lock aquired {:?}", core::any::type_name::<T>());
+      if let Some(write_guard) = self.try_write_as(registered) {
+        //println!("write lock acquired {:?}", core::any::type_name::<T>());
+        metrics::record(LockKind::RWLockWrite, attempt);
         return write_guard;
       }
       // to save energy and cpu consumption we can wait for an event beeing raised that indicates that the
-      // semaphore value has likely beeing changed
-      #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-      unsafe {
-        asm!("wfe");
-      }
+      // semaphore value has likely beeing changed, depending on the selected spin policy
+      spin::on_contention(&mut attempt, "RWLock::write");
     }
   }
 
-  /// Provide a ReadLock to the wrapped data. This call blocks until the recource is available.
-  /// There can be as many concurrent [ReadLockGuard]s being handed out if there is no [WriteLockGuard] to the
-  /// same resource already existing.
-  pub fn try_read(&self) -> Option<ReadLockGuard<T>> {
-    // read locks can only handed out if no write lock is existing already
-    if self.write_lock.load(Ordering::Relaxed) {
-      None
-    } else {
-      self.read_locks.fetch_add(1, Ordering::Acquire);
-      //println!("read lock aquired {:?}", core::any::type_name::<T>());
-      Some(ReadLockGuard { _data: self })
+  /// Provide a WriteLock like [RWLock::write] through the same fallible API as [RWLock::read_checked] and
+  /// [Mutex::lock_checked](super::Mutex::lock_checked). With the `reentrancy_detection` feature in debug builds this
+  /// fails with [LockError::Deadlock] instead of panicking if the current core already holds a lock of any kind of
+  /// this RWLock. Otherwise the cores holding the RWLock are not tracked, so this blocks like [RWLock::write].
+  pub fn write_checked(&self) -> Result<WriteLockGuard<'_, T>, LockError> {
+    if reentrancy::blocking(&self.state, Access::Write).is_some() {
+      return Err(LockError::Deadlock);
     }
+    Ok(self.write())
+  }
+
+  /// Try to provide a ReadLock to the wrapped data. Returns ``None`` if there is a [WriteLockGuard] or the maximum
+  /// number of [ReadLockGuard]s existing already or a writer is pending in [RWLock::write] or
+  /// [RWLock::try_write_spins]. The maximum is [MAX_READERS] unless the lock has been limited with
+  /// [RWLock::with_max_readers]. Otherwise there can be as many concurrent [ReadLockGuard]s being handed out.
+  pub fn try_read(&self) -> Option<ReadLockGuard<'_, T>> {
+    // read locks can only handed out if no write lock is existing already
+    let blocking = self.blocking_readers();
+    self
+      .state
+      .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+        // the reader count saturates at it's maximum instead of overflowing into the pending writers
+        if state & blocking != 0 || state & READERS >= self.max_readers {
+          None
+        } else {
+          Some(state + 1)
+        }
+      })
+      .ok()
+      .map(|_| {
+        //println!("read lock acquired {:?}", core::any::type_name::<T>());
+        reentrancy::track(&self.state, Access::Read);
+        ReadLockGuard {
+          _data: self,
+          _marker: PhantomData,
+        }
+      })
   }
 
   /// Provide a ReadLock to the wrapped data. This call blocks until the recource is available.
   /// There can be as many concurrent [ReadLockGuard]s being handed out if there is no [WriteLockGuard] to the
   /// same resource already existing.
-  pub fn read(&self) -> ReadLockGuard<T> {
+  ///
+  /// If the lock has been limited with [RWLock::with_max_readers] this also blocks while the maximum number of read
+  /// locks exist.
+  ///
+  /// The readers waiting for a writer are all woken by the same event once it releases the lock. They retry staggered
+  /// by their core id, so they do not all update the lock state at once, and are admitted as one batch before the
+  /// next pending writer.
+  ///
+  /// # Panics
+  /// Panics if [MAX_READERS] read locks exist already, as those are likely leaked and this would block forever. With
+  /// the `reentrancy_detection` feature in debug builds this also panics if the current core already holds the write
+  /// lock of this RWLock.
+  pub fn read(&self) -> ReadLockGuard<'_, T> {
+    self.assert_not_reentrant(Access::Read, "RWLock::read");
+    self
+      .read_checked()
+      .unwrap_or_else(|_| panic!("maximum number of read locks exceeded"))
+  }
+
+  /// Provide a ReadLock like [RWLock::read], but fail with [LockError::Deadlock] instead of panicking if [MAX_READERS]
+  /// read locks exist already, as those are likely leaked and waiting would never end. With the `reentrancy_detection`
+  /// feature in debug builds this also fails if the current core already holds the write lock of this RWLock.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static DATA: RWLock<u32> = RWLock::new(10);
+  /// # fn main() {
+  ///     match DATA.read_checked() {
+  ///         Ok(data) => println!("data: {}", *data),
+  ///         Err(error) => println!("{}", error),
+  ///     }
+  /// # }
+  /// ```
+  pub fn read_checked(&self) -> Result<ReadLockGuard<'_, T>, LockError> {
+    if reentrancy::blocking(&self.state, Access::Read).is_some() {
+      return Err(LockError::Deadlock);
+    }
     // read locks can only handed out if no write lock is existing already
-    loop {
+    if let Some(read_guard) = self.try_read() {
+      metrics::record(LockKind::RWLockRead, 0);
+      return Ok(read_guard);
+    }
+
+    // announce the reader before waiting, so a writer releasing the lock admits it with the next batch of readers
+    self.waiting_readers.fetch_add(1, Ordering::Relaxed);
+    arch::dmb();
+    let mut attempt = 0;
+    let result = loop {
       if let Some(read_guard) = self.try_read() {
-        //println!("write lock aquired {:?}", core::any::type_name::<T>());
-        return read_guard;
+        // the reader might be counted in the batch of the last released writer, so it uses up one admission of it.
+        // Otherwise the admission would be left to a reader arriving later and let it pass the next pending writer
+        self.take_read_admission();
+        metrics::record(LockKind::RWLockRead, attempt);
+        break Ok(read_guard);
+      }
+      if let Some(read_guard) = self.try_read_batched() {
+        //println!("write lock acquired {:?}", core::any::type_name::<T>());
+        metrics::record(LockKind::RWLockRead, attempt);
+        break Ok(read_guard);
+      }
+      if self.state.load(Ordering::Relaxed) & READERS == MAX_READERS {
+        self.take_read_admission();
+        break Err(LockError::Deadlock);
       }
 
       // to save energy and cpu consumption we can wait for an event beeing raised that indicates that the
-      // lock value has likely beeing changed
-      #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-      unsafe {
-        asm!("wfe");
+      // lock value has likely beeing changed, depending on the selected spin policy
+      spin::on_contention(&mut attempt, "RWLock::read");
+      // all readers are woken by the same event, so they retry one after another
+      Self::stagger_reader();
+    };
+    self.waiting_readers.fetch_sub(1, Ordering::Relaxed);
+    result
+  }
+
+  /// Take one admission of the batch of readers admitted by the last released writer. Returns `false` if the batch is
+  /// used up already.
+  fn take_read_admission(&self) -> bool {
+    self
+      .read_batch
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |batch| {
+        batch.checked_sub(1)
+      })
+      .is_ok()
+  }
+
+  /// Try to provide a ReadLock to a waiting reader with the batch of readers admitted by the last released writer,
+  /// although writers are pending.
+  fn try_read_batched(&self) -> Option<ReadLockGuard<'_, T>> {
+    if !self.take_read_admission() {
+      return None;
+    }
+    let admitted = self
+      .state
+      .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+        if state & WRITER != 0 || state & READERS >= self.max_readers {
+          None
+        } else {
+          Some(state + 1)
+        }
+      })
+      .is_ok();
+    if !admitted {
+      // a writer acquired the lock before this reader, so keep the admission for the next attempt
+      self.read_batch.fetch_add(1, Ordering::Relaxed);
+      return None;
+    }
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    reentrancy::track(&self.state, Access::Read);
+    Some(ReadLockGuard {
+      _data: self,
+      _marker: PhantomData,
+    })
+  }
+
+  /// Wait a number of spins depending on the id of the current core before a woken reader retries
+  #[inline]
+  fn stagger_reader() {
+    for _ in 0..core_id() * READER_STAGGER_SPINS {
+      core::hint::spin_loop();
+    }
+  }
+
+  /// Try to provide an upgradable read lock to the wrapped data. Returns ``None`` if there is a [WriteLockGuard] or
+  /// another [UpgradableReadGuard] existing. Plain [ReadLockGuard]s can still be handed out while the upgradable read
+  /// lock exists.
+  pub fn try_upgradable_read(&self) -> Option<UpgradableReadGuard<'_, T>> {
+    let blocking = self.blocking_readers() | UPGRADABLE;
+    self
+      .state
+      .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+        if state & blocking != 0 {
+          None
+        } else {
+          Some(state | UPGRADABLE)
+        }
+      })
+      .ok()
+      .map(|_| {
+        reentrancy::track(&self.state, Access::Upgradable);
+        UpgradableReadGuard {
+          _data: self,
+          _marker: PhantomData,
+        }
+      })
+  }
+
+  /// Provide an upgradable read lock to the wrapped data. This call blocks until there is no [WriteLockGuard] and no
+  /// other [UpgradableReadGuard] existing.
+  ///
+  /// # Panics
+  /// With the `reentrancy_detection` feature in debug builds this panics if the current core already holds the write
+  /// lock or the upgradable read lock of this RWLock, as this would block forever.
+  pub fn upgradable_read(&self) -> UpgradableReadGuard<'_, T> {
+    self.assert_not_reentrant(Access::Upgradable, "RWLock::upgradable_read");
+    let mut attempt = 0;
+    loop {
+      if let Some(guard) = self.try_upgradable_read() {
+        metrics::record(LockKind::RWLockRead, attempt);
+        return guard;
       }
+
+      // to save energy and cpu consumption we can wait for an event beeing raised that indicates that the
+      // lock value has likely beeing changed, depending on the selected spin policy
+      spin::on_contention(&mut attempt, "RWLock::upgradable_read");
+    }
+  }
+
+  /// Acquire the write lock, replace the data with the given value and return the previous one. This blocks until
+  /// the write lock could be acquired and releases it before returning.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static DATA: RWLock<u32> = RWLock::new(10);
+  /// # fn main() {
+  ///     let old = DATA.replace(20);
+  ///     assert_eq!(old, 10);
+  ///     assert_eq!(*DATA.read(), 20);
+  /// # }
+  /// ```
+  pub fn replace(&self, value: T) -> T
+  where
+    T: Sized,
+  {
+    let mut data = self.write();
+    core::mem::replace(&mut *data, value)
+  }
+
+  /// Return a clone of the secured data. The read lock is only held while cloning, so the clone can be used for long
+  /// running work, like formatting, without holding off writers.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static DATA: RWLock<u32> = RWLock::new(10);
+  /// # fn main() {
+  ///     let value = DATA.cloned();
+  ///     assert_eq!(value, 10);
+  /// # }
+  /// ```
+  pub fn cloned(&self) -> T
+  where
+    T: Clone,
+  {
+    self.read().clone()
+  }
+
+  /// Return a clone of the secured data in an [Arc], so the snapshot can be shared cheaply. The read lock is only held
+  /// while cloning, the allocation is done after it has been released.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static DATA: RWLock<u32> = RWLock::new(10);
+  /// # fn main() {
+  ///     let snapshot = DATA.snapshot();
+  ///     assert_eq!(*snapshot, 10);
+  /// # }
+  /// ```
+  #[cfg(any(feature = "alloc", doc))]
+  pub fn snapshot(&self) -> Arc<T>
+  where
+    T: Clone,
+  {
+    Arc::new(self.cloned())
+  }
+
+  /// Acquire a read lock of the RWLock shared with an [Arc] like [RWLock::read]. The returned [OwnedReadLockGuard]
+  /// keeps the RWLock alive and is not bound to the lifetime of a borrow, so it can be stored in a struct or a
+  /// `Future` that is handed to the executor of another core.
+  ///
+  /// # Example
+  /// ```
+  /// # extern crate alloc;
+  /// # use alloc::sync::Arc;
+  /// # use ruspiro_lock::sync::{OwnedReadLockGuard, RWLock};
+  /// struct Renderer {
+  ///     layers: OwnedReadLockGuard<[u32; 4]>,
+  /// }
+  ///
+  /// # fn main() {
+  /// let layers = Arc::new(RWLock::new([0; 4]));
+  /// let renderer = Renderer { layers: layers.read_owned() };
+  /// assert_eq!(renderer.layers[0], 0);
+  /// # }
+  /// ```
+  #[cfg(any(feature = "alloc", doc))]
+  pub fn read_owned(self: &Arc<Self>) -> OwnedReadLockGuard<T> {
+    core::mem::forget(self.read());
+    OwnedReadLockGuard {
+      lock: Arc::clone(self),
+      _marker: PhantomData,
     }
   }
 
+  /// Try to acquire a read lock of the RWLock shared with an [Arc] like [RWLock::try_read]. Returns `None` if there
+  /// is a write lock or writers are pending.
+  #[cfg(any(feature = "alloc", doc))]
+  pub fn try_read_owned(self: &Arc<Self>) -> Option<OwnedReadLockGuard<T>> {
+    core::mem::forget(self.try_read()?);
+    Some(OwnedReadLockGuard {
+      lock: Arc::clone(self),
+      _marker: PhantomData,
+    })
+  }
+
+  /// Acquire the write lock of the RWLock shared with an [Arc] like [RWLock::write]. The returned
+  /// [OwnedWriteLockGuard] keeps the RWLock alive and is not bound to the lifetime of a borrow.
+  ///
+  /// # Example
+  /// ```
+  /// # extern crate alloc;
+  /// # use alloc::sync::Arc;
+  /// # use ruspiro_lock::sync::RWLock;
+  /// # fn main() {
+  /// let config = Arc::new(RWLock::new(0u32));
+  /// let mut guard = config.write_owned();
+  /// // the guard can be moved to another core, which releases the lock once done
+  /// *guard = 10;
+  /// drop(guard);
+  /// assert_eq!(*config.read(), 10);
+  /// # }
+  /// ```
+  #[cfg(any(feature = "alloc", doc))]
+  pub fn write_owned(self: &Arc<Self>) -> OwnedWriteLockGuard<T> {
+    core::mem::forget(self.write());
+    OwnedWriteLockGuard {
+      lock: Arc::clone(self),
+      _marker: PhantomData,
+    }
+  }
+
+  /// Try to acquire the write lock of the RWLock shared with an [Arc] like [RWLock::try_write]. Returns `None` if
+  /// there is any other lock existing.
+  #[cfg(any(feature = "alloc", doc))]
+  pub fn try_write_owned(self: &Arc<Self>) -> Option<OwnedWriteLockGuard<T>> {
+    core::mem::forget(self.try_write()?);
+    Some(OwnedWriteLockGuard {
+      lock: Arc::clone(self),
+      _marker: PhantomData,
+    })
+  }
+
   /// Provide an immutable borrow to the data secured by the RWLock.
   ///
   /// # Safety
@@ -139,6 +782,70 @@ impl<T: ?Sized> RWLock<T> {
     &*self.data.get()
   }
 
+  /// Panic if the current core already holds a lock of this RWLock that blocks the requested one forever. This is only
+  /// detected with the `reentrancy_detection` feature in debug builds.
+  #[track_caller]
+  fn assert_not_reentrant(&self, requested: Access, operation: &str) {
+    if let Some(held) = reentrancy::blocking(&self.state, requested) {
+      panic!(
+        "{} would block forever, as the current core already holds {} of this RWLock",
+        operation, held
+      );
+    }
+  }
+
+  /// The state bits that prevent new read locks from being handed out
+  fn blocking_readers(&self) -> u32 {
+    if self.prefer_writers {
+      WRITER | PENDING
+    } else {
+      WRITER
+    }
+  }
+
+  /// Complete an upgrade begun with [UpgradableReadGuard::begin_upgrade] if the existing read locks have been
+  /// released. Returns `None` otherwise.
+  ///
+  /// # Safety
+  /// The caller need to have begun the upgrade of this lock and shall not have completed or aborted it yet.
+  pub(crate) unsafe fn try_complete_upgrade(&self) -> Option<WriteLockGuard<'_, T>> {
+    if self.state.load(Ordering::Acquire) & READERS != 0 {
+      return None;
+    }
+    self.state.fetch_and(!UPGRADABLE, Ordering::Relaxed);
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    reentrancy::track(&self.state, Access::Write);
+    Some(WriteLockGuard {
+      _data: self,
+      _marker: PhantomData,
+    })
+  }
+
+  /// Abort an upgrade begun with [UpgradableReadGuard::begin_upgrade] and release the lock.
+  ///
+  /// # Safety
+  /// The caller need to have begun the upgrade of this lock and shall not have completed or aborted it yet.
+  #[cfg(feature = "async_locks")]
+  pub(crate) unsafe fn abort_upgrade(&self) {
+    self
+      .state
+      .fetch_and(!(WRITER | UPGRADABLE), Ordering::Release);
+
+    // dmb required after atomic operations, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // the pending upgrade held off readers and writers, so raise a signal to wake them
+    arch::signal_event();
+  }
+
+  /// Raw pointer to the data secured by the RWLock
+  pub(crate) fn data_ptr(&self) -> *mut T {
+    self.data.get()
+  }
+
   /// Consume the Mutex and return the inner value
   pub fn into_inner(self) -> T
   where
@@ -146,61 +853,395 @@ impl<T: ?Sized> RWLock<T> {
   {
     self.data.into_inner()
   }
+
+  /// Release all locks of the RWLock regardless of which cores hold them and signal this to the waiting cores. This
+  /// clears the write lock, the upgradable read lock and the read locks at once. The writers waiting for the lock keep
+  /// their registration, as they remove it themselves once they acquired the lock or gave up. It is intended for
+  /// exception recovery code that need to reclaim a lock held by a crashed core.
+  ///
+  /// # Safety
+  /// No [WriteLockGuard], [ReadLockGuard] or [UpgradableReadGuard] of this RWLock shall be used or dropped
+  /// afterwards, e.g. because the cores holding them have been halted or reset. Otherwise a dropped guard would
+  /// corrupt the lock state. The secured data might be left in an inconsistent state by a former writer, so the caller
+  /// need to restore a consistent state before the data is used again.
+  pub unsafe fn force_unlock(&self) {
+    self.state.fetch_and(PENDING, Ordering::Release);
+    reentrancy::untrack_all(&self.state);
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // also raise a signal to indicate the lock has been changed (this trigger all WFE's to continue processing)
+    arch::signal_event();
+  }
+
+  /// The current state of the RWLock. This only reads the write lock flag and the reader count and never acquires the
+  /// lock, so it is safe to be used while the lock is held by the current core, eg. from a panic handler.
+  pub fn fmt_state(&self) -> LockState {
+    let state = self.state.load(Ordering::Relaxed);
+    LockState::RWLock {
+      write_locked: state & WRITER != 0,
+      // the upgradable read lock is reported as a reader
+      readers: (state & READERS) + (state & UPGRADABLE != 0) as u32,
+    }
+  }
+
+  /// Provide a [fmt::Debug] representation of the secured data. Formatting it tries to acquire a read lock and prints
+  /// `<write locked>` if this fails. In contrast to the [fmt::Debug] implementation of the RWLock this does acquire
+  /// the lock and shall therefore only be used where this is known to be safe.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static DATA: RWLock<u32> = RWLock::new(10);
+  /// # fn main() {
+  ///     println!("{:?}", DATA.debug_value());
+  /// # }
+  /// ```
+  pub fn debug_value(&self) -> impl fmt::Debug + '_
+  where
+    T: fmt::Debug,
+  {
+    DebugValue(self)
+  }
 }
 
-impl<T: ?Sized + fmt::Debug> fmt::Debug for RWLock<T>
-where
-  T: fmt::Debug,
-{
+/// The Debug implementation only reports the lock state and never acquires the lock. Use [RWLock::debug_value] to
+/// print the secured data.
+impl<T: ?Sized> fmt::Debug for RWLock<T> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    let mut dbg = f.debug_struct("DataLock");
-    match self.try_read() {
-      Some(guard) => {
-        dbg.field("Value", &&*guard);
-      }
-      _ => {
-        dbg.field("Value", &"unable to r-lock");
-      }
+    let state = self.state.load(Ordering::Relaxed);
+    f.debug_struct("RWLock")
+      .field("write_locked", &(state & WRITER != 0))
+      .field("upgradable", &(state & UPGRADABLE != 0))
+      .field("pending_writers", &((state & PENDING) / PENDING_ONE))
+      .field("readers", &(state & READERS))
+      .finish_non_exhaustive()
+  }
+}
+
+struct DebugValue<'a, T: ?Sized>(&'a RWLock<T>);
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for DebugValue<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.0.try_read() {
+      Some(guard) => fmt::Debug::fmt(&*guard, f),
+      None => f.write_str("<write locked>"),
     }
-    dbg.field("ReadLocks", &self.read_locks);
-    dbg.finish_non_exhaustive()
+  }
+}
+
+impl<T: ?Sized> BlockingRwLock<T> for RWLock<T> {
+  type ReadGuard<'a>
+    = ReadLockGuard<'a, T>
+  where
+    Self: 'a;
+  type WriteGuard<'a>
+    = WriteLockGuard<'a, T>
+  where
+    Self: 'a;
+
+  fn read(&self) -> Self::ReadGuard<'_> {
+    RWLock::read(self)
+  }
+
+  fn try_read(&self) -> Option<Self::ReadGuard<'_>> {
+    RWLock::try_read(self)
+  }
+
+  fn write(&self) -> Self::WriteGuard<'_> {
+    RWLock::write(self)
+  }
+
+  fn try_write(&self) -> Option<Self::WriteGuard<'_>> {
+    RWLock::try_write(self)
+  }
+}
+
+impl<T: ?Sized + Send> InspectLock for RWLock<T> {
+  fn lock_state(&self) -> LockState {
+    self.fmt_state()
+  }
+}
+
+/// The read lock of a [RWLock] shared with an [Arc], acquired with [RWLock::read_owned]. It keeps the RWLock alive and
+/// releases the read lock once dropped. Unlike the [ReadLockGuard] it is always `Send` if `T` is `Send` and `Sync`, as
+/// it is meant to be moved to other cores.
+#[cfg(any(feature = "alloc", doc))]
+pub struct OwnedReadLockGuard<T: ?Sized> {
+  lock: Arc<RWLock<T>>,
+  /// suppresses the auto traits, which would only require `T: Send`
+  _marker: PhantomData<*const ()>,
+}
+
+/// The write lock of a [RWLock] shared with an [Arc], acquired with [RWLock::write_owned]. It keeps the RWLock alive
+/// and releases the write lock once dropped. Unlike the [WriteLockGuard] it is always `Send` if `T` is `Send` and
+/// `Sync`, as it is meant to be moved to other cores.
+#[cfg(any(feature = "alloc", doc))]
+pub struct OwnedWriteLockGuard<T: ?Sized> {
+  lock: Arc<RWLock<T>>,
+  /// suppresses the auto traits, which would only require `T: Send`
+  _marker: PhantomData<*const ()>,
+}
+
+#[cfg(any(feature = "alloc", doc))]
+impl<T: ?Sized> OwnedReadLockGuard<T> {
+  /// Returns the [RWLock] this guard has been acquired from
+  pub fn rwlock(&self) -> &Arc<RWLock<T>> {
+    &self.lock
+  }
+}
+
+#[cfg(any(feature = "alloc", doc))]
+impl<T: ?Sized> OwnedWriteLockGuard<T> {
+  /// Returns the [RWLock] this guard has been acquired from
+  pub fn rwlock(&self) -> &Arc<RWLock<T>> {
+    &self.lock
+  }
+}
+
+// the owned guards hold the lock acquired by a forgotten borrowed guard, so they release it the same way
+#[cfg(any(feature = "alloc", doc))]
+impl<T: ?Sized> Drop for OwnedReadLockGuard<T> {
+  fn drop(&mut self) {
+    drop(ReadLockGuard {
+      _data: &*self.lock,
+      _marker: PhantomData,
+    });
+  }
+}
+
+#[cfg(any(feature = "alloc", doc))]
+impl<T: ?Sized> Drop for OwnedWriteLockGuard<T> {
+  fn drop(&mut self) {
+    drop(WriteLockGuard {
+      _data: &*self.lock,
+      _marker: PhantomData,
+    });
+  }
+}
+
+#[cfg(any(feature = "alloc", doc))]
+impl<T: ?Sized> Deref for OwnedReadLockGuard<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    // SAFETY: the guard does only exist while the read lock is held
+    unsafe { &*self.lock.data.get() }
+  }
+}
+
+#[cfg(any(feature = "alloc", doc))]
+impl<T: ?Sized> Deref for OwnedWriteLockGuard<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    // SAFETY: the guard does only exist while the write lock is held
+    unsafe { &*self.lock.data.get() }
+  }
+}
+
+#[cfg(any(feature = "alloc", doc))]
+impl<T: ?Sized> DerefMut for OwnedWriteLockGuard<T> {
+  fn deref_mut(&mut self) -> &mut T {
+    // SAFETY: the guard does only exist while the write lock is held
+    unsafe { &mut *self.lock.data.get() }
+  }
+}
+
+#[cfg(any(feature = "alloc", doc))]
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedReadLockGuard<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&**self, f)
+  }
+}
+
+#[cfg(any(feature = "alloc", doc))]
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedWriteLockGuard<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&**self, f)
+  }
+}
+
+// the data is accessed from the core the owned guard is moved to while other cores might hold a read lock as well
+#[cfg(any(feature = "alloc", doc))]
+unsafe impl<T: ?Sized + Send + Sync> Send for OwnedReadLockGuard<T> {}
+#[cfg(any(feature = "alloc", doc))]
+unsafe impl<T: ?Sized + Send + Sync> Sync for OwnedReadLockGuard<T> {}
+#[cfg(any(feature = "alloc", doc))]
+unsafe impl<T: ?Sized + Send + Sync> Send for OwnedWriteLockGuard<T> {}
+#[cfg(any(feature = "alloc", doc))]
+unsafe impl<T: ?Sized + Send + Sync> Sync for OwnedWriteLockGuard<T> {}
+
+impl<'a, T: ?Sized> WriteLockGuard<'a, T> {
+  /// Consume the guard without releasing the write lock and return a mutable reference to the secured data. The
+  /// lock remains held forever. This is intended for boot-time scenarios where the initialization path shall keep
+  /// exclusive access to the data for the rest of the runtime.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::RWLock;
+  /// static CONFIG: RWLock<u32> = RWLock::new(0);
+  /// # fn main() {
+  ///     let config: &'static mut u32 = CONFIG.write().leak();
+  ///     *config = 10;
+  ///     // the lock is now held forever
+  ///     assert!(CONFIG.try_read().is_none());
+  /// # }
+  /// ```
+  pub fn leak(self) -> &'a mut T {
+    // SAFETY: the write lock is never released as the guard is forgotten, so the returned reference is the only way
+    // to access the data for the rest of the lifetime 'a
+    let data = unsafe { &mut *self._data.data.get() };
+    // the RWLock might be dropped and its memory reused once the lifetime 'a ended, so it is no longer tracked
+    reentrancy::untrack(&self._data.state, Access::Write);
+    core::mem::forget(self);
+    data
+  }
+
+  /// Returns the [RWLock] this guard has been acquired from, e.g. to acquire it again after the guard has been dropped
+  pub fn rwlock(&self) -> &'a RWLock<T> {
+    self._data
+  }
+}
+
+impl<'a, T: ?Sized> ReadLockGuard<'a, T> {
+  /// Returns the [RWLock] this guard has been acquired from, e.g. to acquire it again after the guard has been dropped
+  pub fn rwlock(&self) -> &'a RWLock<T> {
+    self._data
   }
 }
 
 // when the WriteLockGuard is dropped release the owning lock
 impl<T: ?Sized> Drop for WriteLockGuard<'_, T> {
   fn drop(&mut self) {
-    self._data.write_lock.store(false, Ordering::Release);
+    let lock = self._data;
+    reentrancy::untrack(&lock.state, Access::Write);
+    if lock.prefer_writers {
+      // admit the readers that waited for this writer before the next pending writer, so they get through in one pass
+      // instead of waiting for all pending writers
+      let waiting = lock.waiting_readers.load(Ordering::Relaxed);
+      lock.read_batch.store(waiting, Ordering::Relaxed);
+    }
+    lock.state.fetch_and(!WRITER, Ordering::Release);
     //println!("write lock released {:?}", core::any::type_name::<T>());
 
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    unsafe {
-      // dmb required before allow access to the protected resource, see:
-      // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
-      asm!("dmb sy");
-      // also raise a signal to indicate the semaphore has been changed (this trigger all WFE's to continue
-      // processing) but do data syncronisation barrier upfront to ensure any data updates has been finished
-      asm!(
-        "dsb sy
-         sev"
-      );
-    }
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // also raise a signal to indicate the semaphore has been changed (this trigger all WFE's to continue
+    // processing) but do data syncronisation barrier upfront to ensure any data updates has been finished
+    arch::signal_event();
   }
 }
 
 // when the ReadLockGuard is dropped release the owning lock
 impl<T: ?Sized> Drop for ReadLockGuard<'_, T> {
   fn drop(&mut self) {
-    self._data.read_locks.fetch_sub(1, Ordering::Release);
+    reentrancy::untrack(&self._data.state, Access::Read);
+    self._data.state.fetch_sub(1, Ordering::Release);
     //println!("read lock released {:?}", core::any::type_name::<T>());
 
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    unsafe {
-      // dmb required after atomic operations, see:
+    // dmb required after atomic operations, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // a writer might wait for the last reader to leave, so raise a signal to wake it
+    arch::signal_event();
+  }
+}
+
+impl<'a, T: ?Sized> UpgradableReadGuard<'a, T> {
+  /// Returns the [RWLock] this guard has been acquired from, e.g. to acquire it again after the guard has been dropped
+  pub fn rwlock(&self) -> &'a RWLock<T> {
+    self._data
+  }
+
+  /// Try to upgrade to a [WriteLockGuard] without blocking. This fails and returns the upgradable read lock if there
+  /// are plain read locks existing.
+  pub fn try_upgrade(self) -> Result<WriteLockGuard<'a, T>, Self> {
+    let lock = self._data;
+    // a pending writer keeps it's bit, so it is only cleared by this writer
+    if lock
+      .state
+      .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+        if state & !PENDING == UPGRADABLE {
+          Some((state ^ UPGRADABLE) | WRITER)
+        } else {
+          None
+        }
+      })
+      .is_ok()
+    {
+      core::mem::forget(self);
+      // dmb required before allow access to the protected resource, see:
       // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
-      asm!("dmb sy");
+      arch::dmb();
+      reentrancy::untrack(&lock.state, Access::Upgradable);
+      reentrancy::track(&lock.state, Access::Write);
+      Ok(WriteLockGuard {
+        _data: lock,
+        _marker: PhantomData,
+      })
+    } else {
+      Err(self)
+    }
+  }
+
+  /// Upgrade to a [WriteLockGuard]. The write bit is set immediately, so no new read locks are handed out and this
+  /// only blocks until the existing read locks are released.
+  ///
+  /// # Panics
+  /// With the `reentrancy_detection` feature in debug builds this panics if the current core also holds a plain read
+  /// lock of this RWLock, as the upgrade would wait for it forever.
+  pub fn upgrade(self) -> WriteLockGuard<'a, T> {
+    // the upgradable read lock held by this guard does not block the upgrade
+    if let Some(held @ (Access::Read | Access::Write)) =
+      reentrancy::blocking(&self._data.state, Access::Write)
+    {
+      panic!(
+        "UpgradableReadGuard::upgrade would block forever, as the current core also holds {} of this RWLock",
+        held
+      );
+    }
+    let lock = self.begin_upgrade();
+    let mut attempt = 0;
+    loop {
+      // SAFETY: the upgrade has been begun above and is only completed here
+      if let Some(guard) = unsafe { lock.try_complete_upgrade() } {
+        metrics::record(LockKind::RWLockWrite, attempt);
+        return guard;
+      }
+      // to save energy and cpu consumption we can wait for an event beeing raised that indicates that the
+      // lock value has likely beeing changed, depending on the selected spin policy
+      spin::on_contention(&mut attempt, "UpgradableReadGuard::upgrade");
     }
   }
+
+  /// Set the write bit, so no new read locks are handed out, and return the lock the upgrade is completed on with
+  /// [RWLock::try_complete_upgrade] once the existing read locks are released.
+  pub(crate) fn begin_upgrade(self) -> &'a RWLock<T> {
+    let lock = self._data;
+    core::mem::forget(self);
+    // the core holds neither the upgradable read lock nor the write lock until the upgrade is completed
+    reentrancy::untrack(&lock.state, Access::Upgradable);
+    // only the holder of the upgradable read lock can set the write bit while the upgradable bit is set
+    lock.state.fetch_or(WRITER, Ordering::Acquire);
+    lock
+  }
+}
+
+// when the UpgradableReadGuard is dropped release the owning lock
+impl<T: ?Sized> Drop for UpgradableReadGuard<'_, T> {
+  fn drop(&mut self) {
+    reentrancy::untrack(&self._data.state, Access::Upgradable);
+    self._data.state.fetch_and(!UPGRADABLE, Ordering::Release);
+
+    // dmb required after atomic operations, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // a writer or another upgradable reader might wait for this lock to be released, so raise a signal to wake it
+    arch::signal_event();
+  }
 }
 
 // dereferencing the value contained in the DataWriteLock
@@ -230,6 +1271,15 @@ impl<T: ?Sized> Deref for ReadLockGuard<'_, T> {
   }
 }
 
+// the ``UpgradableReadGuard`` can only be immutable dereferenced
+impl<T: ?Sized> Deref for UpgradableReadGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    unsafe { &*self._data.data.get() }
+  }
+}
+
 /// implement debug trait to forward to the type wrapped within the guard
 impl<T: ?Sized + fmt::Debug> fmt::Debug for WriteLockGuard<'_, T> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -244,6 +1294,13 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for ReadLockGuard<'_, T> {
   }
 }
 
+/// implement debug trait to forward to the type wrapped within the guard
+impl<T: ?Sized + fmt::Debug> fmt::Debug for UpgradableReadGuard<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&**self, f)
+  }
+}
+
 /// The RWLock is always `Sync`, to make it `Send` as well it need to be wrapped into an `Arc`.
 unsafe impl<T: ?Sized + Send> Sync for RWLock<T> {}
 
@@ -260,7 +1317,7 @@ mod tests {
     // try_lock and lock will provide a WriteLock
     let mut data = rwlock.write();
     *data = 20;
-    // if a write lock exists no read lock's could be aquired
+    // if a write lock exists no read lock's could be acquired
     assert!(rwlock_clone.try_write().is_none());
   }
 
@@ -271,7 +1328,7 @@ mod tests {
     // try_lock and lock will provide a WriteLock
     let mut data = rwlock.write();
     *data = 20;
-    // if a write lock exists no read lock's could be aquired
+    // if a write lock exists no read lock's could be acquired
     assert!(rwlock_clone.try_read().is_none());
   }
 
@@ -281,19 +1338,324 @@ mod tests {
     let rwlock_clone = Arc::clone(&rwlock);
     // try_lock and lock will provide a WriteLock
     let data = rwlock.read();
-    // if a write lock exists no read lock's could be aquired
+    // if a write lock exists no read lock's could be acquired
     assert!(rwlock_clone.try_read().is_some());
     println!("{}", *data);
   }
 
+  #[test]
+  fn leaked_write_lock_is_never_released() {
+    let rwlock = RWLock::new(0u32);
+    let data = rwlock.write().leak();
+    *data = 20;
+    assert!(rwlock.try_write().is_none());
+    assert!(rwlock.try_read().is_none());
+  }
+
   #[test]
   fn only_read_no_write_lock() {
     let rwlock = Arc::new(RWLock::new(0u32));
     let rwlock_clone = Arc::clone(&rwlock);
     // try_lock and lock will provide a WriteLock
     let data = rwlock.read();
-    // if a write lock exists no read lock's could be aquired
+    // if a write lock exists no read lock's could be acquired
     assert!(rwlock_clone.try_write().is_none());
     println!("{}", *data);
   }
+
+  #[test]
+  fn upgradable_read_coexists_with_readers() {
+    let rwlock = RWLock::new(0u32);
+    let upgradable = rwlock.upgradable_read();
+    assert!(rwlock.try_read().is_some());
+    assert!(rwlock.try_upgradable_read().is_none());
+    assert!(rwlock.try_write().is_none());
+    let mut data = upgradable.upgrade();
+    *data = 20;
+    assert!(rwlock.try_read().is_none());
+    drop(data);
+    assert_eq!(*rwlock.read(), 20);
+  }
+
+  #[test]
+  fn upgrade_fails_with_readers() {
+    let rwlock = RWLock::new(0u32);
+    let upgradable = rwlock.upgradable_read();
+    let reader = rwlock.read();
+    let upgradable = upgradable.try_upgrade().err().unwrap();
+    drop(reader);
+    assert!(upgradable.try_upgrade().is_ok());
+  }
+
+  #[test]
+  fn read_locks_saturate_at_max_readers() {
+    let rwlock = RWLock::new(0u32);
+    // simulate leaked read guards without looping until the maximum is reached
+    rwlock.state.store(MAX_READERS - 1, Ordering::Relaxed);
+    let data = rwlock.try_read();
+    assert!(data.is_some());
+    assert!(rwlock.try_read().is_none());
+    assert!(rwlock.try_upgradable_read().is_some());
+    assert!(rwlock.try_write().is_none());
+    drop(data);
+    assert!(rwlock.try_read().is_some());
+  }
+
+  #[test]
+  #[should_panic(expected = "maximum number of read locks exceeded")]
+  fn read_panics_at_max_readers() {
+    let rwlock = RWLock::new(0u32);
+    rwlock.state.store(MAX_READERS, Ordering::Relaxed);
+    let _data = rwlock.read();
+  }
+
+  #[test]
+  fn read_checked_reports_deadlock_at_max_readers() {
+    let rwlock = RWLock::new(0u32);
+    rwlock.state.store(MAX_READERS, Ordering::Relaxed);
+    assert_eq!(rwlock.read_checked().err(), Some(LockError::Deadlock));
+  }
+
+  #[test]
+  fn try_write_spins_gives_up_and_lets_readers_in_again() {
+    let rwlock = RWLock::new(0u32);
+    let reader = rwlock.read();
+    assert!(rwlock.try_write_spins(10).is_none());
+    assert_eq!(rwlock.state.load(Ordering::Relaxed), 1);
+    assert!(rwlock.try_read().is_some());
+    drop(reader);
+    assert!(rwlock.try_write_spins(10).is_some());
+    assert_eq!(rwlock.state.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn pending_writer_holds_off_new_readers() {
+    let rwlock = RWLock::new(0u32);
+    let _reader = rwlock.read();
+    // simulate a writer spinning in try_write_spins
+    rwlock.state.fetch_or(PENDING, Ordering::Relaxed);
+    assert!(rwlock.try_read().is_none());
+    assert!(rwlock.try_upgradable_read().is_none());
+    assert!(rwlock.try_write().is_none());
+  }
+
+  #[test]
+  fn force_unlock_clears_all_locks() {
+    let rwlock = RWLock::new(0u32);
+    let _ = rwlock.upgradable_read().upgrade().leak();
+    assert!(rwlock.try_read().is_none());
+    unsafe { rwlock.force_unlock() };
+    assert!(rwlock.try_write().is_some());
+  }
+
+  #[test]
+  fn force_unlock_keeps_pending_writers_registered() {
+    let rwlock = RWLock::new(0u32);
+    core::mem::forget(rwlock.read());
+    assert!(rwlock.register_writer());
+    unsafe { rwlock.force_unlock() };
+    assert_eq!(rwlock.state.load(Ordering::Relaxed) & PENDING, PENDING_ONE);
+    rwlock.unregister_writer();
+    assert_eq!(rwlock.state.load(Ordering::Relaxed), 0);
+    assert!(rwlock.try_write().is_some());
+  }
+
+  #[test]
+  fn guards_return_the_owning_lock() {
+    let rwlock = RWLock::new(0u32);
+    let reader = rwlock.read();
+    let lock = reader.rwlock();
+    assert!(core::ptr::eq(lock, &rwlock));
+    drop(reader);
+    let mut writer = lock.write();
+    *writer = 10;
+    assert!(core::ptr::eq(writer.rwlock(), &rwlock));
+    drop(writer);
+    assert!(core::ptr::eq(rwlock.upgradable_read().rwlock(), &rwlock));
+  }
+
+  #[test]
+  fn max_readers_bounds_read_locks() {
+    let rwlock = RWLock::new(0u32).with_max_readers(2);
+    let first = rwlock.read();
+    let second = rwlock.try_read().unwrap();
+    assert!(rwlock.try_read().is_none());
+    drop(first);
+    assert!(rwlock.try_read().is_some());
+    drop(second);
+    assert!(rwlock.try_write().is_some());
+  }
+
+  #[test]
+  fn cloned_releases_the_read_lock() {
+    let rwlock = RWLock::new([1u32, 2, 3]);
+    let value = rwlock.cloned();
+    // the read lock is released once the value has been cloned
+    assert!(rwlock.try_write().is_some());
+    assert_eq!(value, [1, 2, 3]);
+  }
+
+  #[test]
+  // all threads run on the same core of the host, so the re-entrancy detection takes them for one core
+  #[cfg(not(feature = "reentrancy_detection"))]
+  fn waiting_writer_holds_off_new_readers() {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    let rwlock = Arc::new(RWLock::new(0u32));
+    let reader = rwlock.read();
+    let written = Arc::new(AtomicBool::new(false));
+    let writer = {
+      let rwlock = Arc::clone(&rwlock);
+      let written = Arc::clone(&written);
+      std::thread::spawn(move || {
+        *rwlock.write() = 10;
+        written.store(true, Ordering::Release);
+      })
+    };
+    while rwlock.state.load(Ordering::Relaxed) & PENDING == 0 {
+      std::thread::yield_now();
+    }
+    // the pending writer is not starved by further readers
+    assert!(rwlock.try_read().is_none());
+    assert!(!written.load(Ordering::Acquire));
+    drop(reader);
+    writer.join().unwrap();
+    assert_eq!(*rwlock.read(), 10);
+    assert_eq!(rwlock.state.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  // all threads run on the same core of the host, so the re-entrancy detection takes them for one core
+  #[cfg(not(feature = "reentrancy_detection"))]
+  fn later_readers_do_not_pass_a_pending_writer_with_a_left_admission() {
+    use std::sync::Arc;
+
+    let rwlock = Arc::new(RWLock::new(0u32));
+    let writer = rwlock.write();
+    let reader = {
+      let rwlock = Arc::clone(&rwlock);
+      std::thread::spawn(move || drop(rwlock.read()))
+    };
+    while rwlock.waiting_readers.load(Ordering::Relaxed) == 0 {
+      std::thread::yield_now();
+    }
+    // the released writer admits the waiting reader, which gets the lock as no other writer is pending
+    drop(writer);
+    reader.join().unwrap();
+
+    // a reader arriving after the release waits for the next pending writer
+    assert!(rwlock.register_writer());
+    assert!(rwlock.try_read().is_none());
+    assert!(rwlock.try_read_batched().is_none());
+    rwlock.unregister_writer();
+    assert_eq!(rwlock.state.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  #[cfg(feature = "alloc")]
+  fn owned_guards_are_released_on_another_thread() {
+    let rwlock = Arc::new(RWLock::new(0u32));
+    let mut writer = rwlock.write_owned();
+    *writer = 10;
+    assert!(rwlock.try_read_owned().is_none());
+    std::thread::spawn(move || drop(writer)).join().unwrap();
+
+    let reader = rwlock.read_owned();
+    assert_eq!(*reader, 10);
+    assert!(rwlock.try_write_owned().is_none());
+    let other = rwlock.try_read_owned().unwrap();
+    std::thread::spawn(move || drop((reader, other)))
+      .join()
+      .unwrap();
+    assert_eq!(rwlock.state.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  #[cfg(feature = "alloc")]
+  fn owned_guards_are_send_for_shareable_data() {
+    fn assert_send<T: Send>() {}
+    assert_send::<OwnedReadLockGuard<u32>>();
+    assert_send::<OwnedWriteLockGuard<[u8; 4]>>();
+  }
+
+  #[test]
+  fn read_preferring_ignores_pending_writers() {
+    let rwlock = RWLock::new_read_preferring(0u32);
+    let _reader = rwlock.read();
+    assert!(rwlock.try_write_spins(10).is_none());
+    rwlock.state.fetch_add(PENDING_ONE, Ordering::Relaxed);
+    assert!(rwlock.try_read().is_some());
+    assert!(rwlock.try_upgradable_read().is_some());
+  }
+
+  #[test]
+  // all threads run on the same core of the host, so the re-entrancy detection takes them for one core
+  #[cfg(not(feature = "reentrancy_detection"))]
+  fn writers_are_not_starved_by_overlapping_readers() {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    let rwlock = Arc::new(RWLock::new(0u32));
+    let stop = Arc::new(AtomicBool::new(false));
+    let readers: Vec<_> = (0..2)
+      .map(|_| {
+        let rwlock = Arc::clone(&rwlock);
+        let stop = Arc::clone(&stop);
+        std::thread::spawn(move || {
+          // the readers overlap, so the lock is never free without a writer holding them off
+          let mut held = rwlock.read();
+          while !stop.load(Ordering::Relaxed) {
+            let next = rwlock.try_read();
+            drop(held);
+            held = match next {
+              Some(next) => next,
+              None => rwlock.read(),
+            };
+          }
+        })
+      })
+      .collect();
+
+    for value in 1..=100 {
+      *rwlock.write() = value;
+    }
+    stop.store(true, Ordering::Relaxed);
+    for reader in readers {
+      reader.join().unwrap();
+    }
+    assert_eq!(*rwlock.read(), 100);
+  }
+
+  #[test]
+  #[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+  #[should_panic(
+    expected = "RWLock::read would block forever, as the current core already holds the write lock"
+  )]
+  fn read_while_holding_the_write_lock_panics() {
+    let rwlock = RWLock::new(0u32);
+    let _writer = rwlock.write();
+    let _ = rwlock.read();
+  }
+
+  #[test]
+  #[cfg(all(feature = "reentrancy_detection", debug_assertions))]
+  fn checked_locks_report_the_reentrant_lock_as_deadlock() {
+    let rwlock = RWLock::new(0u32);
+    let reader = rwlock.read();
+    assert!(matches!(rwlock.write_checked(), Err(LockError::Deadlock)));
+    // the upgrade would wait for the read lock of the same core
+    let upgradable = rwlock.upgradable_read();
+    assert!(
+      std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| upgradable.upgrade())).is_err()
+    );
+    drop(reader);
+
+    let writer = rwlock.upgradable_read().upgrade();
+    assert!(matches!(rwlock.read_checked(), Err(LockError::Deadlock)));
+    drop(writer);
+    // released locks are no longer tracked
+    assert!(rwlock.read_checked().is_ok());
+    assert!(rwlock.write_checked().is_ok());
+  }
 }
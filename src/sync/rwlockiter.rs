@@ -0,0 +1,103 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # RWLock Iterators
+//!
+//! Iterators over collections secured by a [RWLock], like `RWLock<Vec<T>>` or `RWLock<BTreeMap<K, V>>`. The
+//! iterator holds the lock guard, so the lock is kept as long as the iterator exists and can not be released
+//! accidentally in the middle of the iteration.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::RWLock;
+//!
+//! static DATA: RWLock<[u32; 4]> = RWLock::new([1, 2, 3, 4]);
+//!
+//! fn main() {
+//!     for value in DATA.write_iter_mut() {
+//!         *value *= 2;
+//!     }
+//!     let sum: u32 = DATA.read_iter().sum();
+//!     assert_eq!(sum, 20);
+//! }
+//! ```
+
+use super::rwlock::{RWLock, ReadLockGuard, WriteLockGuard};
+
+/// Iterator over a collection secured by a [RWLock] holding the read lock until it is dropped
+pub struct ReadIter<'a, T: ?Sized, I> {
+  // the iterator borrows from the guarded data and need to be dropped before the guard
+  iter: I,
+  _guard: ReadLockGuard<'a, T>,
+}
+
+/// Iterator over mutable references to the items of a collection secured by a [RWLock] holding the write lock until
+/// it is dropped
+pub struct WriteIterMut<'a, T: ?Sized, I> {
+  // the iterator borrows from the guarded data and need to be dropped before the guard
+  iter: I,
+  _guard: WriteLockGuard<'a, T>,
+}
+
+impl<T: ?Sized> RWLock<T> {
+  /// Acquire a read lock and provide an iterator over the secured collection. The read lock is held until the
+  /// iterator is dropped. This blocks until the read lock could be acquired.
+  pub fn read_iter<'a>(&'a self) -> ReadIter<'a, T, <&'a T as IntoIterator>::IntoIter>
+  where
+    &'a T: IntoIterator,
+  {
+    let guard = self.read();
+    // SAFETY: the data lives as long as the lock and the read lock is held as long as the iterator exists
+    let data: &'a T = unsafe { &*self.data_ptr() };
+    ReadIter {
+      iter: data.into_iter(),
+      _guard: guard,
+    }
+  }
+
+  /// Acquire the write lock and provide an iterator over mutable references to the items of the secured collection.
+  /// The write lock is held until the iterator is dropped. This blocks until the write lock could be acquired.
+  // the mutable access is secured by the write lock held by the iterator
+  #[allow(clippy::mut_from_ref)]
+  pub fn write_iter_mut<'a>(&'a self) -> WriteIterMut<'a, T, <&'a mut T as IntoIterator>::IntoIter>
+  where
+    &'a mut T: IntoIterator,
+  {
+    let guard = self.write();
+    // SAFETY: the data lives as long as the lock and the write lock is held as long as the iterator exists, so this
+    // is the only reference to the data
+    let data: &'a mut T = unsafe { &mut *self.data_ptr() };
+    WriteIterMut {
+      iter: data.into_iter(),
+      _guard: guard,
+    }
+  }
+}
+
+impl<T: ?Sized, I: Iterator> Iterator for ReadIter<'_, T, I> {
+  type Item = I::Item;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.iter.next()
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.iter.size_hint()
+  }
+}
+
+impl<T: ?Sized, I: Iterator> Iterator for WriteIterMut<'_, T, I> {
+  type Item = I::Item;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.iter.next()
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.iter.size_hint()
+  }
+}
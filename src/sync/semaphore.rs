@@ -24,14 +24,104 @@
 //!     SEMA.up(); // increase the counter for another usage
 //! }
 //! ```
-use core::arch::asm;
-use core::sync::atomic::{AtomicU32, Ordering};
+use super::metrics::{self, LockKind};
+use super::registry::{InspectLock, LockState};
+use super::spin;
+use crate::{arch, LockError};
+use core::fmt;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
 /// Simple counting blocking or non-blocking lock
-#[derive(Debug)]
+///
+/// Cores blocking in [Semaphore::down] are served in the order they started waiting. Each waiting core draws a ticket
+/// and only the core holding the ticket that is currently served is allowed to decrease the semaphore. So each
+/// [Semaphore::up] lets exactly one waiting core proceed, while the others immediately continue to wait.
+///
+/// The counter and the ticket queue share a single state word. This allows [Semaphore::up] to know whether any core
+/// is waiting when it increases the counter and to only signal an event in this case.
+///
+/// The width of the counter is given by the [Counter] type `C` and defaults to `u32`. A `Semaphore<u64>` can count up
+/// to 2^48 - 1 permits, which leaves room for 255 cores waiting at the same time.
+///
+/// # Example
+/// ```
+/// # use ruspiro_lock::sync::Semaphore;
+/// // the number of sectors that can be queued to the block device
+/// static QUEUE_DEPTH: Semaphore<u64> = Semaphore::new_u64(1 << 20);
+/// # fn main() {
+///     QUEUE_DEPTH.try_acquire_n(70_000).unwrap();
+///     QUEUE_DEPTH.up_n(70_000);
+/// # }
+/// ```
 #[repr(C, align(16))]
-pub struct Semaphore {
-  count: AtomicU32,
+pub struct Semaphore<C: Counter = u32> {
+  /// The lower [Counter::COUNT_BITS] contain the counter. The remaining bits are split into the ticket currently
+  /// served and, in the upper bits, the next ticket to be drawn by a core waiting in [Semaphore::down].
+  state: AtomicU64,
+  /// The number of cores waiting in [Semaphore::down_while]. They do not draw a ticket, so [Semaphore::up] need to
+  /// know about them to signal an event
+  pollers: AtomicU32,
+  _counter: PhantomData<C>,
+}
+
+/// The type of the counter of a [Semaphore]. It is implemented for `u32`, `u64` and `usize`.
+pub trait Counter: Copy + fmt::Debug + private::Sealed {
+  /// The number of bits of the state word containing the counter. The remaining bits are shared equally by the
+  /// ticket currently served and the next ticket to be drawn.
+  const COUNT_BITS: u32;
+
+  /// The counter value as stored in the state word
+  #[doc(hidden)]
+  fn into_count(self) -> u64;
+
+  /// The counter value stored in the state word
+  #[doc(hidden)]
+  fn from_count(count: u64) -> Self;
+}
+
+impl Counter for u32 {
+  const COUNT_BITS: u32 = 32;
+
+  fn into_count(self) -> u64 {
+    self as u64
+  }
+
+  fn from_count(count: u64) -> Self {
+    count as u32
+  }
+}
+
+impl Counter for u64 {
+  const COUNT_BITS: u32 = 48;
+
+  fn into_count(self) -> u64 {
+    self
+  }
+
+  fn from_count(count: u64) -> Self {
+    count
+  }
+}
+
+impl Counter for usize {
+  const COUNT_BITS: u32 = if usize::BITS > 32 { 48 } else { 32 };
+
+  fn into_count(self) -> u64 {
+    self as u64
+  }
+
+  fn from_count(count: u64) -> Self {
+    count as usize
+  }
+}
+
+mod private {
+  /// Only the counters provided by this crate fit into the state word of the [Semaphore](super::Semaphore)
+  pub trait Sealed {}
+  impl Sealed for u32 {}
+  impl Sealed for u64 {}
+  impl Sealed for usize {}
 }
 
 impl Semaphore {
@@ -40,15 +130,86 @@ impl Semaphore {
   /// ```
   /// # use ruspiro_lock::sync::Semaphore;
   /// # fn doc() {
-  ///     let mut sema = Semaphore::new(5); // semaphore could be used/aquired 5 times
+  ///     let mut sema = Semaphore::new(5); // semaphore could be used/acquired 5 times
   /// # }
   /// ```
   pub const fn new(initial: u32) -> Semaphore {
-    Semaphore {
-      count: AtomicU32::new(initial),
+    Semaphore::with_count(initial as u64)
+  }
+}
+
+impl Semaphore<u64> {
+  /// Instantiate a new semaphore with a 64 bit counter and the given initial value. The value saturates at 2^48 - 1.
+  pub const fn new_u64(initial: u64) -> Self {
+    Self::with_count(initial)
+  }
+}
+
+impl Semaphore<usize> {
+  /// Instantiate a new semaphore with a pointer sized counter and the given initial value. On 64 bit targets the
+  /// value saturates at 2^48 - 1.
+  pub const fn new_usize(initial: usize) -> Self {
+    Self::with_count(initial as u64)
+  }
+}
+
+impl<C: Counter> Semaphore<C> {
+  /// The bits of the state word containing the counter
+  const COUNT: u64 = u64::MAX >> (64 - C::COUNT_BITS);
+  /// The number of bits of each ticket
+  const TICKET_BITS: u32 = (64 - C::COUNT_BITS) / 2;
+  /// The bits of a ticket
+  const TICKET: u64 = (1 << Self::TICKET_BITS) - 1;
+  /// The first bit of the ticket currently served
+  const SERVING_SHIFT: u32 = C::COUNT_BITS;
+  /// The first bit of the next ticket to be drawn
+  const NEXT_SHIFT: u32 = C::COUNT_BITS + Self::TICKET_BITS;
+  /// The bits of the state word containing the next ticket to be drawn
+  const NEXT: u64 = Self::TICKET << Self::NEXT_SHIFT;
+
+  /// Create the semaphore with the given counter value that saturates at the maximum of the counter
+  const fn with_count(count: u64) -> Self {
+    Self {
+      state: AtomicU64::new(if count > Self::COUNT {
+        Self::COUNT
+      } else {
+        count
+      }),
+      pollers: AtomicU32::new(0),
+      _counter: PhantomData,
     }
   }
 
+  /// The counter stored in the state word
+  #[inline]
+  const fn count(state: u64) -> u64 {
+    state & Self::COUNT
+  }
+
+  /// The ticket currently served stored in the state word
+  #[inline]
+  const fn serving(state: u64) -> u64 {
+    (state >> Self::SERVING_SHIFT) & Self::TICKET
+  }
+
+  /// The next ticket to be drawn stored in the state word
+  #[inline]
+  const fn next(state: u64) -> u64 {
+    state >> Self::NEXT_SHIFT
+  }
+
+  /// Returns `true` if the state word contains cores waiting in [Semaphore::down]
+  #[inline]
+  const fn has_waiters(state: u64) -> bool {
+    Self::serving(state) != Self::next(state)
+  }
+
+  /// The number of cores waiting in [Semaphore::down] stored in the state word
+  #[inline]
+  const fn waiters(state: u64) -> u64 {
+    Self::next(state).wrapping_sub(Self::serving(state)) & Self::TICKET
+  }
+
   /// increase the inner count of a semaphore allowing it to be used as many times as the inner counters value
   ///
   /// # Example
@@ -61,24 +222,102 @@ impl Semaphore {
   /// ```
   #[inline]
   pub fn up(&self) {
-    self.count.fetch_add(1, Ordering::AcqRel);
-
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    unsafe {
-      // dmb required before allow access to the protected resource, see:
-      // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
-      asm!("dmb sy");
-      // also raise a signal to indicate the semaphore has been changed (this trigger all WFE's to continue
-      // processing) but do data syncronisation barrier upfront to ensure any data updates has been finished
-      asm!(
-        "dsb sy
-         sev"
-      );
+    self.up_n(C::from_count(1));
+  }
+
+  /// increase the inner count of a semaphore by the given number of permits at once
+  ///
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_lock::sync::Semaphore;
+  /// # fn doc() {
+  ///     let sema = Semaphore::new(0);
+  ///     sema.up_n(3); // the counter of the semaphore will be increased by 3
+  /// # }
+  /// ```
+  #[inline]
+  pub fn up_n(&self, n: C) {
+    // the counter shall not overflow into the ticket queue, so it saturates at it's maximum value
+    let state = self
+      .state
+      .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+        let count = Self::count(state).saturating_add(n.into_count());
+        Some((state & !Self::COUNT) | count.min(Self::COUNT))
+      })
+      .unwrap_or_else(|state| state);
+
+    self.signal_waiters(state);
+  }
+
+  /// Signal the cores waiting for the semaphore after the counter has been increased with an update that read the
+  /// given state word
+  #[inline]
+  fn signal_waiters(&self, state: u64) {
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // raise a signal to indicate the semaphore has been changed (this trigger all WFE's to continue processing) but
+    // only if there is a core waiting for it. As the waiting cores draw their ticket from the same state word, a core
+    // that starts waiting after the counter has been increased will see the new value before it waits for an event.
+    // The barrier above orders the update of the counter before reading the cores waiting in `down_while`
+    if self.is_waited_for(state) {
+      arch::signal_event();
     }
   }
 
+  /// Returns `true` if the given state word contains cores waiting in [Semaphore::down] or if any core is waiting in
+  /// [Semaphore::down_while]
+  #[inline]
+  fn is_waited_for(&self, state: u64) -> bool {
+    Self::has_waiters(state) || self.pollers.load(Ordering::SeqCst) != 0
+  }
+
+  /// increase the inner count of a semaphore by one unless it has reached the given maximum already. Returns `true`
+  /// if the counter has been increased. This is a single atomic operation, so it is safe to be called from an
+  /// interrupt handler.
+  #[inline]
+  pub(crate) fn up_bounded(&self, max: C) -> bool {
+    match self
+      .state
+      .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+        let count = Self::count(state);
+        if count >= max.into_count().min(Self::COUNT) {
+          None
+        } else {
+          Some(state + 1)
+        }
+      }) {
+      Ok(state) => {
+        self.signal_waiters(state);
+        true
+      }
+      Err(_) => false,
+    }
+  }
+
+  /// increase the inner count of a semaphore from an interrupt handler. This only updates the state word with a single
+  /// atomic operation and raises an event if a core is waiting. It never waits for another core, so it is safe to be
+  /// called while the interrupted code on the same core is waiting in [Semaphore::down]. An interrupt handler shall
+  /// never decrease the semaphore with a blocking call though.
+  ///
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_lock::sync::Semaphore;
+  /// static RX_READY: Semaphore = Semaphore::new(0);
+  ///
+  /// fn uart_irq_handler() {
+  ///     // the received data is available, let the waiting core continue
+  ///     RX_READY.up_from_isr();
+  /// }
+  /// # fn main() {}
+  /// ```
+  #[inline]
+  pub fn up_from_isr(&self) {
+    self.up_n(C::from_count(1));
+  }
+
   /// decrease the inner count of a semaphore. This blocks the current core if the current count is 0
-  /// and could not beeing decreased. For an unblocking operation use [Semaphore::try_down]
+  /// and could not beeing decreased. For an unblocking operation use [Semaphore::try_acquire]
   ///
   /// # Example
   /// ```no_run
@@ -91,57 +330,410 @@ impl Semaphore {
   /// ```
   #[inline]
   pub fn down(&self) {
+    if self.try_acquire().is_ok() {
+      metrics::record(LockKind::Semaphore, 0);
+      return;
+    }
+
+    // draw a ticket and wait until it is served. The ticket wraps around without touching the other parts of the
+    // state word
+    let ticket = Self::next(
+      self
+        .state
+        .fetch_add(1 << Self::NEXT_SHIFT, Ordering::AcqRel),
+    );
+    let mut attempt = 0;
     loop {
-      if self.try_down().is_ok() {
+      // decrease the counter and serve the next ticket with a single atomic operation
+      if let Ok(state) = self
+        .state
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+          if Self::serving(state) != ticket || Self::count(state) == 0 {
+            return None;
+          }
+          let serving = (ticket + 1) & Self::TICKET;
+          Some((state & Self::NEXT) | (serving << Self::SERVING_SHIFT) | (Self::count(state) - 1))
+        })
+      {
+        // dmb required before allow access to the protected resource see:
+        // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+        arch::dmb();
+        // signal the next waiting core that it's ticket is now served
+        if Self::next(state) != (ticket + 1) & Self::TICKET {
+          arch::signal_event();
+        }
+        metrics::record(LockKind::Semaphore, attempt);
         return;
       }
       // to save energy and cpu consumption we can wait for an event beeing raised that indicates that the
-      // semaphore value has likely beeing changed
-      #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-      unsafe {
-        asm!("wfe");
-      }
+      // semaphore value has likely beeing changed, depending on the selected spin policy
+      spin::on_contention(&mut attempt, "Semaphore::down");
     }
   }
 
-  /// try to decrease a semaphore for usage. Returns [value@Ok] if the semaphore could be used.
+  /// decrease the inner count of a semaphore like [Semaphore::down] through the same fallible API as
+  /// [Mutex::lock_checked](super::Mutex::lock_checked). A semaphore has no owner, so there is nothing that would
+  /// prevent [Semaphore::down] from returning that could be detected yet and this always succeeds once the counter
+  /// could be decreased. Such conditions are reported here once they can be detected, while [Semaphore::down] stays
+  /// infallible.
+  pub fn down_checked(&self) -> Result<(), LockError> {
+    self.down();
+    Ok(())
+  }
+
+  /// decrease the inner count of a semaphore like [Semaphore::down], but call `keep_waiting` with the number of failed
+  /// attempts each time the core is about to wait again. If it returns `false` waiting is aborted and this fails with
+  /// [LockError::Cancelled]. This allows to stop waiting on external conditions, like a shutdown flag or a deadline,
+  /// without a global time source.
+  ///
+  /// Cores waiting here do not draw a ticket, so cores waiting in [Semaphore::down] are served first.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::{sync::Semaphore, LockError};
+  /// # use core::sync::atomic::{AtomicBool, Ordering};
+  /// static SHUTDOWN: AtomicBool = AtomicBool::new(true);
+  /// # fn doc() {
+  ///     let sema = Semaphore::new(0);
+  ///     let result = sema.down_while(|_| !SHUTDOWN.load(Ordering::Relaxed));
+  ///     assert_eq!(result, Err(LockError::Cancelled));
+  /// # }
+  /// ```
+  pub fn down_while<F: FnMut(u32) -> bool>(&self, mut keep_waiting: F) -> Result<(), LockError> {
+    if self.try_acquire().is_ok() {
+      metrics::record(LockKind::Semaphore, 0);
+      return Ok(());
+    }
+
+    // announce the waiting core before the next attempt, so an `up` that is not seen by this attempt signals the event
+    // this core waits for
+    self.pollers.fetch_add(1, Ordering::SeqCst);
+    arch::dmb();
+    let mut attempt = 0;
+    let result = loop {
+      if self.try_acquire().is_ok() {
+        metrics::record(LockKind::Semaphore, attempt);
+        break Ok(());
+      }
+      if !keep_waiting(attempt) {
+        break Err(LockError::Cancelled);
+      }
+      spin::on_contention(&mut attempt, "Semaphore::down_while");
+    };
+    self.pollers.fetch_sub(1, Ordering::Release);
+    result
+  }
+
+  /// try to decrease a semaphore for usage. Returns [value@Ok] if the semaphore could be used. If there are cores
+  /// waiting in [Semaphore::down] they are served first and this fails with [LockError::WouldBlock].
   ///
   /// # Example
   /// ```
   /// # use ruspiro_lock::sync::Semaphore;
   /// # fn doc() {
   ///     let sema = Semaphore::new(0);
-  ///     if sema.try_down().is_ok() {
+  ///     if sema.try_acquire().is_ok() {
   ///         // do something... the counter of the semaphore has been decreased by 1
   ///     }
   /// # }
   /// ```
   #[inline]
+  pub fn try_acquire(&self) -> Result<(), LockError> {
+    // a separate load and store of the counter would allow two cores to decrease the same value, so this need to be
+    // a single atomic operation
+    self.try_acquire_n(C::from_count(1))
+  }
+
+  /// try to decrease a semaphore by the given number of permits at once. Returns [value@Ok] if the semaphore could be
+  /// decreased by all requested permits. If there are not enough permits available or other cores are waiting in
+  /// [Semaphore::down] the semaphore remains unchanged and this fails with [LockError::WouldBlock].
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::{sync::Semaphore, LockError};
+  /// # fn doc() {
+  ///     let sema = Semaphore::new(3);
+  ///     assert!(sema.try_acquire_n(2).is_ok());
+  ///     assert_eq!(sema.try_acquire_n(2), Err(LockError::WouldBlock));
+  /// # }
+  /// ```
+  #[inline]
+  pub fn try_acquire_n(&self, n: C) -> Result<(), LockError> {
+    self
+      .state
+      .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+        if Self::has_waiters(state) {
+          return None;
+        }
+        let count = Self::count(state).checked_sub(n.into_count())?;
+        Some((state & !Self::COUNT) | count)
+      })
+      .map_err(|_| LockError::WouldBlock)?;
+
+    // dmb required before allow access to the protected resource see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    Ok(())
+  }
+
+  /// take all permits of the semaphore at once and return their number. This is a single atomic operation, so it does
+  /// not race with other cores acquiring or releasing permits at the same time, as looping [Semaphore::try_acquire]
+  /// would do. Cores waiting in [Semaphore::down] keep waiting for new permits.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Semaphore;
+  /// # fn main() {
+  ///     let sema = Semaphore::new(3);
+  ///     assert_eq!(sema.drain(), 3);
+  ///     assert_eq!(sema.drain(), 0);
+  /// # }
+  /// ```
+  #[inline]
+  pub fn drain(&self) -> C {
+    let state = self.state.fetch_and(!Self::COUNT, Ordering::AcqRel);
+
+    // dmb required before allow access to the protected resource see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    C::from_count(Self::count(state))
+  }
+
+  /// replace the available permits of the semaphore with the given number and return the previous one, eg. to
+  /// reinitialize a pool of buffers after the device owning them has been reset. The number saturates at the maximum
+  /// of the counter. Cores waiting in [Semaphore::down] are signalled and served in the order they started waiting.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Semaphore;
+  /// # fn main() {
+  ///     let sema = Semaphore::new(1);
+  ///     sema.try_acquire().unwrap();
+  ///     // the device has been reset and provides 4 fresh buffers
+  ///     assert_eq!(sema.set_permits(4), 0);
+  ///     assert!(sema.try_acquire_n(4).is_ok());
+  /// # }
+  /// ```
+  #[inline]
+  pub fn set_permits(&self, n: C) -> C {
+    let state = self
+      .state
+      .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+        Some((state & !Self::COUNT) | n.into_count().min(Self::COUNT))
+      })
+      .unwrap_or_else(|state| state);
+
+    self.signal_waiters(state);
+    C::from_count(Self::count(state))
+  }
+
+  /// try to decrease a semaphore for usage. Returns [value@Ok] if the semaphore could be used.
+  #[inline]
+  #[deprecated(
+    since = "0.6.0",
+    note = "use `try_acquire` that reports the reason of a failure"
+  )]
+  #[allow(clippy::result_unit_err)]
   pub fn try_down(&self) -> Result<(), ()> {
-    let mut value = self.count.load(Ordering::Acquire);
-    if value > 0 {
-      value -= 1;
-      self.count.store(value, Ordering::Release);
-      // dmb required before allow access to the protected resource see:
-      // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
-      #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-      unsafe {
-        asm!("dmb sy");
-      }
-      Ok(())
-    } else {
-      // set the current value as "dummy" store to clear the atomic monitor
-      self.count.store(value, Ordering::Release);
-      Err(())
+    self.try_acquire().map_err(|_| ())
+  }
+
+  /// try to decrease a semaphore by the given number of permits at once. Returns [value@Ok] if the semaphore could be
+  /// decreased by all requested permits.
+  #[inline]
+  #[deprecated(
+    since = "0.6.0",
+    note = "use `try_acquire_n` that reports the reason of a failure"
+  )]
+  #[allow(clippy::result_unit_err)]
+  pub fn try_down_n(&self, n: C) -> Result<(), ()> {
+    self.try_acquire_n(n).map_err(|_| ())
+  }
+
+  /// The available permits and the number of cores waiting in [Semaphore::down] or [Semaphore::down_while], read
+  /// without acquiring the semaphore
+  fn observe(&self) -> (C, u64) {
+    let state = self.state.load(Ordering::Relaxed);
+    let waiters = Self::waiters(state) + self.pollers.load(Ordering::Relaxed) as u64;
+    (C::from_count(Self::count(state)), waiters)
+  }
+
+  /// The current state of the Semaphore. This only reads the counter and never acquires the semaphore, so it is safe
+  /// to be used from a panic handler.
+  pub fn fmt_state(&self) -> LockState {
+    LockState::Semaphore {
+      // the state reports the permits of wider counters saturated to 32 bits
+      permits: Self::count(self.state.load(Ordering::Relaxed)).min(u32::MAX as u64) as u32,
     }
   }
 }
 
-impl Default for Semaphore {
+impl<C: Counter> InspectLock for Semaphore<C> {
+  fn lock_state(&self) -> LockState {
+    self.fmt_state()
+  }
+}
+
+impl<C: Counter> fmt::Debug for Semaphore<C> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (permits, waiters) = self.observe();
+    f.debug_struct("Semaphore")
+      .field("permits", &permits)
+      .field("waiters", &waiters)
+      .finish()
+  }
+}
+
+impl<C: Counter> fmt::Display for Semaphore<C> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (permits, waiters) = self.observe();
+    write!(
+      f,
+      "Semaphore (permits: {:?}, waiters: {})",
+      permits, waiters
+    )
+  }
+}
+
+impl<C: Counter> Default for Semaphore<C> {
   fn default() -> Self {
-    Semaphore::new(0)
+    Self::with_count(0)
   }
 }
 
-unsafe impl Sync for Semaphore {}
-unsafe impl Send for Semaphore {}
+unsafe impl<C: Counter> Sync for Semaphore<C> {}
+unsafe impl<C: Counter> Send for Semaphore<C> {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::sync::atomic::AtomicUsize;
+  use std::thread;
+
+  /// Wait until the given number of cores waits in [Semaphore::down]
+  fn wait_for_waiters<C: Counter>(sema: &Semaphore<C>, waiters: u64) {
+    while Semaphore::<C>::waiters(sema.state.load(Ordering::Acquire)) != waiters {
+      thread::yield_now();
+    }
+  }
+
+  #[test]
+  fn waiting_cores_are_served_in_ticket_order() {
+    let sema = Semaphore::new(0);
+    let served = AtomicUsize::new(0);
+    thread::scope(|s| {
+      let first = s.spawn(|| {
+        sema.down();
+        served.fetch_add(1, Ordering::SeqCst)
+      });
+      wait_for_waiters(&sema, 1);
+      let second = s.spawn(|| {
+        sema.down();
+        served.fetch_add(1, Ordering::SeqCst)
+      });
+      wait_for_waiters(&sema, 2);
+
+      // each permit lets exactly one waiting core proceed, the one that started waiting first
+      sema.up();
+      wait_for_waiters(&sema, 1);
+      sema.up();
+      assert_eq!(first.join().unwrap(), 0);
+      assert_eq!(second.join().unwrap(), 1);
+    });
+    assert_eq!(sema.observe(), (0, 0));
+  }
+
+  #[test]
+  fn try_acquire_does_not_overtake_waiting_cores() {
+    let sema = Semaphore::new(0);
+    // a core has drawn ticket 0 and the permit released for it has not been taken yet
+    sema
+      .state
+      .store((1 << Semaphore::<u32>::NEXT_SHIFT) | 1, Ordering::Relaxed);
+    assert_eq!(sema.try_acquire(), Err(LockError::WouldBlock));
+    assert_eq!(sema.try_acquire_n(1), Err(LockError::WouldBlock));
+    // once the waiting core has been served new permits are available to everyone
+    sema.state.store(
+      (1 << Semaphore::<u32>::NEXT_SHIFT) | (1 << Semaphore::<u32>::SERVING_SHIFT),
+      Ordering::Relaxed,
+    );
+    sema.up();
+    assert!(sema.try_acquire().is_ok());
+  }
+
+  #[test]
+  fn up_signals_only_if_cores_wait() {
+    let sema = Semaphore::new(0);
+    assert!(!sema.is_waited_for(sema.state.load(Ordering::Relaxed)));
+    thread::scope(|s| {
+      s.spawn(|| sema.down());
+      wait_for_waiters(&sema, 1);
+      assert!(sema.is_waited_for(sema.state.load(Ordering::Relaxed)));
+      sema.up();
+    });
+    assert!(!sema.is_waited_for(sema.state.load(Ordering::Relaxed)));
+
+    // the cores waiting in down_while do not draw a ticket, but need to be signalled as well
+    thread::scope(|s| {
+      s.spawn(|| sema.down_while(|_| true));
+      while sema.pollers.load(Ordering::SeqCst) == 0 {
+        thread::yield_now();
+      }
+      assert!(sema.is_waited_for(sema.state.load(Ordering::Relaxed)));
+      sema.up();
+    });
+    assert!(!sema.is_waited_for(sema.state.load(Ordering::Relaxed)));
+  }
+
+  #[test]
+  fn up_bounded_saturates_at_the_maximum() {
+    let sema = Semaphore::new(0);
+    assert!(sema.up_bounded(2));
+    assert!(sema.up_bounded(2));
+    assert!(!sema.up_bounded(2));
+    assert_eq!(sema.observe(), (2, 0));
+    // a maximum beyond the width of the counter is limited to it
+    let sema = Semaphore::new_u64(Semaphore::<u64>::COUNT - 1);
+    assert!(sema.up_bounded(u64::MAX));
+    assert!(!sema.up_bounded(u64::MAX));
+    assert_eq!(sema.observe(), (Semaphore::<u64>::COUNT, 0));
+  }
+
+  #[test]
+  fn counter_saturates_at_its_width() {
+    let sema = Semaphore::new(u32::MAX);
+    sema.up_n(5);
+    assert_eq!(sema.observe(), (u32::MAX, 0));
+
+    let sema = Semaphore::new_u64(u64::MAX);
+    assert_eq!(sema.observe(), ((1 << 48) - 1, 0));
+    sema.up();
+    assert_eq!(sema.set_permits(u64::MAX), (1 << 48) - 1);
+    // the saturated counter never overflows into the tickets
+    let state = sema.state.load(Ordering::Relaxed);
+    assert_eq!(Semaphore::<u64>::serving(state), 0);
+    assert_eq!(Semaphore::<u64>::next(state), 0);
+  }
+
+  #[test]
+  fn tickets_wrap_around_without_touching_the_counter() {
+    type Sema = Semaphore<u64>;
+    let sema = Sema::new_u64(0);
+    // the 64 bit counter leaves 8 bits for each ticket, so start with the last one
+    let last = Sema::TICKET;
+    sema.state.store(
+      (last << Sema::NEXT_SHIFT) | (last << Sema::SERVING_SHIFT),
+      Ordering::Relaxed,
+    );
+    thread::scope(|s| {
+      s.spawn(|| sema.down());
+      wait_for_waiters(&sema, 1);
+      assert_eq!(Sema::next(sema.state.load(Ordering::Relaxed)), 0);
+      sema.up();
+    });
+    let state = sema.state.load(Ordering::Relaxed);
+    assert_eq!(Sema::count(state), 0);
+    assert_eq!(Sema::serving(state), 0);
+    assert_eq!(Sema::next(state), 0);
+  }
+}
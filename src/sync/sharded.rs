@@ -0,0 +1,196 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # ShardedRWLock
+//!
+//! Data accessed by many cores at the same time, like a global handle table, suffers from the contention on a single
+//! lock even if the cores access different entries. The [ShardedRWLock] splits the data into `SHARDS` parts, each
+//! secured by its own [RWLock]. Accesses are routed to a shard by hashing their key, so cores working on different
+//! keys usually acquire different locks.
+//!
+//! All keys hashed to the same shard share its lock, so an access to one key holds off the writers of the other keys of
+//! this shard. Locks of different shards shall always be acquired in the order of their shard index, as acquiring them
+//! in different orders on different cores can deadlock.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::{RWLock, ShardedRWLock};
+//!
+//! // the handles are spread across 4 shards, each with 16 slots
+//! static HANDLES: ShardedRWLock<[u32; 16], 4> = ShardedRWLock::from_shards([
+//!     RWLock::new([0; 16]),
+//!     RWLock::new([0; 16]),
+//!     RWLock::new([0; 16]),
+//!     RWLock::new([0; 16]),
+//! ]);
+//!
+//! fn main() {
+//!     let handle = 42_usize;
+//!     HANDLES.write(&handle)[handle % 16] = 1;
+//!     assert_eq!(HANDLES.read(&handle)[handle % 16], 1);
+//! }
+//! ```
+
+use super::{RWLock, ReadLockGuard, WriteLockGuard};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+
+/// Data split into `SHARDS` parts, each secured by its own [RWLock]
+pub struct ShardedRWLock<T, const SHARDS: usize> {
+  shards: [RWLock<T>; SHARDS],
+}
+
+impl<T, const SHARDS: usize> ShardedRWLock<T, SHARDS> {
+  /// Create a new [ShardedRWLock] with each shard securing the value at its index
+  ///
+  /// # Panics
+  /// Panics if `SHARDS` is 0
+  pub fn new(shards: [T; SHARDS]) -> Self {
+    Self::from_shards(shards.map(RWLock::new))
+  }
+
+  /// Create a new [ShardedRWLock] from the given locks. This can be used to create the lock in a `static`.
+  ///
+  /// # Panics
+  /// Panics if `SHARDS` is 0
+  pub const fn from_shards(shards: [RWLock<T>; SHARDS]) -> Self {
+    assert!(SHARDS > 0, "a ShardedRWLock requires at least one shard");
+    Self { shards }
+  }
+
+  /// The index of the shard the given key is routed to
+  pub fn shard_of<K: Hash + ?Sized>(&self, key: &K) -> usize {
+    let mut hasher = Fnv1a::default();
+    key.hash(&mut hasher);
+    (hasher.finish() % SHARDS as u64) as usize
+  }
+
+  /// The lock of the shard at the given index
+  ///
+  /// # Panics
+  /// Panics if the index is not less than `SHARDS`
+  pub fn shard(&self, index: usize) -> &RWLock<T> {
+    &self.shards[index]
+  }
+
+  /// The locks of all shards in the order they shall be acquired
+  pub fn shards(&self) -> &[RWLock<T>; SHARDS] {
+    &self.shards
+  }
+
+  /// Acquire a read lock to the shard the given key is routed to. This blocks until the read lock could be acquired.
+  pub fn read<K: Hash + ?Sized>(&self, key: &K) -> ReadLockGuard<'_, T> {
+    self.shards[self.shard_of(key)].read()
+  }
+
+  /// Try to acquire a read lock to the shard the given key is routed to. Returns `None` if the shard is write locked.
+  pub fn try_read<K: Hash + ?Sized>(&self, key: &K) -> Option<ReadLockGuard<'_, T>> {
+    self.shards[self.shard_of(key)].try_read()
+  }
+
+  /// Acquire the write lock to the shard the given key is routed to. This blocks until the write lock could be
+  /// acquired.
+  pub fn write<K: Hash + ?Sized>(&self, key: &K) -> WriteLockGuard<'_, T> {
+    self.shards[self.shard_of(key)].write()
+  }
+
+  /// Try to acquire the write lock to the shard the given key is routed to. Returns `None` if the shard is locked.
+  pub fn try_write<K: Hash + ?Sized>(&self, key: &K) -> Option<WriteLockGuard<'_, T>> {
+    self.shards[self.shard_of(key)].try_write()
+  }
+
+  /// Consume the [ShardedRWLock] and return the data of all shards
+  pub fn into_inner(self) -> [T; SHARDS] {
+    self.shards.map(RWLock::into_inner)
+  }
+}
+
+impl<T, const SHARDS: usize> fmt::Debug for ShardedRWLock<T, SHARDS> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ShardedRWLock")
+      .field("shards", &self.shards)
+      .finish()
+  }
+}
+
+/// The FNV-1a hash routing the keys to the shards. It is small, needs no allocation and spreads sequential keys, like
+/// handles, well enough across the shards.
+pub(super) struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+  fn default() -> Self {
+    Self(0xcbf2_9ce4_8422_2325)
+  }
+}
+
+impl Hasher for Fnv1a {
+  fn write(&mut self, bytes: &[u8]) {
+    for byte in bytes {
+      self.0 ^= *byte as u64;
+      self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+    }
+  }
+
+  fn finish(&self) -> u64 {
+    self.0
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  /// Two keys routed to different shards of the given lock
+  fn keys_of_different_shards<T, const SHARDS: usize>(
+    lock: &ShardedRWLock<T, SHARDS>,
+  ) -> (usize, usize) {
+    let other = (1..)
+      .find(|key| lock.shard_of(key) != lock.shard_of(&0))
+      .unwrap();
+    (0, other)
+  }
+
+  #[test]
+  fn sequential_keys_are_spread_across_all_shards() {
+    let lock = ShardedRWLock::new([0u32; 4]);
+    let mut used = [false; 4];
+    for key in 0..64usize {
+      let shard = lock.shard_of(&key);
+      assert_eq!(shard, lock.shard_of(&key));
+      used[shard] = true;
+    }
+    assert_eq!(used, [true; 4]);
+  }
+
+  #[test]
+  fn shards_are_locked_independently() {
+    let lock = ShardedRWLock::new([0u32; 4]);
+    let (first, second) = keys_of_different_shards(&lock);
+    let mut writer = lock.write(&first);
+    *writer = 1;
+    assert!(lock.try_read(&first).is_none());
+    assert!(lock.try_write(&first).is_none());
+    *lock.try_write(&second).unwrap() = 2;
+    drop(writer);
+
+    let reader = lock.read(&first);
+    assert!(lock.try_write(&first).is_none());
+    assert_eq!(*lock.try_read(&first).unwrap(), 1);
+    drop(reader);
+    assert!(lock.try_write(&first).is_some());
+
+    let mut data = lock.into_inner();
+    data.sort();
+    assert_eq!(data, [0, 0, 1, 2]);
+  }
+
+  #[test]
+  #[should_panic]
+  fn a_lock_without_shards_is_rejected() {
+    let _ = ShardedRWLock::<u32, 0>::new([]);
+  }
+}
@@ -0,0 +1,209 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Shared
+//!
+//! Reference counted access to data that is not allocated on the heap, eg. because it is placed in a static variable
+//! before the heap is set up. A [Shared] hands out up to `MAX` [SharedHandle]s that can be cloned and moved between
+//! tasks like an `Arc`. The number of handles is counted with atomic operations only, so it is known at any time
+//! whether the data is still in use.
+//!
+//! The async locks for heap-less systems accept a [SharedHandle] to hand out guards that keep the handle, eg.
+//! [AsyncMutexN::lock_owned](crate::r#async::AsyncMutexN::lock_owned).
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::{Shared, SharedHandle};
+//!
+//! static CONFIG: Shared<[u8; 4], 2> = Shared::new([1, 2, 3, 4]);
+//!
+//! fn main() {
+//!     let config = CONFIG.handle().unwrap();
+//!     let second = config.clone();
+//!     assert_eq!(SharedHandle::count(&config), 2);
+//!     // the limit of handles has been reached
+//!     assert!(CONFIG.handle().is_none());
+//!
+//!     drop(second);
+//!     assert_eq!(config[0], 1);
+//! }
+//! ```
+
+use core::fmt;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Data that can be accessed through up to `MAX` [SharedHandle]s at the same time
+pub struct Shared<T, const MAX: usize> {
+  value: T,
+  handles: AtomicUsize,
+}
+
+/// A handle to the data of a [Shared]. Cloning the handle only increases the number of handles of the [Shared].
+pub struct SharedHandle<'a, T, const MAX: usize> {
+  shared: &'a Shared<T, MAX>,
+}
+
+impl<T, const MAX: usize> Shared<T, MAX> {
+  /// Create the [Shared] data without any handle. As this does not require any allocation it can be assigned to a
+  /// static variable
+  ///
+  /// # Panics
+  /// Panics if `MAX` is 0
+  pub const fn new(value: T) -> Self {
+    assert!(MAX > 0, "a Shared requires at least 1 handle");
+    Self {
+      value,
+      handles: AtomicUsize::new(0),
+    }
+  }
+
+  /// Create a new handle to the data. Returns `None` if there are already `MAX` handles.
+  pub fn handle(&self) -> Option<SharedHandle<'_, T, MAX>> {
+    self
+      .handles
+      .fetch_update(Ordering::Acquire, Ordering::Relaxed, |handles| {
+        (handles < MAX).then_some(handles + 1)
+      })
+      .ok()?;
+
+    Some(SharedHandle { shared: self })
+  }
+
+  /// The number of handles currently alive. The answer might be outdated already when it is returned.
+  pub fn handles(&self) -> usize {
+    self.handles.load(Ordering::Relaxed)
+  }
+
+  /// Mutably access the data. As this requires a mutable reference to the [Shared] no handle can be alive.
+  pub fn get_mut(&mut self) -> &mut T {
+    &mut self.value
+  }
+
+  /// Consume the [Shared] and return the inner value
+  pub fn into_inner(self) -> T {
+    self.value
+  }
+}
+
+impl<T: Default, const MAX: usize> Default for Shared<T, MAX> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+impl<T, const MAX: usize> fmt::Debug for Shared<T, MAX> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Shared")
+      .field("handles", &self.handles())
+      .field("max", &MAX)
+      .finish()
+  }
+}
+
+impl<'a, T, const MAX: usize> SharedHandle<'a, T, MAX> {
+  /// Access the data for the whole lifetime of the [Shared], independent of the lifetime of this handle. This is an
+  /// associated function, so it does not hide a method of the same name of `T`.
+  pub fn get(this: &Self) -> &'a T {
+    &this.shared.value
+  }
+
+  /// The number of handles of the [Shared] currently alive, including this one
+  pub fn count(this: &Self) -> usize {
+    this.shared.handles()
+  }
+
+  /// Clone the handle. Returns `None` if there are already `MAX` handles.
+  pub fn try_clone(this: &Self) -> Option<Self> {
+    this.shared.handle()
+  }
+}
+
+impl<T, const MAX: usize> Clone for SharedHandle<'_, T, MAX> {
+  /// Clone the handle
+  ///
+  /// # Panics
+  /// Panics if there are already `MAX` handles
+  fn clone(&self) -> Self {
+    Self::try_clone(self).expect("the maximum number of SharedHandles has been reached")
+  }
+}
+
+impl<T, const MAX: usize> Deref for SharedHandle<'_, T, MAX> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.shared.value
+  }
+}
+
+impl<T, const MAX: usize> AsRef<T> for SharedHandle<'_, T, MAX> {
+  fn as_ref(&self) -> &T {
+    &self.shared.value
+  }
+}
+
+impl<T, const MAX: usize> Drop for SharedHandle<'_, T, MAX> {
+  fn drop(&mut self) {
+    self.shared.handles.fetch_sub(1, Ordering::Release);
+  }
+}
+
+impl<T: fmt::Debug, const MAX: usize> fmt::Debug for SharedHandle<'_, T, MAX> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&self.shared.value, f)
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn handles_are_limited_and_released_once_dropped() {
+    let shared: Shared<u32, 2> = Shared::new(10);
+    let first = shared.handle().unwrap();
+    let second = SharedHandle::try_clone(&first).unwrap();
+    assert_eq!(SharedHandle::count(&first), 2);
+    assert!(shared.handle().is_none());
+    assert!(SharedHandle::try_clone(&second).is_none());
+    drop(first);
+    assert_eq!(*second, 10);
+    assert_eq!(shared.handles(), 1);
+    drop(second);
+    assert_eq!(shared.handles(), 0);
+  }
+
+  #[test]
+  #[should_panic]
+  fn clone_panics_once_the_limit_is_reached() {
+    let shared: Shared<u32, 1> = Shared::new(10);
+    let handle = shared.handle().unwrap();
+    let _ = handle.clone();
+  }
+
+  #[test]
+  fn concurrent_handles_never_exceed_the_limit() {
+    const THREADS: usize = 4;
+    const MAX: usize = 2;
+
+    let shared: Shared<u32, MAX> = Shared::new(10);
+    std::thread::scope(|s| {
+      for _ in 0..THREADS {
+        s.spawn(|| {
+          for _ in 0..100 {
+            if let Some(handle) = shared.handle() {
+              assert!(SharedHandle::count(&handle) <= MAX);
+              assert_eq!(*SharedHandle::get(&handle), 10);
+            }
+          }
+        });
+      }
+    });
+    assert_eq!(shared.handles(), 0);
+  }
+}
@@ -0,0 +1,236 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Spin Policy
+//!
+//! The blocking functions of the [Spinlock](super::Spinlock), [Mutex](super::Mutex), [RWLock](super::RWLock) and
+//! [Semaphore](super::Semaphore) retry to acquire the lock until they succeed. Between two attempts they call the
+//! globally selected [SpinPolicy] that decides how the core is waiting. This allows the same crate to be used bare
+//! metal, as a guest of a hypervisor or in host tests without changing the locks.
+//!
+//! The default is the [WfePolicy].
+//!
+//! # Yield Hook
+//!
+//! On a single core with a cooperative scheduler the task holding a lock can only release it if the task waiting for
+//! it gives up the core. Otherwise the waiting task spins forever. A hook selected with [set_yield_hook] is called
+//! between two attempts, before the core waits according to the [SpinPolicy], so the scheduler can switch to another
+//! task. The lock released meanwhile has signalled an event, so a core waiting for an event afterwards continues
+//! immediately.
+//!
+//! # Stall Detection
+//!
+//! If the MMU is not configured properly the atomic operations of the locks never succeed and the core hangs silently.
+//! With the `stall_detection` feature each blocking call counts its failed attempts and calls the handler selected
+//! with `set_stall_handler` once they reach the threshold selected with `set_stall_threshold`. The handler receives
+//! the name of the blocking function or of the lock, and panics by default, which turns a silent hang during the
+//! bring-up of a board into a diagnosable failure. A core waiting with the [WfePolicy] only counts an attempt if it
+//! is woken by an event, so the event stream of the generic timer (`CNTKCTL_EL1.EVNTEN`) should be enabled to wake it
+//! regularly even if no lock is ever released.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::spin::{self, SpinPolicy, YieldPolicy};
+//!
+//! /// spin a few times before waiting for an event
+//! struct Backoff;
+//!
+//! impl SpinPolicy for Backoff {
+//!     fn on_contention(attempt: u32) {
+//!         if attempt < 10 {
+//!             core::hint::spin_loop();
+//!         } else {
+//!             YieldPolicy::on_contention(attempt);
+//!         }
+//!     }
+//! }
+//!
+//! fn main() {
+//!     spin::set_policy::<Backoff>();
+//! }
+//! ```
+
+use super::AtomicCell;
+use crate::arch;
+#[cfg(feature = "stall_detection")]
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Decides how a core waits between two attempts to acquire a contended lock
+pub trait SpinPolicy {
+  /// Called each time an attempt to acquire a lock failed. `attempt` counts the failed attempts of the current blocking
+  /// call starting at `0`.
+  fn on_contention(attempt: u32);
+}
+
+/// Wait for an event (`wfe`) that is signalled once a lock is released. With the `no_sev` feature no events are
+/// signalled and this executes an `isb` instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WfePolicy;
+
+impl SpinPolicy for WfePolicy {
+  #[inline]
+  fn on_contention(_: u32) {
+    arch::wait_for_event();
+  }
+}
+
+/// Immediately retry without any hint to the core
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BusyPolicy;
+
+impl SpinPolicy for BusyPolicy {
+  #[inline]
+  fn on_contention(_: u32) {}
+}
+
+/// Execute a `yield` hint that allows other hardware threads or a hypervisor to make progress
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YieldPolicy;
+
+impl SpinPolicy for YieldPolicy {
+  #[inline]
+  fn on_contention(_: u32) {
+    arch::yield_hint();
+  }
+}
+
+/// The `on_contention` function of the selected policy
+static POLICY: AtomicCell<fn(u32)> = AtomicCell::new(WfePolicy::on_contention as fn(u32));
+
+/// Select the [SpinPolicy] used by all blocking lock functions
+pub fn set_policy<P: SpinPolicy>() {
+  POLICY.store(P::on_contention);
+}
+
+/// The hook called between two attempts to acquire a contended lock
+static YIELD_HOOK: AtomicCell<Option<fn()>> = AtomicCell::new(None);
+
+/// Select the hook called each time a blocking call failed to acquire a lock, before the core waits according to the
+/// [SpinPolicy]. A cooperative scheduler can switch to another task from within the hook, so the task holding the lock
+/// on the same core can release it. The hook is also called by blocking calls outside of any task, e.g. in an
+/// interrupt handler, and need to return immediately there.
+///
+/// # Example
+/// ```
+/// use ruspiro_lock::sync::spin;
+///
+/// fn yield_task() {
+///     // eg. switch to the next ready task if called from within a task
+/// }
+///
+/// fn main() {
+///     spin::set_yield_hook(yield_task);
+/// }
+/// ```
+pub fn set_yield_hook(hook: fn()) {
+  YIELD_HOOK.store(Some(hook));
+}
+
+/// Remove the hook selected with [set_yield_hook]
+pub fn clear_yield_hook() {
+  YIELD_HOOK.store(None);
+}
+
+/// The number of failed attempts of a blocking call after which it is considered stalled, if not selected otherwise
+#[cfg(feature = "stall_detection")]
+pub const DEFAULT_STALL_THRESHOLD: u32 = 1 << 28;
+
+#[cfg(feature = "stall_detection")]
+static STALL_THRESHOLD: AtomicU32 = AtomicU32::new(DEFAULT_STALL_THRESHOLD);
+
+/// The handler called once a blocking call is stalled
+#[cfg(feature = "stall_detection")]
+static STALL_HANDLER: AtomicCell<fn(&'static str)> =
+  AtomicCell::new(stall_panic as fn(&'static str));
+
+/// The default stall handler
+#[cfg(feature = "stall_detection")]
+fn stall_panic(lock: &'static str) {
+  panic!(
+    "{} stalled, the atomic operations might not work as the MMU is not configured properly",
+    lock
+  );
+}
+
+/// Select the handler called with the name of the blocking function or of the lock once a blocking call reached the
+/// stall threshold. It is called on the stalled core. If it returns the core continues waiting.
+///
+/// # Example
+/// ```
+/// # #[cfg(feature = "stall_detection")]
+/// # mod doc {
+/// use ruspiro_lock::sync::spin;
+///
+/// fn report_stall(lock: &'static str) {
+///     // eg. print this to the debug UART and halt the core
+///     println!("{} stalled", lock);
+/// }
+///
+/// fn main() {
+///     spin::set_stall_threshold(1_000_000);
+///     spin::set_stall_handler(report_stall);
+/// }
+/// # }
+/// # fn main() {}
+/// ```
+#[cfg(feature = "stall_detection")]
+pub fn set_stall_handler(handler: fn(&'static str)) {
+  STALL_HANDLER.store(handler);
+}
+
+/// Select the number of failed attempts after which a blocking call is considered stalled. The default is
+/// [DEFAULT_STALL_THRESHOLD].
+#[cfg(feature = "stall_detection")]
+pub fn set_stall_threshold(attempts: u32) {
+  STALL_THRESHOLD.store(attempts, Ordering::Relaxed);
+}
+
+/// Call the selected yield hook and wait according to the selected [SpinPolicy] after an attempt to acquire the given
+/// lock failed
+#[inline]
+pub(crate) fn on_contention(attempt: &mut u32, lock: &'static str) {
+  if let Some(hook) = YIELD_HOOK.load() {
+    hook();
+  }
+  (POLICY.load())(*attempt);
+  *attempt = attempt.wrapping_add(1);
+  #[cfg(feature = "stall_detection")]
+  if *attempt == STALL_THRESHOLD.load(Ordering::Relaxed) {
+    (STALL_HANDLER.load())(lock);
+  }
+  #[cfg(not(feature = "stall_detection"))]
+  let _ = lock;
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use crate::sync::{Mutex, MutexGuard};
+  use std::cell::RefCell;
+
+  static DATA: Mutex<u32> = Mutex::new(0);
+
+  std::thread_local! {
+    /// The guard held by the other task of the cooperative scheduler running on this thread
+    static OTHER_TASK: RefCell<Option<MutexGuard<'static, u32>>> = const { RefCell::new(None) };
+  }
+
+  /// Switch to the other task, which releases its lock
+  fn switch_task() {
+    OTHER_TASK.with(|task| drop(task.borrow_mut().take()));
+  }
+
+  #[test]
+  fn yield_hook_lets_the_task_holding_the_lock_release_it() {
+    OTHER_TASK.with(|task| *task.borrow_mut() = Some(DATA.lock()));
+    set_yield_hook(switch_task);
+    // without the hook this would wait forever for the task on the same core
+    *DATA.lock() += 1;
+    clear_yield_hook();
+    assert_eq!(*DATA.lock(), 1);
+  }
+}
@@ -9,7 +9,7 @@
 //! Providing simple atomic Spinlock. This can be used to ensure cross core atomic access to data that is typically
 //! shared between them. For example MMIO mapped registers that allow access to peripherals. Please note that usage
 //! of Spinlocks on Raspberry Pi is only safe if the MMU has ben configured properly. Otherwise the cores trying to
-//! aquire a lock will just hang, even if the lock would be available to them.
+//! acquire a lock will just hang, even if the lock would be available to them.
 //!
 //! # Example
 //! ```
@@ -18,25 +18,41 @@
 //! static LOCK: Spinlock = Spinlock::new();
 //!
 //! fn main () {
-//!     LOCK.aquire(); // will only return if the lock could be set
+//!     LOCK.acquire(); // will only return if the lock could be set
 //!     // do something
 //!
 //!     LOCK.release(); // releasing the lock
 //! }
 //! ```
-use core::arch::asm;
-use core::sync::atomic::{AtomicBool, Ordering};
+use super::ceiling;
+use super::held;
+use super::metrics::{self, LockKind};
+use super::registry::{InspectLock, LockState};
+use super::spin;
+use crate::{arch, LockError};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
 /// A blocking cross core lock to guarantee mutual exclusive access. While this lock might block other cores
 /// to continue processing this lock should be held as short as possible. Also care shall be taken
 /// while using this lock within interrupt handlers, as this might lead to deadlock situations if the
-/// lock holding core is interrupted and the interrupt is also trying to aquire the same lock.
+/// lock holding core is interrupted and the interrupt is also trying to acquire the same lock.
+///
+/// A Spinlock created with [Spinlock::with_ceiling] raises the priority of the acquiring core to its priority ceiling,
+/// see the [ceiling] module.
 #[derive(Debug)]
 #[repr(C, align(16))]
 pub struct Spinlock {
   flag: AtomicBool,
+  /// The priority the acquiring core is raised to, or [NO_PRIORITY] if the Spinlock has no ceiling
+  ceiling: u32,
+  /// The priority of the holding core before it has been raised to the ceiling, or [NO_PRIORITY]
+  restore: AtomicU32,
 }
 
+/// The Spinlock has no ceiling or the priority of the holding core has not been raised. Keeping the priorities in
+/// plain integers keeps the Spinlock within 16 bytes.
+const NO_PRIORITY: u32 = u32::MAX;
+
 impl Spinlock {
   /// Create a new Spinlock. To ensure it is shared between cores, it's typically assigned to a static variable
   /// # Example
@@ -47,37 +63,123 @@ impl Spinlock {
   pub const fn new() -> Spinlock {
     Spinlock {
       flag: AtomicBool::new(false),
+      ceiling: NO_PRIORITY,
+      restore: AtomicU32::new(NO_PRIORITY),
     }
   }
 
-  /// Aquire a spinlock. This will block the current core until the lock could be aquired.
+  /// Create a new Spinlock with the given priority ceiling, the highest priority of all tasks using the lock. The
+  /// priority of the acquiring core is raised to the ceiling before the lock is acquired and restored once it is
+  /// released, using the hooks registered with [ceiling::set_ceiling_hooks]. The priority `u32::MAX` is reserved and
+  /// creates a Spinlock without ceiling.
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::Spinlock;
+  /// static LOCK: Spinlock = Spinlock::with_ceiling(3);
+  /// ```
+  pub const fn with_ceiling(ceiling: u32) -> Spinlock {
+    Spinlock {
+      flag: AtomicBool::new(false),
+      ceiling,
+      restore: AtomicU32::new(NO_PRIORITY),
+    }
+  }
+
+  /// The priority ceiling of the Spinlock, if it has been created with [Spinlock::with_ceiling]
+  pub fn ceiling(&self) -> Option<u32> {
+    (self.ceiling != NO_PRIORITY).then_some(self.ceiling)
+  }
+
+  /// Raise the priority of the current core to the ceiling of the Spinlock before it is acquired. Returns the
+  /// priority to restore
+  #[inline]
+  fn raise_to_ceiling(&self) -> Option<u32> {
+    self.ceiling().and_then(ceiling::raise)
+  }
+
+  /// Remember the priority to restore once the Spinlock acquired by the current core is released
+  #[inline]
+  fn raised(&self, restore: Option<u32>) {
+    if let Some(priority) = restore {
+      self.restore.store(priority, Ordering::Relaxed);
+    }
+  }
+
+  /// Acquire a spinlock. This will block the current core until the lock could be acquired.
   /// # Example
   /// ```no_run
   /// # use ruspiro_lock::sync::Spinlock;
   /// static LOCK: Spinlock = Spinlock::new();
   /// # fn main() {
-  ///     LOCK.aquire();
-  ///     // execution continues only if the lock could be aquired
+  ///     LOCK.acquire();
+  ///     // execution continues only if the lock could be acquired
   /// # }
   /// ```
   #[inline]
-  pub fn aquire(&self) {
+  pub fn acquire(&self) {
+    // the priority is raised before the lock is acquired, so the holder can not be preempted in between
+    let restore = self.raise_to_ceiling();
     // set the atomic value to true if it has been false before (set the lock)
+    let mut attempt = 0;
     while self
       .flag
       .compare_exchange(false, true, Ordering::SeqCst, Ordering::Acquire)
       .is_err()
-    {}
+    {
+      // the lock is held by another core, wait according to the selected spin policy
+      spin::on_contention(&mut attempt, "Spinlock::acquire");
+    }
+    metrics::record(LockKind::Spinlock, attempt);
+    held::track(&self.flag);
+    self.raised(restore);
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+  }
 
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    unsafe {
-      // dmb required before allow access to the protected resource, see:
-      // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
-      asm!("dmb sy");
+  /// Try to acquire the spinlock without blocking. Fails with [LockError::WouldBlock] if the lock is currently held.
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_lock::sync::Spinlock;
+  /// static LOCK: Spinlock = Spinlock::new();
+  /// # fn main() {
+  ///     if LOCK.try_acquire().is_ok() {
+  ///         // the lock has been acquired
+  ///         LOCK.release();
+  ///     }
+  /// # }
+  /// ```
+  #[inline]
+  pub fn try_acquire(&self) -> Result<(), LockError> {
+    let restore = self.raise_to_ceiling();
+    if self
+      .flag
+      .compare_exchange(false, true, Ordering::SeqCst, Ordering::Acquire)
+      .is_err()
+    {
+      if let Some(priority) = restore {
+        ceiling::restore(priority);
+      }
+      return Err(LockError::WouldBlock);
     }
+    held::track(&self.flag);
+    self.raised(restore);
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    Ok(())
+  }
+
+  /// Acquire a spinlock. This will block the current core until the lock could be acquired.
+  #[inline]
+  #[deprecated(since = "0.6.0", note = "use the correctly spelled `acquire`")]
+  pub fn aquire(&self) {
+    self.acquire();
   }
 
-  /// Release an aquired spinlock.
+  /// Release an acquired spinlock.
   /// # Example
   /// ```no_run
   /// # use ruspiro_lock::sync::Spinlock;
@@ -88,19 +190,60 @@ impl Spinlock {
   /// ```
   #[inline]
   pub fn release(&self) {
+    // the priority to restore need to be taken while the lock is still held, as the next holder overwrites it
+    let restore = if self.ceiling != NO_PRIORITY {
+      self.restore.swap(NO_PRIORITY, Ordering::Relaxed)
+    } else {
+      NO_PRIORITY
+    };
+    held::untrack(&self.flag);
+    self.flag.store(false, Ordering::SeqCst);
+
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // also raise a signal to indicate the spinlock has been changed (this trigger all WFE's to continue
+    // processing) but do data syncronisation barrier upfront to ensure any data updates has been finished
+    arch::signal_event();
+    // the priority is restored once the lock is released, so the holder can not be preempted while holding it
+    if restore != NO_PRIORITY {
+      ceiling::restore(restore);
+    }
+  }
+
+  /// Release the Spinlock regardless of which core holds it and signal this to the waiting cores. This is intended for
+  /// exception recovery code that need to reclaim a lock held by a crashed core.
+  ///
+  /// # Safety
+  /// The core holding the lock shall never continue to access the resource secured by this Spinlock, e.g. because it
+  /// has been halted or reset. The resource might be left in an inconsistent state by the former holder, so the
+  /// caller need to restore a consistent state before the resource is used again.
+  ///
+  /// The priority the former holder had before it has been raised to the ceiling is dropped and not restored, as it
+  /// belongs to the core holding the lock and restoring it would change the priority of the current core. The caller
+  /// need to reset the priority of the former holder if it is ever restarted.
+  pub unsafe fn force_unlock(&self) {
+    self.restore.store(NO_PRIORITY, Ordering::Relaxed);
+    held::untrack(&self.flag);
     self.flag.store(false, Ordering::SeqCst);
 
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    unsafe {
-      // dmb required before allow access to the protected resource, see:
-      // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
-      asm!("dmb sy");
-      // also raise a signal to indicate the spinlock has been changed (this trigger all WFE's to continue
-      // processing) but do data syncronisation barrier upfront to ensure any data updates has been finished
-      asm!(
-        "dsb sy
-                 sev"
-      );
+    // dmb required before allow access to the protected resource, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    arch::signal_event();
+  }
+
+  /// The current state of the Spinlock. This only reads the lock flag and never acquires the spinlock, so it is safe
+  /// to be used from a panic handler.
+  pub fn fmt_state(&self) -> LockState {
+    LockState::Spinlock {
+      locked: self.flag.load(Ordering::Relaxed),
     }
   }
 }
+
+impl InspectLock for Spinlock {
+  fn lock_state(&self) -> LockState {
+    self.fmt_state()
+  }
+}
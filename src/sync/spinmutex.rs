@@ -0,0 +1,205 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # SpinMutex
+//!
+//! A minimal data carrying spinlock for very short critical sections, like a read-modify-write of a peripheral
+//! register. Other than the [Mutex](super::Mutex) waiting cores busy spin with a spin loop hint instead of using the
+//! selected [SpinPolicy](super::spin::SpinPolicy), and releasing the lock does not signal an event. This keeps both
+//! paths as short as possible, but a waiting core does not save any energy.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::SpinMutex;
+//!
+//! static GPFSEL: SpinMutex<u32> = SpinMutex::new(0);
+//!
+//! fn main() {
+//!     let mut gpfsel = GPFSEL.lock();
+//!     *gpfsel = (*gpfsel & !(0b111 << 12)) | (0b100 << 12);
+//! }
+//! ```
+
+use super::marker::GuardMarker;
+use super::BlockingLock;
+use crate::arch;
+use core::cell::{Cell, UnsafeCell};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A spinlock securing the contained data
+#[repr(C, align(16))]
+pub struct SpinMutex<T: ?Sized> {
+  locked: AtomicBool,
+  data: UnsafeCell<T>,
+}
+
+/// The guard of an acquired [SpinMutex]. If this goes out of scope the lock is released.
+#[cfg_attr(
+  feature = "must_not_suspend",
+  must_not_suspend = "holding a SpinMutexGuard across a suspend point blocks the other cores waiting for the lock"
+)]
+pub struct SpinMutexGuard<'a, T: ?Sized + 'a> {
+  _data: &'a SpinMutex<T>,
+  _marker: GuardMarker,
+  /// suppresses the auto `Sync`, which would only require `T: Send`
+  _not_sync: PhantomData<Cell<()>>,
+}
+
+impl<T> SpinMutex<T> {
+  /// Create a new [SpinMutex] securing the given value
+  pub const fn new(value: T) -> Self {
+    SpinMutex {
+      locked: AtomicBool::new(false),
+      data: UnsafeCell::new(value),
+    }
+  }
+
+  /// Consume the SpinMutex and return the inner value
+  pub fn into_inner(self) -> T {
+    self.data.into_inner()
+  }
+}
+
+impl<T: ?Sized> SpinMutex<T> {
+  /// Try to lock the secured data. Returns `None` if the lock is currently held.
+  pub fn try_lock(&self) -> Option<SpinMutexGuard<'_, T>> {
+    if self
+      .locked
+      .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+      .is_ok()
+    {
+      // dmb required before allow access to the protected resource, see:
+      // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+      arch::dmb();
+      Some(SpinMutexGuard {
+        _data: self,
+        _marker: PhantomData,
+        _not_sync: PhantomData,
+      })
+    } else {
+      None
+    }
+  }
+
+  /// Lock the secured data. This busy spins until the lock could be acquired.
+  pub fn lock(&self) -> SpinMutexGuard<'_, T> {
+    loop {
+      if let Some(guard) = self.try_lock() {
+        return guard;
+      }
+      // only try again once the lock has been seen released, so the spinning core does not claim the cache line
+      while self.locked.load(Ordering::Relaxed) {
+        core::hint::spin_loop();
+      }
+    }
+  }
+
+  /// Returns `true` if the SpinMutex is currently locked
+  pub fn is_locked(&self) -> bool {
+    self.locked.load(Ordering::Relaxed)
+  }
+
+  /// Provide a mutable borrow to the secured data. As this requires a mutable borrow of the SpinMutex no lock need
+  /// to be acquired.
+  pub fn get_mut(&mut self) -> &mut T {
+    self.data.get_mut()
+  }
+}
+
+impl<T: Default> Default for SpinMutex<T> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+/// The Debug implementation only reports the lock state and never acquires the lock
+impl<T: ?Sized> fmt::Debug for SpinMutex<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SpinMutex")
+      .field("locked", &self.is_locked())
+      .finish_non_exhaustive()
+  }
+}
+
+impl<T: ?Sized> BlockingLock<T> for SpinMutex<T> {
+  type Guard<'a>
+    = SpinMutexGuard<'a, T>
+  where
+    Self: 'a;
+
+  fn lock(&self) -> Self::Guard<'_> {
+    SpinMutex::lock(self)
+  }
+
+  fn try_lock(&self) -> Option<Self::Guard<'_>> {
+    SpinMutex::try_lock(self)
+  }
+}
+
+// when the SpinMutexGuard is dropped release the owning lock
+impl<T: ?Sized> Drop for SpinMutexGuard<'_, T> {
+  fn drop(&mut self) {
+    // dmb required to finish all accesses to the protected resource before the lock is released, see:
+    // http://infocenter.arm.com/help/topic/com.arm.doc.dht0008a/DHT0008A_arm_synchronization_primitives.pdf
+    arch::dmb();
+    // waiting cores spin on the lock flag, so no event need to be signalled
+    self._data.locked.store(false, Ordering::Release);
+  }
+}
+
+// the SpinMutexGuard does only exist while the lock is held, so it is the only access to the secured data
+impl<T: ?Sized> Deref for SpinMutexGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    unsafe { &*self._data.data.get() }
+  }
+}
+
+impl<T: ?Sized> DerefMut for SpinMutexGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    unsafe { &mut *self._data.data.get() }
+  }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinMutexGuard<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&**self, f)
+  }
+}
+
+/// The SpinMutex hands out mutual exclusive access to the secured data, so it is `Sync` if the data is `Send`
+unsafe impl<T: ?Sized + Send> Sync for SpinMutex<T> {}
+
+// a shared guard only hands out shared references to the data
+unsafe impl<T: ?Sized + Sync> Sync for SpinMutexGuard<'_, T> {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn guard_releases_the_lock_once_dropped() {
+    let mutex = SpinMutex::new(0u32);
+    let mut guard = mutex.lock();
+    *guard = 10;
+    assert!(mutex.is_locked());
+    assert!(mutex.try_lock().is_none());
+    drop(guard);
+    assert!(!mutex.is_locked());
+    assert_eq!(*mutex.try_lock().unwrap(), 10);
+  }
+
+  #[test]
+  fn guard_is_sync_for_sync_data() {
+    fn assert_sync<T: Sync>() {}
+    assert_sync::<SpinMutexGuard<'_, u32>>();
+  }
+}
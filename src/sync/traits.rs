@@ -0,0 +1,137 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Blocking Lock Traits
+//!
+//! Traits abstracting over the blocking locks, mirroring the async lock traits. Crates can be generic over the lock
+//! used to secure their data, so the primitive can be swapped per target without conditional compilation. With the
+//! `std` feature the traits are also implemented for `std::sync::Mutex` and `std::sync::RwLock`, e.g. to run the
+//! code in host tests. A poisoned `std` lock is acquired anyway, as the locks of this crate do not know poisoning.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::{BlockingLock, Mutex};
+//!
+//! struct Counter<L> {
+//!     value: L,
+//! }
+//!
+//! impl<L: BlockingLock<u32>> Counter<L> {
+//!     fn increment(&self) -> u32 {
+//!         let mut value = self.value.lock();
+//!         *value += 1;
+//!         *value
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let counter = Counter { value: Mutex::new(0) };
+//!     assert_eq!(counter.increment(), 1);
+//! }
+//! ```
+
+use core::ops::{Deref, DerefMut};
+
+/// A lock providing mutual exclusive access to the secured data
+pub trait BlockingLock<T: ?Sized> {
+  /// The guard providing access to the secured data as long as the lock is held
+  type Guard<'a>: DerefMut<Target = T>
+  where
+    Self: 'a;
+
+  /// Lock the secured data. This blocks until the lock could be acquired.
+  fn lock(&self) -> Self::Guard<'_>;
+
+  /// Try to lock the secured data without blocking. Returns `None` if the lock is currently held.
+  fn try_lock(&self) -> Option<Self::Guard<'_>>;
+}
+
+/// A lock providing shared read access or mutual exclusive write access to the secured data
+pub trait BlockingRwLock<T: ?Sized> {
+  /// The guard providing read access to the secured data as long as the read lock is held
+  type ReadGuard<'a>: Deref<Target = T>
+  where
+    Self: 'a;
+
+  /// The guard providing write access to the secured data as long as the write lock is held
+  type WriteGuard<'a>: DerefMut<Target = T>
+  where
+    Self: 'a;
+
+  /// Lock the secured data for read access. This blocks until the lock could be acquired.
+  fn read(&self) -> Self::ReadGuard<'_>;
+
+  /// Try to lock the secured data for read access without blocking. Returns `None` if a write lock is held.
+  fn try_read(&self) -> Option<Self::ReadGuard<'_>>;
+
+  /// Lock the secured data for write access. This blocks until the lock could be acquired.
+  fn write(&self) -> Self::WriteGuard<'_>;
+
+  /// Try to lock the secured data for write access without blocking. Returns `None` if any other lock is held.
+  fn try_write(&self) -> Option<Self::WriteGuard<'_>>;
+}
+
+#[cfg(feature = "std")]
+mod std_impl {
+  extern crate std;
+
+  use super::{BlockingLock, BlockingRwLock};
+  use std::sync::{
+    Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+  };
+
+  /// Acquire the guard of a `try_*` call, even if the lock is poisoned
+  fn unpoison<G>(result: Result<G, TryLockError<G>>) -> Option<G> {
+    match result {
+      Ok(guard) => Some(guard),
+      Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+      Err(TryLockError::WouldBlock) => None,
+    }
+  }
+
+  impl<T: ?Sized> BlockingLock<T> for Mutex<T> {
+    type Guard<'a>
+      = MutexGuard<'a, T>
+    where
+      Self: 'a;
+
+    fn lock(&self) -> Self::Guard<'_> {
+      Mutex::lock(self).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn try_lock(&self) -> Option<Self::Guard<'_>> {
+      unpoison(Mutex::try_lock(self))
+    }
+  }
+
+  impl<T: ?Sized> BlockingRwLock<T> for RwLock<T> {
+    type ReadGuard<'a>
+      = RwLockReadGuard<'a, T>
+    where
+      Self: 'a;
+    type WriteGuard<'a>
+      = RwLockWriteGuard<'a, T>
+    where
+      Self: 'a;
+
+    fn read(&self) -> Self::ReadGuard<'_> {
+      RwLock::read(self).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn try_read(&self) -> Option<Self::ReadGuard<'_>> {
+      unpoison(RwLock::try_read(self))
+    }
+
+    fn write(&self) -> Self::WriteGuard<'_> {
+      RwLock::write(self).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn try_write(&self) -> Option<Self::WriteGuard<'_>> {
+      unpoison(RwLock::try_write(self))
+    }
+  }
+}
@@ -0,0 +1,264 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # TxLock
+//!
+//! A [RWLock] with all-or-nothing updates. [TxLock::begin] hands out a [Transaction] with a working copy of the secured
+//! data, that can be changed and validated without holding any lock. [Transaction::commit] swaps the working copy in
+//! while holding the write lock only for the swap. Dropping the [Transaction] without committing it discards all
+//! changes, so readers either see the data before or after all edits of a transaction, never a partial update.
+//!
+//! Transactions do not exclude each other. Each commit increases the generation of the [TxLock], so a transaction
+//! that is committed after another one has been committed since it began is rejected and handed back to the caller.
+//! It might then be discarded or its changes applied to a fresh transaction, but a concurrent update is never lost
+//! silently.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::TxLock;
+//!
+//! #[derive(Clone)]
+//! struct Config {
+//!     baud_rate: u32,
+//!     data_bits: u8,
+//! }
+//!
+//! static CONFIG: TxLock<Config> = TxLock::new(Config {
+//!     baud_rate: 115_200,
+//!     data_bits: 8,
+//! });
+//!
+//! fn main() {
+//!     let mut config = CONFIG.begin();
+//!     config.baud_rate = 9_600;
+//!     config.data_bits = 9;
+//!     // invalid settings are discarded by dropping the transaction
+//!     if config.data_bits <= 8 {
+//!         config.commit().ok();
+//!     }
+//!     assert_eq!(CONFIG.read().baud_rate, 115_200);
+//!
+//!     // retry the update if another one has been committed in the meantime
+//!     loop {
+//!         let mut config = CONFIG.begin();
+//!         config.baud_rate = 9_600;
+//!         if config.commit().is_ok() {
+//!             break;
+//!         }
+//!     }
+//!     assert_eq!(CONFIG.read().baud_rate, 9_600);
+//! }
+//! ```
+
+use super::{RWLock, ReadLockGuard};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A [RWLock] where the secured data is updated with [Transaction]s
+pub struct TxLock<T: Clone> {
+  data: RWLock<T>,
+  /// The number of commits so far, only updated while holding the write lock
+  generation: AtomicUsize,
+}
+
+/// A working copy of the data secured by a [TxLock]. The changes become visible once the transaction is committed, if
+/// it goes out of scope without being committed they are discarded.
+pub struct Transaction<'a, T: Clone> {
+  lock: &'a TxLock<T>,
+  /// The generation of the data the working copy has been cloned from
+  generation: usize,
+  working: T,
+}
+
+impl<T: Clone> TxLock<T> {
+  /// Create a new [TxLock] securing the given value
+  pub const fn new(value: T) -> Self {
+    Self {
+      data: RWLock::new(value),
+      generation: AtomicUsize::new(0),
+    }
+  }
+
+  /// Begin a [Transaction] on a working copy of the secured data. The read lock is only held while cloning.
+  pub fn begin(&self) -> Transaction<'_, T> {
+    let data = self.data.read();
+    Transaction {
+      lock: self,
+      generation: self.generation.load(Ordering::Relaxed),
+      working: (*data).clone(),
+    }
+  }
+
+  /// Acquire a read lock to the committed data. This blocks until the read lock could be acquired.
+  pub fn read(&self) -> ReadLockGuard<'_, T> {
+    self.data.read()
+  }
+
+  /// Try to acquire a read lock to the committed data. Returns `None` if a commit is currently in progress.
+  pub fn try_read(&self) -> Option<ReadLockGuard<'_, T>> {
+    self.data.try_read()
+  }
+
+  /// Consume the [TxLock] and return the committed data
+  pub fn into_inner(self) -> T {
+    self.data.into_inner()
+  }
+}
+
+impl<'a, T: Clone> Transaction<'a, T> {
+  /// Make the changes of this transaction visible. The write lock is only held while swapping the working copy in,
+  /// the previous data is dropped after it has been released.
+  ///
+  /// If another transaction has been committed since this one began, its changes would be lost. In this case the data
+  /// remains unchanged and the transaction is returned in the `Err` variant.
+  ///
+  /// # Example
+  /// ```
+  /// # use ruspiro_lock::sync::TxLock;
+  /// # fn main() {
+  ///     let counter = TxLock::new(0_u32);
+  ///     let mut first = counter.begin();
+  ///     let mut second = counter.begin();
+  ///     *first += 1;
+  ///     *second += 1;
+  ///     assert!(first.commit().is_ok());
+  ///     // the second transaction has been based on the data before the first commit
+  ///     assert!(second.commit().is_err());
+  ///     assert_eq!(*counter.read(), 1);
+  /// # }
+  /// ```
+  pub fn commit(self) -> Result<(), Transaction<'a, T>> {
+    let mut data = self.lock.data.write();
+    if self.lock.generation.load(Ordering::Relaxed) != self.generation {
+      drop(data);
+      return Err(self);
+    }
+    // the generation is only updated while holding the write lock, so there is no need for an atomic increment
+    self
+      .lock
+      .generation
+      .store(self.generation.wrapping_add(1), Ordering::Relaxed);
+    let Self { mut working, .. } = self;
+    core::mem::swap(&mut *data, &mut working);
+    drop(data);
+    Ok(())
+  }
+
+  /// Discard the changes of this transaction. This is the same as dropping it, but makes the intent explicit.
+  pub fn rollback(self) {}
+}
+
+impl<T: Clone> Deref for Transaction<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.working
+  }
+}
+
+impl<T: Clone> DerefMut for Transaction<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    &mut self.working
+  }
+}
+
+impl<T: Clone> fmt::Debug for TxLock<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("TxLock").field("data", &self.data).finish()
+  }
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for Transaction<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Transaction")
+      .field("generation", &self.generation)
+      .field("working", &self.working)
+      .finish()
+  }
+}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn changes_are_visible_once_committed() {
+    let tx = TxLock::new([0u32; 2]);
+    let mut transaction = tx.begin();
+    transaction[0] = 1;
+    transaction[1] = 2;
+    assert_eq!(*tx.read(), [0, 0]);
+    assert!(transaction.commit().is_ok());
+    assert_eq!(*tx.read(), [1, 2]);
+  }
+
+  #[test]
+  fn dropped_transactions_are_discarded() {
+    let tx = TxLock::new(1u32);
+    let mut transaction = tx.begin();
+    *transaction = 2;
+    transaction.rollback();
+    *tx.begin() = 3;
+    assert_eq!(tx.into_inner(), 1);
+  }
+
+  #[test]
+  fn outdated_transactions_are_handed_back() {
+    let tx = TxLock::new(0u32);
+    let mut outdated = tx.begin();
+    *outdated = 10;
+    let mut current = tx.begin();
+    *current = 1;
+    assert!(current.commit().is_ok());
+
+    let outdated = outdated.commit().unwrap_err();
+    assert_eq!(*outdated, 10);
+    assert_eq!(*tx.read(), 1);
+    // the changes can be applied to a fresh transaction
+    let mut retry = tx.begin();
+    *retry += *outdated;
+    drop(outdated);
+    assert!(retry.commit().is_ok());
+    assert_eq!(*tx.read(), 11);
+  }
+
+  #[test]
+  fn try_read_fails_while_committing() {
+    let tx = TxLock::new(0u32);
+    let writer = tx.data.write();
+    assert!(tx.try_read().is_none());
+    drop(writer);
+    assert_eq!(*tx.try_read().unwrap(), 0);
+  }
+
+  #[test]
+  // all threads run on the same core of the host, so the re-entrancy detection takes them for one core
+  #[cfg(not(feature = "reentrancy_detection"))]
+  fn concurrent_transactions_do_not_lose_updates() {
+    const THREADS: usize = 4;
+    const UPDATES: usize = 100;
+
+    let tx = TxLock::new(0usize);
+    std::thread::scope(|s| {
+      for _ in 0..THREADS {
+        s.spawn(|| {
+          for _ in 0..UPDATES {
+            loop {
+              let mut transaction = tx.begin();
+              *transaction += 1;
+              if transaction.commit().is_ok() {
+                break;
+              }
+            }
+          }
+        });
+      }
+    });
+    assert_eq!(tx.into_inner(), THREADS * UPDATES);
+  }
+}
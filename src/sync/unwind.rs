@@ -0,0 +1,134 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Unwind Fixup
+//!
+//! The locks of this crate are not poisoned if a panic unwinds while they are held, so the secured data might be left
+//! partially updated. [MutexGuard::on_unwind](super::MutexGuard::on_unwind) registers a fixup closure that is called
+//! with the secured data if the guard is dropped while the core unwinds, so the data can be reset to a consistent state
+//! before the lock is released.
+//!
+//! Whether the core is unwinding is queried from the hook selected with [set_panicking_hook]. With the `std` feature it
+//! defaults to `std::thread::panicking`. Bare metal systems unwinding with their own panic runtime need to select a
+//! hook reporting the panicking state of the current core, otherwise the fixup is never called.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::Mutex;
+//!
+//! struct Transfer {
+//!     active: bool,
+//!     length: usize,
+//! }
+//!
+//! static TRANSFER: Mutex<Transfer> = Mutex::new(Transfer {
+//!     active: false,
+//!     length: 0,
+//! });
+//!
+//! fn start(length: usize) {
+//!     let mut transfer = TRANSFER.lock().on_unwind(|transfer| {
+//!         transfer.active = false;
+//!         transfer.length = 0;
+//!     });
+//!     transfer.active = true;
+//!     transfer.length = length;
+//!     // a panic in here resets the transfer before the lock is released
+//! }
+//!
+//! fn main() {
+//!     start(512);
+//! }
+//! ```
+
+use super::marker::GuardMarker;
+use super::{AtomicCell, MutexGuard};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+/// A [MutexGuard] calling a fixup closure with the secured data if it is dropped while the core unwinds. If this goes
+/// out of scope the lock is released.
+#[cfg_attr(
+  feature = "must_not_suspend",
+  must_not_suspend = "holding an UnwindGuard across a suspend point blocks the other cores waiting for the lock"
+)]
+pub struct UnwindGuard<'a, T: ?Sized, F: FnOnce(&mut T)> {
+  guard: MutexGuard<'a, T>,
+  fixup: Option<F>,
+  _marker: GuardMarker,
+}
+
+/// The hook reporting whether the current core is unwinding
+static PANICKING: AtomicCell<fn() -> bool> = AtomicCell::new(default_panicking as fn() -> bool);
+
+#[cfg(feature = "std")]
+fn default_panicking() -> bool {
+  extern crate std;
+  std::thread::panicking()
+}
+
+#[cfg(not(feature = "std"))]
+fn default_panicking() -> bool {
+  false
+}
+
+/// Select the hook reporting whether the current core is unwinding from a panic. It is called each time an
+/// [UnwindGuard] is dropped and shall therefore not acquire any lock.
+pub fn set_panicking_hook(hook: fn() -> bool) {
+  PANICKING.store(hook);
+}
+
+impl<'a, T: ?Sized, F: FnOnce(&mut T)> UnwindGuard<'a, T, F> {
+  pub(crate) fn new(guard: MutexGuard<'a, T>, fixup: F) -> Self {
+    Self {
+      guard,
+      fixup: Some(fixup),
+      _marker: PhantomData,
+    }
+  }
+
+  /// Drop the fixup closure and return the plain [MutexGuard], e.g. once the update has been completed
+  pub fn into_guard(mut self) -> MutexGuard<'a, T> {
+    self.fixup = None;
+    // SAFETY: the guard is read exactly once and self is forgotten right away, so it is not dropped twice
+    let guard = unsafe { core::ptr::read(&self.guard) };
+    core::mem::forget(self);
+    guard
+  }
+}
+
+// when the UnwindGuard is dropped while unwinding the fixup is called before the lock is released
+impl<T: ?Sized, F: FnOnce(&mut T)> Drop for UnwindGuard<'_, T, F> {
+  fn drop(&mut self) {
+    if let Some(fixup) = self.fixup.take() {
+      if (PANICKING.load())() {
+        fixup(&mut self.guard);
+      }
+    }
+  }
+}
+
+impl<T: ?Sized, F: FnOnce(&mut T)> Deref for UnwindGuard<'_, T, F> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.guard
+  }
+}
+
+impl<T: ?Sized, F: FnOnce(&mut T)> DerefMut for UnwindGuard<'_, T, F> {
+  fn deref_mut(&mut self) -> &mut T {
+    &mut self.guard
+  }
+}
+
+impl<T: ?Sized + fmt::Debug, F: FnOnce(&mut T)> fmt::Debug for UnwindGuard<'_, T, F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&**self, f)
+  }
+}
@@ -0,0 +1,204 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # VolatileLock
+//!
+//! A read/write lock for memory that is not owned by the lock, like MMIO mapped register blocks of peripherals.
+//! Creating a `&mut` reference to device memory is undefined behavior as the compiler is free to elide, merge or
+//! reorder the accesses. The guards of the [VolatileLock] therefore never hand out references to the data but
+//! perform volatile reads and writes only.
+//!
+//! # Example
+//! ```no_run
+//! use ruspiro_lock::sync::VolatileLock;
+//!
+//! // the GPIO function select register of the Raspberry Pi 3
+//! static GPFSEL0: VolatileLock<u32> = unsafe { VolatileLock::new(0x3F20_0000 as *mut u32) };
+//!
+//! fn main() {
+//!     let reg = GPFSEL0.write();
+//!     reg.modify(|value| (value & !0b111) | 0b001);
+//!     drop(reg);
+//!
+//!     let value = GPFSEL0.read().get();
+//! }
+//! ```
+
+use super::rwlock::{RWLock, ReadLockGuard, WriteLockGuard};
+
+/// A read/write lock securing volatile access to memory at a fixed address
+pub struct VolatileLock<T> {
+  lock: RWLock<()>,
+  ptr: *mut T,
+}
+
+/// Shared read access to the memory secured by the [VolatileLock]
+pub struct VolatileReadGuard<'a, T> {
+  _guard: ReadLockGuard<'a, ()>,
+  ptr: *const T,
+}
+
+/// Exclusive access to the memory secured by the [VolatileLock]
+pub struct VolatileWriteGuard<'a, T> {
+  _guard: WriteLockGuard<'a, ()>,
+  ptr: *mut T,
+}
+
+impl<T> VolatileLock<T> {
+  /// Create a new [VolatileLock] for the memory at the given address.
+  ///
+  /// # Safety
+  /// The address need to be valid for volatile reads and writes of `T` for the whole lifetime of the lock and the
+  /// memory shall only be accessed through this lock.
+  pub const unsafe fn new(ptr: *mut T) -> Self {
+    Self {
+      lock: RWLock::new(()),
+      ptr,
+    }
+  }
+
+  /// Try to acquire shared read access. Returns `None` if there is a write access active.
+  pub fn try_read(&self) -> Option<VolatileReadGuard<'_, T>> {
+    self.lock.try_read().map(|guard| VolatileReadGuard {
+      _guard: guard,
+      ptr: self.ptr,
+    })
+  }
+
+  /// Acquire shared read access. This blocks until there is no write access active.
+  pub fn read(&self) -> VolatileReadGuard<'_, T> {
+    VolatileReadGuard {
+      _guard: self.lock.read(),
+      ptr: self.ptr,
+    }
+  }
+
+  /// Try to acquire exclusive write access. Returns `None` if there is any other access active.
+  pub fn try_write(&self) -> Option<VolatileWriteGuard<'_, T>> {
+    self.lock.try_write().map(|guard| VolatileWriteGuard {
+      _guard: guard,
+      ptr: self.ptr,
+    })
+  }
+
+  /// Acquire exclusive write access. This blocks until there is no other access active.
+  pub fn write(&self) -> VolatileWriteGuard<'_, T> {
+    VolatileWriteGuard {
+      _guard: self.lock.write(),
+      ptr: self.ptr,
+    }
+  }
+
+  /// The address of the memory secured by this lock
+  pub fn as_ptr(&self) -> *mut T {
+    self.ptr
+  }
+}
+
+impl<T: Copy> VolatileReadGuard<'_, T> {
+  /// Read the value with a volatile read
+  pub fn get(&self) -> T {
+    // SAFETY: the caller of `VolatileLock::new` guaranteed the address is valid for volatile reads
+    unsafe { self.ptr.read_volatile() }
+  }
+}
+
+impl<T> VolatileReadGuard<'_, T> {
+  /// The address of the memory secured by the lock. This can be used to read parts of `T`, like a single register of
+  /// a register block, with volatile reads.
+  pub fn as_ptr(&self) -> *const T {
+    self.ptr
+  }
+}
+
+impl<T: Copy> VolatileWriteGuard<'_, T> {
+  /// Read the value with a volatile read
+  pub fn get(&self) -> T {
+    // SAFETY: the caller of `VolatileLock::new` guaranteed the address is valid for volatile reads
+    unsafe { self.ptr.read_volatile() }
+  }
+
+  /// Write the value with a volatile write
+  pub fn set(&self, value: T) {
+    // SAFETY: the caller of `VolatileLock::new` guaranteed the address is valid for volatile writes
+    unsafe { self.ptr.write_volatile(value) }
+  }
+
+  /// Volatile read-modify-write of the value. The update is atomic with respect to other users of the lock.
+  pub fn modify<F: FnOnce(T) -> T>(&self, f: F) {
+    self.set(f(self.get()));
+  }
+}
+
+impl<T> VolatileWriteGuard<'_, T> {
+  /// The address of the memory secured by the lock. This can be used to access parts of `T`, like a single register
+  /// of a register block, with volatile reads and writes.
+  pub fn as_mut_ptr(&self) -> *mut T {
+    self.ptr
+  }
+}
+
+// the VolatileLock does only hand out volatile accesses to the memory secured by the RWLock, so it is safe to be
+// shared across cores
+unsafe impl<T: Send> Sync for VolatileLock<T> {}
+unsafe impl<T: Send> Send for VolatileLock<T> {}
+
+#[cfg(testing)]
+mod tests {
+  use super::*;
+  use core::cell::UnsafeCell;
+
+  #[test]
+  fn guards_access_the_memory_at_the_address() {
+    let memory = UnsafeCell::new(0u32);
+    let lock = unsafe { VolatileLock::new(memory.get()) };
+    let writer = lock.write();
+    writer.set(0b1010);
+    writer.modify(|value| value | 1);
+    assert_eq!(writer.get(), 0b1011);
+    drop(writer);
+    assert_eq!(lock.read().get(), 0b1011);
+    assert_eq!(lock.as_ptr(), memory.get());
+  }
+
+  #[test]
+  fn try_access_fails_while_excluded() {
+    let memory = UnsafeCell::new(0u32);
+    let lock = unsafe { VolatileLock::new(memory.get()) };
+    let reader = lock.read();
+    assert!(lock.try_write().is_none());
+    assert!(lock.try_read().is_some());
+    drop(reader);
+
+    let writer = lock.try_write().unwrap();
+    assert!(lock.try_read().is_none());
+    assert!(lock.try_write().is_none());
+    drop(writer);
+    assert!(lock.try_write().is_some());
+  }
+
+  #[test]
+  // all threads run on the same core of the host, so the re-entrancy detection takes them for one core
+  #[cfg(not(feature = "reentrancy_detection"))]
+  fn concurrent_modifications_are_not_lost() {
+    const THREADS: u32 = 4;
+    const UPDATES: u32 = 1000;
+
+    let memory = UnsafeCell::new(0u32);
+    let lock = unsafe { VolatileLock::new(memory.get()) };
+    std::thread::scope(|s| {
+      for _ in 0..THREADS {
+        s.spawn(|| {
+          for _ in 0..UPDATES {
+            lock.write().modify(|value| value + 1);
+          }
+        });
+      }
+    });
+    assert_eq!(lock.read().get(), THREADS * UPDATES);
+  }
+}
@@ -0,0 +1,123 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Watermarks
+//!
+//! The peak depth of a queue or the longest time an interrupt has been masked is recorded by many cores, but only read
+//! now and then. A [Watermark] keeps the largest and a [LowWatermark] the smallest value recorded since it has been
+//! reset. Recording a value is a single atomic operation, so it can be done by all cores at the same time, even from
+//! interrupt handlers, without a lock.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::{LowWatermark, Watermark};
+//!
+//! static QUEUE_PEAK: Watermark = Watermark::new();
+//! static FREE_BUFFERS_LOW: LowWatermark = LowWatermark::new();
+//!
+//! fn main() {
+//!     for depth in [3, 7, 2] {
+//!         QUEUE_PEAK.record(depth);
+//!         FREE_BUFFERS_LOW.record(16 - depth);
+//!     }
+//!     assert_eq!(QUEUE_PEAK.get(), 7);
+//!     assert_eq!(FREE_BUFFERS_LOW.get(), 9);
+//!     // report the peak of the last period and start the next one
+//!     assert_eq!(QUEUE_PEAK.reset(), 7);
+//!     assert_eq!(QUEUE_PEAK.get(), 0);
+//! }
+//! ```
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The largest value recorded with atomic operations only
+pub struct Watermark {
+  value: AtomicU64,
+}
+
+impl Watermark {
+  /// Create a [Watermark] at `0`. As this does not require any allocation it can be assigned to a static variable
+  pub const fn new() -> Self {
+    Self {
+      value: AtomicU64::new(0),
+    }
+  }
+
+  /// Record the given value. Returns `true` if it exceeds all values recorded before, i.e. it raised the watermark.
+  #[inline]
+  pub fn record(&self, value: u64) -> bool {
+    self.value.fetch_max(value, Ordering::Relaxed) < value
+  }
+
+  /// The largest value recorded since the last reset, or `0` if no value has been recorded
+  pub fn get(&self) -> u64 {
+    self.value.load(Ordering::Relaxed)
+  }
+
+  /// Reset the watermark to `0` and return the largest value recorded before. A value recorded at the same time is
+  /// either returned or kept, so it is not lost.
+  pub fn reset(&self) -> u64 {
+    self.value.swap(0, Ordering::Relaxed)
+  }
+}
+
+impl Default for Watermark {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl fmt::Debug for Watermark {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Watermark").field(&self.get()).finish()
+  }
+}
+
+/// The smallest value recorded with atomic operations only
+pub struct LowWatermark {
+  value: AtomicU64,
+}
+
+impl LowWatermark {
+  /// Create a [LowWatermark] at `u64::MAX`. As this does not require any allocation it can be assigned to a static
+  /// variable
+  pub const fn new() -> Self {
+    Self {
+      value: AtomicU64::new(u64::MAX),
+    }
+  }
+
+  /// Record the given value. Returns `true` if it is below all values recorded before, i.e. it lowered the watermark.
+  #[inline]
+  pub fn record(&self, value: u64) -> bool {
+    self.value.fetch_min(value, Ordering::Relaxed) > value
+  }
+
+  /// The smallest value recorded since the last reset, or `u64::MAX` if no value has been recorded
+  pub fn get(&self) -> u64 {
+    self.value.load(Ordering::Relaxed)
+  }
+
+  /// Reset the watermark to `u64::MAX` and return the smallest value recorded before. A value recorded at the same time
+  /// is either returned or kept, so it is not lost.
+  pub fn reset(&self) -> u64 {
+    self.value.swap(u64::MAX, Ordering::Relaxed)
+  }
+}
+
+impl Default for LowWatermark {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl fmt::Debug for LowWatermark {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("LowWatermark").field(&self.get()).finish()
+  }
+}
@@ -0,0 +1,331 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2020 by the authors
+ *
+ * Author: André Borrmann <pspwizard@gmx.de>
+ * License: Apache License 2.0 / MIT
+ **********************************************************************************************************************/
+
+//! # Test Doubles
+//!
+//! Locks whose contention is scripted by the test instead of caused by other cores. Driver crates can unit test the
+//! code handling a contended lock, like a retry or a fallback path, deterministically and without real concurrency.
+//!
+//! Each attempt to acquire a [MockMutex] or [MockSemaphore] is counted. With `fail_next(n)` the next `n` attempts fail
+//! before the lock can be acquired again, with `hold()` all attempts fail until the test calls `release()`. A blocking
+//! `lock` or `down` waits until the scripted failures are used up, or until another thread of the test releases the
+//! held lock. The [MockMutex] implements the [BlockingLock] trait and, with one of the async features, the
+//! [AsyncLock](crate::async::AsyncLock) trait, so it can replace the locks of this crate in generic driver code.
+//!
+//! # Example
+//! ```
+//! use ruspiro_lock::sync::BlockingLock;
+//! use ruspiro_lock::testing::MockMutex;
+//!
+//! // the driver code under test gives up after 3 attempts
+//! fn try_send<L: BlockingLock<u32>>(fifo: &L) -> bool {
+//!     for _ in 0..3 {
+//!         if let Some(mut fifo) = fifo.try_lock() {
+//!             *fifo += 1;
+//!             return true;
+//!         }
+//!     }
+//!     false
+//! }
+//!
+//! fn main() {
+//!     let fifo = MockMutex::new(0);
+//!     fifo.fail_next(2);
+//!     assert!(try_send(&fifo));
+//!     assert_eq!(fifo.attempts(), 3);
+//!
+//!     fifo.hold();
+//!     assert!(!try_send(&fifo));
+//!     fifo.release();
+//!     assert_eq!(*fifo.lock(), 1);
+//! }
+//! ```
+
+use crate::arch;
+use crate::sync::{spin, BlockingLock, Mutex, MutexGuard, Semaphore};
+use crate::LockError;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// A [Mutex] whose aquisition outcomes are scripted by the test
+pub struct MockMutex<T> {
+  script: Script,
+  data: Mutex<T>,
+}
+
+/// The guard of a [MockMutex]. If this goes out of scope the lock is released.
+pub struct MockMutexGuard<'a, T> {
+  guard: MutexGuard<'a, T>,
+}
+
+/// A [Semaphore] whose aquisition outcomes are scripted by the test
+pub struct MockSemaphore {
+  script: Script,
+  sema: Semaphore,
+}
+
+impl<T> MockMutex<T> {
+  /// Create a new [MockMutex] that can be acquired without scripted failures
+  pub const fn new(value: T) -> Self {
+    Self {
+      script: Script::new(),
+      data: Mutex::new(value),
+    }
+  }
+
+  /// Let the next `count` attempts to acquire the lock fail, regardless of the lock being held
+  pub fn fail_next(&self, count: u32) {
+    self.script.fail_next(count);
+  }
+
+  /// Hold the lock on behalf of the test. All attempts to acquire it fail until [MockMutex::release] is called.
+  pub fn hold(&self) {
+    self.script.hold();
+  }
+
+  /// Release the lock held on behalf of the test
+  pub fn release(&self) {
+    self.script.release();
+  }
+
+  /// The number of attempts to acquire the lock so far, including the failed ones
+  pub fn attempts(&self) -> u32 {
+    self.script.attempts.load(Ordering::Acquire)
+  }
+
+  /// The number of times the lock has been acquired so far
+  pub fn acquisitions(&self) -> u32 {
+    self.script.acquisitions.load(Ordering::Acquire)
+  }
+
+  /// Try to lock the secured data. Returns `None` if a scripted failure is pending, the lock is held by the test or
+  /// actually locked.
+  pub fn try_lock(&self) -> Option<MockMutexGuard<'_, T>> {
+    if !self.script.attempt() {
+      return None;
+    }
+    let guard = self.data.try_lock()?;
+    self.script.acquired();
+    Some(MockMutexGuard { guard })
+  }
+
+  /// Lock the secured data. This blocks until all scripted failures are used up and the lock could be acquired.
+  pub fn lock(&self) -> MockMutexGuard<'_, T> {
+    let mut attempt = 0;
+    loop {
+      if let Some(guard) = self.try_lock() {
+        return guard;
+      }
+      spin::on_contention(&mut attempt, "MockMutex::lock");
+    }
+  }
+}
+
+impl MockSemaphore {
+  /// Create a new [MockSemaphore] with the given number of permits and without scripted failures
+  pub const fn new(initial: u32) -> Self {
+    Self {
+      script: Script::new(),
+      sema: Semaphore::new(initial),
+    }
+  }
+
+  /// Let the next `count` attempts to acquire a permit fail, regardless of the available permits
+  pub fn fail_next(&self, count: u32) {
+    self.script.fail_next(count);
+  }
+
+  /// Hold back all permits on behalf of the test. All attempts to acquire one fail until [MockSemaphore::release] is
+  /// called.
+  pub fn hold(&self) {
+    self.script.hold();
+  }
+
+  /// Release the permits held back on behalf of the test
+  pub fn release(&self) {
+    self.script.release();
+  }
+
+  /// The number of attempts to acquire a permit so far, including the failed ones
+  pub fn attempts(&self) -> u32 {
+    self.script.attempts.load(Ordering::Acquire)
+  }
+
+  /// The number of permits acquired so far
+  pub fn acquisitions(&self) -> u32 {
+    self.script.acquisitions.load(Ordering::Acquire)
+  }
+
+  /// Try to acquire a permit. Returns [LockError::WouldBlock] if a scripted failure is pending, the permits are held
+  /// back by the test or no permit is available.
+  pub fn try_acquire(&self) -> Result<(), LockError> {
+    if !self.script.attempt() {
+      return Err(LockError::WouldBlock);
+    }
+    self.sema.try_acquire()?;
+    self.script.acquired();
+    Ok(())
+  }
+
+  /// Acquire a permit. This blocks until all scripted failures are used up and a permit could be acquired.
+  pub fn down(&self) {
+    let mut attempt = 0;
+    while self.try_acquire().is_err() {
+      spin::on_contention(&mut attempt, "MockSemaphore::down");
+    }
+  }
+
+  /// Return a permit
+  pub fn up(&self) {
+    self.sema.up();
+  }
+}
+
+impl<T> BlockingLock<T> for MockMutex<T> {
+  type Guard<'a>
+    = MockMutexGuard<'a, T>
+  where
+    Self: 'a;
+
+  fn lock(&self) -> Self::Guard<'_> {
+    MockMutex::lock(self)
+  }
+
+  fn try_lock(&self) -> Option<Self::Guard<'_>> {
+    MockMutex::try_lock(self)
+  }
+}
+
+#[cfg(any(feature = "async_locks", feature = "async_locks_noalloc"))]
+impl<T> crate::r#async::AsyncLock<T> for MockMutex<T> {
+  type Guard<'a>
+    = MockMutexGuard<'a, T>
+  where
+    Self: 'a;
+
+  /// Each poll of the returned `Future` is one attempt to acquire the lock. If it fails the `Future` wakes itself, so a
+  /// single threaded executor polls it again until the scripted failures are used up.
+  fn lock(&self) -> impl core::future::Future<Output = Self::Guard<'_>> {
+    core::future::poll_fn(move |cx| match self.try_lock() {
+      Some(guard) => core::task::Poll::Ready(guard),
+      None => {
+        cx.waker().wake_by_ref();
+        core::task::Poll::Pending
+      }
+    })
+  }
+}
+
+impl<T> Deref for MockMutexGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.guard
+  }
+}
+
+impl<T> DerefMut for MockMutexGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    &mut self.guard
+  }
+}
+
+impl<T> AsRef<T> for MockMutexGuard<'_, T> {
+  fn as_ref(&self) -> &T {
+    &self.guard
+  }
+}
+
+impl<T> AsMut<T> for MockMutexGuard<'_, T> {
+  fn as_mut(&mut self) -> &mut T {
+    &mut self.guard
+  }
+}
+
+impl<T> fmt::Debug for MockMutex<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MockMutex")
+      .field("script", &self.script)
+      .field("data", &self.data)
+      .finish()
+  }
+}
+
+impl fmt::Debug for MockSemaphore {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MockSemaphore")
+      .field("script", &self.script)
+      .field("sema", &self.sema)
+      .finish()
+  }
+}
+
+/// The scripted outcomes shared by the test doubles
+struct Script {
+  /// The number of attempts that still need to fail
+  failures: AtomicU32,
+  /// The lock is held on behalf of the test
+  held: AtomicBool,
+  attempts: AtomicU32,
+  acquisitions: AtomicU32,
+}
+
+impl Script {
+  const fn new() -> Self {
+    Self {
+      failures: AtomicU32::new(0),
+      held: AtomicBool::new(false),
+      attempts: AtomicU32::new(0),
+      acquisitions: AtomicU32::new(0),
+    }
+  }
+
+  fn fail_next(&self, count: u32) {
+    self.failures.store(count, Ordering::Release);
+  }
+
+  fn hold(&self) {
+    self.held.store(true, Ordering::Release);
+  }
+
+  fn release(&self) {
+    self.held.store(false, Ordering::Release);
+    // wake the cores blocked on the mock lock
+    arch::signal_event();
+  }
+
+  /// Count an attempt and return `true` if it is not failed by the script
+  fn attempt(&self) -> bool {
+    self.attempts.fetch_add(1, Ordering::AcqRel);
+    if self.held.load(Ordering::Acquire) {
+      return false;
+    }
+    // use up one of the scripted failures
+    self
+      .failures
+      .fetch_update(Ordering::AcqRel, Ordering::Acquire, |failures| {
+        failures.checked_sub(1)
+      })
+      .is_err()
+  }
+
+  fn acquired(&self) {
+    self.acquisitions.fetch_add(1, Ordering::AcqRel);
+  }
+}
+
+impl fmt::Debug for Script {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Script")
+      .field("failures", &self.failures.load(Ordering::Relaxed))
+      .field("held", &self.held.load(Ordering::Relaxed))
+      .field("attempts", &self.attempts.load(Ordering::Relaxed))
+      .field("acquisitions", &self.acquisitions.load(Ordering::Relaxed))
+      .finish()
+  }
+}